@@ -0,0 +1,33 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+#[rtfm::app]
+const APP: () = {
+    // NOTE `std::env::args` is empty in a static musl binary; these aren't
+    #[init(spawn = [greet])]
+    fn init(c: init::Context) {
+        // e.g. `args world`
+        let name = c
+            .args
+            .get(1)
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "nobody".to_string());
+
+        let shell = c
+            .env
+            .get("SHELL")
+            .map(|shell| shell.to_string_lossy().into_owned());
+
+        println!("{} arguments, SHELL = {:?}", c.args.len(), shell);
+        c.spawn.greet(name).ok();
+    }
+
+    #[task]
+    fn greet(_: greet::Context, name: String) {
+        println!("hello, {}", name);
+
+        rtfm::shutdown(0);
+    }
+};
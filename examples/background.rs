@@ -0,0 +1,54 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+use std::{fs::OpenOptions, io::Write, thread};
+
+#[rtfm::app(background_workers = 2)]
+const APP: () = {
+    #[init(spawn = [sample])]
+    fn init(c: init::Context) {
+        c.spawn.sample(0).ok();
+    }
+
+    // real-time: never waits for `store` or `upload`
+    #[task(priority = 2, schedule = [sample], spawn = [store, upload])]
+    fn sample(c: sample::Context, n: u32) {
+        let reading = n * 10;
+
+        // a full queue only drops the reading
+        c.spawn.store(n, reading).ok();
+        if n % 10 == 9 {
+            c.spawn.upload(n / 10).ok();
+        }
+
+        if n < 99 {
+            c.schedule
+                .sample(c.scheduled + Duration::from_millis(10), n + 1)
+                .ok();
+        } else {
+            rtfm::shutdown(0);
+        }
+    }
+
+    // runs under `SCHED_OTHER` on a worker thread
+    #[task(class = "background", capacity = 16)]
+    fn store(_: store::Context, n: u32, reading: u32) {
+        if let Ok(mut file) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open("/tmp/readings.log")
+        {
+            writeln!(file, "{} {}", n, reading).ok();
+        }
+    }
+
+    // takes its time; `store` keeps running on the other worker
+    #[task(class = "background")]
+    fn upload(_: upload::Context, batch: u32) {
+        thread::sleep(Duration::from_millis(50));
+        println!("uploaded batch {}", batch);
+    }
+};
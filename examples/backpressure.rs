@@ -0,0 +1,30 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::{process, thread};
+
+#[rtfm::app]
+const APP: () = {
+    #[idle(spawn = [consume])]
+    fn idle(c: idle::Context) -> ! {
+        // the producer runs at the lowest priority; it sleeps whenever `consume` falls behind
+        for i in 0..8 {
+            c.spawn_blocking.consume(i).ok();
+        }
+
+        loop {
+            thread::park();
+        }
+    }
+
+    #[task(backpressure = true, capacity = 2)]
+    fn consume(_: consume::Context, x: u32) {
+        println!("consume({})", x);
+
+        if x == 7 {
+            process::exit(0);
+        }
+    }
+};
@@ -0,0 +1,41 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+#[rtfm::app(broadcast(sensors = [fuse, store, report]))]
+const APP: () = {
+    #[init(spawn = [sample])]
+    fn init(c: init::Context) {
+        c.spawn.sample().ok();
+    }
+
+    // spawns the three tasks with two signals: one for priority 2 and one for priority 1
+    #[task(spawn = [fuse, store, report])]
+    fn sample(c: sample::Context) {
+        for reading in 0..3 {
+            c.broadcast.sensors(reading).ok();
+        }
+    }
+
+    #[task(capacity = 3, priority = 2)]
+    fn fuse(_: fuse::Context, reading: u32) {
+        println!("fuse({})", reading);
+    }
+
+    #[task(capacity = 3, priority = 2)]
+    fn store(_: store::Context, reading: u32) {
+        println!("store({})", reading);
+    }
+
+    #[task(capacity = 3)]
+    fn report(_: report::Context, reading: u32) {
+        println!("report({})", reading);
+
+        if reading == 2 {
+            process::exit(0);
+        }
+    }
+};
@@ -0,0 +1,41 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+#[rtfm::app]
+const APP: () = {
+    #[init]
+    fn init(_: init::Context) {}
+
+    // may use up to 1 ms of CPU time per run
+    #[task(period = "10ms", budget = "1ms")]
+    fn foo(_: foo::Context) {
+        static mut COUNT: u8 = 0;
+
+        *COUNT += 1;
+        println!("foo({})", COUNT);
+
+        if *COUNT == 3 {
+            // a runaway loop; only the overrun handler gets it out
+            while !STOP.load(Ordering::Relaxed) {}
+
+            rtfm::shutdown(0);
+        }
+    }
+
+    // called, on top of the task, when a task uses more CPU time than its `budget`
+    #[budget_overrun]
+    fn on_overrun(task: &'static str, budget: Duration) {
+        println!("`{}` used more than {:?} of CPU time", task, budget);
+
+        STOP.store(true, Ordering::Relaxed);
+    }
+};
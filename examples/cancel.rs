@@ -0,0 +1,35 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app]
+const APP: () = {
+    #[init(schedule = [bar, baz])]
+    fn init(c: init::Context) {
+        let now = rtfm::Instant::now();
+
+        let bar = c.schedule.bar(now + Duration::from_secs(1), 0).unwrap();
+        let baz = c.schedule.baz(now + Duration::from_secs(2)).unwrap();
+
+        // `bar` will never run
+        assert_eq!(bar.cancel(), Ok(0));
+
+        // run `baz` sooner
+        baz.reschedule_at(now + Duration::from_millis(500)).ok();
+    }
+
+    #[task]
+    fn bar(_: bar::Context, x: i32) {
+        println!("bar({})", x);
+    }
+
+    #[task]
+    fn baz(_: baz::Context) {
+        println!("baz");
+
+        std::process::exit(0);
+    }
+};
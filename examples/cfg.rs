@@ -0,0 +1,47 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+#[rtfm::app]
+const APP: () = {
+    struct Resources {
+        // only debug builds keep count
+        #[cfg(debug_assertions)]
+        #[init(0)]
+        count: u32,
+    }
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    #[task(resources = [count], spawn = [log])]
+    fn foo(c: foo::Context) {
+        #[cfg(debug_assertions)]
+        {
+            *c.resources.count += 1;
+
+            c.spawn.log(*c.resources.count).ok();
+        }
+
+        #[cfg(not(debug_assertions))]
+        {
+            let _ = c;
+
+            process::exit(0);
+        }
+    }
+
+    // this task, its message slots and `spawn.log` don't exist in release builds
+    #[cfg(debug_assertions)]
+    #[task]
+    fn log(_: log::Context, count: u32) {
+        println!("foo ran {} time(s)", count);
+
+        process::exit(0);
+    }
+};
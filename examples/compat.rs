@@ -0,0 +1,58 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+// on the target this would be `use stm32f4::stm32f411 as pac;`
+rtfm::compat::device!(pub pac: USART1 = 37, EXTI0 = 6, EXTI1 = 7);
+
+use rtfm::compat as rtic;
+
+#[rtic::app(device = crate::pac, peripherals = true, dispatchers = [EXTI0, EXTI1])]
+mod app {
+    use std::process;
+
+    use crate::{pac::Interrupt, rtic::Mutex as _};
+
+    #[shared]
+    struct Shared {
+        received: u32,
+    }
+
+    #[local]
+    struct Local {}
+
+    #[init]
+    fn init(c: init::Context) -> (Shared, Local, init::Monotonics) {
+        let _device = c.device;
+
+        // a byte arrives
+        rtfm::compat::pend(Interrupt::USART1);
+
+        (Shared { received: 0 }, Local {}, init::Monotonics())
+    }
+
+    #[task(binds = USART1, priority = 2, shared = [received])]
+    fn usart1(mut c: usart1::Context) {
+        let received = c.shared.received.lock(|received| {
+            *received += 1;
+            *received
+        });
+
+        foo::spawn(received).ok();
+    }
+
+    #[task(shared = [received])]
+    fn foo(mut c: foo::Context, n: u32) {
+        println!("foo({})", n);
+
+        if n == 3 {
+            let received = c.shared.received.lock(|received| *received);
+            println!("received {} bytes", received);
+
+            process::exit(0);
+        }
+
+        rtfm::compat::pend(Interrupt::USART1);
+    }
+}
@@ -0,0 +1,32 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+use std::thread;
+
+#[rtfm::app]
+const APP: () = {
+    #[init(schedule = [foo])]
+    fn init(c: init::Context) {
+        c.schedule
+            .foo(rtfm::Instant::now() + Duration::from_millis(100))
+            .ok();
+    }
+
+    // must complete within 1 ms of the instant it was scheduled at
+    #[task(deadline = "1ms")]
+    fn foo(_: foo::Context) {
+        // too much work
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    // called when a task with a `deadline` completes late
+    #[deadline_miss]
+    fn on_miss(task: &'static str, lateness: Duration) {
+        println!("`{}` missed its deadline by {:?}", task, lateness);
+
+        rtfm::shutdown(0);
+    }
+};
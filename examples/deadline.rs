@@ -0,0 +1,33 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app]
+const APP: () = {
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    // the thread that dispatches this task gets a 2 ms every 10 ms `SCHED_DEADLINE` reservation
+    #[task(
+        schedule = [foo],
+        sched_deadline(runtime = "2ms", deadline = "10ms", period = "10ms")
+    )]
+    fn foo(c: foo::Context) {
+        static mut COUNT: u8 = 0;
+
+        print!(".");
+
+        *COUNT += 1;
+        if *COUNT >= 3 {
+            print!("\n");
+            std::process::exit(0);
+        }
+
+        c.schedule.foo(c.scheduled + Duration::from_millis(10)).ok();
+    }
+};
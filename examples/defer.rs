@@ -0,0 +1,37 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+#[rtfm::app(defer = 4)]
+const APP: () = {
+    #[init(spawn = [slow])]
+    fn init(c: init::Context) {
+        c.spawn.slow().ok();
+    }
+
+    #[task(spawn = [fast])]
+    fn slow(c: slow::Context) {
+        let samples = [1, 2, 3];
+
+        // runs at priority 2 right away, preempting this task
+        rtfm::defer(2, move || {
+            println!("deferred: {}", samples.iter().sum::<i32>());
+        })
+        .ok();
+
+        println!("slow");
+
+        c.spawn.fast().ok();
+    }
+
+    // a task at priority 2 gives `defer` a dispatcher to run the closures on
+    #[task(priority = 2)]
+    fn fast(_: fast::Context) {
+        println!("fast");
+
+        process::exit(0);
+    }
+};
@@ -0,0 +1,33 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+// the bodies of the tasks can live in any module, or crate
+mod tasks {
+    use std::process;
+
+    pub fn foo(_: crate::foo::Context<'_>, n: u32) {
+        println!("foo({})", n);
+
+        if n == 2 {
+            process::exit(0);
+        }
+    }
+}
+
+use tasks::foo;
+
+#[rtfm::app]
+const APP: () = {
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo(1).ok();
+        c.spawn.foo(2).ok();
+    }
+
+    extern "Rust" {
+        #[task(capacity = 2)]
+        fn foo(c: foo::Context, n: u32);
+    }
+};
@@ -0,0 +1,30 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::{io, process};
+
+#[rtfm::app(epoll_priority = 10)]
+const APP: () = {
+    /// The standard input
+    static STDIN: i32 = 0;
+
+    #[init]
+    fn init(_: init::Context) {
+        println!("type something and press enter (Ctrl-D to exit)");
+    }
+
+    // runs every time STDIN becomes readable
+    #[task(binds_fd = STDIN, priority = 2)]
+    fn on_stdin(_: on_stdin::Context) {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line).ok();
+
+        if line.is_empty() {
+            process::exit(0);
+        }
+
+        print!("echo: {}", line);
+    }
+};
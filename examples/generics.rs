@@ -0,0 +1,43 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use rtfm::{Exclusive, Mutex};
+
+#[rtfm::app]
+const APP: () = {
+    static mut SHARED: u32 = 0;
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    #[task(priority = 1, resources = [SHARED], spawn = [bar])]
+    fn foo(c: foo::Context) {
+        // below the ceiling: a resource proxy that must be locked
+        advance("foo", c.resources.SHARED);
+
+        c.spawn.bar().ok();
+    }
+
+    #[task(priority = 2, resources = [SHARED])]
+    fn bar(c: bar::Context) {
+        // at the ceiling: a plain mutable reference, no locking needed
+        *c.resources.SHARED += 1;
+
+        // wrap it to pass it to code that's generic over `Mutex`
+        advance("bar", Exclusive(c.resources.SHARED));
+
+        rtfm::shutdown(0);
+    }
+};
+
+fn advance(task: &str, mut shared: impl Mutex<T = u32>) {
+    shared.lock(|shared| {
+        *shared += 1;
+
+        println!("{}: SHARED = {}", task, *shared);
+    });
+}
@@ -0,0 +1,39 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+use std::thread;
+
+#[rtfm::app]
+const APP: () = {
+    #[init]
+    fn init(_: init::Context) {}
+
+    // must beat at least once every 50 ms
+    #[task(period = "10ms", heartbeat = "50ms")]
+    fn foo(c: foo::Context) {
+        c.heartbeat();
+    }
+
+    // starves `foo` on its third release
+    #[task(period = "10ms", priority = 2)]
+    fn bar(_: bar::Context) {
+        static mut COUNT: u8 = 0;
+
+        *COUNT += 1;
+        if *COUNT == 3 {
+            // stuck; `foo` runs on the same thread at a lower priority
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    // called, on the monitor thread, when a task doesn't beat within its interval
+    #[heartbeat_missed]
+    fn on_missed(task: &'static str, silent: Duration) {
+        println!("`{}` has not beaten for {:?}", task, silent);
+
+        rtfm::shutdown(0);
+    }
+};
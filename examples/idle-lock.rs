@@ -0,0 +1,44 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app]
+const APP: () = {
+    static mut COUNTER: u32 = 0;
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    // `idle` runs at priority 0 so it has to `lock` the resources it shares with the tasks
+    #[idle(resources = [COUNTER])]
+    fn idle(mut c: idle::Context) -> ! {
+        let mut last = 0;
+
+        loop {
+            let counter = c.resources.COUNTER.lock(|counter| *counter);
+
+            if counter != last {
+                println!("idle: COUNTER = {}", counter);
+                last = counter;
+
+                if counter == 3 {
+                    rtfm::shutdown(0);
+                }
+            }
+        }
+    }
+
+    #[task(resources = [COUNTER], schedule = [foo])]
+    fn foo(c: foo::Context) {
+        *c.resources.COUNTER += 1;
+
+        c.schedule
+            .foo(c.scheduled + Duration::from_millis(100))
+            .ok();
+    }
+};
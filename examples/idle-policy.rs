@@ -0,0 +1,46 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app(dispatch = "threads")]
+const APP: () = {
+    static mut COUNTER: u32 = 0;
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    // busy-waits under `SCHED_IDLE` so it neither trips the real-time throttling nor starves
+    // the other threads of the CPU; `foo` still runs under `SCHED_FIFO`
+    #[idle(policy = "idle", resources = [COUNTER])]
+    fn idle(mut c: idle::Context) -> ! {
+        let mut last = 0;
+
+        loop {
+            // runs under `SCHED_FIFO`, at the ceiling of `COUNTER`
+            let counter = c.resources.COUNTER.lock(|counter| *counter);
+
+            if counter != last {
+                println!("idle: COUNTER = {}", counter);
+                last = counter;
+
+                if counter == 3 {
+                    rtfm::shutdown(0);
+                }
+            }
+        }
+    }
+
+    #[task(resources = [COUNTER], schedule = [foo])]
+    fn foo(c: foo::Context) {
+        *c.resources.COUNTER += 1;
+
+        c.schedule
+            .foo(c.scheduled + Duration::from_millis(100))
+            .ok();
+    }
+};
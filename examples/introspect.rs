@@ -0,0 +1,51 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app(introspect = true)]
+const APP: () = {
+    #[init(spawn = [foo], schedule = [report])]
+    fn init(c: init::Context) {
+        for i in 0..3 {
+            c.spawn.foo(i).ok();
+        }
+
+        c.spawn_after.report(Duration::from_millis(100)).ok();
+    }
+
+    #[task(capacity = 4)]
+    fn foo(_: foo::Context, x: u32) {
+        println!("foo({})", x);
+    }
+
+    #[task]
+    fn report(_: report::Context) {
+        let introspection = rtfm::introspect();
+
+        for task in introspection.tasks() {
+            println!(
+                "{}: core={} priority={} depth={}/{} high-water={}",
+                task.name(),
+                task.core(),
+                task.priority(),
+                task.depth(),
+                task.capacity(),
+                task.high_water_mark(),
+            );
+        }
+
+        for tq in introspection.timer_queues() {
+            println!(
+                "TQ{}: len={} armed={}",
+                tq.core(),
+                tq.len(),
+                tq.next_deadline().is_some(),
+            );
+        }
+
+        rtfm::shutdown(0);
+    }
+};
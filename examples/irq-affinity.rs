@@ -0,0 +1,40 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::net::UdpSocket;
+
+use rtfm::IrqCpus;
+
+#[rtfm::app(epoll_priority = 10)]
+const APP: () = {
+    static SOCKET: UdpSocket = ();
+
+    #[init]
+    fn init(_: init::Context) -> init::LateResources {
+        // the NIC interrupts wake up `on_packet` on CPU #0, the CPU of the only core ..
+        for irq in rtfm::irqs("eth0") {
+            if let Err(errno) = rtfm::set_irq_affinity(irq, IrqCpus::Only(&[0])) {
+                eprintln!("couldn't move IRQ {} (errno {})", irq, errno);
+            }
+        }
+
+        // .. and the disk keeps away from it
+        for irq in rtfm::irqs("nvme0q1") {
+            rtfm::set_irq_affinity(irq, IrqCpus::Except(&[0])).ok();
+        }
+
+        init::LateResources {
+            SOCKET: UdpSocket::bind("0.0.0.0:7000").expect("couldn't bind the socket"),
+        }
+    }
+
+    #[task(binds_fd = SOCKET, priority = 2, resources = [SOCKET])]
+    fn on_packet(c: on_packet::Context) {
+        let mut buffer = [0; 1500];
+        if let Ok((len, from)) = c.resources.SOCKET.recv_from(&mut buffer) {
+            println!("{} bytes from {}", len, from);
+        }
+    }
+};
@@ -0,0 +1,30 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::{fs::File, io::Write, process};
+
+#[rtfm::app]
+const APP: () = {
+    // late resource: initialized at runtime by `init`
+    static mut LOG: File = ();
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) -> init::LateResources {
+        c.spawn.foo(42).ok();
+
+        init::LateResources {
+            LOG: File::create("late.log").expect("couldn't create late.log"),
+        }
+    }
+
+    #[task(resources = [LOG])]
+    fn foo(c: foo::Context, x: u32) {
+        writeln!(c.resources.LOG, "foo({})", x).ok();
+
+        println!("wrote `foo({})` to late.log", x);
+
+        process::exit(0);
+    }
+};
@@ -0,0 +1,29 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app(latency_probe = "1ms")]
+const APP: () = {
+    #[init(schedule = [report])]
+    fn init(c: init::Context) {
+        c.spawn_after.report(Duration::from_secs(5)).ok();
+    }
+
+    #[task]
+    fn report(_: report::Context) {
+        for histogram in rtfm::latency_probe() {
+            println!(
+                "core #{}: {} samples, p99.9 {:?}",
+                histogram.core(),
+                histogram.count(),
+                histogram.percentile(99.9),
+            );
+        }
+
+        // the full histograms are written to stderr on shutdown
+        rtfm::shutdown(0);
+    }
+};
@@ -0,0 +1,54 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+#[rtfm::app(lock_groups = true, stats = true)]
+const APP: () = {
+    static mut A: u32 = 0;
+    static mut B: u32 = 0;
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    #[task(priority = 1, resources = [A, B], spawn = [bar, baz])]
+    fn foo(mut c: foo::Context) {
+        let mut b = c.resources.B;
+        let spawn = c.spawn;
+
+        // one `rt_sigprocmask` pair raises the priority straight to the ceiling of `B`
+        c.resources.A.lock(|a| {
+            *a += 1;
+
+            spawn.bar().ok();
+
+            b.lock(|b| {
+                *b += 1;
+
+                spawn.baz().ok();
+
+                println!("foo: A = {}, B = {}", a, b);
+            });
+        });
+
+        for resource in rtfm::lock_stats() {
+            println!("{}: locks = {}", resource.name(), resource.count());
+        }
+
+        process::exit(0);
+    }
+
+    #[task(priority = 2, resources = [A])]
+    fn bar(c: bar::Context) {
+        println!("bar: A = {}", c.resources.A);
+    }
+
+    #[task(priority = 3, resources = [B])]
+    fn baz(c: baz::Context) {
+        println!("baz: B = {}", c.resources.B);
+    }
+};
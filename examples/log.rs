@@ -0,0 +1,34 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app(log = "stderr")]
+const APP: () = {
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        writeln!(c.log, "init").ok();
+
+        c.spawn.foo().ok();
+    }
+
+    #[task(schedule = [foo])]
+    fn foo(c: foo::Context) {
+        static mut COUNT: u32 = 0;
+
+        *COUNT += 1;
+
+        // unlike `println!` this doesn't do any syscall; the record is written out by another thread
+        writeln!(c.log, "foo({})", COUNT).ok();
+
+        if *COUNT == 3 {
+            rtfm::shutdown(0);
+        }
+
+        c.schedule
+            .foo(c.scheduled + Duration::from_millis(100))
+            .ok();
+    }
+};
@@ -0,0 +1,61 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+#[rtfm::app(cores = 2)]
+const APP: () = {
+    // each core counts its own packets
+    #[per_core]
+    static mut PACKETS: u64 = 0;
+
+    #[init(core = 0, spawn = [rx0, rx1])]
+    fn init(c: init::Context) {
+        c.spawn.rx0().ok();
+        c.spawn.rx1().ok();
+    }
+
+    #[task(core = 0, resources = [PACKETS], spawn = [rx0, report])]
+    fn rx0(mut c: rx0::Context) {
+        // `report` shares `PACKETS` on this core so this masks it
+        let packets = c.resources.PACKETS.lock(|packets| {
+            *packets += 1;
+            *packets
+        });
+
+        if packets == 10 {
+            c.spawn.report().ok();
+        } else {
+            c.spawn.rx0().ok();
+        }
+    }
+
+    #[task(core = 1, resources = [PACKETS], spawn = [rx1])]
+    fn rx1(mut c: rx1::Context) {
+        // the only task of core #1 that uses `PACKETS`; this `lock` costs nothing
+        c.resources.PACKETS.lock(|packets| *packets += 1);
+
+        c.spawn.rx1().ok();
+    }
+
+    #[task(core = 0, priority = 2, resources = [PACKETS])]
+    fn report(mut c: report::Context) {
+        for core in 0..c.resources.PACKETS.cores() {
+            println!(
+                "core #{}: {:?} packets",
+                core,
+                c.resources.PACKETS.read(core)
+            );
+        }
+
+        let total = c
+            .resources
+            .PACKETS
+            .fold(0, |total, packets| total + packets);
+        println!("total: {} packets", total);
+
+        process::exit(0);
+    }
+};
@@ -0,0 +1,59 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+#[rtfm::app(cores = 2)]
+const APP: () = {
+    // shared by the tasks of both cores
+    #[lock(policy = "pi_futex")]
+    static mut COUNTER: u32 = 0;
+
+    #[init(core = 0, spawn = [foo, baz])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+        c.spawn.baz().ok();
+    }
+
+    #[task(core = 0, priority = 1, resources = [COUNTER], spawn = [bar])]
+    fn foo(mut c: foo::Context) {
+        let spawn = c.spawn;
+
+        // masks `bar`, which runs on this core, and then takes the futex
+        c.resources.COUNTER.lock(|counter| {
+            *counter += 1;
+
+            spawn.bar().ok();
+
+            println!("[0] foo: COUNTER = {}", counter);
+        });
+    }
+
+    #[task(core = 0, priority = 2, resources = [COUNTER])]
+    fn bar(mut c: bar::Context) {
+        let counter = c.resources.COUNTER.lock(|counter| {
+            *counter += 1;
+            *counter
+        });
+
+        println!("[0] bar: COUNTER = {}", counter);
+    }
+
+    #[task(core = 1, resources = [COUNTER], spawn = [baz])]
+    fn baz(mut c: baz::Context) {
+        let counter = c.resources.COUNTER.lock(|counter| {
+            *counter += 1;
+            *counter
+        });
+
+        println!("[1] baz: COUNTER = {}", counter);
+
+        if counter >= 10 {
+            process::exit(0);
+        }
+
+        c.spawn.baz().ok();
+    }
+};
@@ -0,0 +1,47 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+// core #1 runs in a process of its own
+#[rtfm::app(cores = 2, processes = true)]
+const APP: () = {
+    // lives in memory that both processes map
+    #[lock(policy = "pi_futex")]
+    static mut COUNTER: u32 = 0;
+
+    #[init(core = 0, spawn = [ping])]
+    fn init(c: init::Context) {
+        c.spawn.ping().ok();
+    }
+
+    #[task(core = 1, resources = [COUNTER], spawn = [pong])]
+    fn ping(mut c: ping::Context) {
+        let counter = c.resources.COUNTER.lock(|counter| {
+            *counter += 1;
+            *counter
+        });
+
+        // the message travels through shared memory to the process of core #0
+        c.spawn.pong(counter).ok();
+    }
+
+    #[task(core = 0, resources = [COUNTER], spawn = [ping])]
+    fn pong(c: pong::Context, counter: u32) {
+        println!(
+            "[{}] pong({}), owner deaths: {}",
+            process::id(),
+            counter,
+            c.resources.COUNTER.owner_deaths(),
+        );
+
+        if counter >= 10 {
+            // the process of core #1 dies with this one
+            process::exit(0);
+        }
+
+        c.spawn.ping().ok();
+    }
+};
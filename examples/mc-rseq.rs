@@ -0,0 +1,81 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+use rtfm::{
+    pool::{Box, Singleton as _},
+    PerCpuCounter,
+};
+
+/// A received packet
+pub struct Packet {
+    pub len: usize,
+    pub data: [u8; 1500],
+}
+
+// every CPU keeps up to 2 free packets for itself
+rtfm::pool!(PACKET: Packet, cache = 2);
+
+// counted on both cores without atomic instructions
+static BYTES: PerCpuCounter = PerCpuCounter::new();
+
+#[rtfm::app(cores = 2)]
+const APP: () = {
+    #[init(core = 0, spawn = [rx0, rx1])]
+    fn init(c: init::Context) {
+        // 4 packets in flight plus 2 cached per CPU on a machine of up to 4 CPUs
+        static mut MEMORY: [u8; 12 * 1520 + 8] = [0; 12 * 1520 + 8];
+
+        println!("{} packets", PACKET::grow(MEMORY));
+
+        c.spawn.rx0().ok();
+        c.spawn.rx1().ok();
+    }
+
+    #[task(core = 0, spawn = [rx0, process0])]
+    fn rx0(c: rx0::Context) {
+        if let Ok(packet) = Box::<PACKET>::new(Packet {
+            len: 64,
+            data: [0; 1500],
+        }) {
+            c.spawn.process0(packet).ok();
+        }
+    }
+
+    #[task(core = 0, capacity = 2, spawn = [rx0])]
+    fn process0(c: process0::Context, packet: Box<PACKET>) {
+        BYTES.add(packet.len as u64);
+
+        // dropping `packet` caches it on this CPU for the next `rx0`
+        drop(packet);
+
+        if BYTES.get() >= 64 * 1000 {
+            println!("received {} bytes", BYTES.get());
+            process::exit(0);
+        }
+
+        c.spawn.rx0().ok();
+    }
+
+    #[task(core = 1, spawn = [process1])]
+    fn rx1(c: rx1::Context) {
+        if let Ok(packet) = Box::<PACKET>::new(Packet {
+            len: 64,
+            data: [1; 1500],
+        }) {
+            c.spawn.process1(packet).ok();
+        }
+    }
+
+    #[task(core = 1, capacity = 2, spawn = [rx1])]
+    fn process1(c: process1::Context, packet: Box<PACKET>) {
+        BYTES.add(packet.len as u64);
+
+        drop(packet);
+
+        c.spawn.rx1().ok();
+    }
+};
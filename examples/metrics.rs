@@ -0,0 +1,27 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+// try `curl http://127.0.0.1:9100/metrics`
+#[rtfm::app(metrics = "127.0.0.1:9100", stats = true)]
+const APP: () = {
+    #[init(spawn = [tick])]
+    fn init(c: init::Context) {
+        c.spawn.tick().ok();
+    }
+
+    #[task(schedule = [tick], deadline = "1ms")]
+    fn tick(c: tick::Context) {
+        c.schedule
+            .tick(c.scheduled + Duration::from_millis(100))
+            .ok();
+    }
+
+    #[deadline_miss]
+    fn on_miss(task: &'static str, lateness: Duration) {
+        eprintln!("{} missed its deadline by {:?}", task, lateness);
+    }
+};
@@ -0,0 +1,38 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+#[rtfm::app(modes = [startup, normal, degraded])]
+const APP: () = {
+    #[init]
+    fn init(_: init::Context) {}
+
+    // only runs while the application starts up
+    #[task(period = "250ms", modes = [startup])]
+    fn warm_up(_: warm_up::Context) {
+        println!("warm_up");
+    }
+
+    // every 250 ms in `normal`, every second in `degraded`
+    #[task(period = "250ms", modes = [normal, degraded = "1s"])]
+    fn control(_: control::Context) {
+        println!("control");
+    }
+
+    #[task(priority = 2, period = "1s", mode_switch = true)]
+    fn supervisor(c: supervisor::Context) {
+        static mut SECONDS: u32 = 0;
+
+        *SECONDS += 1;
+        let next = match *SECONDS {
+            2 => Mode::normal,
+            4 => Mode::degraded,
+            6 => return rtfm::shutdown(0),
+            _ => return,
+        };
+
+        let previous = c.mode_switch.switch(next);
+        println!("{:?} -> {:?}", previous, Mode::current());
+    }
+};
@@ -0,0 +1,29 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+use std::thread;
+
+#[rtfm::app]
+const APP: () = {
+    #[init]
+    fn init(_: init::Context) {}
+
+    #[task(period = "10ms")]
+    fn foo(c: foo::Context) {
+        static mut COUNT: u8 = 0;
+
+        // a control loop would scale its time step by `1 + c.overruns`
+        println!("foo: {} period(s) missed", c.overruns);
+
+        *COUNT += 1;
+        if *COUNT == 2 {
+            // takes longer than 3 periods
+            thread::sleep(Duration::from_millis(35));
+        } else if *COUNT >= 4 {
+            std::process::exit(0);
+        }
+    }
+};
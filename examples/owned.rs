@@ -0,0 +1,43 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+pub struct Reminder(&'static str);
+
+impl Drop for Reminder {
+    fn drop(&mut self) {
+        println!("dropping the reminder {:?}", self.0);
+    }
+}
+
+#[rtfm::app]
+const APP: () = {
+    #[init(spawn = [echo], schedule = [remind])]
+    fn init(c: init::Context) {
+        c.spawn.echo(b"hello".to_vec()).ok();
+
+        // never runs: the payload is dropped when the application shuts down
+        c.schedule
+            .remind(
+                rtfm::Instant::now() + Duration::from_secs(60),
+                Reminder("water the plants"),
+            )
+            .ok();
+    }
+
+    // the `Vec` is moved into the message slot and out of it, never copied
+    #[task]
+    fn echo(_: echo::Context, bytes: Vec<u8>) {
+        println!("echo({:?})", String::from_utf8_lossy(&bytes));
+
+        rtfm::shutdown(0);
+    }
+
+    #[task]
+    fn remind(_: remind::Context, reminder: Reminder) {
+        println!("{}", reminder.0);
+    }
+};
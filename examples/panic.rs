@@ -0,0 +1,32 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+#[rtfm::app]
+const APP: () = {
+    #[init(spawn = [foo, bar])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+        c.spawn.bar().ok();
+    }
+
+    #[task]
+    fn foo(_: foo::Context) {
+        panic!("foo");
+    }
+
+    #[task]
+    fn bar(_: bar::Context) {
+        // the application survives the panic of `foo`
+        println!("bar");
+
+        rtfm::shutdown(0);
+    }
+
+    // called, with all the tasks of the core blocked, when a task panics
+    #[panic_task]
+    fn on_panic(task: &'static str) {
+        println!("task `{}` panicked", task);
+    }
+};
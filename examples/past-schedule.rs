@@ -0,0 +1,37 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+use rtfm::{Instant, SpawnError};
+
+#[rtfm::app(past_schedule = "reject")]
+const APP: () = {
+    #[init(schedule = [late, clamped])]
+    fn init(c: init::Context) {
+        let past = Instant::now() - Duration::from_millis(10);
+
+        match c.schedule.late(past, 0) {
+            Err(SpawnError::InstantPassed(x)) => println!("rejected late({})", x),
+            _ => unreachable!(),
+        }
+
+        // this task moves the instant to "now" instead
+        c.schedule.clamped(past, 1).ok();
+    }
+
+    #[task]
+    fn late(_: late::Context, x: u32) {
+        println!("late({})", x);
+    }
+
+    #[task(past_schedule = "clamp")]
+    fn clamped(c: clamped::Context, x: u32) {
+        // `scheduled` is the clamped instant, not the one passed to `schedule`
+        println!("clamped({}) {:?} late", x, Instant::now() - c.scheduled);
+
+        std::process::exit(0);
+    }
+};
@@ -0,0 +1,49 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app(stats = true, perf_counters = true)]
+const APP: () = {
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    #[task(schedule = [foo], spawn = [report])]
+    fn foo(c: foo::Context) {
+        static mut COUNT: u32 = 0;
+        static mut BUFFER: [u8; 1 << 20] = [0; 1 << 20];
+
+        // touch a buffer larger than most L2 caches
+        for i in (0..BUFFER.len()).step_by(64) {
+            BUFFER[i] = BUFFER[i].wrapping_add(1);
+        }
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+
+        *COUNT += 1;
+        if *COUNT == 10 {
+            c.spawn.report().ok();
+        } else {
+            c.schedule.foo(c.scheduled + Duration::from_millis(10)).ok();
+        }
+    }
+
+    #[task]
+    fn report(_: report::Context) {
+        for task in rtfm::stats() {
+            println!(
+                "{}: count={} cycles={:?} cache_misses={:?} context_switches={:?}",
+                task.name(),
+                task.count(),
+                task.cycles(),
+                task.cache_misses(),
+                task.context_switches(),
+            );
+        }
+
+        rtfm::shutdown(0);
+    }
+};
@@ -0,0 +1,24 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+#[rtfm::app]
+const APP: () = {
+    #[init]
+    fn init(_: init::Context) {}
+
+    // released by the runtime every second; no `spawn` or `schedule` needed
+    #[task(period = "1s")]
+    fn foo(_: foo::Context) {
+        static mut COUNT: u8 = 0;
+
+        print!(".");
+
+        *COUNT += 1;
+        if *COUNT >= 3 {
+            print!("\n");
+            std::process::exit(0);
+        }
+    }
+};
@@ -0,0 +1,55 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+use rtfm::pool::{Box, Singleton as _};
+
+/// A frame of audio samples
+pub struct Frame {
+    pub seq: u32,
+    pub samples: [i16; 2048],
+}
+
+rtfm::pool!(FRAME: Frame);
+
+#[rtfm::app]
+const APP: () = {
+    #[init(spawn = [capture])]
+    fn init(c: init::Context) {
+        // room for 4 frames; a block is a frame plus a pointer, and the start may need aligning
+        static mut MEMORY: [u8; 4 * 4112 + 8] = [0; 4 * 4112 + 8];
+
+        println!("{} frames", FRAME::grow(MEMORY));
+
+        c.spawn.capture().ok();
+    }
+
+    #[task(spawn = [analyze])]
+    fn capture(c: capture::Context) {
+        for seq in 0..3 {
+            let frame = Box::<FRAME>::new(Frame {
+                seq,
+                samples: [seq as i16; 2048],
+            })
+            .unwrap_or_else(|_| panic!("out of frames"));
+
+            // only the pointer to the frame is copied
+            c.spawn.analyze(frame).ok();
+        }
+    }
+
+    #[task(capacity = 4, priority = 2)]
+    fn analyze(_: analyze::Context, frame: Box<FRAME>) {
+        let sum = frame.samples.iter().map(|&s| i32::from(s)).sum::<i32>();
+
+        println!("frame {}: {}", frame.seq, sum);
+
+        // dropping `frame` returns it to the pool
+        if frame.seq == 2 {
+            process::exit(0);
+        }
+    }
+};
@@ -0,0 +1,61 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::{
+    env,
+    fs::File,
+    io::Read,
+    os::unix::io::{FromRawFd, IntoRawFd},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+// the device that `setup` opened; `init` takes it over
+static DEVICE_FD: AtomicI32 = AtomicI32::new(-1);
+
+#[rtfm::app]
+const APP: () = {
+    static mut DEVICE: File = ();
+
+    // runs before the runtime changes the scheduling policy of the process
+    #[pre_init]
+    fn setup() -> rtfm::RuntimeConfig {
+        // e.g. `pre-init 10` keeps the CPUs out of the idle states slower than 10 us
+        let latency = env::args()
+            .nth(1)
+            .map(|arg| arg.parse().expect("not a latency"));
+
+        // only root can read it
+        let device = File::open("/dev/cpu_dma_latency").expect("couldn't open the device");
+        DEVICE_FD.store(device.into_raw_fd(), Ordering::Relaxed);
+
+        // the tasks run as `nobody`; `RTFM_CORES` and friends still apply
+        rtfm::RuntimeConfig {
+            cpu_dma_latency: latency,
+            ..Default::default()
+        }
+        .from_env()
+        .drop_privileges(65534, 65534)
+    }
+
+    #[init(spawn = [read])]
+    fn init(c: init::Context) -> init::LateResources {
+        c.spawn.read().ok();
+
+        init::LateResources {
+            // NOTE(unsafe) `setup` gave up the file descriptor
+            DEVICE: unsafe { File::from_raw_fd(DEVICE_FD.load(Ordering::Relaxed)) },
+        }
+    }
+
+    #[task(resources = [DEVICE])]
+    fn read(c: read::Context) {
+        let mut latency = [0; 4];
+        c.resources.DEVICE.read_exact(&mut latency).ok();
+
+        println!("current latency target: {} us", i32::from_ne_bytes(latency));
+
+        rtfm::shutdown(0);
+    }
+};
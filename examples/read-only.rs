@@ -0,0 +1,40 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+pub struct Config {
+    pub gain: u32,
+}
+
+#[rtfm::app]
+const APP: () = {
+    // read-only resources are shared as `&T`; no task ever needs to lock them
+    static TABLE: [u32; 4] = [1, 2, 4, 8];
+
+    // late read-only resource: initialized at runtime by `init`, read-only afterwards
+    static CONFIG: Config = ();
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) -> init::LateResources {
+        c.spawn.foo(0).ok();
+
+        init::LateResources {
+            CONFIG: Config { gain: 3 },
+        }
+    }
+
+    #[task(priority = 1, resources = [TABLE, CONFIG], spawn = [bar])]
+    fn foo(c: foo::Context, i: usize) {
+        println!("foo: {}", c.resources.TABLE[i] * c.resources.CONFIG.gain);
+
+        c.spawn.bar(i + 1).ok();
+    }
+
+    #[task(priority = 2, resources = [TABLE, CONFIG])]
+    fn bar(c: bar::Context, i: usize) {
+        println!("bar: {}", c.resources.TABLE[i] * c.resources.CONFIG.gain);
+
+        rtfm::shutdown(0);
+    }
+};
@@ -0,0 +1,33 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+// foo: R = 2ms (wcet) + 1ms (one release of `bar`) = 3ms <= 10ms
+// bar: R = 1ms (wcet) + 2ms (`foo` holds `SAMPLES`) = 3ms <= 5ms
+#[rtfm::app]
+const APP: () = {
+    static mut SAMPLES: u32 = 0;
+
+    #[init]
+    fn init(_: init::Context) {}
+
+    #[task(period = "10ms", wcet = "2ms", resources = [SAMPLES], spawn = [bar])]
+    fn foo(mut c: foo::Context) {
+        let samples = c.resources.SAMPLES.lock(|samples| *samples);
+        println!("foo: {} samples", samples);
+
+        if samples >= 3 {
+            process::exit(0);
+        }
+
+        c.spawn.bar().ok();
+    }
+
+    #[task(priority = 2, interarrival = "5ms", wcet = "1ms", resources = [SAMPLES])]
+    fn bar(c: bar::Context) {
+        *c.resources.SAMPLES += 1;
+    }
+};
@@ -0,0 +1,33 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+// the level threads wait for their signals with `rt_sigtimedwait` and the periodic task reads the
+// overruns of its timer with `timer_getoverrun`; both are allowed by the filter
+#[rtfm::app(dispatch = "threads", seccomp = true)]
+const APP: () = {
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    #[task(priority = 1)]
+    fn foo(_: foo::Context) {
+        println!("foo");
+    }
+
+    #[task(priority = 2, period = "100ms")]
+    fn bar(_: bar::Context) {
+        static mut COUNT: u8 = 0;
+
+        println!("bar");
+
+        *COUNT += 1;
+        if *COUNT >= 3 {
+            process::exit(0);
+        }
+    }
+};
@@ -0,0 +1,38 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+use std::{fs::File, io::Write};
+
+#[rtfm::app(seccomp = true)]
+const APP: () = {
+    // NOTE files must be opened before the filter is installed
+    static mut LOG: File = ();
+
+    #[init(spawn = [tick])]
+    fn init(c: init::Context) -> init::LateResources {
+        c.spawn.tick(0).ok();
+
+        init::LateResources {
+            LOG: File::create("seccomp.log").expect("couldn't create seccomp.log"),
+        }
+    }
+
+    #[task(schedule = [tick], resources = [LOG])]
+    fn tick(c: tick::Context, n: u32) {
+        // `write` is allowed
+        writeln!(c.resources.LOG, "tick {}", n).ok();
+        println!("tick {}", n);
+
+        if n < 9 {
+            c.schedule
+                .tick(c.scheduled + Duration::from_millis(100), n + 1)
+                .ok();
+        } else {
+            // opening a file now would kill the process
+            rtfm::shutdown(0);
+        }
+    }
+};
@@ -0,0 +1,66 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+use rtfm::Mutex as _;
+
+#[rtfm::app]
+const APP: () = {
+    #[shared]
+    struct Shared {
+        count: u32,
+        total: u64,
+    }
+
+    #[local]
+    struct Local {
+        step: u32,
+    }
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) -> (Shared, Local, init::Monotonics) {
+        c.spawn.foo().ok();
+
+        (
+            Shared { count: 0, total: 0 },
+            Local { step: 2 },
+            init::Monotonics(),
+        )
+    }
+
+    #[task(shared = [count, total], local = [step, runs: u32 = 0], spawn = [bar])]
+    fn foo(mut c: foo::Context) {
+        *c.local.runs += 1;
+
+        let step = *c.local.step;
+        c.shared.count.lock(|count| *count += step);
+
+        // one critical section for both resources
+        let runs = *c.local.runs;
+        c.shared.lock(|shared| {
+            *shared.total += u64::from(*shared.count);
+
+            println!(
+                "foo #{}: count = {}, total = {}",
+                runs, shared.count, shared.total
+            );
+        });
+
+        c.spawn.bar().ok();
+    }
+
+    #[task(priority = 2, shared = [count], spawn = [foo])]
+    fn bar(mut c: bar::Context) {
+        let count = c.shared.count.lock(|count| *count);
+        println!("bar: count = {}", count);
+
+        if count >= 6 {
+            process::exit(0);
+        }
+
+        c.spawn.foo().ok();
+    }
+};
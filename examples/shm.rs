@@ -0,0 +1,43 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+use rtfm::shm::Producer;
+
+/// A message from the (non real-time) frontend
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Command {
+    pub setpoint: i32,
+}
+
+#[rtfm::app]
+const APP: () = {
+    #[init(spawn = [frontend])]
+    fn init(c: init::Context) {
+        c.spawn.frontend().ok();
+    }
+
+    // NOTE this would normally run in another process
+    #[task]
+    fn frontend(_: frontend::Context) {
+        let mut producer = Producer::<Command>::open("/rtfm-shm-example").unwrap();
+
+        for setpoint in 1..=3 {
+            producer.push(Command { setpoint }).ok();
+        }
+    }
+
+    // runs once per message that the frontend pushes
+    #[task(shm = "/rtfm-shm-example", capacity = 4, priority = 2)]
+    fn command(_: command::Context, command: Command) {
+        println!("setpoint = {}", command.setpoint);
+
+        if command.setpoint == 3 {
+            process::exit(0);
+        }
+    }
+};
@@ -0,0 +1,54 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+pub struct Counter(u32);
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        println!("dropping the counter at {}", self.0);
+    }
+}
+
+#[rtfm::app]
+const APP: () = {
+    static mut COUNTER: Counter = Counter(0);
+
+    // late resources are dropped too
+    static mut LATE: Counter = ();
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) -> init::LateResources {
+        c.spawn.foo().ok();
+
+        init::LateResources { LATE: Counter(42) }
+    }
+
+    // runs last, after all the resources have been dropped
+    #[deinit]
+    fn teardown() {
+        println!("teardown");
+    }
+
+    #[task(resources = [COUNTER], schedule = [foo], spawn = [bar])]
+    fn foo(c: foo::Context) {
+        c.resources.COUNTER.0 += 1;
+
+        if c.resources.COUNTER.0 == 3 {
+            c.spawn.bar().ok();
+
+            // `bar` still runs but the `schedule` call below fails
+            rtfm::shutdown(0);
+        }
+
+        c.schedule.foo(c.scheduled + Duration::from_secs(1)).ok();
+    }
+
+    #[task]
+    fn bar(_: bar::Context) {
+        println!("bar");
+    }
+};
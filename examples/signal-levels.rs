@@ -0,0 +1,36 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+// 16 priorities, as on a Cortex-M NVIC, on 4 signals: `low` (priority 2) and `high` (priority 4)
+// share level 1 so they don't preempt each other, but `high` runs first when both are pending.
+// Prints "top", "high" and then "low"
+#[rtfm::app(signal_levels = 4)]
+const APP: () = {
+    #[init(spawn = [low, high, top])]
+    fn init(c: init::Context) {
+        c.spawn.low().ok();
+        c.spawn.high().ok();
+        c.spawn.top().ok();
+    }
+
+    #[task(priority = 2)]
+    fn low(_: low::Context) {
+        println!("low");
+
+        process::exit(0);
+    }
+
+    #[task(priority = 4)]
+    fn high(_: high::Context) {
+        println!("high");
+    }
+
+    #[task(priority = 16)]
+    fn top(_: top::Context) {
+        println!("top");
+    }
+};
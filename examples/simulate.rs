@@ -0,0 +1,58 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![cfg_attr(not(test), no_main)]
+
+use core::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
+#[rtfm::app(simulate = true)]
+const APP: () = {
+    #[init(schedule = [tick])]
+    fn init(c: init::Context) {
+        c.spawn_after.tick(Duration::from_millis(10)).ok();
+    }
+
+    #[task(schedule = [tick])]
+    fn tick(c: tick::Context) {
+        let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+        println!("tick {}", ticks);
+
+        if ticks == 3 {
+            rtfm::shutdown(0);
+        } else {
+            c.schedule
+                .tick(c.scheduled + Duration::from_millis(10))
+                .ok();
+        }
+    }
+};
+
+#[cfg(test)]
+mod tests {
+    use core::{sync::atomic::Ordering, time::Duration};
+
+    use super::TICKS;
+
+    #[test]
+    fn ticks_every_10ms() {
+        rtfm::simulate(|sim| {
+            let start = sim.now();
+
+            sim.advance(Duration::from_millis(9));
+            assert_eq!(TICKS.load(Ordering::Relaxed), 0);
+
+            sim.advance(Duration::from_millis(1));
+            assert_eq!(TICKS.load(Ordering::Relaxed), 1);
+            assert!(sim.next_expiration() == Some(start + Duration::from_millis(20)));
+
+            sim.advance(Duration::from_millis(25));
+            assert_eq!(TICKS.load(Ordering::Relaxed), 3);
+            assert_eq!(sim.shutdown_code(), Some(0));
+        });
+    }
+}
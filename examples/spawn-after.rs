@@ -0,0 +1,28 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app]
+const APP: () = {
+    #[init(schedule = [foo])]
+    fn init(c: init::Context) {
+        c.spawn_after.foo(Duration::from_millis(100), 0).ok();
+    }
+
+    #[task(schedule = [foo])]
+    fn foo(c: foo::Context, count: u8) {
+        println!("foo({})", count);
+
+        if count >= 2 {
+            std::process::exit(0);
+        }
+
+        // relative to "now" rather than to `c.scheduled`
+        c.spawn_after
+            .foo(Duration::from_millis(100), count + 1)
+            .ok();
+    }
+};
@@ -0,0 +1,31 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::{process, thread, time::Duration};
+
+#[rtfm::app]
+const APP: () = {
+    #[init]
+    fn init(_: init::Context) {
+        let spawner = foo::spawner();
+
+        // a thread that the runtime doesn't manage, e.g. a tokio worker
+        thread::spawn(move || {
+            for i in 0..3 {
+                spawner.spawn((i, i * i)).ok();
+                thread::sleep(Duration::from_millis(100));
+            }
+        });
+    }
+
+    #[task(spawner = true, capacity = 2)]
+    fn foo(_: foo::Context, x: i32, y: i32) {
+        println!("foo({}, {})", x, y);
+
+        if x == 2 {
+            process::exit(0);
+        }
+    }
+};
@@ -0,0 +1,38 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+#[rtfm::app(stack_size = "1MiB")]
+const APP: () = {
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    #[task(spawn = [report])]
+    fn foo(c: foo::Context) {
+        // use some stack
+        let mut buffer = [0u8; 64 * 1024];
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+
+        c.spawn.report().ok();
+    }
+
+    #[task]
+    fn report(_: report::Context) {
+        for stack in rtfm::stack_usage() {
+            println!(
+                "{}: {} of {} bytes",
+                stack.name(),
+                stack.peak(),
+                stack.size()
+            );
+        }
+
+        rtfm::shutdown(0);
+    }
+};
@@ -0,0 +1,60 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+#[rtfm::app(stats = true)]
+const APP: () = {
+    static mut SUM: u64 = 0;
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        c.spawn.foo().ok();
+    }
+
+    #[task(resources = [SUM], schedule = [foo], spawn = [report])]
+    fn foo(mut c: foo::Context) {
+        static mut COUNT: u32 = 0;
+
+        // some busy work
+        let mut x = 0u64;
+        for i in 0..100_000 {
+            x = x.wrapping_mul(31).wrapping_add(i);
+        }
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+
+        // `report` can preempt `foo` so this raises the priority
+        c.resources.SUM.lock(|sum| *sum = sum.wrapping_add(x));
+
+        *COUNT += 1;
+        if *COUNT == 10 {
+            c.spawn.report().ok();
+        } else {
+            c.schedule.foo(c.scheduled + Duration::from_millis(10)).ok();
+        }
+    }
+
+    #[task(priority = 2, resources = [SUM])]
+    fn report(c: report::Context) {
+        println!("SUM={}", c.resources.SUM);
+
+        for task in rtfm::stats() {
+            println!(
+                "{}: count={} min={:?} mean={:?} max={:?}",
+                task.name(),
+                task.count(),
+                task.min(),
+                task.mean(),
+                task.max(),
+            );
+        }
+
+        for resource in rtfm::lock_stats() {
+            println!("{}: locks={}", resource.name(), resource.count());
+        }
+
+        rtfm::shutdown(0);
+    }
+};
@@ -0,0 +1,22 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+
+// NOTE no `#![no_main]`: `std` starts first and then calls the `main` that `#[app]` generates
+#[rtfm::app(std_main = true)]
+const APP: () = {
+    #[init(spawn = [hello])]
+    fn init(c: init::Context) {
+        // `std` is fully initialized at this point
+        println!("{:?}", std::env::current_dir());
+
+        c.spawn.hello().ok();
+    }
+
+    #[task]
+    fn hello(_: hello::Context) {
+        println!("hello");
+
+        rtfm::shutdown(0);
+    }
+};
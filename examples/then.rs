@@ -0,0 +1,41 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+#[rtfm::app]
+const APP: () = {
+    #[init(spawn = [sample])]
+    fn init(c: init::Context) {
+        c.spawn.sample(1).ok();
+    }
+
+    // the returned reading is spawned as the message of `filter`
+    #[task(then = filter)]
+    fn sample(_: sample::Context, n: u32) -> (u32, i32) {
+        println!("sample({})", n);
+
+        (n, n as i32 * 10)
+    }
+
+    #[task(then = report)]
+    fn filter(_: filter::Context, n: u32, reading: i32) -> u32 {
+        println!("filter({}, {})", n, reading);
+
+        n
+    }
+
+    // `then` can loop back to the start of the pipeline
+    #[task(spawn = [sample])]
+    fn report(c: report::Context, n: u32) {
+        println!("report({})", n);
+
+        if n == 3 {
+            process::exit(0);
+        }
+
+        c.spawn.sample(n + 1).ok();
+    }
+};
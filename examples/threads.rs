@@ -0,0 +1,53 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use std::process;
+
+// each priority level runs on a `SCHED_FIFO` thread of its own
+#[rtfm::app(dispatch = "threads")]
+const APP: () = {
+    static mut SHARED: u128 = 0;
+
+    #[init(spawn = [foo])]
+    fn init(c: init::Context) {
+        println!("A(tid={})", gettid());
+
+        c.spawn.foo().ok();
+    }
+
+    #[idle]
+    fn idle(_: idle::Context) -> ! {
+        // `idle` runs at the lowest priority so `foo` and `bar` have completed by now
+        process::exit(0);
+    }
+
+    #[task(priority = 1, resources = [SHARED], spawn = [bar])]
+    fn foo(mut c: foo::Context) {
+        println!("B(tid={})", gettid());
+
+        let spawn = c.spawn;
+        c.resources.SHARED.lock(|shared| {
+            *shared += 1;
+
+            // `bar` can't preempt this critical section
+            spawn.bar().ok();
+
+            println!("C(SHARED={})", *shared as u64);
+        });
+
+        println!("E");
+    }
+
+    #[task(priority = 2, resources = [SHARED])]
+    fn bar(c: bar::Context) {
+        *c.resources.SHARED += 1;
+
+        println!("D(tid={}, SHARED={})", gettid(), *c.resources.SHARED as u64);
+    }
+};
+
+fn gettid() -> i32 {
+    nc::gettid()
+}
@@ -0,0 +1,45 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+
+use rtfm::CpuThrottling;
+
+#[rtfm::app(throttling_poll = "500ms")]
+const APP: () = {
+    // sample at half the rate while the CPUs are throttled
+    static mut DEGRADED: bool = false;
+
+    #[init(spawn = [sample])]
+    fn init(c: init::Context) {
+        c.spawn.sample(0).ok();
+    }
+
+    #[task(priority = 2, schedule = [sample], resources = [DEGRADED])]
+    fn sample(c: sample::Context, n: u32) {
+        let period = if *c.resources.DEGRADED { 20 } else { 10 };
+
+        if n < 999 {
+            c.schedule
+                .sample(c.scheduled + Duration::from_millis(period), n + 1)
+                .ok();
+        } else {
+            rtfm::shutdown(0);
+        }
+    }
+
+    // spawned by the runtime when the CPUs start or stop being throttled
+    #[task(throttling = true, resources = [DEGRADED])]
+    fn throttled(mut c: throttled::Context, state: CpuThrottling) {
+        c.resources
+            .DEGRADED
+            .lock(|degraded| *degraded = state.throttled);
+
+        println!(
+            "throttled: {}; {:?} m°C; {:?} of {:?} kHz",
+            state.throttled, state.temperature, state.max_freq, state.start_freq
+        );
+    }
+};
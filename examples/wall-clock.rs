@@ -0,0 +1,40 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[rtfm::app(wall_clock = true)]
+const APP: () = {
+    #[init(schedule = [foo])]
+    fn init(c: init::Context) {
+        c.schedule_wall.foo(next_second(), 0).ok();
+    }
+
+    // runs at the start of each wall-clock second
+    #[task(schedule = [foo])]
+    fn foo(c: foo::Context, count: u8) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        println!(
+            "foo({}) at {}.{:03}",
+            count,
+            now.as_secs(),
+            now.subsec_millis()
+        );
+
+        if count >= 2 {
+            std::process::exit(0);
+        }
+
+        c.schedule_wall.foo(next_second(), count + 1).ok();
+    }
+};
+
+// start of the next wall-clock second
+fn next_second() -> SystemTime {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+    UNIX_EPOCH + Duration::from_secs(now.as_secs() + 1)
+}
@@ -0,0 +1,35 @@
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+#![no_main]
+
+use core::time::Duration;
+use std::thread;
+
+// `foo` must feed the watchdog at least once every 100 ms
+#[rtfm::app(watchdog = "100ms")]
+const APP: () = {
+    #[init]
+    fn init(_: init::Context) {}
+
+    #[task(period = "20ms")]
+    fn foo(_: foo::Context) {
+        static mut COUNT: u8 = 0;
+
+        *COUNT += 1;
+        if *COUNT <= 10 {
+            rtfm::feed_watchdog();
+        } else {
+            // stuck
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    // called, on the watchdog thread, when the tasks stop feeding the watchdog
+    #[watchdog_starved]
+    fn on_starved(starved: Duration) {
+        println!("the watchdog has not been fed for {:?}", starved);
+
+        rtfm::shutdown(1);
+    }
+};
@@ -1,18 +1,45 @@
-use core::ops::{self, Range};
+use core::{
+    cmp,
+    ops::{self, Range},
+};
 use std::collections::{BTreeMap, BTreeSet};
 
 use rtfm_syntax::{
-    analyze::{self, Priority},
+    analyze::{self, Ownership, Priority},
     ast::App,
-    Core, P,
+    Context, Core, P,
 };
+use syn::Ident;
+
+use crate::parse::{Extensions, TaskArgs, TaskClass};
 
 /// Signal number
 pub type Signal = u8;
 
 pub struct Analysis {
     parent: P<analyze::Analysis>,
+    pub extensions: Extensions,
+    /// Tasks bound to a file descriptor and their epoll IDs
+    pub fd_tasks: BTreeMap<Ident, u8>,
+    /// Periodic tasks and their timer IDs
+    pub periodic_tasks: BTreeMap<Ident, u8>,
+    /// Tasks that other processes can spawn and their external IDs
+    pub external_tasks: BTreeMap<Ident, u8>,
+    /// Tasks fed by a shared memory channel and their channel IDs
+    pub shm_tasks: BTreeMap<Ident, u8>,
+    /// Tasks that have a `Spawner`, including the FFI and throttling tasks, and their spawner IDs
+    pub spawner_tasks: BTreeMap<Ident, u8>,
+    /// The signals that broadcasts send; the position of each is its broadcast ID
+    pub broadcasts: Vec<Broadcast>,
+    pub reservations: BTreeMap<Core, Reservation>,
     pub signals: BTreeMap<Core, Signals>,
+    /// Stack size of the thread of each core, in bytes
+    pub stack_sizes: BTreeMap<Core, usize>,
+    /// Timing parameters of the tasks that declare a `wcet`
+    pub timings: BTreeMap<Ident, Timing>,
+    /// Background tasks and, for each core that spawns them, the ceiling of their message queue;
+    /// `None` if only `init` spawns them from that core
+    pub background: BTreeMap<Ident, BTreeMap<Core, Option<Priority>>>,
 }
 
 impl ops::Deref for Analysis {
@@ -26,6 +53,8 @@ impl ops::Deref for Analysis {
 pub struct Signals {
     pub map: BTreeMap<Priority, Signal>,
     pub start: Signal,
+    /// Signal of the shutdown handler; it comes right after `range()`
+    pub shutdown: Signal,
 }
 
 impl Signals {
@@ -36,36 +65,463 @@ impl Signals {
     }
 }
 
-// Assign a RT signal handler to each priority level
-pub fn app(parent: P<analyze::Analysis>, app: &App) -> P<Analysis> {
-    let mut rt = 0;
+/// The tasks of a broadcast group that a single signal notifies: those that run on `receiver` at
+/// `priority`, when the message comes from core `sender`
+pub struct Broadcast {
+    pub group: Ident,
+    pub sender: Core,
+    pub receiver: Core,
+    pub priority: Priority,
+    /// The signal carries the message slot of the first task; the slots of the others are in a
+    /// table indexed by that slot
+    pub tasks: Vec<Ident>,
+}
 
-    let mut signals = BTreeMap::new();
-    for core in 0..app.args.cores {
-        let priorities = app
-            .software_tasks
-            .values()
-            .filter_map(|task| {
-                if task.args.core == core {
-                    Some(task.args.priority)
-                } else {
-                    None
+/// Timing parameters of a task and its worst-case response time, in nanoseconds
+pub struct Timing {
+    pub wcet: u64,
+    /// The `period` or the `interarrival` time
+    pub period: u64,
+    /// The `deadline`, or the period
+    pub deadline: u64,
+    /// Longest time a lower priority task can keep this one from starting by holding a resource
+    pub blocking: u64,
+    /// `None` if the response time can exceed the deadline
+    pub response: Option<u64>,
+}
+
+impl Timing {
+    /// e.g. `wcet = 2ms, blocking = 1ms, period = 10ms, deadline = 5ms`
+    pub fn describe(&self) -> String {
+        format!(
+            "wcet = {}, blocking = {}, period = {}, deadline = {}",
+            duration(self.wcet),
+            duration(self.blocking),
+            duration(self.period),
+            duration(self.deadline),
+        )
+    }
+}
+
+// e.g. `1500000` -> `1500us`
+fn duration(nanos: u64) -> String {
+    for &(unit, scale) in &[("s", 1_000_000_000), ("ms", 1_000_000), ("us", 1_000)] {
+        if nanos != 0 && nanos % scale == 0 {
+            return format!("{}{}", nanos / scale, unit);
+        }
+    }
+
+    format!("{}ns", nanos)
+}
+
+/// Share of the CPU that the tasks of each priority level of `core` use, among the tasks that
+/// declare a `wcet`
+pub fn utilization(core: Core, app: &App, analysis: &Analysis) -> BTreeMap<Priority, f64> {
+    let mut levels = BTreeMap::new();
+    for (name, timing) in &analysis.timings {
+        let task = &app.software_tasks[name];
+        if task.args.core == core {
+            *levels.entry(task.args.priority).or_insert(0.) +=
+                timing.wcet as f64 / timing.period as f64;
+        }
+    }
+
+    levels
+}
+
+/// `SCHED_DEADLINE` reservation of a core, in nanoseconds
+pub struct Reservation {
+    pub runtime: u64,
+    pub deadline: u64,
+    pub period: u64,
+}
+
+// Combines the `sched_deadline` parameters of the tasks that run on `core`
+//
+// All the tasks of a core share the same thread so they also share a single reservation: the
+// shortest period and deadline with enough runtime to cover the utilization of every task
+pub fn reservation(core: Core, app: &App, extensions: &Extensions) -> Option<Reservation> {
+    let params = app
+        .software_tasks
+        .iter()
+        .filter(|(_, task)| task.args.core == core)
+        .filter_map(|(name, _)| {
+            extensions
+                .tasks
+                .get(name)
+                .and_then(|args| args.sched_deadline)
+        })
+        .collect::<Vec<_>>();
+
+    let period = params.iter().map(|params| params.period).min()?;
+    let deadline = params.iter().map(|params| params.deadline).min()?;
+    let runtime = params
+        .iter()
+        .map(|params| {
+            // runtime scaled to the shortest period, rounded up
+            let scaled = u128::from(params.runtime) * u128::from(period);
+            let divisor = u128::from(params.period);
+            ((scaled + divisor - 1) / divisor) as u64
+        })
+        .sum();
+
+    Some(Reservation {
+        runtime,
+        deadline,
+        period,
+    })
+}
+
+/// Whether task `name` runs on the background workers rather than at a priority level
+pub fn is_background(name: &Ident, extensions: &Extensions) -> bool {
+    extensions
+        .tasks
+        .get(name)
+        .and_then(|args| args.class)
+        .map(|(class, _)| class == TaskClass::Background)
+        .unwrap_or(false)
+}
+
+/// Whether the runtime spawns the task with these arguments when the CPUs are throttled
+pub fn is_throttling(args: &TaskArgs) -> bool {
+    args.throttling
+        .map(|(throttling, _)| throttling)
+        .unwrap_or(false)
+}
+
+/// Number of priority levels, and signals, that `core` needs: as many as its highest priority
+pub fn levels(core: Core, app: &App, analysis: &analyze::Analysis, extensions: &Extensions) -> u8 {
+    app.software_tasks
+        .iter()
+        .filter(|(name, task)| task.args.core == core && !is_background(name, extensions))
+        .map(|(_, task)| task.args.priority)
+        // NOTE the timer handler may be higher priority than all the other tasks
+        .chain(analysis.timer_queues.get(&core).map(|tq| tq.priority))
+        .max()
+        .unwrap_or(0)
+}
+
+// Response-time analysis of the tasks that declare a `wcet`
+//
+// `R = C + B + Σ ceil(R / T_j) * C_j` over the tasks `j` of the same core with a higher, or the
+// same, priority. The length of the critical sections is unknown so `B` is the longest `wcet` of
+// the lower priority tasks that use a resource whose ceiling is at least the priority of the task
+pub fn timings(
+    app: &App,
+    parent: &analyze::Analysis,
+    extensions: &Extensions,
+) -> BTreeMap<Ident, Timing> {
+    let mut params = BTreeMap::new();
+    for (name, task) in &app.software_tasks {
+        let args = match extensions.tasks.get(name) {
+            Some(args) => args,
+            None => continue,
+        };
+
+        if let (Some((wcet, _)), Some((period, _))) = (args.wcet, args.period.or(args.interarrival))
+        {
+            let deadline = args
+                .deadline
+                .map(|(deadline, _)| deadline)
+                .unwrap_or(period);
+            params.insert(name, (task, wcet, period, deadline));
+        }
+    }
+
+    let ceiling = |resource: &Ident| match parent.ownerships.get(resource) {
+        Some(Ownership::Shared { ceiling }) => Some(*ceiling),
+        _ => None,
+    };
+
+    params
+        .iter()
+        .map(|(&name, &(task, wcet, period, deadline))| {
+            let core = task.args.core;
+            let priority = task.args.priority;
+
+            let blocking = params
+                .values()
+                .filter(|(other, ..)| other.args.core == core && other.args.priority < priority)
+                .filter(|(other, ..)| {
+                    other
+                        .args
+                        .resources
+                        .iter()
+                        .any(|res| ceiling(res).map(|c| c >= priority).unwrap_or(false))
+                })
+                .map(|&(_, wcet, ..)| wcet)
+                .max()
+                .unwrap_or(0);
+
+            let interference = params
+                .iter()
+                .filter(|(&other, (task, ..))| {
+                    other != name && task.args.core == core && task.args.priority >= priority
+                })
+                .map(|(_, &(_, wcet, period, _))| (wcet, period))
+                .collect::<Vec<_>>();
+
+            let mut response = wcet.saturating_add(blocking);
+            let response = loop {
+                if response > deadline {
+                    break None;
                 }
-            })
-            // NOTE the timer handler may be higher priority than all the other tasks
-            .chain(parent.timer_queues.get(&core).map(|tq| tq.priority))
-            .collect::<BTreeSet<_>>();
 
-        let map = priorities
+                let next = interference.iter().fold(
+                    wcet.saturating_add(blocking),
+                    |total, &(wcet, period)| {
+                        let releases = (response + period - 1) / period;
+                        total.saturating_add(releases.saturating_mul(wcet))
+                    },
+                );
+
+                if next == response {
+                    break Some(response);
+                }
+                response = next;
+            };
+
+            (
+                name.clone(),
+                Timing {
+                    wcet,
+                    period,
+                    deadline,
+                    blocking,
+                    response,
+                },
+            )
+        })
+        .collect()
+}
+
+// Splits each broadcast group by sender core, receiver core and priority level
+//
+// A core can broadcast to a group if one of its contexts can spawn every task of the group
+pub fn broadcasts(app: &App, extensions: &Extensions) -> Vec<Broadcast> {
+    let spawn_lists = app
+        .inits
+        .iter()
+        .map(|(&core, init)| (core, &init.args.spawn))
+        .chain(
+            app.idles
+                .iter()
+                .map(|(&core, idle)| (core, &idle.args.spawn)),
+        )
+        .chain(
+            app.software_tasks
+                .values()
+                .map(|task| (task.args.core, &task.args.spawn)),
+        )
+        .collect::<Vec<_>>();
+
+    let mut broadcasts = vec![];
+    for (group, tasks) in extensions.app.broadcast.iter().flatten() {
+        let senders = spawn_lists
             .iter()
+            .filter(|(_, spawn)| tasks.iter().all(|task| spawn.contains(task)))
+            .map(|(core, _)| *core)
+            .collect::<BTreeSet<_>>();
+
+        for sender in senders {
+            let mut levels = BTreeMap::<_, Vec<_>>::new();
+            for task in tasks {
+                let args = &app.software_tasks[task].args;
+                levels
+                    .entry((args.core, args.priority))
+                    .or_default()
+                    .push(task.clone());
+            }
+
+            for ((receiver, priority), tasks) in levels {
+                broadcasts.push(Broadcast {
+                    group: group.clone(),
+                    sender,
+                    receiver,
+                    priority,
+                    tasks,
+                });
+            }
+        }
+    }
+
+    broadcasts
+}
+
+/// Base stack size of the spawned threads when the app doesn't specify one; 8 MiB (output of
+/// `ulimit -s`)
+pub const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+// The tasks of a core preempt each other on the same stack so, in the worst case, the stack holds
+// one frame per priority level on top of the frames of `init` / `idle` (the base size)
+fn stack_size(core: Core, app: &App, extensions: &Extensions) -> usize {
+    let mut levels = BTreeMap::new();
+    for (name, task) in &app.software_tasks {
+        if task.args.core != core {
+            continue;
+        }
+
+        if let Some(size) = extensions.tasks.get(name).and_then(|args| args.stack_size) {
+            let level = levels.entry(task.args.priority).or_insert(0);
+            *level = cmp::max(*level, size);
+        }
+    }
+
+    levels.values().fold(
+        extensions.app.stack_size.unwrap_or(STACK_SIZE),
+        |total, size| total.saturating_add(*size),
+    )
+}
+
+// The background tasks are neither dispatched at a priority level nor use message slots; their
+// messages go through queues of their own, one per sender core
+fn background(
+    parent: &mut analyze::Analysis,
+    app: &App,
+    extensions: &Extensions,
+) -> BTreeMap<Ident, BTreeMap<Core, Option<Priority>>> {
+    let mut background = BTreeMap::new();
+    for name in app.software_tasks.keys() {
+        if !is_background(name, extensions) {
+            continue;
+        }
+
+        parent.free_queues.remove(name);
+
+        // the ceiling covers the contexts of the sender core that spawn the task; `init` runs
+        // before all of them
+        let mut senders = BTreeMap::new();
+        for (spawner, spawnees) in app.spawn_callers() {
+            if !spawnees.contains(name) {
+                continue;
+            }
+
+            let ceiling = senders.entry(spawner.core(app)).or_insert(None);
+            let priority = match spawner {
+                Context::Init(_) => continue,
+                Context::Idle(_) => 0,
+                Context::HardwareTask(name) => app.hardware_tasks[name].args.priority,
+                Context::SoftwareTask(name) => app.software_tasks[name].args.priority,
+            };
+            *ceiling = Some(cmp::max(ceiling.unwrap_or(0), priority));
+        }
+        background.insert(name.clone(), senders);
+    }
+
+    for dispatchers in parent.channels.values_mut() {
+        for channels in dispatchers.values_mut() {
+            for channel in channels.values_mut() {
+                channel.tasks.retain(|name| !background.contains_key(name));
+            }
+            channels.retain(|_, channel| !channel.tasks.is_empty());
+        }
+        dispatchers.retain(|_, channels| !channels.is_empty());
+    }
+    parent
+        .channels
+        .retain(|_, dispatchers| !dispatchers.is_empty());
+
+    background
+}
+
+// Assign a RT signal handler to each priority level
+pub fn app(mut parent: P<analyze::Analysis>, app: &App, extensions: Extensions) -> P<Analysis> {
+    let background = background(&mut parent, app, &extensions);
+
+    let mut rt = extensions
+        .app
+        .signals
+        .as_ref()
+        .map(|(signals, _)| signals.start)
+        .unwrap_or(0);
+
+    let mut signals = BTreeMap::new();
+    for core in 0..app.args.cores {
+        // NOTE the runtime finds the signal of priority `p` at `end - p` so every level up to the
+        // highest one gets a signal, even if no task runs at it
+        let map = (1..=levels(core, app, &parent, &extensions))
             .rev()
-            .cloned()
             .zip(rt..)
             .collect::<BTreeMap<_, _>>();
-        let len = map.len();
-        signals.insert(core, Signals { map, start: rt });
-        rt += len as u8;
+        let len = map.len() as u8;
+        signals.insert(
+            core,
+            Signals {
+                map,
+                start: rt,
+                shutdown: rt + len,
+            },
+        );
+        rt += len + 1;
     }
 
-    P::new(Analysis { parent, signals })
+    let reservations = (0..app.args.cores)
+        .filter_map(|core| reservation(core, app, &extensions).map(|r| (core, r)))
+        .collect();
+
+    let stack_sizes = (0..app.args.cores)
+        .map(|core| (core, stack_size(core, app, &extensions)))
+        .collect();
+
+    let fd_tasks = extensions
+        .tasks
+        .iter()
+        .filter(|(_, args)| args.binds_fd.is_some())
+        .map(|(name, _)| name.clone())
+        .zip(0..)
+        .collect();
+
+    let periodic_tasks = extensions
+        .tasks
+        .iter()
+        .filter(|(_, args)| args.period.is_some())
+        .map(|(name, _)| name.clone())
+        .zip(0..)
+        .collect();
+
+    let external_tasks = extensions
+        .tasks
+        .iter()
+        .filter(|(_, args)| args.external == Some(true))
+        .map(|(name, _)| name.clone())
+        .zip(0..)
+        .collect();
+
+    let shm_tasks = extensions
+        .tasks
+        .iter()
+        .filter(|(_, args)| args.shm.is_some())
+        .map(|(name, _)| name.clone())
+        .zip(0..)
+        .collect();
+
+    let spawner_tasks = extensions
+        .tasks
+        .iter()
+        .filter(|(_, args)| {
+            args.spawner == Some(true) || args.ffi == Some(true) || is_throttling(args)
+        })
+        .map(|(name, _)| name.clone())
+        .zip(0..)
+        .collect();
+
+    let broadcasts = broadcasts(app, &extensions);
+
+    let timings = timings(app, &parent, &extensions);
+
+    P::new(Analysis {
+        parent,
+        extensions,
+        fd_tasks,
+        periodic_tasks,
+        external_tasks,
+        shm_tasks,
+        spawner_tasks,
+        broadcasts,
+        reservations,
+        signals,
+        stack_sizes,
+        timings,
+        background,
+    })
 }
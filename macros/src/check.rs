@@ -1,13 +1,21 @@
-use std::collections::{BTreeSet, HashSet};
+use std::collections::HashSet;
 
-use proc_macro2::Span;
+use proc_macro2::{Span, TokenStream as TokenStream2, TokenTree};
+use quote::ToTokens;
 use rtfm_syntax::{analyze::Analysis, ast::App};
-use syn::parse;
+use syn::{parse, Ident, ReturnType};
+
+use crate::{
+    analyze,
+    parse::{
+        BudgetPolicy, Dispatch, Extensions, PastSchedule, Schedulability, TaskClass, WatchdogPolicy,
+    },
+};
 
 // Linux 5.0 only supports 32 real time signals
 const NSIGNALS: usize = 32;
 
-pub fn app(app: &App, analysis: &Analysis) -> parse::Result<()> {
+pub fn app(app: &App, analysis: &Analysis, extensions: &Extensions) -> parse::Result<()> {
     // this RTFM implementation uses the same namespace for all cores so we need to check that the
     // identifiers used for each core `#[init]` and `#[idle]` functions don't collide
     let mut seen = HashSet::new();
@@ -28,25 +36,1274 @@ pub fn app(app: &App, analysis: &Analysis) -> parse::Result<()> {
         }
     }
 
-    // check that there are enough signal handlers to dispatch all tasks
-    let signals = app
-        .software_tasks
+    // check that there are enough signal handlers to dispatch all tasks; every priority level up
+    // to the highest one of a core needs a signal and each core also has a shutdown handler
+    let levels = (0..app.args.cores)
+        .map(|core| analyze::levels(core, app, analysis, extensions))
+        .collect::<Vec<_>>();
+    let needed = levels
+        .iter()
+        .map(|levels| usize::from(*levels) + 1)
+        .sum::<usize>();
+    let available = extensions
+        .app
+        .signals
+        .as_ref()
+        .map(|(range, _)| usize::from(range.end - range.start))
+        .unwrap_or(NSIGNALS);
+    if needed > available {
+        // blame the highest priority of the core with the most levels
+        let core = (0..app.args.cores)
+            .max_by_key(|&core| levels[usize::from(core)])
+            .unwrap_or(0);
+        let highest = app
+            .software_tasks
+            .iter()
+            .filter(|(_, task)| task.args.core == core)
+            .max_by_key(|(_, task)| task.args.priority);
+        let (span, priority) = match highest {
+            Some((name, task)) => (
+                extensions.tasks[name]
+                    .priority
+                    .map(|(_, span)| span)
+                    .unwrap_or_else(|| name.span()),
+                task.args.priority,
+            ),
+            None => (Span::call_site(), levels[usize::from(core)]),
+        };
+
+        // signals left for this core once the other cores and its shutdown handler have theirs
+        let left = (available + usize::from(levels[usize::from(core)])).saturating_sub(needed);
+        let more = match &extensions.app.signals {
+            Some((range, _)) if usize::from(range.end - range.start) < NSIGNALS => {
+                "; or give the application more signals with `#[app(signals = ..)]`"
+            }
+            Some(_) | None => "",
+        };
+        let remedy = if left == 0 {
+            format!(
+                "no signals are left for the tasks of core #{}; use fewer cores{}",
+                core, more
+            )
+        } else {
+            format!(
+                "only {} of the {} signals are left for the tasks of core #{}; lower it to at most \
+                 {}, only the order of the priorities matters{}",
+                left, available, core, left, more
+            )
+        };
+
+        return Err(parse::Error::new(
+            span,
+            format!(
+                "priority {} needs {} real-time signals, one per level up to it, but {}",
+                priority, priority, remedy
+            ),
+        ));
+    }
+
+    // only software tasks get a wrapper that calls the body of an `extern` task
+    for (name, &span) in &extensions.extern_tasks {
+        if !app.software_tasks.contains_key(name) {
+            return Err(parse::Error::new(
+                span,
+                "only software tasks can be declared in `extern` blocks",
+            ));
+        }
+    }
+
+    // the payloads of the messages live in `static` buffers until their task runs
+    for task in app.software_tasks.values() {
+        for input in &task.inputs {
+            if let Some(span) = borrow(input.ty.clone().into_token_stream()) {
+                return Err(parse::Error::new(
+                    span,
+                    "task inputs must be `'static`; send owned data (e.g. a `Vec` or an \
+                     `rtfm::pool::Box`) instead of a borrow",
+                ));
+            }
+        }
+    }
+
+    // check the tasks bound to file descriptors
+    let mut fd_tasks = 0;
+    for (name, args) in &extensions.tasks {
+        if let Some(resource) = &args.binds_fd {
+            if app.resource(resource).is_none() {
+                return Err(parse::Error::new(
+                    resource.span(),
+                    "this resource has NOT been declared",
+                ));
+            }
+
+            if !app.software_tasks[name].inputs.is_empty() {
+                return Err(parse::Error::new(
+                    name.span(),
+                    "tasks bound to a file descriptor can't take inputs",
+                ));
+            }
+
+            fd_tasks += 1;
+        } else if let Some((_, span)) = args.fd_events {
+            return Err(parse::Error::new(
+                span,
+                "`fd_events` can only be used together with `binds_fd`",
+            ));
+        }
+    }
+
+    if fd_tasks > usize::from(u8::max_value()) + 1 {
+        return Err(parse::Error::new(
+            Span::call_site(),
+            "at most 256 tasks can be bound to file descriptors",
+        ));
+    }
+
+    // `rtfm::compat::pend` releases the tasks bound to interrupts through their `Spawner`s
+    for (name, args) in &extensions.tasks {
+        if let Some(interrupt) = &args.binds {
+            if extensions.app.device.is_none() {
+                return Err(parse::Error::new(
+                    interrupt.span(),
+                    "binding a task to an interrupt requires `#[app(device = ..)]`",
+                ));
+            }
+
+            if !app.software_tasks[name].inputs.is_empty() {
+                return Err(parse::Error::new(
+                    name.span(),
+                    "tasks bound to an interrupt can't take inputs",
+                ));
+            }
+
+            if args.spawner == Some(false) || args.binds_fd.is_some() || args.period.is_some() {
+                return Err(parse::Error::new(
+                    interrupt.span(),
+                    "`binds` can't be used together with `spawner = false`, `binds_fd` or `period`",
+                ));
+            }
+        }
+    }
+
+    // the runtime spawns the throttling task, with an `rtfm::CpuThrottling`, through its `Spawner`
+    let mut throttling = None;
+    for (name, args) in &extensions.tasks {
+        if let Some((true, span)) = args.throttling {
+            if throttling.is_some() {
+                return Err(parse::Error::new(
+                    span,
+                    "only one task can be spawned on CPU throttling",
+                ));
+            }
+
+            if app.software_tasks[name].inputs.len() != 1 {
+                return Err(parse::Error::new(
+                    name.span(),
+                    "the throttling task takes a single input, an `rtfm::CpuThrottling`",
+                ));
+            }
+
+            if args.spawner == Some(false)
+                || args.binds_fd.is_some()
+                || args.period.is_some()
+                || args.binds.is_some()
+            {
+                return Err(parse::Error::new(
+                    span,
+                    "`throttling` can't be used together with `spawner = false`, `binds_fd`, \
+                     `period` or `binds`",
+                ));
+            }
+
+            throttling = Some(name);
+        }
+    }
+
+    if let (None, Some((_, span))) = (throttling, extensions.app.throttling_poll) {
+        return Err(parse::Error::new(
+            span,
+            "`throttling_poll` requires a task with `throttling = true`",
+        ));
+    }
+
+    // the threads of these features open files or sockets after the tasks are enabled, and the
+    // `perf_event_open` counters of a thread are opened the first time its stats are read
+    if let Some((true, span)) = extensions.app.seccomp {
+        if throttling.is_some()
+            || extensions.app.metrics.is_some()
+            || extensions.app.watchdog.is_some()
+            || extensions
+                .app
+                .perf_counters
+                .map(|(perf_counters, _)| perf_counters)
+                .unwrap_or(false)
+        {
+            return Err(parse::Error::new(
+                span,
+                "`seccomp` can't be used together with a `throttling` task, `metrics`, \
+                 `watchdog` or `perf_counters`",
+            ));
+        }
+    }
+
+    if let (None, Some((_, span))) = (&extensions.app.device, extensions.app.peripherals) {
+        return Err(parse::Error::new(
+            span,
+            "`peripherals` requires `#[app(device = ..)]`",
+        ));
+    }
+
+    // the response-time analysis needs the WCET and the period of every task of a core
+    for (name, args) in &extensions.tasks {
+        match (args.wcet, args.period, args.interarrival) {
+            (_, Some(_), Some((_, span))) => {
+                return Err(parse::Error::new(
+                    span,
+                    "`interarrival` can't be used together with `period`",
+                ));
+            }
+            (Some((_, span)), None, None) => {
+                return Err(parse::Error::new(
+                    span,
+                    "`wcet` requires `period` or `interarrival`",
+                ));
+            }
+            (None, _, Some((_, span))) => {
+                return Err(parse::Error::new(span, "`interarrival` requires `wcet`"));
+            }
+            _ => {}
+        }
+
+        if args.wcet.is_none() {
+            let core = app.software_tasks[name].args.core;
+            let analyzed = extensions.tasks.iter().any(|(other, args)| {
+                args.wcet.is_some() && app.software_tasks[other].args.core == core
+            });
+
+            if analyzed {
+                return Err(parse::Error::new(
+                    name.span(),
+                    "other tasks of this core declare a `wcet`; this one must declare it too",
+                ));
+            }
+        }
+    }
+
+    // periodic tasks are released by the runtime so there's no one to provide their inputs
+    let mut periodic_tasks = 0;
+    for (name, args) in &extensions.tasks {
+        if let Some((_, span)) = args.period {
+            if !app.software_tasks[name].inputs.is_empty() {
+                return Err(parse::Error::new(span, "periodic tasks can't take inputs"));
+            }
+
+            if args.binds_fd.is_some() {
+                return Err(parse::Error::new(
+                    span,
+                    "`period` can't be used together with `binds_fd`",
+                ));
+            }
+
+            periodic_tasks += 1;
+        }
+    }
+
+    if periodic_tasks > usize::from(u8::max_value()) + 1 {
+        return Err(parse::Error::new(
+            Span::call_site(),
+            "at most 256 tasks can be periodic",
+        ));
+    }
+
+    // the `sigval` of an external task only has room for its ID and a single byte of payload
+    let mut external_tasks = 0;
+    for (name, args) in &extensions.tasks {
+        if args.external == Some(true) {
+            let inputs = &app.software_tasks[name].inputs;
+            if inputs.len() > 1 {
+                return Err(parse::Error::new(
+                    name.span(),
+                    "external tasks can take at most one input, a `u8`",
+                ));
+            }
+
+            if args.binds_fd.is_some() || args.period.is_some() {
+                return Err(parse::Error::new(
+                    name.span(),
+                    "`external` can't be used together with `binds_fd` or `period`",
+                ));
+            }
+
+            // NOTE other processes can only send process-directed signals
+            if app.args.cores > 1 && extensions.app.dispatch != Some(Dispatch::Threads) {
+                return Err(parse::Error::new(
+                    name.span(),
+                    "multi-core applications need `dispatch = \"threads\"` to have external tasks",
+                ));
+            }
+
+            external_tasks += 1;
+        }
+    }
+
+    if external_tasks > usize::from(u8::max_value()) + 1 {
+        return Err(parse::Error::new(
+            Span::call_site(),
+            "at most 256 tasks can be external",
+        ));
+    }
+
+    // the messages of a shared memory channel are the only input of its task
+    let mut shm_tasks = 0;
+    for (name, args) in &extensions.tasks {
+        if let Some(lit) = &args.shm {
+            if app.software_tasks[name].inputs.len() != 1 {
+                return Err(parse::Error::new(
+                    lit.span(),
+                    "tasks fed by a shared memory channel must take exactly one input",
+                ));
+            }
+
+            if args.binds_fd.is_some() || args.period.is_some() || args.external.is_some() {
+                return Err(parse::Error::new(
+                    lit.span(),
+                    "`shm` can't be used together with `binds_fd`, `period` or `external`",
+                ));
+            }
+
+            if let Some((_, span)) = args.capacity {
+                return Err(parse::Error::new(
+                    span,
+                    "the capacity of a shared memory channel can't exceed 255",
+                ));
+            }
+
+            shm_tasks += 1;
+        }
+    }
+
+    if shm_tasks > usize::from(u8::max_value()) + 1 {
+        return Err(parse::Error::new(
+            Span::call_site(),
+            "at most 256 tasks can be fed by shared memory channels",
+        ));
+    }
+
+    if extensions
+        .tasks
         .values()
-        .map(|task| (task.args.core, task.args.priority))
+        .filter(|args| {
+            args.spawner == Some(true) || args.ffi == Some(true) || analyze::is_throttling(args)
+        })
+        .count()
+        > usize::from(u8::max_value()) + 1
+    {
+        return Err(parse::Error::new(
+            Span::call_site(),
+            "at most 256 tasks can have a `Spawner`",
+        ));
+    }
+
+    // tasks with a `deadline` need the instant they were scheduled at and someone to report to
+    for (name, args) in &extensions.tasks {
+        if let Some((_, span)) = args.deadline {
+            if !app.uses_schedule(app.software_tasks[name].args.core) {
+                return Err(parse::Error::new(
+                    span,
+                    "`deadline` requires the `schedule` API to be used on the core of this task",
+                ));
+            }
+
+            if extensions.deadline_miss.is_none() {
+                return Err(parse::Error::new(
+                    span,
+                    "`deadline` requires a `#[deadline_miss]` function",
+                ));
+            }
+        }
+    }
+
+    // the starvation policy is either built in or a `#[watchdog_starved]` function
+    if extensions.app.watchdog.is_none() {
+        if let Some((_, span)) = extensions.app.watchdog_policy {
+            return Err(parse::Error::new(
+                span,
+                "`watchdog_policy` can only be used together with `watchdog`",
+            ));
+        }
+
+        if let Some(f) = &extensions.watchdog_starved {
+            return Err(parse::Error::new(
+                f.ident.span(),
+                "`#[watchdog_starved]` requires `#[app(watchdog = ..)]`",
+            ));
+        }
+    } else if let (Some((_, span)), Some(_)) =
+        (extensions.app.watchdog_policy, &extensions.watchdog_starved)
+    {
+        return Err(parse::Error::new(
+            span,
+            "`watchdog_policy` can't be used together with a `#[watchdog_starved]` function",
+        ));
+    }
+
+    // so is the overrun policy, which only applies to tasks with a `budget`
+    if extensions.tasks.values().all(|args| args.budget.is_none()) {
+        if let Some((_, span)) = extensions.app.budget_policy {
+            return Err(parse::Error::new(
+                span,
+                "`budget_policy` requires a task with a `budget`",
+            ));
+        }
+
+        if let Some(f) = &extensions.budget_overrun {
+            return Err(parse::Error::new(
+                f.ident.span(),
+                "`#[budget_overrun]` requires a task with a `budget`",
+            ));
+        }
+    } else if let (Some((_, span)), Some(_)) =
+        (extensions.app.budget_policy, &extensions.budget_overrun)
+    {
+        return Err(parse::Error::new(
+            span,
+            "`budget_policy` can't be used together with a `#[budget_overrun]` function",
+        ));
+    }
+
+    // NOTE `dispatch = "threads"` relies on `SCHED_FIFO` for the mutual exclusion of `lock`, and
+    // the task may overrun its budget inside a critical section
+    if let Some((BudgetPolicy::Demote, span)) = extensions.app.budget_policy {
+        if extensions.app.dispatch == Some(Dispatch::Threads) {
+            return Err(parse::Error::new(
+                span,
+                "`budget_policy = \"demote\"` can't be used together with `dispatch = \"threads\"`",
+            ));
+        }
+    }
+
+    // the heartbeat policy can follow the `#[heartbeat_missed]` function with an abort
+    if extensions
+        .tasks
+        .values()
+        .all(|args| args.heartbeat.is_none())
+    {
+        if let Some((_, span)) = extensions.app.heartbeat_policy {
+            return Err(parse::Error::new(
+                span,
+                "`heartbeat_policy` requires a task with a `heartbeat`",
+            ));
+        }
+
+        if let Some(f) = &extensions.heartbeat_missed {
+            return Err(parse::Error::new(
+                f.ident.span(),
+                "`#[heartbeat_missed]` requires a task with a `heartbeat`",
+            ));
+        }
+    } else if let (Some((WatchdogPolicy::Log, span)), Some(_)) = (
+        extensions.app.heartbeat_policy,
+        &extensions.heartbeat_missed,
+    ) {
+        return Err(parse::Error::new(
+            span,
+            "`heartbeat_policy = \"log\"` can't be used together with a `#[heartbeat_missed]` \
+             function",
+        ));
+    }
+
+    // the monitor only sees the beats of the tasks of its own process, and a task that's compiled
+    // out, or that the current mode disables, never beats
+    for (name, args) in &extensions.tasks {
+        if let Some((_, span)) = args.heartbeat {
+            if extensions.app.processes.map(|(processes, _)| processes) == Some(true) {
+                return Err(parse::Error::new(
+                    span,
+                    "`heartbeat` can't be used together with `processes = true`",
+                ));
+            }
+
+            if !app.software_tasks[name].cfgs.is_empty() {
+                return Err(parse::Error::new(
+                    span,
+                    "`heartbeat` can't be used on tasks with `#[cfg]` attributes",
+                ));
+            }
+
+            if args.modes.is_some() {
+                return Err(parse::Error::new(
+                    span,
+                    "`heartbeat` can't be used together with `modes`",
+                ));
+            }
+        }
+    }
+
+    // the `#[pre_init]` function returns the whole `RuntimeConfig`
+    if let Some(f) = &extensions.pre_init {
+        if extensions.app.cpu_dma_latency.is_some() || extensions.app.shield_cores.is_some() {
+            return Err(parse::Error::new(
+                f.ident.span(),
+                "`#[pre_init]` can't be used together with `cpu_dma_latency` or `shield_cores`; \
+                 set them in the `RuntimeConfig` it returns",
+            ));
+        }
+
+        if !f.decl.inputs.is_empty() {
+            return Err(parse::Error::new(
+                f.ident.span(),
+                "the `#[pre_init]` function must have signature `fn() -> rtfm::RuntimeConfig`",
+            ));
+        }
+    }
+
+    // the `#[deinit]` function runs in the shutdown handler of core #0
+    if let Some(f) = &extensions.deinit {
+        let returns = match f.decl.output {
+            ReturnType::Default => false,
+            ReturnType::Type(..) => true,
+        };
+
+        if !f.decl.inputs.is_empty() || returns {
+            return Err(parse::Error::new(
+                f.ident.span(),
+                "the `#[deinit]` function must have signature `fn()`",
+            ));
+        }
+    }
+
+    modes(app, extensions)?;
+
+    // only the main thread is replayed
+    if let Some((_, span)) = &extensions.app.replay {
+        if app.args.cores != 1 || extensions.app.dispatch == Some(Dispatch::Threads) {
+            return Err(parse::Error::new(
+                *span,
+                "`replay` only supports single-core applications that dispatch with signals",
+            ));
+        }
+    }
+
+    // the handler of a folded level takes releases off the kernel queue out of the recorded order
+    if let (Some((_, span)), Some(_)) = (extensions.app.signal_levels, &extensions.app.replay) {
+        return Err(parse::Error::new(
+            span,
+            "`signal_levels` can't be used together with `replay`",
+        ));
+    }
+
+    // `rtfm::defer` doesn't know which core it's called from
+    if let Some((_, span)) = extensions.app.defer {
+        if app.args.cores != 1 {
+            return Err(parse::Error::new(
+                span,
+                "`defer` only supports single-core applications",
+            ));
+        }
+    }
+
+    // `init` returns the `#[shared]` and `#[local]` resources but each core has its own `init`
+    if let Some(model) = &extensions.model {
+        if app.args.cores != 1 {
+            return Err(parse::Error::new(
+                model.shared.ident.span(),
+                "`#[shared]` and `#[local]` only support single-core applications",
+            ));
+        }
+    }
+
+    // the simulation dispatches the tasks of the main thread itself
+    if let Some((true, span)) = extensions.app.simulate {
+        if app.args.cores != 1
+            || extensions.app.dispatch == Some(Dispatch::Threads)
+            || extensions.app.replay.is_some()
+        {
+            return Err(parse::Error::new(
+                span,
+                "`simulate` only supports single-core applications that dispatch with signals and \
+                 don't `replay`",
+            ));
+        }
+    }
+
+    // the counters are reported through `rtfm::stats`
+    if let Some((true, span)) = extensions.app.perf_counters {
+        if extensions.app.stats != Some(true) {
+            return Err(parse::Error::new(
+                span,
+                "`perf_counters` requires `stats = true`",
+            ));
+        }
+    }
+
+    // the margin only applies to the tasks that clamp instants that have already passed
+    if let Some((_, span)) = extensions.app.past_schedule_margin {
+        let clamp = Some(PastSchedule::Clamp);
+        if extensions.app.past_schedule != clamp
+            && extensions
+                .tasks
+                .values()
+                .all(|args| args.past_schedule != clamp)
+        {
+            return Err(parse::Error::new(
+                span,
+                "`past_schedule_margin` requires `past_schedule = \"clamp\"`",
+            ));
+        }
+    }
+
+    // the message slots of a task, one set per sender core, and the entries of each timer queue
+    // are indexed with a `u16`
+    for (name, senders) in &analysis.free_queues {
+        if capacity(name, app, extensions) * senders.len() as u32 > u32::from(u16::max_value()) {
+            return Err(parse::Error::new(
+                name.span(),
+                format!(
+                    "`{}` has too many message slots; its capacity times the number of cores \
+                     that send it messages can't exceed {}",
+                    name,
+                    u16::max_value()
+                ),
+            ));
+        }
+    }
+
+    for (sender, tq) in &analysis.timer_queues {
+        let slots = tq
+            .tasks
+            .iter()
+            .map(|name| capacity(name, app, extensions))
+            .sum::<u32>();
+        if slots > u32::from(u16::max_value()) {
+            return Err(parse::Error::new(
+                Span::call_site(),
+                format!(
+                    "the tasks scheduled from core #{} have more than {} message slots in total",
+                    sender,
+                    u16::max_value()
+                ),
+            ));
+        }
+    }
+
+    // `#[lock_free]` resources are never locked so all the contexts that share them must run at the
+    // same priority, on the same core
+    for (name, &span) in &extensions.lock_free {
+        let users = app
+            .software_tasks
+            .iter()
+            .filter(|(_, task)| task.args.resources.contains(name))
+            .map(|(task, spec)| (task, spec.args.core, spec.args.priority))
+            .chain(
+                app.idles
+                    .iter()
+                    .filter(|(_, idle)| idle.args.resources.contains(name))
+                    .map(|(&core, idle)| (&idle.name, core, 0)),
+            )
+            .collect::<Vec<_>>();
+
+        if let Some((first, rest)) = users.split_first() {
+            if let Some(other) = rest
+                .iter()
+                .find(|user| (user.1, user.2) != (first.1, first.2))
+            {
+                return Err(parse::Error::new(
+                    span,
+                    format!(
+                        "`{}` is `#[lock_free]` but it's shared by `{}` (core #{}, priority {}) \
+                         and `{}` (core #{}, priority {}); lock-free resources can only be \
+                         shared by contexts of the same priority",
+                        name, first.0, first.1, first.2, other.0, other.1, other.2
+                    ),
+                ));
+            }
+        }
+    }
+
+    // `#[lock_free]` resources are never locked
+    for (name, &(_, span)) in extensions.pi_futex.iter().chain(&extensions.per_core) {
+        if extensions.lock_free.contains_key(name) {
+            return Err(parse::Error::new(
+                span,
+                "`#[lock_free]` resources can't have a lock policy or be `#[per_core]`",
+            ));
+        }
+    }
+
+    // the threads of the priority levels are spawned by the thread of their core, which
+    // `SCHED_DEADLINE` doesn't allow
+    if extensions.app.dispatch == Some(Dispatch::Threads) {
+        if let Some(params) = extensions
+            .tasks
+            .values()
+            .filter_map(|args| args.sched_deadline)
+            .next()
+        {
+            return Err(parse::Error::new(
+                params.span,
+                "`sched_deadline` can't be used together with `dispatch = \"threads\"`",
+            ));
+        }
+    }
+
+    // the background workers are spawned by the thread of core #0 after `init` returns, which
+    // `SCHED_DEADLINE` doesn't allow
+    if app
+        .software_tasks
+        .keys()
+        .any(|name| analyze::is_background(name, extensions))
+    {
+        if let Some(params) = app
+            .software_tasks
+            .iter()
+            .filter(|(_, task)| task.args.core == 0)
+            .filter_map(|(name, _)| extensions.tasks.get(name)?.sched_deadline)
+            .next()
+        {
+            return Err(parse::Error::new(
+                params.span,
+                "`sched_deadline` tasks on core #0 can't be used together with background tasks",
+            ));
+        }
+    }
+
+    // same for the thread that monitors the CPU throttling
+    if extensions.tasks.values().any(analyze::is_throttling) {
+        if let Some(params) = app
+            .software_tasks
+            .iter()
+            .filter(|(_, task)| task.args.core == 0)
+            .filter_map(|(name, _)| extensions.tasks.get(name)?.sched_deadline)
+            .next()
+        {
+            return Err(parse::Error::new(
+                params.span,
+                "`sched_deadline` tasks on core #0 can't be used together with a `throttling` task",
+            ));
+        }
+    }
+
+    // check that the combined `SCHED_DEADLINE` reservation of each core is admissible
+    for core in 0..app.args.cores {
+        if let Some(reservation) = analyze::reservation(core, app, extensions) {
+            if reservation.runtime > reservation.deadline {
+                let span = app
+                    .software_tasks
+                    .iter()
+                    .filter(|(_, task)| task.args.core == core)
+                    .filter_map(|(name, _)| extensions.tasks.get(name)?.sched_deadline)
+                    .map(|params| params.span)
+                    .last()
+                    .unwrap_or_else(Span::call_site);
+
+                return Err(parse::Error::new(
+                    span,
+                    format!(
+                        "the `sched_deadline` tasks of core #{} need more runtime ({} ns) than \
+                         their shortest deadline ({} ns) allows",
+                        core, reservation.runtime, reservation.deadline
+                    ),
+                ));
+            }
+        }
+    }
+
+    // every task of a broadcast group gets a copy of the same message
+    let mut groups = HashSet::new();
+    for (group, tasks) in extensions.app.broadcast.iter().flatten() {
+        if !groups.insert(group) {
+            return Err(parse::Error::new(
+                group.span(),
+                "this broadcast group has already been declared",
+            ));
+        }
+
+        let first = if let Some(first) = tasks.first() {
+            first
+        } else {
+            return Err(parse::Error::new(
+                group.span(),
+                "a broadcast group needs at least one task",
+            ));
+        };
+
+        let mut seen = HashSet::new();
+        for task in tasks {
+            let spawnee = if let Some(spawnee) = app.software_tasks.get(task) {
+                spawnee
+            } else {
+                return Err(parse::Error::new(
+                    task.span(),
+                    "this software task has NOT been declared",
+                ));
+            };
+
+            if !seen.insert(task) {
+                return Err(parse::Error::new(
+                    task.span(),
+                    "this task is already in the group",
+                ));
+            }
+
+            if !spawnee.cfgs.is_empty() {
+                return Err(parse::Error::new(
+                    task.span(),
+                    "tasks with `#[cfg]` attributes can't be broadcast to",
+                ));
+            }
+
+            let types = |name: &Ident| {
+                app.software_tasks[name]
+                    .inputs
+                    .iter()
+                    .map(|input| input.ty.clone().into_token_stream().to_string())
+                    .collect::<Vec<_>>()
+            };
+            if types(task) != types(first) {
+                return Err(parse::Error::new(
+                    task.span(),
+                    format!("this task must take the same inputs as `{}`", first),
+                ));
+            }
+        }
+    }
+
+    // the `sigval` of a broadcast has room for an 8-bit ID
+    if analyze::broadcasts(app, extensions).len() > usize::from(u8::max_value()) + 1 {
+        return Err(parse::Error::new(
+            Span::call_site(),
+            "broadcast groups can span at most 256 priority levels in total",
+        ));
+    }
+
+    if let Some((true, span)) = extensions.app.processes {
+        processes(span, app, analysis, extensions)?;
+    }
+
+    background(app, extensions)?;
+
+    // a demoted `idle` shares its thread with no task, and `lock` raises it back to `SCHED_FIFO`
+    for (&core, idle) in &app.idles {
+        let span = match extensions
+            .idles
+            .get(&idle.name)
+            .and_then(|args| args.policy)
+        {
+            Some((_, span)) => span,
+            None => continue,
+        };
+
+        if extensions.app.dispatch != Some(Dispatch::Threads) {
+            return Err(parse::Error::new(
+                span,
+                "`policy` requires `dispatch = \"threads\"`; with signals the tasks of the core \
+                 run on the thread of `idle`",
+            ));
+        }
+
+        if app
+            .software_tasks
+            .iter()
+            .filter(|(_, task)| task.args.core == core)
+            .any(|(name, _)| {
+                extensions
+                    .tasks
+                    .get(name)
+                    .map(|args| args.sched_deadline.is_some())
+                    .unwrap_or(false)
+            })
+        {
+            return Err(parse::Error::new(
+                span,
+                "`policy` can't be used on a core with `sched_deadline` tasks",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// The background tasks run outside the priority levels, on threads that share nothing with the
+// tasks of the cores but their message queues
+// a mode change masks the signals of a single core and reaches its timer queue directly
+fn modes(app: &App, extensions: &Extensions) -> parse::Result<()> {
+    let modes = if let Some((modes, span)) = &extensions.app.modes {
+        if app.args.cores != 1 {
+            return Err(parse::Error::new(
+                *span,
+                "`modes` only supports single-core applications",
+            ));
+        }
+
+        Some(modes)
+    } else {
+        None
+    };
+
+    for (name, args) in &extensions.tasks {
+        if let Some((true, span)) = args.mode_switch {
+            if modes.is_none() {
+                return Err(parse::Error::new(
+                    span,
+                    "`mode_switch` requires `#[app(modes = ..)]`",
+                ));
+            }
+        }
+
+        let (task_modes, span) = if let Some((task_modes, span)) = &args.modes {
+            (task_modes, *span)
+        } else {
+            continue;
+        };
+
+        let modes = modes
+            .ok_or_else(|| parse::Error::new(span, "`modes` requires `#[app(modes = ..)]`"))?;
+
+        for (mode, period) in task_modes {
+            if !modes.contains(mode) {
+                return Err(parse::Error::new(
+                    mode.span(),
+                    "this mode is not declared in `#[app(modes = ..)]`",
+                ));
+            }
+
+            if period.is_some() && args.period.is_none() {
+                return Err(parse::Error::new(
+                    mode.span(),
+                    "only periodic tasks can have a period per mode",
+                ));
+            }
+        }
+
+        // these are spawned by other threads or processes, or by `broadcast`, which don't look
+        // at the mode
+        let in_broadcast = extensions
+            .app
+            .broadcast
+            .iter()
+            .flatten()
+            .any(|(_, tasks)| tasks.contains(name));
+        if args.binds_fd.is_some()
+            || args.external == Some(true)
+            || args.shm.is_some()
+            || args.spawner == Some(true)
+            || args.ffi == Some(true)
+            || args.throttling.is_some()
+            || args.class.map(|(class, _)| class) == Some(TaskClass::Background)
+            || in_broadcast
+        {
+            return Err(parse::Error::new(
+                span,
+                "`modes` can't be used together with `binds_fd`, `external`, `shm`, `spawner`, \
+                 `ffi` or `throttling`, on background tasks or on the tasks of a broadcast group",
+            ));
+        }
+
+        if !app.software_tasks[name].cfgs.is_empty() {
+            return Err(parse::Error::new(
+                span,
+                "tasks with `#[cfg]` attributes can't have `modes`",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn background(app: &App, extensions: &Extensions) -> parse::Result<()> {
+    let mut any = false;
+    for (name, args) in &extensions.tasks {
+        let span = match args.class {
+            Some((TaskClass::Background, span)) => span,
+            _ => continue,
+        };
+        any = true;
+
+        let task = &app.software_tasks[name];
+        if task.args.core != 0 {
+            return Err(parse::Error::new(
+                name.span(),
+                "background tasks must be declared on core #0; they run on any CPU all the same",
+            ));
+        }
+
+        if let Some((_, span)) = args.priority {
+            return Err(parse::Error::new(
+                span,
+                "background tasks have no priority; they run under `SCHED_OTHER`",
+            ));
+        }
+
+        if !task.args.resources.is_empty()
+            || !task.args.spawn.is_empty()
+            || !task.args.schedule.is_empty()
+        {
+            return Err(parse::Error::new(
+                name.span(),
+                "background tasks can't use resources, `spawn` or `schedule`",
+            ));
+        }
+
+        if args.sched_deadline.is_some()
+            || args.binds_fd.is_some()
+            || args.stack_size.is_some()
+            || args.deadline.is_some()
+            || args.period.is_some()
+            || args.external.is_some()
+            || args.shm.is_some()
+            || args.spawner.is_some()
+            || args.ffi.is_some()
+            || args.backpressure.is_some()
+            || args.past_schedule.is_some()
+            || args.then.is_some()
+            || args.binds.is_some()
+            || args.wcet.is_some()
+            || args.interarrival.is_some()
+            || args.throttling.is_some()
+            || args.budget.is_some()
+            || args.heartbeat.is_some()
+        {
+            return Err(parse::Error::new(
+                span,
+                "background tasks only accept the `capacity` argument",
+            ));
+        }
+
+        if app
+            .schedule_callers()
+            .any(|(_, schedulees)| schedulees.contains(name))
+        {
+            return Err(parse::Error::new(
+                name.span(),
+                "background tasks can be spawned but not scheduled",
+            ));
+        }
+
+        if extensions
+            .app
+            .broadcast
+            .iter()
+            .flatten()
+            .any(|(_, tasks)| tasks.contains(name))
+        {
+            return Err(parse::Error::new(
+                name.span(),
+                "background tasks can't be in a broadcast group",
+            ));
+        }
+
+        // NOTE the log queues and the message slots of the other processes belong to priority
+        // levels
+        let processes = extensions.app.processes.map(|(processes, _)| processes) == Some(true);
+        if extensions.app.log.is_some() || processes {
+            return Err(parse::Error::new(
+                span,
+                "background tasks can't be used together with `log` or `processes`",
+            ));
+        }
+    }
+
+    if let (false, Some((_, span))) = (any, extensions.app.background_workers) {
+        return Err(parse::Error::new(
+            span,
+            "`background_workers` requires a `class = \"background\"` task",
+        ));
+    }
+
+    Ok(())
+}
+
+// With `processes = true` only the message slots, thread IDs, barriers and `pi_futex` resources
+// are in memory that the processes of the cores share
+fn processes(
+    span: Span,
+    app: &App,
+    analysis: &Analysis,
+    extensions: &Extensions,
+) -> parse::Result<()> {
+    if app.args.cores == 1
+        || extensions.app.dispatch == Some(Dispatch::Threads)
+        || extensions.app.simulate.map(|(simulate, _)| simulate) == Some(true)
+        || extensions.app.replay.is_some()
+    {
+        return Err(parse::Error::new(
+            span,
+            "`processes` requires a multi-core application that dispatches with signals and \
+             doesn't `simulate` or `replay`",
+        ));
+    }
+
+    // these write to queues or counters that only the main process reads
+    if extensions.app.log.is_some()
+        || extensions.app.defer.is_some()
+        || extensions.app.watchdog.is_some()
+    {
+        return Err(parse::Error::new(
+            span,
+            "`processes` can't be used together with `log`, `defer` or `watchdog`",
+        ));
+    }
+
+    // (context, core, resources, schedule)
+    let contexts = app
+        .inits
+        .iter()
+        .map(|(&core, init)| (&init.name, core, &init.args.resources, &init.args.schedule))
         .chain(
-            analysis
-                .timer_queues
+            app.idles
                 .iter()
-                .map(|(core, tq)| (*core, tq.priority)),
+                .map(|(&core, idle)| (&idle.name, core, &idle.args.resources, &idle.args.schedule)),
         )
-        .collect::<BTreeSet<_>>();
+        .chain(app.hardware_tasks.iter().map(|(name, task)| {
+            let args = &task.args;
+            (name, args.core, &args.resources, &args.schedule)
+        }))
+        .chain(app.software_tasks.iter().map(|(name, task)| {
+            let args = &task.args;
+            (name, args.core, &args.resources, &args.schedule)
+        }))
+        .collect::<Vec<_>>();
+
+    for name in app.resources.keys().chain(app.late_resources.keys()) {
+        let mut cores = contexts
+            .iter()
+            .filter(|(_, _, resources, _)| resources.contains(name))
+            .map(|(_, core, _, _)| *core)
+            .collect::<HashSet<_>>();
 
-    if signals.len() > NSIGNALS {
+        // the `init` that initializes a late resource also accesses it
+        cores.extend(
+            analysis
+                .late_resources
+                .iter()
+                .filter(|(_, resources)| resources.contains(name))
+                .map(|(core, _)| *core),
+        );
+
+        if extensions.per_core.contains_key(name)
+            || (cores.len() > 1 && !extensions.pi_futex.contains_key(name))
+        {
+            return Err(parse::Error::new(
+                name.span(),
+                "with `processes = true` the cores can only share \
+                 `#[lock(policy = \"pi_futex\")]` resources",
+            ));
+        }
+    }
+
+    // the timer queue of a core is in the memory of its process
+    for (context, core, _, schedule) in &contexts {
+        if let Some(task) = schedule
+            .iter()
+            .find(|task| app.software_tasks[*task].args.core != *core)
+        {
+            return Err(parse::Error::new(
+                task.span(),
+                format!(
+                    "with `processes = true` `{}` can't schedule `{}`, a task of another core",
+                    context, task
+                ),
+            ));
+        }
+    }
+
+    if let Some(broadcast) = analyze::broadcasts(app, extensions)
+        .iter()
+        .find(|broadcast| broadcast.sender != broadcast.receiver)
+    {
         return Err(parse::Error::new(
-            Span::call_site(),
-            "there are not enough real time signals to dispatch all tasks",
+            broadcast.group.span(),
+            "with `processes = true` a broadcast group can't span several cores",
         ));
     }
 
+    // the epoll loop and the `Spawner`s run in the main process
+    for (name, args) in &extensions.tasks {
+        let core = app
+            .software_tasks
+            .get(name)
+            .map(|task| task.args.core)
+            .unwrap_or(0);
+        let spawner = args.spawner == Some(true) || analyze::is_throttling(args);
+        if core != 0 && (args.binds_fd.is_some() || spawner) {
+            return Err(parse::Error::new(
+                name.span(),
+                "with `processes = true` only the tasks of core #0 can be bound to a file \
+                 descriptor or be `spawner` tasks",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails the build if the response-time analysis shows that a task can miss its deadline, unless
+/// `#[app(schedulability = "warn")]`
+pub fn schedulability(app: &App, analysis: &analyze::Analysis) -> parse::Result<()> {
+    if let Some((Schedulability::Warn, _)) = analysis.extensions.app.schedulability {
+        return Ok(());
+    }
+
+    for (name, timing) in &analysis.timings {
+        if timing.response.is_none() {
+            let core = app.software_tasks[name].args.core;
+            let utilization = analyze::utilization(core, app, analysis)
+                .values()
+                .sum::<f64>();
+            let span = analysis.extensions.tasks[name]
+                .wcet
+                .map(|(_, span)| span)
+                .unwrap_or_else(|| name.span());
+
+            return Err(parse::Error::new(
+                span,
+                format!(
+                    "this task can miss its deadline ({}); the utilization of core #{} is {:.0}%",
+                    timing.describe(),
+                    core,
+                    utilization * 100.
+                ),
+            ));
+        }
+    }
+
     Ok(())
 }
+
+// Span of the first non-`'static` lifetime or elided reference lifetime in `tokens`, if any
+fn borrow(tokens: TokenStream2) -> Option<Span> {
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(tt) = tokens.next() {
+        match tt {
+            TokenTree::Group(group) => {
+                if let Some(span) = borrow(group.stream()) {
+                    return Some(span);
+                }
+            }
+
+            TokenTree::Punct(ref punct) if punct.as_char() == '&' => match tokens.peek() {
+                Some(TokenTree::Punct(next)) if next.as_char() == '\'' => {}
+                _ => return Some(punct.span()),
+            },
+
+            TokenTree::Punct(ref punct) if punct.as_char() == '\'' => match tokens.next() {
+                Some(TokenTree::Ident(ref lifetime)) if lifetime == "static" => {}
+                _ => return Some(punct.span()),
+            },
+
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// `capacity` of task `name`, whether it was parsed by `rtfm-syntax` or by us
+fn capacity(name: &Ident, app: &App, extensions: &Extensions) -> u32 {
+    extensions
+        .tasks
+        .get(name)
+        .and_then(|args| args.capacity)
+        .map(|(capacity, _)| u32::from(capacity))
+        .unwrap_or_else(|| u32::from(app.software_tasks[name].args.capacity))
+}
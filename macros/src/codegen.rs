@@ -6,27 +6,50 @@ use rtfm_syntax::ast::App;
 use crate::analyze::Analysis;
 
 mod assertions;
+mod background;
+mod broadcast;
 mod childs;
+mod compat;
+mod defer;
 mod dispatchers;
+mod epoll;
+pub mod external;
+pub mod ffi;
+pub mod graph;
 mod idle;
 mod init;
+mod introspect;
+mod latency;
 mod locals;
+mod lock_groups;
+mod log;
+mod metrics;
+mod modes;
 mod module;
+mod periodic;
 mod post_init;
 mod pre_init;
 mod resources;
 mod resources_struct;
+mod schedulability;
 mod schedule;
 mod schedule_body;
+mod shm;
+mod shutdown;
 mod spawn;
 mod spawn_body;
+mod spawner;
+mod stats;
 mod tasks;
+mod threads;
+mod throttling;
 mod timer_body;
 mod timer_queue;
 mod util;
+mod watchdog;
 
 pub fn app(app: &App, analysis: &Analysis) -> TokenStream {
-    let assertion_stmts = assertions::codegen(analysis);
+    let assertion_stmts = assertions::codegen(app, analysis);
 
     let (const_app_pre_init, pre_init_stmts) = pre_init::codegen(app, analysis);
 
@@ -42,7 +65,7 @@ pub fn app(app: &App, analysis: &Analysis) -> TokenStream {
         call_init,
     ) = init::codegen(app, analysis);
 
-    let (const_app_post_init, post_init_stmts) = post_init::codegen(analysis);
+    let (const_app_post_init, post_init_stmts) = post_init::codegen(app, analysis);
 
     let (const_app_idle, mod_idle, idle_locals, idle_resources, user_idle, call_idle) =
         idle::codegen(app, analysis);
@@ -56,20 +79,125 @@ pub fn app(app: &App, analysis: &Analysis) -> TokenStream {
 
     let const_app_spawn = spawn::codegen(app, analysis);
 
+    let const_app_broadcast = broadcast::codegen(app, analysis);
+
+    let const_app_background = background::codegen(app, analysis);
+
+    let const_app_ffi = ffi::codegen(app, analysis);
+
+    let const_app_throttling = throttling::codegen(app, analysis);
+
     let const_app_tq = timer_queue::codegen(app, analysis);
 
     let const_app_schedule = schedule::codegen(app, analysis);
 
+    let const_app_schedulability = schedulability::warnings(app, analysis);
+
+    let (mod_app_modes, const_app_modes) = modes::codegen(app, analysis);
+
+    let panic_task = &analysis.extensions.panic_task;
+    let deadline_miss = &analysis.extensions.deadline_miss;
+    let watchdog_starved = &analysis.extensions.watchdog_starved;
+    let budget_overrun = &analysis.extensions.budget_overrun;
+    let heartbeat_missed = &analysis.extensions.heartbeat_missed;
+    let pre_init = &analysis.extensions.pre_init;
+    let deinit = &analysis.extensions.deinit;
+    let (shared, local) = match &analysis.extensions.model {
+        Some(model) => (Some(&model.shared), Some(&model.local)),
+        None => (None, None),
+    };
+
+    // under `cfg(test)` `rtfm::simulate` starts the application; the test harness owns `main`
+    let (main_cfg, simulate) = if let Some((true, _)) = analysis.extensions.app.simulate {
+        (
+            Some(quote!(#[cfg(not(test))])),
+            Some(quote!(
+                #[cfg(test)]
+                #[doc(hidden)]
+                #[no_mangle]
+                unsafe extern "C" fn __rtfm_simulate() {
+                    rtfm::export::set_std_args();
+
+                    #(#assertion_stmts)*
+
+                    #(#pre_init_stmts)*
+
+                    #call_init
+
+                    #(#post_init_stmts)*
+                }
+            )),
+        )
+    } else {
+        (None, None)
+    };
+
+    // with `std_main = true` the C entry point becomes `__rtfm_main`, which a regular `fn main`
+    // calls once `std` has started; `fn main` has to be at the root of the crate
+    let (entry, set_args, std_main) = if let Some((true, _)) = analysis.extensions.app.std_main {
+        (
+            quote!(
+                #[doc(hidden)]
+                #[no_mangle]
+                unsafe extern "C" fn __rtfm_main() -> !
+            ),
+            quote!(rtfm::export::set_std_args();),
+            Some(quote!(
+                #main_cfg
+                fn main() {
+                    extern "C" {
+                        fn __rtfm_main() -> !;
+                    }
+
+                    unsafe { __rtfm_main() }
+                }
+            )),
+        )
+    } else {
+        (
+            quote!(
+                #[no_mangle]
+                unsafe extern "C" fn main(
+                    argc: rtfm::export::c_int,
+                    argv: *const *const rtfm::export::c_char,
+                    envp: *const *const rtfm::export::c_char,
+                ) -> !
+            ),
+            quote!(rtfm::export::set_args(argc, argv, envp);),
+            None,
+        )
+    };
+
     let name = &app.name;
-    quote!(
+    let ts = quote!(
         #(#user_init)*
 
         #(#user_idle)*
 
         #mod_resources
 
+        #(#mod_app_modes)*
+
         #(#user_tasks)*
 
+        #panic_task
+
+        #deadline_miss
+
+        #watchdog_starved
+
+        #budget_overrun
+
+        #heartbeat_missed
+
+        #pre_init
+
+        #deinit
+
+        #shared
+
+        #local
+
         #(#init_locals)*
 
         #(#init_resources)*
@@ -110,12 +238,28 @@ pub fn app(app: &App, analysis: &Analysis) -> TokenStream {
 
             #(#const_app_spawn)*
 
+            #(#const_app_broadcast)*
+
+            #(#const_app_background)*
+
+            #(#const_app_ffi)*
+
+            #(#const_app_throttling)*
+
             #(#const_app_tq)*
 
             #(#const_app_schedule)*
 
-            #[no_mangle]
-            unsafe fn main() -> ! {
+            #(#const_app_schedulability)*
+
+            #(#const_app_modes)*
+
+            #simulate
+
+            #main_cfg
+            #entry {
+                #set_args
+
                 #(#assertion_stmts)*
 
                 #(#pre_init_stmts)*
@@ -127,6 +271,32 @@ pub fn app(app: &App, analysis: &Analysis) -> TokenStream {
                 #call_idle
             }
         };
-    )
-    .into()
+    );
+
+    // the application of RTIC 0.6 is a module; its other items are kept next to the generated code
+    match &analysis.extensions.module {
+        Some(module) => {
+            let attrs = &module.attrs;
+            let vis = &module.vis;
+            let ident = &module.ident;
+            let items = module
+                .content
+                .as_ref()
+                .map(|(_, items)| &items[..])
+                .unwrap_or(&[]);
+
+            quote!(
+                #(#attrs)*
+                #vis mod #ident {
+                    #(#items)*
+
+                    #ts
+                }
+
+                #std_main
+            )
+            .into()
+        }
+        None => quote!(#ts #std_main).into(),
+    }
 }
@@ -2,10 +2,11 @@ use std::collections::HashSet;
 
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
+use rtfm_syntax::ast::App;
 
 use crate::analyze::Analysis;
 
-pub fn codegen(analysis: &Analysis) -> Vec<TokenStream2> {
+pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
     let mut stmts = vec![];
 
     let send_types = analysis
@@ -26,5 +27,18 @@ pub fn codegen(analysis: &Analysis) -> Vec<TokenStream2> {
         stmts.push(quote!(rtfm::export::assert_sync::<#ty>();));
     }
 
+    // the messages of the background tasks move to the worker threads
+    for name in analysis.background.keys() {
+        let task = &app.software_tasks[name];
+        let cfgs = &task.cfgs;
+        for input in &task.inputs {
+            let ty = &input.ty;
+            stmts.push(quote!(
+                #(#cfgs)*
+                rtfm::export::assert_send::<#ty>();
+            ));
+        }
+    }
+
     stmts
 }
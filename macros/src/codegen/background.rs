@@ -0,0 +1,236 @@
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use rtfm_syntax::{ast::App, Context};
+use syn::Ident;
+
+use crate::{
+    analyze::{self, Analysis},
+    codegen::util,
+};
+
+/// Creates the message queues of the background tasks and the workers that run them
+pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let mut items = vec![];
+
+    if analysis.background.is_empty() {
+        return items;
+    }
+
+    let mut rounds = vec![];
+    for (name, senders) in &analysis.background {
+        let task = &app.software_tasks[name];
+        let cfgs = &task.cfgs;
+        let (_, tupled, pats, ty) = util::regroup_inputs(&task.inputs);
+
+        let (message_ty, message, instant) = if app.uses_schedule(0) {
+            (
+                quote!((#ty, rtfm::Instant)),
+                quote!((#tupled, instant)),
+                Some(quote!(, instant)),
+            )
+        } else {
+            (ty, tupled, None)
+        };
+
+        let cap = util::capacity(name, app, analysis);
+        let cap_ty = util::typenum_capacity(cap, true);
+        let queue_ty = quote!(rtfm::export::Queue<#message_ty, #cap_ty>);
+
+        let name_str = name.to_string();
+        let on_panic = on_panic(analysis);
+        let call = quote!(#name(
+            #name::Locals::new(),
+            #name::Context::new(priority #instant)
+            #(,#pats)*
+        ));
+
+        let mut dequeues = vec![];
+        for (&sender, ceiling) in senders {
+            let bq = util::bq_ident(name, sender);
+
+            let doc = format!(
+                "Messages that core #{} sends to the background task `{}`",
+                sender, name
+            );
+            items.push(quote!(
+                #[doc = #doc]
+                #(#cfgs)*
+                static mut #bq: #queue_ty = rtfm::export::Queue(rtfm::export::iQueue::new());
+            ));
+
+            if let Some(ceiling) = ceiling {
+                items.push(quote!(
+                    #(#cfgs)*
+                    struct #bq<'a> {
+                        priority: &'a rtfm::export::Priority,
+                    }
+                ));
+
+                items.push(util::impl_mutex(
+                    cfgs,
+                    false,
+                    &bq,
+                    queue_ty.clone(),
+                    *ceiling,
+                    analysis.signals[&sender].range(),
+                    quote!(&mut #bq),
+                    quote!(&[]),
+                ));
+            }
+
+            // NOTE the claim makes this worker the only consumer of the queue
+            dequeues.push(quote!(
+                if let Some(#message) = #bq.split().1.dequeue() {
+                    rtfm::export::run(#name_str, 0, || #call, #on_panic);
+
+                    return true;
+                }
+            ));
+        }
+
+        let claim = claim_ident(name);
+        items.push(quote!(
+            #(#cfgs)*
+            static #claim: rtfm::export::TaskClaim = rtfm::export::TaskClaim::new();
+        ));
+
+        rounds.push(quote!(
+            #(#cfgs)*
+            {
+                ran |= #claim.try_run(|| {
+                    let priority = &rtfm::export::Priority::new(0);
+
+                    #(#dequeues)*
+
+                    false
+                });
+            }
+        ));
+    }
+
+    items.push(quote!(
+        /// Wakes up the background workers
+        static BACKGROUND: rtfm::export::Doorbell = rtfm::export::Doorbell::new();
+
+        /// Runs the background tasks, one message per task per round
+        extern "C" fn background_worker() -> ! {
+            unsafe {
+                rtfm::export::register_thread(Some("rtfm-background"), None, None, 0);
+                rtfm::export::fault_stack().unwrap_or_else(rtfm::export::fatal);
+
+                loop {
+                    let token = BACKGROUND.token();
+
+                    let mut ran = false;
+                    #(#rounds)*
+
+                    if !ran {
+                        BACKGROUND.wait(token);
+                    }
+                }
+            }
+        }
+    ));
+
+    items
+}
+
+/// Starts the background workers
+///
+/// NOTE the workers inherit the signal mask of the thread of core #0 so this must run before the
+/// thread unblocks its signals
+pub fn start(analysis: &Analysis) -> Vec<TokenStream2> {
+    if analysis.background.is_empty() {
+        return vec![];
+    }
+
+    let workers = analysis
+        .extensions
+        .app
+        .background_workers
+        .map(|(workers, _)| workers)
+        .unwrap_or(1);
+    let stack_size = analysis
+        .extensions
+        .app
+        .stack_size
+        .unwrap_or(analyze::STACK_SIZE);
+
+    vec![quote!(
+        for _ in 0..#workers {
+            let tid = rtfm::export::spawn(
+                background_worker,
+                rtfm::export::stack_size(#stack_size),
+                "a background worker",
+            )
+            .unwrap_or_else(rtfm::export::fatal);
+            rtfm::export::set_normal(tid).unwrap_or_else(rtfm::export::fatal);
+        }
+    )]
+}
+
+/// Creates the body of `spawn_${name}` for the background task `name`
+///
+/// The message goes to the queue of the core of `context`; nothing here waits for the workers
+pub fn spawn_body(context: Context, name: &Ident, app: &App, analysis: &Analysis) -> TokenStream2 {
+    let sender = context.core(app);
+    let spawnee = &app.software_tasks[name];
+
+    let (_, tupled, _, _) = util::regroup_inputs(&spawnee.inputs);
+
+    let bq = util::bq_ident(name, sender);
+    let (message, input) = if app.uses_schedule(0) {
+        (quote!((input, instant)), quote!(message.0))
+    } else {
+        (quote!(input), quote!(message))
+    };
+
+    let (import, enqueue) = if context.is_init() || analysis.background[name][&sender].is_none() {
+        // NOTE `init` is the only context of this core that sends these messages
+        (None, quote!(#bq.split().0.enqueue(#message)))
+    } else {
+        (
+            Some(quote!(
+                use rtfm::Mutex as _;
+            )),
+            quote!((#bq { priority }).lock(|bq| bq.split().0.enqueue(#message))),
+        )
+    };
+
+    quote!(
+        unsafe {
+            #import
+
+            let input = #tupled;
+            if rtfm::export::shutting_down() {
+                Err(rtfm::SpawnError::ShuttingDown(input))
+            } else {
+                match #enqueue {
+                    Ok(()) => {
+                        BACKGROUND.ring();
+
+                        Ok(())
+                    }
+                    Err(message) => Err(rtfm::SpawnError::Full(#input)),
+                }
+            }
+        }
+    )
+}
+
+// A panic in a background task aborts the process unless there's a `#[panic_task]`, which the
+// worker calls itself; it has no signals to block
+fn on_panic(analysis: &Analysis) -> TokenStream2 {
+    if let Some(f) = &analysis.extensions.panic_task {
+        let panic_task = &f.ident;
+
+        quote!(|task| #panic_task(task))
+    } else {
+        quote!(rtfm::export::abort)
+    }
+}
+
+/// e.g. `foo` -> `foo_CLAIM`
+fn claim_ident(task: &Ident) -> Ident {
+    Ident::new(&format!("{}_CLAIM", task), Span::call_site())
+}
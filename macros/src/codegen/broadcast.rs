@@ -0,0 +1,361 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::{analyze::Priority, ast::App, Context, Core};
+use syn::{Attribute, Ident};
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Creates the tables of message slots of the broadcasts and the `broadcast` methods
+pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let mut items = vec![];
+
+    for (id, broadcast) in analysis.broadcasts.iter().enumerate() {
+        if broadcast.tasks.len() < 2 {
+            continue;
+        }
+
+        // one entry per message slot of the first task
+        let leader = &broadcast.tasks[0];
+        let senders = analysis.free_queues[leader].len() as u16;
+        let cap = util::capacity_literal(util::capacity(leader, app, analysis) * senders);
+        let followers = util::capacity_literal(broadcast.tasks.len() as u16 - 1);
+
+        let indexes = util::broadcast_indexes_ident(id);
+        let doc = format!(
+            "Message slots of the tasks that follow `{}` in broadcast #{}",
+            leader, id
+        );
+        let cfgs = tasks_cfgs(&broadcast.tasks, app);
+        items.push(quote!(
+            #[doc = #doc]
+            #(#cfgs)*
+            static mut #indexes: [core::mem::MaybeUninit<[u16; #followers]>; #cap] =
+                [core::mem::MaybeUninit::uninit(); #cap];
+        ));
+    }
+
+    let contexts = app
+        .inits
+        .keys()
+        .map(|&core| Context::Init(core))
+        .chain(app.idles.keys().map(|&core| Context::Idle(core)))
+        .chain(app.software_tasks.keys().map(Context::SoftwareTask));
+    for ctxt in contexts {
+        let methods = util::broadcast_groups(ctxt, app, analysis)
+            .into_iter()
+            .map(|group| method(ctxt, group, app, analysis))
+            .collect::<Vec<_>>();
+
+        if methods.is_empty() {
+            continue;
+        }
+
+        let name = ctxt.ident(app);
+        let lt = if ctxt.is_init() {
+            None
+        } else {
+            Some(quote!('a))
+        };
+        let cfgs = util::cfgs(ctxt, app);
+        items.push(quote!(
+            #(#cfgs)*
+            impl<#lt> #name::Broadcast<#lt> {
+                #(#methods)*
+            }
+        ));
+    }
+
+    items
+}
+
+// `broadcast.${group}` claims a message slot of every task of `group`, or none, writes a copy of
+// the message into each and then sends one signal per priority level
+fn method(ctxt: Context, group: &Ident, app: &App, analysis: &Analysis) -> TokenStream2 {
+    let sender = ctxt.core(app);
+    let broadcasts = analysis
+        .broadcasts
+        .iter()
+        .enumerate()
+        .filter(|(_, broadcast)| broadcast.group == *group && broadcast.sender == sender)
+        .collect::<Vec<_>>();
+
+    let first = &broadcasts[0].1.tasks[0];
+    let (args, tupled, _, ty) = util::regroup_inputs(&app.software_tasks[first].inputs);
+
+    let index = |name: &Ident| Ident::new(&format!("{}_index", name), name.span());
+
+    // gives the message slot `index` of task `name` back
+    let range = analysis.signals[&sender].range();
+    let (start, end) = (range.start, range.end);
+    let free_slot = |name: &Ident, index: &Ident| {
+        let fq = util::fq_ident_(name, sender);
+
+        if ctxt.is_init() {
+            quote!(#fq.enqueue_unchecked(#index);)
+        } else {
+            // NOTE like in `spawn`, this critical section also covers the dispatcher, the other
+            // producer of `#fq`
+            quote!(
+                rtfm::export::free(#start..#end, || #fq.split().0.enqueue_unchecked(#index));
+            )
+        }
+    };
+
+    let tasks = broadcasts
+        .iter()
+        .flat_map(|(_, broadcast)| &broadcast.tasks)
+        .collect::<Vec<_>>();
+    let indexes = tasks.iter().map(|name| index(name)).collect::<Vec<_>>();
+    let cfgs = tasks_cfgs(tasks.iter().cloned(), app);
+
+    let dequeues = tasks.iter().map(|name| {
+        let fq = util::fq_ident_(name, sender);
+
+        if ctxt.is_init() {
+            quote!(#fq.dequeue())
+        } else {
+            quote!((#fq { priority }).lock(|fq| fq.split().1.dequeue()))
+        }
+    });
+
+    let free_claimed = tasks.iter().map(|name| {
+        let free = free_slot(name, &index(name));
+        let index = index(name);
+
+        quote!(
+            if let Some(#index) = #index {
+                #free
+            }
+        )
+    });
+
+    let writes = tasks.iter().map(|name| {
+        let index = index(name);
+        let claim = util::claim_slot(name, app, analysis);
+        let inputs = util::inputs_ident(name);
+        let instant = if app.uses_schedule(app.software_tasks[*name].args.core) {
+            let instants = util::instants_ident(name);
+
+            Some(quote!(
+                #instants.get_unchecked_mut(usize::from(#index)).as_mut_ptr().write(instant);
+            ))
+        } else {
+            None
+        };
+
+        quote!(
+            #claim
+            #inputs.get_unchecked_mut(usize::from(#index)).as_mut_ptr().write(payload.clone());
+            #instant
+        )
+    });
+
+    let signals = broadcasts.iter().enumerate().map(|(i, (id, broadcast))| {
+        let receiver = broadcast.receiver;
+        let signo = analysis.signals[&receiver].map[&broadcast.priority];
+        let tid = if util::process_directed(app, analysis) {
+            quote!(None)
+        } else {
+            let tid = util::tid_ident(receiver);
+            quote!(Some(#tid.get()))
+        };
+        let tgid = util::tgid(receiver, analysis);
+
+        let leader = index(&broadcast.tasks[0]);
+        let write_indexes = if broadcast.tasks.len() > 1 {
+            let table = util::broadcast_indexes_ident(*id);
+            let followers = broadcast.tasks[1..].iter().map(|name| index(name));
+
+            Some(quote!(
+                #table
+                    .get_unchecked_mut(usize::from(#leader))
+                    .as_mut_ptr()
+                    .write([#(#followers),*]);
+            ))
+        } else {
+            None
+        };
+
+        // the messages of this level and of the ones after it won't be sent
+        let unsent = broadcasts[i..]
+            .iter()
+            .flat_map(|(_, broadcast)| &broadcast.tasks)
+            .map(|name| {
+                let index = index(name);
+                let inputs = util::inputs_ident(name);
+                let free = free_slot(name, &index);
+                let release = util::release_slot(name, app, analysis);
+
+                quote!(
+                    core::ptr::drop_in_place(
+                        #inputs.get_unchecked_mut(usize::from(#index)).as_mut_ptr(),
+                    );
+                    #free
+                    #release
+                )
+            });
+
+        let id = *id as u8;
+        quote!(
+            #write_indexes
+            if !rtfm::export::try_broadcast(#tgid, #tid, #signo, #id, #leader) {
+                #(#unsent)*
+
+                return Err(rtfm::SpawnError::SignalQueueFull(payload));
+            }
+        )
+    });
+
+    let let_priority = if ctxt.is_init() {
+        None
+    } else {
+        Some(quote!(
+            use rtfm::Mutex as _;
+
+            let priority = self.spawn().priority();
+        ))
+    };
+
+    let let_instant = if tasks
+        .iter()
+        .any(|name| app.uses_schedule(app.software_tasks[*name].args.core))
+    {
+        Some(if ctxt.is_init() || ctxt.is_idle() {
+            let monotonic = util::monotonic(analysis);
+            quote!(let instant = <#monotonic as rtfm::Monotonic>::now();)
+        } else {
+            quote!(let instant = self.spawn().instant();)
+        })
+    } else {
+        None
+    };
+
+    let doc = format!(
+        "Spawns {} with the same message",
+        tasks
+            .iter()
+            .map(|name| format!("`{}`", name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    quote!(
+        #[doc = #doc]
+        ///
+        /// Either every task gets a copy of the message or, if one of them has no free message
+        /// slot, none does. The tasks of a priority level are notified with a single signal; if
+        /// that fails the tasks of the levels notified before still run
+        #(#cfgs)*
+        fn #group(&self #(,#args)*) -> Result<(), rtfm::SpawnError<#ty>> {
+            unsafe {
+                #let_priority
+                #let_instant
+
+                let payload = #tupled;
+                if rtfm::export::shutting_down() {
+                    return Err(rtfm::SpawnError::ShuttingDown(payload));
+                }
+
+                let claimed = (#(#dequeues,)*);
+                let (#(#indexes,)*) = if let (#(Some(#indexes),)*) = claimed {
+                    (#(#indexes,)*)
+                } else {
+                    let (#(#indexes,)*) = claimed;
+                    #(#free_claimed)*
+
+                    return Err(rtfm::SpawnError::Full(payload));
+                };
+
+                #(#writes)*
+
+                #(#signals)*
+
+                Ok(())
+            }
+        }
+    )
+}
+
+/// Runs the tasks of the broadcasts that core `core` dispatches at priority `level`
+///
+/// Returns `None` if there are no such broadcasts
+pub fn dispatch(
+    core: Core,
+    level: Priority,
+    app: &App,
+    analysis: &Analysis,
+) -> Option<TokenStream2> {
+    let arms = analysis
+        .broadcasts
+        .iter()
+        .enumerate()
+        .filter(|(_, broadcast)| broadcast.receiver == core && broadcast.priority == level)
+        .map(|(id, broadcast)| {
+            let sender = broadcast.sender;
+            let leader = util::run_message(&broadcast.tasks[0], sender, app, analysis);
+
+            // NOTE the table entry belongs to the slot of the first task; read it before that slot
+            // is given back
+            let (read_indexes, followers) = if broadcast.tasks.len() > 1 {
+                let table = util::broadcast_indexes_ident(id);
+                let followers = broadcast.tasks[1..]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        let run = util::run_message(name, sender, app, analysis);
+
+                        quote!({
+                            let index = indexes[#i];
+                            #run
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                (
+                    Some(quote!(
+                        let indexes = #table.get_unchecked(usize::from(index)).as_ptr().read();
+                    )),
+                    followers,
+                )
+            } else {
+                (None, vec![])
+            };
+
+            let cfgs = tasks_cfgs(&broadcast.tasks, app);
+            let id = id as u8;
+            quote!(
+                #(#cfgs)*
+                #id => {
+                    #read_indexes
+                    {
+                        #leader
+                    }
+                    #(#followers)*
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if arms.is_empty() {
+        return None;
+    }
+
+    Some(quote!(
+        if si_value & rtfm::export::BROADCAST_EVENT != 0 {
+            let index = si_value as u16;
+            match (si_value >> 24) as u8 {
+                #(#arms)*
+                _ => {}
+            }
+
+            return;
+        }
+    ))
+}
+
+// a broadcast only exists if all its tasks do
+fn tasks_cfgs<'a>(tasks: impl IntoIterator<Item = &'a Ident>, app: &'a App) -> Vec<&'a Attribute> {
+    tasks
+        .into_iter()
+        .flat_map(|name| &app.software_tasks[name].cfgs)
+        .collect()
+}
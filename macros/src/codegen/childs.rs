@@ -4,7 +4,10 @@ use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use rtfm_syntax::ast::App;
 
-use crate::{analyze::Analysis, codegen::util};
+use crate::{
+    analyze::Analysis,
+    codegen::{epoll, idle, periodic, pre_init, shm, shutdown, threads, util},
+};
 
 pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
     let mut const_app = vec![];
@@ -26,6 +29,29 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
             #tid.wait();
         ));
 
+        let name = format!("rtfm-core{}", core);
+        let (affinity, priority) = if analysis.reservations.contains_key(&core) {
+            (quote!(None), 0u8)
+        } else {
+            (quote!(Some(#core)), 1u8)
+        };
+        stmts.push(quote!(
+            rtfm::export::register_thread(Some(#name), Some(#core), #affinity, #priority);
+        ));
+
+        // NOTE the alternate signal stack is per thread so the child has to install its own
+        let stack_size = analysis.stack_sizes[&core];
+        let name = format!("the tasks of core #{}", core);
+        stmts.push(quote!(
+            rtfm::export::sigaltstack(rtfm::export::stack_size(#stack_size), #name)
+                .unwrap_or_else(rtfm::export::fatal);
+        ));
+
+        // timers belong to the process that creates them
+        if util::processes(analysis) {
+            stmts.extend(pre_init::timers(core, quote!(Some(#tid.get())), analysis));
+        }
+
         if let Some(init) = app.inits.get(&core) {
             let name = &init.name;
             stmts.push(quote!(
@@ -36,12 +62,23 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
         // initialize late resources
         if let Some(late_resources) = analysis.late_resources.get(&core) {
             for name in late_resources {
+                let cfgs = &app.late_resources[name].cfgs;
                 stmts.push(quote!(
+                    #(#cfgs)*
                     #name.as_mut_ptr().write(late.#name);
                 ));
             }
         }
 
+        // watch the file descriptors bound to tasks
+        stmts.extend(epoll::register(core, app, analysis));
+
+        // release the periodic tasks
+        stmts.extend(periodic::start(core, app, analysis));
+
+        // let other processes feed the tasks
+        stmts.extend(shm::open(core, app, analysis));
+
         // initialization barriers
         if let Some(senders) = analysis.initialization_barriers.get(&core) {
             for &sender in senders {
@@ -53,15 +90,36 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
         }
 
         // `interrupt::enable`
-        let signals = &analysis.signals[&core];
-        let max = signals.map.len() as u8;
-        let Range { start, end } = signals.range();
-        stmts.push(quote!(
-            rtfm::export::mask(#start..#end, 0, #max, false);
-        ));
+        // NOTE the threads of a process change their credentials, and get their filter, together;
+        // the process of a core changes its own
+        let (drop_privileges, install_seccomp) = if util::processes(analysis) {
+            (
+                Some(util::drop_privileges()),
+                util::install_seccomp(analysis),
+            )
+        } else {
+            (None, None)
+        };
+        if util::threads(analysis) {
+            stmts.extend(threads::spawn(core, app, analysis));
+            stmts.extend(drop_privileges);
+            stmts.extend(install_seccomp);
+        } else {
+            stmts.extend(drop_privileges);
+            stmts.extend(install_seccomp);
+
+            let signals = &analysis.signals[&core];
+            let max = signals.map.len() as u8;
+            let Range { start, end } = signals.range();
+            stmts.push(quote!(
+                rtfm::export::mask(#start..#end, 0, #max, false);
+            ));
+        }
+        stmts.push(shutdown::enable(core, analysis));
 
         if let Some(idle) = app.idles.get(&core) {
             let name = &idle.name;
+            stmts.extend(idle::demote(core, app, analysis));
             stmts.push(quote!(
                 #name(
                     #name::Locals::new(),
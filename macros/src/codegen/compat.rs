@@ -0,0 +1,30 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+
+use crate::analyze::Analysis;
+
+/// Binds the interrupts of the `#[task(binds = ..)]` tasks to their `Spawner`s
+///
+/// NOTE this must run after the queues of the `Spawner`s have been initialized
+pub fn bind(analysis: &Analysis) -> Vec<TokenStream2> {
+    let device = match &analysis.extensions.app.device {
+        Some(device) => device,
+        None => return vec![],
+    };
+
+    analysis
+        .extensions
+        .tasks
+        .iter()
+        .filter_map(|(name, args)| {
+            let interrupt = args.binds.as_ref()?;
+
+            // NOTE like a pending interrupt, a pending task absorbs more `pend`s
+            Some(quote!(
+                rtfm::export::bind_interrupt(#device::Interrupt::#interrupt, || {
+                    let _ = #name::spawner().spawn(());
+                });
+            ))
+        })
+        .collect()
+}
@@ -0,0 +1,67 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::{ast::App, Core};
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Tells `rtfm::defer` how many slots it has and where the dispatchers are
+pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let mut stmts = vec![];
+
+    if let Some((slots, _)) = analysis.extensions.app.defer {
+        // NOTE `check::app` rejects `defer` in multi-core applications; and a priority level only
+        // has a signal handler if something is dispatched at it
+        let exclusive = util::exclusive_levels(app, analysis);
+        let levels = analysis.signals[&0]
+            .map
+            .iter()
+            .filter(|(priority, _)| {
+                analysis
+                    .channels
+                    .get(&0)
+                    .map(|dispatchers| dispatchers.contains_key(priority))
+                    .unwrap_or(false)
+                    || analysis
+                        .timer_queues
+                        .get(&0)
+                        .map(|tq| tq.priority == **priority)
+                        .unwrap_or(false)
+                    || exclusive.contains(&(0, **priority))
+            })
+            .map(|(priority, signo)| quote!((#priority, #signo)));
+
+        let tid = if util::process_directed(app, analysis) {
+            quote!(None)
+        } else {
+            let tid = util::tid_ident(0);
+            quote!(Some(&#tid))
+        };
+
+        stmts.push(quote!(
+            rtfm::export::defer_init(#slots, &TGID, #tid, &[#(#levels),*]);
+        ));
+    }
+
+    stmts
+}
+
+/// Runs the closures passed to `rtfm::defer`; every dispatcher of the core can receive them
+///
+/// Returns `None` if the application doesn't use `rtfm::defer`
+pub fn dispatch(core: Core, analysis: &Analysis) -> Option<TokenStream2> {
+    analysis.extensions.app.defer?;
+
+    let on_panic = util::on_panic(core, analysis);
+    Some(quote!(
+        if si_value & rtfm::export::DEFER_EVENT != 0 {
+            rtfm::export::run(
+                "defer",
+                PRIORITY,
+                || rtfm::export::run_deferred(si_value as u8),
+                #on_panic,
+            );
+
+            return;
+        }
+    ))
+}
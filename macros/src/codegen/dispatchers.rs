@@ -1,10 +1,11 @@
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
-use rtfm_syntax::ast::App;
+use rtfm_syntax::{analyze::Priority, ast::App, Core};
+use syn::Ident;
 
 use crate::{
     analyze::Analysis,
-    codegen::{timer_body, util},
+    codegen::{broadcast, defer, epoll, external, periodic, shm, spawner, timer_body, util},
 };
 
 pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
@@ -49,58 +50,29 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
                 .flat_map(|(&sender, channel)| {
                     let t = &t;
                     channel.tasks.iter().map(move |name| {
-                        let task = &app.software_tasks[name];
-                        let cfgs = &task.cfgs;
-                        let (_, tupled, pats, _) = util::regroup_inputs(&task.inputs);
-
-                        let inputs = util::inputs_ident(name);
-                        let fq = util::fq_ident_(name, sender);
-
-                        let input =
-                            quote!(#inputs.get_unchecked(usize::from(index)).as_ptr().read());
-
-                        let (let_instant, instant) = if app.uses_schedule(receiver) {
-                            let instants = util::instants_ident(name);
-                            let instant =
-                                quote!(#instants.get_unchecked(usize::from(index)).as_ptr().read());
-
-                            (
-                                Some(quote!(let instant = #instant;)),
-                                Some(quote!(, instant)),
-                            )
-                        } else {
-                            (None, None)
-                        };
-
-                        let call = {
-                            let pats = pats.clone();
-
-                            quote!(
-                                #name(
-                                    #name::Locals::new(),
-                                    #name::Context::new(priority #instant)
-                                    #(,#pats)*
-                                )
-                            )
-                        };
+                        let cfgs = &app.software_tasks[name].cfgs;
+                        let run = util::run_message(name, sender, app, analysis);
 
                         let t = t.clone();
                         let variant = util::task_ident(name, sender);
                         quote!(
                             #(#cfgs)*
                             #t::#variant => {
-                                let #tupled = #input;
-                                #let_instant
-                                #fq.split().0.enqueue_unchecked(index);
-                                let priority = &rtfm::export::Priority::new(PRIORITY);
-                                #call
+                                #run
                             }
                         )
                     })
                 })
                 .collect::<Vec<_>>();
 
-            let handler = util::rt_ident(signals.map[&level]);
+            let fd_dispatch = epoll::dispatch(receiver, level, app, analysis);
+            let external_dispatch = external::dispatch(receiver, level, app, analysis);
+            let shm_dispatch = shm::dispatch(receiver, level, app, analysis);
+            let spawner_dispatch = spawner::dispatch(receiver, level, app, analysis);
+            let broadcast_dispatch = broadcast::dispatch(receiver, level, app, analysis);
+            let defer_dispatch = defer::dispatch(receiver, analysis);
+            let period_dispatch = periodic::dispatch(receiver, level, app, analysis);
+            let signo = signals.map[&level];
             if analysis
                 .timer_queues
                 .get(&receiver)
@@ -111,55 +83,65 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
 
                 let tq =
                     timer_body::codegen(receiver, &analysis.timer_queues[&receiver], app, analysis);
-                items.push(quote!(
-                    #[allow(non_snake_case)]
-                    #[doc = #doc]
-                    extern "C" fn #handler(
-                        _: i32,
-                        si: &mut rtfm::export::siginfo_t,
-                        _: usize,
-                    ) {
-                        unsafe {
-                            use rtfm::Mutex as _;
-
-                            /// The priority of this interrupt handler
-                            const PRIORITY: u8 = #level;
-
-                            if si.siginfo.si_code == rtfm::export::SI_QUEUE {
-                                let si_value = si.siginfo.sifields.rt.sigval.sival_ptr;
-                                let task: #t = core::mem::transmute((si_value >> 8) as u8);
-                                let index = (si_value & 0xff) as u8;
-                                match task {
-                                    #(#arms)*
-                                }
-                            } else {
-                                #tq
+                items.push(handler(
+                    receiver,
+                    level,
+                    &doc,
+                    quote!(
+                        use rtfm::Mutex as _;
+
+                        /// The priority of this interrupt handler
+                        const PRIORITY: u8 = #level;
+
+                        let _dispatched = rtfm::export::Dispatched::new(#signo, si);
+                        #period_dispatch
+                        if si.siginfo.si_code == rtfm::export::SI_QUEUE {
+                            let si_value = si.siginfo.sifields.rt.sigval.sival_ptr;
+                            #fd_dispatch
+                            #external_dispatch
+                            #shm_dispatch
+                            #spawner_dispatch
+                            #broadcast_dispatch
+                            #defer_dispatch
+                            let task: #t = core::mem::transmute((si_value >> 24) as u8);
+                            let index = si_value as u16;
+                            match task {
+                                #(#arms)*
                             }
+                        } else {
+                            #tq
                         }
-                    }
+                    ),
+                    app,
+                    analysis,
                 ));
             } else {
                 let doc = format!("Priority {} task dispatcher", level);
-                items.push(quote!(
-                    #[allow(non_snake_case)]
-                    #[doc = #doc]
-                    extern "C" fn #handler(
-                        _: i32,
-                        si: &mut rtfm::export::siginfo_t,
-                        _: usize,
-                    ) {
-                        unsafe {
-                            /// The priority of this interrupt handler
-                            const PRIORITY: u8 = #level;
+                items.push(handler(
+                    receiver,
+                    level,
+                    &doc,
+                    quote!(
+                        /// The priority of this interrupt handler
+                        const PRIORITY: u8 = #level;
 
-                            let si_value = si.siginfo.sifields.rt.sigval.sival_ptr;
-                            let task: #t = core::mem::transmute((si_value >> 8) as u8);
-                            let index = (si_value & 0xff) as u8;
-                            match task {
-                                #(#arms)*
-                            }
+                        let _dispatched = rtfm::export::Dispatched::new(#signo, si);
+                        #period_dispatch
+                        let si_value = si.siginfo.sifields.rt.sigval.sival_ptr;
+                        #fd_dispatch
+                        #external_dispatch
+                        #shm_dispatch
+                        #spawner_dispatch
+                        #broadcast_dispatch
+                        #defer_dispatch
+                        let task: #t = core::mem::transmute((si_value >> 24) as u8);
+                        let index = si_value as u16;
+                        match task {
+                            #(#arms)*
                         }
-                    }
+                    ),
+                    app,
+                    analysis,
                 ));
             }
         }
@@ -169,30 +151,267 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
             let priority = timer_queue.priority;
 
             if !dispatchers.contains_key(&priority) {
-                let handler = util::rt_ident(signals.map[&priority]);
-                let tqh =
+                let signo = signals.map[&priority];
+                let mut tqh =
                     timer_body::codegen(receiver, &analysis.timer_queues[&receiver], app, analysis);
-                items.push(quote!(
-                    /// Timer queue handler
-                    #[allow(non_snake_case)]
-                    extern "C" fn #handler(
-                        _: i32,
-                        si: &mut rtfm::export::siginfo_t,
-                        _: usize,
-                    ) {
-                        unsafe {
-                            use rtfm::Mutex as _;
-
-                            /// The priority of this interrupt handler
-                            const PRIORITY: u8 = #priority;
-
-                            #tqh
+                let fd_dispatch = epoll::dispatch(receiver, priority, app, analysis);
+                let external_dispatch = external::dispatch(receiver, priority, app, analysis);
+                let shm_dispatch = shm::dispatch(receiver, priority, app, analysis);
+                let spawner_dispatch = spawner::dispatch(receiver, priority, app, analysis);
+                let defer_dispatch = defer::dispatch(receiver, analysis);
+                if fd_dispatch.is_some()
+                    || external_dispatch.is_some()
+                    || shm_dispatch.is_some()
+                    || spawner_dispatch.is_some()
+                    || defer_dispatch.is_some()
+                {
+                    tqh = quote!(
+                        if si.siginfo.si_code == rtfm::export::SI_QUEUE {
+                            let si_value = si.siginfo.sifields.rt.sigval.sival_ptr;
+                            #fd_dispatch
+                            #external_dispatch
+                            #shm_dispatch
+                            #spawner_dispatch
+                            #defer_dispatch
                         }
-                    }
+
+                        #tqh
+                    );
+                }
+                if let Some(period_dispatch) = periodic::dispatch(receiver, priority, app, analysis)
+                {
+                    tqh = quote!(
+                        #period_dispatch
+                        #tqh
+                    );
+                }
+
+                items.push(handler(
+                    receiver,
+                    priority,
+                    "Timer queue handler",
+                    quote!(
+                        use rtfm::Mutex as _;
+
+                        /// The priority of this interrupt handler
+                        const PRIORITY: u8 = #priority;
+
+                        let _dispatched = rtfm::export::Dispatched::new(#signo, si);
+                        #tqh
+                    ),
+                    app,
+                    analysis,
                 ));
             }
         }
     }
 
+    // priority levels that only dispatch tasks bound to file descriptors, periodic, external,
+    // shared memory and `Spawner` tasks
+    for (core, level) in util::exclusive_levels(app, analysis) {
+        let signo = analysis.signals[&core].map[&level];
+        let fd_dispatch = epoll::dispatch(core, level, app, analysis);
+        let period_dispatch = periodic::dispatch(core, level, app, analysis);
+        let external_dispatch = external::dispatch(core, level, app, analysis);
+        let shm_dispatch = shm::dispatch(core, level, app, analysis);
+        let spawner_dispatch = spawner::dispatch(core, level, app, analysis);
+        let defer_dispatch = defer::dispatch(core, analysis);
+        let doc = format!(
+            "Priority {} file descriptor, periodic, external, shared memory and `Spawner` task \
+             dispatcher",
+            level
+        );
+        items.push(handler(
+            core,
+            level,
+            &doc,
+            quote!(
+                /// The priority of this interrupt handler
+                const PRIORITY: u8 = #level;
+
+                let _dispatched = rtfm::export::Dispatched::new(#signo, si);
+                #period_dispatch
+                let si_value = si.siginfo.sifields.rt.sigval.sival_ptr;
+                #fd_dispatch
+                #external_dispatch
+                #shm_dispatch
+                #spawner_dispatch
+                #defer_dispatch
+            ),
+            app,
+            analysis,
+        ));
+    }
+
     items
 }
+
+// The signal handler of priority level `level` of `core`; `body` runs each release
+//
+// When the level holds tasks of several logical priorities (see `#[app(signal_levels)]`) the
+// handler runs its pending releases in priority order; see `rtfm::export::hybrid`
+fn handler(
+    core: Core,
+    level: Priority,
+    doc: &str,
+    body: TokenStream2,
+    app: &App,
+    analysis: &Analysis,
+) -> TokenStream2 {
+    let signo = analysis.signals[&core].map[&level];
+    let handler = util::rt_ident(signo);
+
+    let rank = match rank(core, level, app, analysis) {
+        Some(rank) => rank,
+        None => {
+            return quote!(
+                #[allow(non_snake_case)]
+                #[doc = #doc]
+                extern "C" fn #handler(
+                    _: i32,
+                    si: &mut rtfm::export::siginfo_t,
+                    _: usize,
+                ) {
+                    unsafe {
+                        #body
+                    }
+                }
+            )
+        }
+    };
+
+    quote!(
+        #[allow(non_snake_case)]
+        #[doc = #doc]
+        extern "C" fn #handler(
+            _: i32,
+            si: &mut rtfm::export::siginfo_t,
+            _: usize,
+        ) {
+            /// Logical priority of a release of this level
+            fn rank(si: &rtfm::export::siginfo_t) -> u8 {
+                unsafe {
+                    #rank
+                }
+            }
+
+            /// Runs a release of this level
+            unsafe fn dispatch(si: &mut rtfm::export::siginfo_t) {
+                #body
+            }
+
+            unsafe { rtfm::export::hybrid(#signo, si, rank, dispatch) }
+        }
+    )
+}
+
+// The body of the `rank` function of a level that holds several logical priorities
+//
+// Returns `None` if all the tasks of the level have the same logical priority. Releases that
+// don't name a task (timers, file descriptors, ..) rank the highest
+fn rank(core: Core, level: Priority, app: &App, analysis: &Analysis) -> Option<TokenStream2> {
+    let tasks = app
+        .software_tasks
+        .iter()
+        .filter(|(_, task)| task.args.core == core && task.args.priority == level)
+        .map(|(name, _)| (name, util::logical_priority(name, app, analysis)))
+        .collect::<Vec<_>>();
+
+    let lowest = tasks.iter().map(|(_, priority)| *priority).min()?;
+    let highest = tasks.iter().map(|(_, priority)| *priority).max()?;
+    if lowest == highest {
+        return None;
+    }
+
+    let priority = |name: &Ident| util::logical_priority(name, app, analysis);
+
+    let spawners = analysis
+        .spawner_tasks
+        .iter()
+        .filter(|(name, _)| tasks.iter().any(|(task, _)| task == name))
+        .map(|(name, id)| {
+            let cfgs = &app.software_tasks[name].cfgs;
+            let priority = priority(name);
+
+            quote!(
+                #(#cfgs)*
+                #id => #priority,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let broadcasts = analysis
+        .broadcasts
+        .iter()
+        .enumerate()
+        .filter(|(_, broadcast)| broadcast.receiver == core && broadcast.priority == level)
+        .map(|(id, broadcast)| {
+            let id = id as u8;
+            let priority = broadcast
+                .tasks
+                .iter()
+                .map(priority)
+                .max()
+                .unwrap_or(highest);
+
+            quote!(#id => #priority,)
+        })
+        .collect::<Vec<_>>();
+
+    let messages = analysis
+        .channels
+        .get(&core)
+        .and_then(|dispatchers| dispatchers.get(&level))
+        .map(|channels| {
+            let t = util::spawn_t_ident(core, level);
+            let arms = channels.iter().flat_map(|(&sender, channel)| {
+                let t = t.clone();
+                channel.tasks.iter().map(move |name| {
+                    let cfgs = &app.software_tasks[name].cfgs;
+                    let variant = util::task_ident(name, sender);
+                    let priority = priority(name);
+
+                    quote!(
+                        #(#cfgs)*
+                        #t::#variant => #priority,
+                    )
+                })
+            });
+
+            quote!(
+                let task: #t = core::mem::transmute((si_value >> 24) as u8);
+                match task {
+                    #(#arms)*
+                }
+            )
+        })
+        .unwrap_or_else(|| quote!(#highest));
+
+    Some(quote!(
+        if si.siginfo.si_code != rtfm::export::SI_QUEUE {
+            return #highest;
+        }
+
+        let si_value = si.siginfo.sifields.rt.sigval.sival_ptr;
+        if si_value & rtfm::export::SPAWNER_EVENT != 0 {
+            return match si_value as u8 {
+                #(#spawners)*
+                _ => #highest,
+            };
+        }
+
+        if si_value & rtfm::export::BROADCAST_EVENT != 0 {
+            return match (si_value >> 24) as u8 {
+                #(#broadcasts)*
+                _ => #highest,
+            };
+        }
+
+        // file descriptor, external, shared memory and deferred releases
+        if si_value & (0xff << 16) != 0 {
+            return #highest;
+        }
+
+        #messages
+    ))
+}
@@ -0,0 +1,222 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::{analyze::Priority, ast::App, Core};
+use syn::Ident;
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// `SCHED_FIFO` priority of the epoll thread when `epoll_priority` is not specified
+pub const EPOLL_PRIORITY: u8 = 2;
+
+/// Stack size of the epoll thread, in bytes
+const EPOLL_STACK_SIZE: usize = 64 * 1024;
+
+/// `EPOLLIN`
+const READABLE: u32 = 0x001;
+
+/// Creates the epoll instance and the thread that forwards readiness events to the dispatchers
+pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    if analysis.fd_tasks.is_empty() {
+        return (const_app, stmts);
+    }
+
+    const_app.push(quote!(
+        /// epoll instance that watches the file descriptors bound to tasks
+        static EPFD: rtfm::export::Fd = rtfm::export::Fd::uninit();
+    ));
+
+    let mut arms = vec![];
+    for (name, &id) in &analysis.fd_tasks {
+        let task = &app.software_tasks[name];
+        let receiver = task.args.core;
+
+        let fd = util::fd_ident(name);
+        let doc = format!("File descriptor bound to `{}`", name);
+        let cfgs = &task.cfgs;
+        const_app.push(quote!(
+            #[doc = #doc]
+            #(#cfgs)*
+            static #fd: rtfm::export::Fd = rtfm::export::Fd::uninit();
+        ));
+
+        let signo = analysis.signals[&receiver].map[&task.args.priority];
+        let tid = if util::process_directed(app, analysis) {
+            quote!(None)
+        } else {
+            let tid = util::tid_ident(receiver);
+            quote!(Some(#tid.get()))
+        };
+
+        arms.push(quote!(
+            #(#cfgs)*
+            #id => rtfm::export::enqueue_fd_event(tgid, #tid, #signo, #id),
+        ));
+    }
+
+    let priority = analysis
+        .extensions
+        .app
+        .epoll_priority
+        .unwrap_or(EPOLL_PRIORITY);
+
+    const_app.push(quote!(
+        /// Forwards the readiness of the bound file descriptors to the task dispatchers
+        extern "C" fn epoll() -> ! {
+            unsafe {
+                rtfm::export::register_thread(Some("rtfm-epoll"), None, Some(0), #priority);
+                rtfm::export::fault_stack().unwrap_or_else(rtfm::export::fatal);
+
+                let tgid = TGID.get();
+                let epfd = EPFD.get();
+
+                loop {
+                    let id = rtfm::export::epoll_wait(epfd);
+
+                    // stop feeding the dispatchers so that they can drain
+                    if rtfm::export::shutting_down() {
+                        continue;
+                    }
+
+                    match id {
+                        #(#arms)*
+                        _ => {}
+                    }
+                }
+            }
+        }
+    ));
+
+    stmts.push(quote!(
+        EPFD.init(rtfm::export::epoll_create().unwrap_or_else(rtfm::export::fatal));
+
+        // NOTE the epoll thread inherits the signal mask of this thread so it will never run any
+        // of the signal handlers
+        let tid = rtfm::export::spawn(epoll, #EPOLL_STACK_SIZE, "the epoll thread")
+            .unwrap_or_else(rtfm::export::fatal);
+        rtfm::export::set_priority(tid, #priority).unwrap_or_else(rtfm::export::fatal);
+    ));
+
+    (const_app, stmts)
+}
+
+/// Adds the file descriptors bound to the tasks of `core` to the epoll instance
+///
+/// NOTE this must run after the late resources of `core` have been initialized
+pub fn register(core: Core, app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    analysis
+        .fd_tasks
+        .iter()
+        .filter(|(name, _)| app.software_tasks[*name].args.core == core)
+        .map(|(name, &id)| {
+            let resource = analysis.extensions.tasks[name]
+                .binds_fd
+                .as_ref()
+                .expect("UNREACHABLE");
+            let resource = if app.late_resources.contains_key(resource) {
+                quote!(&*#resource.as_ptr())
+            } else {
+                quote!(&#resource)
+            };
+
+            let fd = util::fd_ident(name);
+            let events = events(name, analysis);
+            let cfgs = &app.software_tasks[name].cfgs;
+            quote!(
+                #(#cfgs)*
+                #fd.init(rtfm::export::AsRawFd::as_raw_fd(#resource));
+                #(#cfgs)*
+                rtfm::export::epoll_add(EPFD.get(), #fd.get(), #events, #id)
+                    .unwrap_or_else(rtfm::export::fatal);
+            )
+        })
+        .collect()
+}
+
+/// Runs the tasks bound to file descriptors that are dispatched at priority `level`
+///
+/// Returns `None` if there are no such tasks
+pub fn dispatch(
+    core: Core,
+    level: Priority,
+    app: &App,
+    analysis: &Analysis,
+) -> Option<TokenStream2> {
+    let arms = tasks(core, level, app, analysis)
+        .map(|(name, id)| {
+            let (let_instant, instant) = if app.uses_schedule(core) {
+                let monotonic = util::monotonic(analysis);
+                (
+                    Some(quote!(let instant = <#monotonic as rtfm::Monotonic>::now();)),
+                    Some(quote!(, instant)),
+                )
+            } else {
+                (None, None)
+            };
+
+            let call = util::run_task(
+                name,
+                core,
+                app,
+                analysis,
+                quote!(#name(#name::Locals::new(), #name::Context::new(priority #instant))),
+            );
+
+            let fd = util::fd_ident(name);
+            let events = events(name, analysis);
+            let cfgs = &app.software_tasks[name].cfgs;
+            quote!(
+                #(#cfgs)*
+                #id => {
+                    #let_instant
+                    #call
+
+                    // the file descriptor was registered as one-shot; watch it again
+                    rtfm::export::epoll_rearm(EPFD.get(), #fd.get(), #events, #id);
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if arms.is_empty() {
+        return None;
+    }
+
+    Some(quote!(
+        if si_value & rtfm::export::FD_EVENT != 0 {
+            let priority = &rtfm::export::Priority::new(PRIORITY);
+            match si_value as u8 {
+                #(#arms)*
+                _ => {}
+            }
+
+            return;
+        }
+    ))
+}
+
+fn tasks<'a>(
+    core: Core,
+    level: Priority,
+    app: &'a App,
+    analysis: &'a Analysis,
+) -> impl Iterator<Item = (&'a Ident, u8)> {
+    analysis.fd_tasks.iter().filter_map(move |(name, &id)| {
+        let task = &app.software_tasks[name];
+
+        if task.args.core == core && task.args.priority == level {
+            Some((name, id))
+        } else {
+            None
+        }
+    })
+}
+
+fn events(name: &Ident, analysis: &Analysis) -> u32 {
+    analysis.extensions.tasks[name]
+        .fd_events
+        .map(|(events, _)| events)
+        .unwrap_or(READABLE)
+}
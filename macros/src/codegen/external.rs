@@ -0,0 +1,144 @@
+use std::fmt::Write;
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::{analyze::Priority, ast::App, Core};
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Runs the tasks that other processes spawned at priority `level`
+///
+/// Returns `None` if there are no such tasks
+pub fn dispatch(
+    core: Core,
+    level: Priority,
+    app: &App,
+    analysis: &Analysis,
+) -> Option<TokenStream2> {
+    let tasks = analysis
+        .external_tasks
+        .iter()
+        .filter(|(name, _)| {
+            let task = &app.software_tasks[*name];
+
+            task.args.core == core && task.args.priority == level
+        })
+        .collect::<Vec<_>>();
+
+    let arms = tasks
+        .iter()
+        .map(|(name, id)| {
+            let task = &app.software_tasks[*name];
+
+            let (let_instant, instant) = if app.uses_schedule(core) {
+                let monotonic = util::monotonic(analysis);
+                (
+                    Some(quote!(let instant = <#monotonic as rtfm::Monotonic>::now();)),
+                    Some(quote!(, instant)),
+                )
+            } else {
+                (None, None)
+            };
+
+            // NOTE the payload is the only input an external task can take
+            let payload = if task.inputs.is_empty() {
+                None
+            } else {
+                Some(quote!(, payload))
+            };
+
+            let call = util::run_task(
+                name,
+                core,
+                app,
+                analysis,
+                quote!(#name(
+                    #name::Locals::new(),
+                    #name::Context::new(priority #instant)
+                    #payload
+                )),
+            );
+
+            let cfgs = &task.cfgs;
+            quote!(
+                #(#cfgs)*
+                #id => {
+                    #let_instant
+                    #call
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if arms.is_empty() {
+        return None;
+    }
+
+    let let_payload = if tasks
+        .iter()
+        .any(|(name, _)| !app.software_tasks[*name].inputs.is_empty())
+    {
+        Some(quote!(let payload = (si_value >> 8) as u8;))
+    } else {
+        None
+    };
+
+    // NOTE anyone allowed to signal this process can send anything so unknown IDs are ignored
+    Some(quote!(
+        if si_value & rtfm::external::EVENT != 0 {
+            let priority = &rtfm::export::Priority::new(PRIORITY);
+            #let_payload
+            match si_value as u8 {
+                #(#arms)*
+                _ => {}
+            }
+
+            return;
+        }
+    ))
+}
+
+/// C header that describes the tasks other processes can spawn
+pub fn header(app: &App, analysis: &Analysis) -> String {
+    let mut header = String::new();
+
+    // NOTE `SIGRTMIN` is not used because the C library reserves, and hides, the first few real
+    // time signals; the runtime's own base, `rtfm::export::SIGRTMIN`, depends on the target
+    let _ = writeln!(
+        header,
+        "/* Generated by `#[rtfm::app]`; tasks that can be spawned from other processes */\n\
+         \n\
+         #ifndef RTFM_EXTERNAL_H\n\
+         #define RTFM_EXTERNAL_H\n\
+         \n\
+         /* first real-time signal of the runtime; bionic reserves the first 9 of the kernel */\n\
+         #ifdef __ANDROID__\n\
+         #define RTFM_SIGRTMIN 41\n\
+         #else\n\
+         #define RTFM_SIGRTMIN 32\n\
+         #endif\n\
+         \n\
+         /* `sival_int` that spawns the task with ID `id` and `payload` as its input */\n\
+         #define RTFM_SIGVAL(id, payload) \
+         ((int)(0x{:x} | (((payload) & 0xff) << 8) | ((id) & 0xff)))\n",
+        1 << 18
+    );
+
+    for (name, id) in &analysis.external_tasks {
+        let task = &app.software_tasks[name];
+        let signo = analysis.signals[&task.args.core].map[&task.args.priority];
+        let upper = name.to_string().to_uppercase();
+
+        let _ = writeln!(
+            header,
+            "/* `{}`; send `RTFM_SIGVAL(RTFM_{}_ID, payload)` with `RTFM_{}_SIGNAL` */\n\
+             #define RTFM_{}_SIGNAL (RTFM_SIGRTMIN + {})\n\
+             #define RTFM_{}_ID {}\n",
+            name, upper, upper, upper, signo, upper, id
+        );
+    }
+
+    header.push_str("#endif /* RTFM_EXTERNAL_H */\n");
+
+    header
+}
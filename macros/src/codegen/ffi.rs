@@ -0,0 +1,150 @@
+use std::fmt::Write;
+
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, ToTokens};
+use rtfm_syntax::ast::App;
+use syn::{Ident, Type};
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Generates the `extern "C"` functions that spawn the FFI tasks
+///
+/// These go through the `Spawner` of the task so C code can call them from any thread
+pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    tasks(analysis)
+        .map(|name| {
+            let task = &app.software_tasks[name];
+            let (args, tupled, _, _) = util::regroup_inputs(&task.inputs);
+
+            let function = symbol(name);
+            let doc = format!(
+                "Spawns `{}` from C; returns `0` on success and `-1` if it has too many messages \
+                 pending or the application is shutting down",
+                name
+            );
+            let cfgs = &task.cfgs;
+            quote!(
+                #[doc = #doc]
+                #(#cfgs)*
+                #[no_mangle]
+                pub extern "C" fn #function(#(#args),*) -> i32 {
+                    match #name::spawner().spawn(#tupled) {
+                        Ok(()) => 0,
+                        Err(_) => -1,
+                    }
+                }
+            )
+        })
+        .collect()
+}
+
+/// C header that declares the functions that spawn the FFI tasks
+pub fn header(app: &App, analysis: &Analysis) -> String {
+    let mut header = String::new();
+
+    let _ = writeln!(
+        header,
+        "/* Generated by `#[rtfm::app]`; tasks that can be spawned from C */\n\
+         \n\
+         #ifndef RTFM_FFI_H\n\
+         #define RTFM_FFI_H\n\
+         \n\
+         #include <stdbool.h>\n\
+         #include <stddef.h>\n\
+         #include <stdint.h>\n\
+         \n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {{\n\
+         #endif\n"
+    );
+
+    for name in tasks(analysis) {
+        let task = &app.software_tasks[name];
+
+        let params = if task.inputs.is_empty() {
+            "void".to_owned()
+        } else {
+            task.inputs
+                .iter()
+                .enumerate()
+                .map(|(i, input)| format!("{} _{}", c_type(&input.ty), i))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let _ = writeln!(
+            header,
+            "/* spawns `{}`; returns 0 on success and -1 if it has too many messages pending */\n\
+             int32_t {}({});\n",
+            name,
+            symbol(name),
+            params
+        );
+    }
+
+    header.push_str(
+        "#ifdef __cplusplus\n\
+         }\n\
+         #endif\n\
+         \n\
+         #endif /* RTFM_FFI_H */\n",
+    );
+
+    header
+}
+
+fn tasks<'a>(analysis: &'a Analysis) -> impl Iterator<Item = &'a Ident> {
+    analysis
+        .spawner_tasks
+        .keys()
+        .filter(move |name| analysis.extensions.tasks[*name].ffi == Some(true))
+}
+
+/// e.g. `foo` -> `rtfm_spawn_foo`
+fn symbol(task: &Ident) -> Ident {
+    Ident::new(&format!("rtfm_spawn_{}", task), Span::call_site())
+}
+
+// C spelling of `ty`; types that are not primitives keep their Rust name so C code has to declare
+// a matching (`#[repr(C)]`) type
+//
+// NOTE raw pointers are not `Send` so they can't be inputs of FFI tasks
+fn c_type(ty: &Type) -> String {
+    match ty {
+        Type::Path(p) if p.qself.is_none() && p.path.segments.len() == 1 => {
+            let ident = p.path.segments[0].ident.to_string();
+            match &*ident {
+                "bool" => "bool",
+                "i8" => "int8_t",
+                "i16" => "int16_t",
+                "i32" => "int32_t",
+                "i64" => "int64_t",
+                "isize" => "ptrdiff_t",
+                "u8" => "uint8_t",
+                "u16" => "uint16_t",
+                "u32" => "uint32_t",
+                "u64" => "uint64_t",
+                "usize" => "size_t",
+                "f32" => "float",
+                "f64" => "double",
+                "c_char" => "char",
+                "c_schar" => "signed char",
+                "c_uchar" => "unsigned char",
+                "c_short" => "short",
+                "c_ushort" => "unsigned short",
+                "c_int" => "int",
+                "c_uint" => "unsigned int",
+                "c_long" => "long",
+                "c_ulong" => "unsigned long",
+                "c_longlong" => "long long",
+                "c_ulonglong" => "unsigned long long",
+                "c_float" => "float",
+                "c_double" => "double",
+                _ => return ident,
+            }
+            .to_owned()
+        }
+
+        _ => ty.into_token_stream().to_string(),
+    }
+}
@@ -0,0 +1,347 @@
+use std::fmt::Write;
+
+use quote::ToTokens;
+use rtfm_syntax::{analyze::Ownership, ast::App, Context, Core};
+use syn::Ident;
+
+use crate::{
+    analyze::{self, Analysis, Timing},
+    codegen::util,
+};
+
+/// The task and resource graph of the application as JSON
+///
+/// ``` text
+/// {
+///   "cores": 1,
+///   "contexts": [
+///     { "name": "foo", "kind": "task", "core": 0, "priority": 1, "capacity": 2,
+///       "resources": ["X"], "wcet": 200000, "period": 10000000, "deadline": 10000000,
+///       "response_time": 700000, "schedulable": true }
+///   ],
+///   "resources": [{ "name": "X", "type": "u32", "core": 0, "ceiling": 2 }],
+///   "edges": [{ "from": "init", "to": "foo", "kind": "spawn" }],
+///   "utilization": [{ "core": 0, "priority": 1, "utilization": 0.02 }]
+/// }
+/// ```
+///
+/// `priority` and `capacity` are `null` for `init`; `capacity` is also `null` for `idle`. The
+/// timing parameters, in nanoseconds, and `schedulable` are `null` for the contexts without a
+/// `wcet`; `response_time` is also `null` if it can exceed the deadline. The `ceiling` of a
+/// resource that doesn't need a lock, and the `core` of one that is shared between cores, are
+/// `null`. `kind` is `spawn`, `schedule` or `then`
+pub fn json(app: &App, analysis: &Analysis) -> String {
+    let graph = Graph::new(app, analysis);
+    let mut json = String::new();
+
+    let _ = writeln!(
+        json,
+        "{{\n  \"cores\": {},\n  \"contexts\": [",
+        app.args.cores
+    );
+    for (i, ctxt) in graph.contexts.iter().enumerate() {
+        let _ = writeln!(
+            json,
+            "    {{ \"name\": {}, \"kind\": \"{}\", \"core\": {}, \"priority\": {}, \
+             \"capacity\": {}, \"resources\": [{}], \"wcet\": {}, \"period\": {}, \
+             \"deadline\": {}, \"response_time\": {}, \"schedulable\": {} }}{}",
+            string(&ctxt.name.to_string()),
+            ctxt.kind,
+            ctxt.core,
+            or_null(ctxt.priority),
+            or_null(ctxt.capacity),
+            ctxt.resources
+                .iter()
+                .map(|name| string(&name.to_string()))
+                .collect::<Vec<_>>()
+                .join(", "),
+            or_null(ctxt.timing.map(|timing| timing.wcet)),
+            or_null(ctxt.timing.map(|timing| timing.period)),
+            or_null(ctxt.timing.map(|timing| timing.deadline)),
+            or_null(ctxt.timing.and_then(|timing| timing.response)),
+            or_null(ctxt.timing.map(|timing| timing.response.is_some())),
+            comma(i, graph.contexts.len()),
+        );
+    }
+
+    json.push_str("  ],\n  \"resources\": [\n");
+    for (i, res) in graph.resources.iter().enumerate() {
+        let _ = writeln!(
+            json,
+            "    {{ \"name\": {}, \"type\": {}, \"core\": {}, \"ceiling\": {} }}{}",
+            string(&res.name.to_string()),
+            string(&res.ty),
+            or_null(res.core),
+            or_null(res.ceiling),
+            comma(i, graph.resources.len()),
+        );
+    }
+
+    json.push_str("  ],\n  \"edges\": [\n");
+    for (i, edge) in graph.edges.iter().enumerate() {
+        let _ = writeln!(
+            json,
+            "    {{ \"from\": {}, \"to\": {}, \"kind\": \"{}\" }}{}",
+            string(&edge.from.to_string()),
+            string(&edge.to.to_string()),
+            edge.kind,
+            comma(i, graph.edges.len()),
+        );
+    }
+
+    json.push_str("  ],\n  \"utilization\": [\n");
+    let levels = (0..app.args.cores)
+        .flat_map(|core| {
+            analyze::utilization(core, app, analysis)
+                .into_iter()
+                .map(move |(priority, utilization)| (core, priority, utilization))
+        })
+        .collect::<Vec<_>>();
+    for (i, (core, priority, utilization)) in levels.iter().enumerate() {
+        let _ = writeln!(
+            json,
+            "    {{ \"core\": {}, \"priority\": {}, \"utilization\": {} }}{}",
+            core,
+            priority,
+            utilization,
+            comma(i, levels.len()),
+        );
+    }
+
+    json.push_str("  ]\n}\n");
+
+    json
+}
+
+/// The task and resource graph of the application as a Graphviz digraph
+///
+/// Contexts are boxes and resources are ellipses. `spawn` edges are solid, `schedule` edges are
+/// dashed, `then` edges are bold and resource accesses are dotted
+pub fn dot(app: &App, analysis: &Analysis) -> String {
+    let graph = Graph::new(app, analysis);
+    let mut dot = String::new();
+
+    dot.push_str("// Generated by `#[rtfm::app]`\ndigraph app {\n");
+
+    for core in 0..app.args.cores {
+        let _ = writeln!(
+            dot,
+            "  subgraph cluster_{} {{\n    label = \"core #{}\";",
+            core, core
+        );
+
+        for ctxt in graph.contexts.iter().filter(|ctxt| ctxt.core == core) {
+            let mut label = format!("{}\\n{}", ctxt.name, ctxt.kind);
+            if let Some(priority) = ctxt.priority {
+                let _ = write!(label, ", priority = {}", priority);
+            }
+            if let Some(capacity) = ctxt.capacity {
+                let _ = write!(label, ", capacity = {}", capacity);
+            }
+
+            let _ = writeln!(
+                dot,
+                "    \"{}\" [shape = box, label = \"{}\"];",
+                ctxt.name, label
+            );
+        }
+
+        dot.push_str("  }\n");
+    }
+
+    for res in &graph.resources {
+        let label = match res.ceiling {
+            Some(ceiling) => format!("{}\\nceiling = {}", res.name, ceiling),
+            None => res.name.to_string(),
+        };
+
+        let _ = writeln!(
+            dot,
+            "  \"{}\" [shape = ellipse, label = \"{}\"];",
+            res.name, label
+        );
+    }
+
+    for edge in &graph.edges {
+        let style = match edge.kind {
+            "schedule" => "dashed",
+            "then" => "bold",
+            _ => "solid",
+        };
+
+        let _ = writeln!(
+            dot,
+            "  \"{}\" -> \"{}\" [style = {}, label = \"{}\"];",
+            edge.from, edge.to, style, edge.kind
+        );
+    }
+
+    for ctxt in &graph.contexts {
+        for res in &ctxt.resources {
+            let _ = writeln!(
+                dot,
+                "  \"{}\" -> \"{}\" [style = dotted, arrowhead = none];",
+                ctxt.name, res
+            );
+        }
+    }
+
+    dot.push_str("}\n");
+
+    dot
+}
+
+struct Graph<'a> {
+    contexts: Vec<Node<'a>>,
+    resources: Vec<Resource<'a>>,
+    edges: Vec<Edge<'a>>,
+}
+
+struct Node<'a> {
+    name: &'a Ident,
+    kind: &'static str,
+    core: Core,
+    priority: Option<u8>,
+    capacity: Option<u16>,
+    resources: Vec<&'a Ident>,
+    timing: Option<&'a Timing>,
+}
+
+struct Resource<'a> {
+    name: &'a Ident,
+    ty: String,
+    core: Option<Core>,
+    ceiling: Option<u8>,
+}
+
+struct Edge<'a> {
+    from: &'a Ident,
+    to: &'a Ident,
+    kind: &'static str,
+}
+
+impl<'a> Graph<'a> {
+    fn new(app: &'a App, analysis: &'a Analysis) -> Self {
+        let contexts = app
+            .inits
+            .keys()
+            .map(|&core| Context::Init(core))
+            .chain(app.idles.keys().map(|&core| Context::Idle(core)))
+            .chain(app.software_tasks.keys().map(Context::SoftwareTask))
+            .collect::<Vec<_>>();
+
+        let mut nodes = vec![];
+        let mut edges = vec![];
+        for ctxt in contexts {
+            let name = ctxt.ident(app);
+            let (kind, core, priority, capacity, resources, spawn, schedule) = match ctxt {
+                Context::Init(core) => {
+                    let args = &app.inits[&core].args;
+                    (
+                        "init",
+                        core,
+                        None,
+                        None,
+                        &args.resources,
+                        &args.spawn,
+                        &args.schedule,
+                    )
+                }
+                Context::Idle(core) => {
+                    let args = &app.idles[&core].args;
+                    (
+                        "idle",
+                        core,
+                        Some(0),
+                        None,
+                        &args.resources,
+                        &args.spawn,
+                        &args.schedule,
+                    )
+                }
+                Context::SoftwareTask(name) => {
+                    let args = &app.software_tasks[name].args;
+                    (
+                        "task",
+                        args.core,
+                        Some(args.priority),
+                        Some(util::capacity(name, app, analysis)),
+                        &args.resources,
+                        &args.spawn,
+                        &args.schedule,
+                    )
+                }
+                Context::HardwareTask(_) => unreachable!(),
+            };
+
+            let then = analysis
+                .extensions
+                .tasks
+                .get(name)
+                .and_then(|args| args.then.as_ref());
+            for to in spawn {
+                let kind = if then == Some(to) { "then" } else { "spawn" };
+                edges.push(Edge {
+                    from: name,
+                    to,
+                    kind,
+                });
+            }
+
+            for to in schedule {
+                edges.push(Edge {
+                    from: name,
+                    to,
+                    kind: "schedule",
+                });
+            }
+
+            nodes.push(Node {
+                name,
+                kind,
+                core,
+                priority,
+                capacity,
+                resources: resources.iter().collect(),
+                timing: analysis.timings.get(name),
+            });
+        }
+
+        let resources = app
+            .resources(analysis)
+            .map(|(name, res, _, loc)| Resource {
+                name,
+                ty: res.ty.clone().into_token_stream().to_string(),
+                core: loc.core(),
+                ceiling: match analysis.ownerships.get(name) {
+                    Some(Ownership::Shared { ceiling }) => Some(*ceiling),
+                    _ => None,
+                },
+            })
+            .collect();
+
+        Graph {
+            contexts: nodes,
+            resources,
+            edges,
+        }
+    }
+}
+
+// JSON string literal
+fn string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn or_null(n: Option<impl ToString>) -> String {
+    n.map(|n| n.to_string())
+        .unwrap_or_else(|| String::from("null"))
+}
+
+fn comma(i: usize, len: usize) -> &'static str {
+    if i + 1 == len {
+        ""
+    } else {
+        ","
+    }
+}
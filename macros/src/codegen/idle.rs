@@ -5,7 +5,8 @@ use rtfm_syntax::{ast::App, Context};
 
 use crate::{
     analyze::Analysis,
-    codegen::{locals, module, resources_struct},
+    codegen::{locals, lock_groups, module, resources_struct},
+    parse::IdlePolicy,
 };
 
 pub fn codegen(
@@ -37,7 +38,8 @@ pub fn codegen(
     for (&core, idle) in &app.idles {
         let mut needs_lt = false;
 
-        if !idle.args.resources.is_empty() {
+        let has_resources = !idle.args.resources.is_empty() || analysis.extensions.model.is_some();
+        if has_resources {
             let (item, constructor) =
                 resources_struct::codegen(Context::Idle(core), 0, &mut needs_lt, app, analysis);
 
@@ -47,34 +49,39 @@ pub fn codegen(
 
         let name = &idle.name;
         if core == 0 {
-            call_idle = quote!(#name(
-                #name::Locals::new(),
-                #name::Context::new(&rtfm::export::Priority::new(0))
-            ));
+            let demote = demote(core, app, analysis);
+            call_idle = quote!(
+                #demote
+                #name(
+                    #name::Locals::new(),
+                    #name::Context::new(&rtfm::export::Priority::new(0))
+                )
+            );
         }
 
         let attrs = &idle.attrs;
         let context = &idle.context;
         let (locals, locals_pat) = locals::codegen(Context::Idle(core), &idle.locals, app);
         idle_locals.push(locals);
-        let stmts = &idle.stmts;
+        let stmts = lock_groups::rewrite(Context::Idle(core), 0, &idle.stmts, app, analysis);
         user_idle.push(quote!(
             #(#attrs)*
             #[allow(non_snake_case)]
             fn #name(#locals_pat, #context: #name::Context) -> ! {
                 use rtfm::Mutex as _;
 
-                #(#stmts)*
+                #stmts
             }
         ));
 
         mod_idle.push(module::codegen(
             Context::Idle(core),
-            (!idle.args.resources.is_empty(), needs_lt),
+            (has_resources, needs_lt),
             !idle.args.schedule.is_empty(),
             !idle.args.spawn.is_empty(),
             false,
             app,
+            analysis,
         ));
     }
 
@@ -87,3 +94,19 @@ pub fn codegen(
         call_idle,
     )
 }
+
+/// Moves the thread of the `idle` of `core` off `SCHED_FIFO`, if it has a `policy`; this must
+/// run after the threads of the priority levels have been spawned because they inherit the policy
+pub fn demote(core: u8, app: &App, analysis: &Analysis) -> Option<TokenStream2> {
+    let idle = app.idles.get(&core)?;
+    let (policy, _) = analysis.extensions.idles.get(&idle.name)?.policy?;
+
+    let policy = match policy {
+        IdlePolicy::Idle => quote!(rtfm::export::IdlePolicy::Idle),
+        IdlePolicy::Other => quote!(rtfm::export::IdlePolicy::Other),
+    };
+
+    Some(quote!(
+        rtfm::export::demote_idle(#policy).unwrap_or_else(rtfm::export::fatal);
+    ))
+}
@@ -38,7 +38,8 @@ pub fn codegen(
         let mut needs_lt = false;
         let name = &init.name;
 
-        if !init.args.resources.is_empty() {
+        let has_resources = !init.args.resources.is_empty() || analysis.extensions.model.is_some();
+        if has_resources {
             let (item, constructor) =
                 resources_struct::codegen(Context::Init(core), 0, &mut needs_lt, app, analysis);
 
@@ -58,9 +59,14 @@ pub fn codegen(
                 resources
                     .iter()
                     .map(|name| {
-                        let ty = &app.late_resources[name].ty;
+                        let res = &app.late_resources[name];
+                        let cfgs = &res.cfgs;
+                        let ty = &res.ty;
 
-                        quote!(pub #name: #ty)
+                        quote!(
+                            #(#cfgs)*
+                            pub #name: #ty
+                        )
                     })
                     .collect::<Vec<_>>()
             })
@@ -97,11 +103,12 @@ pub fn codegen(
 
         mod_init.push(module::codegen(
             Context::Init(core),
-            (!init.args.resources.is_empty(), needs_lt),
+            (has_resources, needs_lt),
             !init.args.schedule.is_empty(),
             !init.args.spawn.is_empty(),
             has_late_resources,
             app,
+            analysis,
         ));
     }
 
@@ -0,0 +1,72 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::ast::App;
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Creates the tables returned by `rtfm::introspect`
+pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    if !util::introspect(analysis) {
+        return (const_app, stmts);
+    }
+
+    // NOTE `util::claim_slot` and `util::release_slot` index this table using the position of the
+    // task in `software_tasks`
+    let tasks = app
+        .software_tasks
+        .iter()
+        .map(|(name, task)| {
+            let capacity = analysis
+                .free_queues
+                .get(name)
+                .map(|senders| util::capacity(name, app, analysis) * senders.len() as u16)
+                .unwrap_or(0);
+            let name = name.to_string();
+            let priority = task.args.priority;
+            let core = task.args.core;
+
+            quote!(rtfm::export::TaskInfo::new(#name, #priority, #core, #capacity))
+        })
+        .collect::<Vec<_>>();
+    let ntasks = tasks.len();
+    const_app.push(quote!(
+        /// Message queues of the software tasks
+        static TASK_INFO: [rtfm::export::TaskInfo; #ntasks] = [#(#tasks,)*];
+    ));
+
+    let mut timer_queues = vec![];
+    for &sender in analysis.timer_queues.keys() {
+        let walls = if util::wall_clock(analysis) {
+            &[false, true][..]
+        } else {
+            &[false][..]
+        };
+
+        for &wall in walls {
+            let info = util::tq_info_ident(sender, wall);
+            let doc = format!("Length and head of the {} timer queue", info);
+            const_app.push(quote!(
+                #[doc = #doc]
+                static #info: rtfm::export::TimerQueueInfo =
+                    rtfm::export::TimerQueueInfo::new(#sender, #wall);
+            ));
+
+            timer_queues.push(quote!(&#info));
+        }
+    }
+    let ntimer_queues = timer_queues.len();
+    const_app.push(quote!(
+        /// Timer queues of the cores
+        static TIMER_QUEUE_INFO: [&rtfm::export::TimerQueueInfo; #ntimer_queues] =
+            [#(#timer_queues,)*];
+    ));
+
+    stmts.push(quote!(
+        rtfm::export::init_introspection(&TASK_INFO, &TIMER_QUEUE_INFO);
+    ));
+
+    (const_app, stmts)
+}
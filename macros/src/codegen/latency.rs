@@ -0,0 +1,79 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::ast::App;
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// `SCHED_FIFO` priority of the probe threads; above every task, below the watchdog
+pub const PROBE_PRIORITY: u8 = 98;
+
+/// Stack size of a probe thread, in bytes
+const PROBE_STACK_SIZE: usize = 64 * 1024;
+
+/// Creates the histograms and one probe thread per core
+pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    let period = if let Some(nanos) = analysis.extensions.app.latency_probe {
+        nanos
+    } else {
+        return (const_app, stmts);
+    };
+
+    let cores = 0..app.args.cores;
+    let len = usize::from(app.args.cores);
+    const_app.push(quote!(
+        /// Wakeup-latency histograms of the cores
+        static LATENCY: [rtfm::export::LatencyHistogram; #len] = [
+            #(rtfm::export::LatencyHistogram::new(#cores),)*
+        ];
+    ));
+
+    stmts.push(quote!(
+        rtfm::export::init_latency_probe(&LATENCY);
+    ));
+
+    for core in 0..app.args.cores {
+        let probe = util::latency_probe_ident(core);
+        let index = usize::from(core);
+        let name = format!("rtfm-probe-{}", core);
+        let doc = format!("Measures the wakeup latency of core #{}", core);
+        let thread = format!("the latency probe of core #{}", core);
+
+        const_app.push(quote!(
+            #[doc = #doc]
+            extern "C" fn #probe() -> ! {
+                unsafe {
+                    rtfm::export::register_thread(Some(#name), None, Some(#core), #PROBE_PRIORITY);
+                    rtfm::export::fault_stack().unwrap_or_else(rtfm::export::fatal);
+
+                    rtfm::export::run_latency_probe(
+                        &LATENCY[#index],
+                        core::time::Duration::from_nanos(#period),
+                    )
+                }
+            }
+        ));
+
+        stmts.push(quote!(
+            // NOTE the probe thread inherits the signal mask of this thread so it will never run
+            // any of the signal handlers
+            let tid = rtfm::export::spawn(#probe, #PROBE_STACK_SIZE, #thread)
+                .unwrap_or_else(rtfm::export::fatal);
+            rtfm::export::set_affinity(tid, #core).unwrap_or_else(rtfm::export::fatal);
+            rtfm::export::set_priority(tid, #PROBE_PRIORITY).unwrap_or_else(rtfm::export::fatal);
+        ));
+    }
+
+    (const_app, stmts)
+}
+
+/// Writes the histograms to stderr on shutdown
+pub fn dump(analysis: &Analysis) -> Vec<TokenStream2> {
+    if analysis.extensions.app.latency_probe.is_some() {
+        vec![quote!(rtfm::export::dump_latency_probe();)]
+    } else {
+        vec![]
+    }
+}
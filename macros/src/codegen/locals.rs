@@ -60,13 +60,16 @@ pub fn codegen(
         values.push(quote!(__marker__: core::marker::PhantomData));
     }
 
+    let ctxt_cfgs = util::cfgs(ctxt, app);
     let locals = quote!(
+        #(#ctxt_cfgs)*
         #[allow(non_snake_case)]
         #[doc(hidden)]
         pub struct #ident<#lt> {
             #(#fields),*
         }
 
+        #(#ctxt_cfgs)*
         impl<#lt> #ident<#lt> {
             #[inline(always)]
             unsafe fn new() -> Self {
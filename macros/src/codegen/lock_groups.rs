@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use proc_macro2::{Delimiter, Group, Ident, TokenStream as TokenStream2, TokenTree};
+use quote::quote;
+use rtfm_syntax::{analyze::Ownership, ast::App, Context};
+use syn::{IntSuffix, LitInt, Stmt};
+
+use crate::analyze::Analysis;
+
+/// The body of `context`, with its lock groups merged when the application has `lock_groups =
+/// true`
+///
+/// A `RES.lock(..)` call whose arguments lock resources with higher ceilings becomes
+/// `RES.__lock_group(CEILING, ..)`, where `CEILING` is the highest of those ceilings. `RES` is the
+/// name of a resource of the context, e.g. in `c.resources.RES.lock(..)`, or a variable that a
+/// `let x = c.resources.RES;` statement binds its proxy to. The outer `lock` then masks the
+/// signals of all the priorities of the group with a single `rt_sigprocmask` pair, and the nested
+/// `lock`s find the priority already at (or above) their ceilings. Locks inside a nested `lock`
+/// are part of the group too, whether or not they run, so the group can block higher priority
+/// tasks for longer than strictly needed
+pub fn rewrite(
+    context: Context,
+    priority: u8,
+    stmts: &[Stmt],
+    app: &App,
+    analysis: &Analysis,
+) -> TokenStream2 {
+    let stmts = quote!(#(#stmts)*);
+
+    if analysis.extensions.app.lock_groups != Some(true) {
+        return stmts;
+    }
+
+    let resources = match context {
+        Context::Init(core) => &app.inits[&core].args.resources,
+        Context::Idle(core) => &app.idles[&core].args.resources,
+        Context::HardwareTask(name) => &app.hardware_tasks[name].args.resources,
+        Context::SoftwareTask(name) => &app.software_tasks[name].args.resources,
+    };
+
+    // the resources that this context accesses through a proxy, by name
+    let ceilings = resources
+        .iter()
+        .filter_map(|name| {
+            let (res, _) = app.resource(name).expect("UNREACHABLE");
+            match analysis.ownerships.get(name) {
+                Some(Ownership::Shared { ceiling })
+                    if res.mutability.is_some() && *ceiling > priority =>
+                {
+                    Some((name.to_string(), *ceiling))
+                }
+                _ => None,
+            }
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    if ceilings.is_empty() {
+        return stmts;
+    }
+
+    let mut receivers = ceilings.clone();
+    aliases(stmts.clone(), &ceilings, &mut receivers);
+
+    walk(stmts, &receivers).0
+}
+
+// Finds the `let x = c.resources.RES;` statements in `tokens` and adds `x` to `receivers`
+//
+// NOTE this ignores scopes; a variable that shadows `x` is still taken to be the proxy of `RES`
+fn aliases(
+    tokens: TokenStream2,
+    ceilings: &BTreeMap<String, u8>,
+    receivers: &mut BTreeMap<String, u8>,
+) {
+    let tokens = tokens.into_iter().collect::<Vec<_>>();
+
+    for (i, tt) in tokens.iter().enumerate() {
+        match tt {
+            TokenTree::Group(group) => aliases(group.stream(), ceilings, receivers),
+
+            TokenTree::Ident(let_) if let_ == "let" => {
+                let stmt = tokens[i + 1..]
+                    .iter()
+                    .take_while(|tt| !is_punct(tt, ';'))
+                    .collect::<Vec<_>>();
+
+                // `let [mut] x =`
+                let skip = match stmt.first() {
+                    Some(TokenTree::Ident(mut_)) if mut_ == "mut" => 1,
+                    _ => 0,
+                };
+                let binding = match (stmt.get(skip), stmt.get(skip + 1)) {
+                    (Some(TokenTree::Ident(x)), Some(eq)) if is_punct(eq, '=') => x,
+                    _ => continue,
+                };
+
+                // ends in `.RES`
+                let n = stmt.len();
+                if n >= 2 && is_punct(stmt[n - 2], '.') {
+                    if let TokenTree::Ident(res) = stmt[n - 1] {
+                        if let Some(ceiling) = ceilings.get(&res.to_string()) {
+                            receivers.insert(binding.to_string(), *ceiling);
+                        }
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+}
+
+fn is_punct(tt: &TokenTree, c: char) -> bool {
+    match tt {
+        TokenTree::Punct(punct) => punct.as_char() == c,
+        _ => false,
+    }
+}
+
+// Merges the lock groups in `tokens`; also returns the highest ceiling that `tokens` lock
+//
+// `receivers` maps the names that `lock` is called on to the ceilings of their resources
+fn walk(tokens: TokenStream2, receivers: &BTreeMap<String, u8>) -> (TokenStream2, u8) {
+    let mut out = vec![];
+    let mut highest = 0;
+
+    for tt in tokens {
+        let group = match tt {
+            TokenTree::Group(group) => group,
+            tt => {
+                out.push(tt);
+                continue;
+            }
+        };
+
+        let (mut stream, nested) = walk(group.stream(), receivers);
+        highest = highest.max(nested);
+
+        // `RES.lock(..)`
+        let n = out.len();
+        let ceiling = if group.delimiter() == Delimiter::Parenthesis && n >= 3 {
+            match (&out[n - 3], &out[n - 2], &out[n - 1]) {
+                (TokenTree::Ident(res), dot, TokenTree::Ident(method))
+                    if is_punct(dot, '.') && method == "lock" =>
+                {
+                    receivers.get(&res.to_string()).cloned()
+                }
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        if let Some(ceiling) = ceiling {
+            highest = highest.max(ceiling);
+
+            if nested > ceiling {
+                let span = out[n - 1].span();
+                out[n - 1] = TokenTree::Ident(Ident::new("__lock_group", span));
+
+                let nested = LitInt::new(u64::from(nested), IntSuffix::None, span);
+                stream = quote!(#nested, #stream);
+            }
+        }
+
+        let mut new = Group::new(group.delimiter(), stream);
+        new.set_span(group.span());
+        out.push(TokenTree::Group(new));
+    }
+
+    (out.into_iter().collect(), highest)
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeSet;
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::{analyze::Priority, ast::App, Context, Core};
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Stack size of the log writer thread, in bytes
+const LOG_STACK_SIZE: usize = 64 * 1024;
+
+/// Creates the log queues, the `Log` methods and the thread that drains the queues
+pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    let output = if let Some(output) = &analysis.extensions.app.log {
+        output
+    } else {
+        return (const_app, stmts);
+    };
+
+    let levels = levels(app);
+    for &(core, level) in &levels {
+        let log = util::log_ident(core, level);
+        let producer = util::log_producer_ident(core, level);
+        let consumer = util::log_consumer_ident(core, level);
+        let doc = format!("Log records of core #{} priority level {}", core, level);
+        const_app.push(quote!(
+            #[doc = #doc]
+            static mut #log: rtfm::export::LogQueue =
+                rtfm::export::Queue(rtfm::export::iQueue::new());
+
+            static mut #producer: core::mem::MaybeUninit<rtfm::export::LogProducer> =
+                core::mem::MaybeUninit::uninit();
+
+            static mut #consumer: core::mem::MaybeUninit<rtfm::export::LogConsumer> =
+                core::mem::MaybeUninit::uninit();
+        ));
+
+        stmts.push(quote!(
+            let (producer, consumer) = #log.split();
+            #producer.as_mut_ptr().write(producer);
+            #consumer.as_mut_ptr().write(consumer);
+        ));
+    }
+
+    // all the contexts that run at the same level (on the same core) share a log queue
+    let contexts = app
+        .inits
+        .keys()
+        .map(|&core| Context::Init(core))
+        .chain(app.idles.keys().map(|&core| Context::Idle(core)))
+        .chain(app.software_tasks.keys().map(Context::SoftwareTask));
+    for ctxt in contexts {
+        let name = ctxt.ident(app);
+        let level = match ctxt {
+            Context::SoftwareTask(name) => app.software_tasks[name].args.priority,
+            _ => 0,
+        };
+        let producer = util::log_producer_ident(ctxt.core(app), level);
+
+        let cfgs = util::cfgs(ctxt, app);
+        const_app.push(quote!(
+            #(#cfgs)*
+            impl #name::Log {
+                /// Formats `args` into a log record and queues it for the writer thread
+                ///
+                /// Returns `Err` if the record doesn't fit or the queue is full
+                pub fn write_fmt(&self, args: core::fmt::Arguments<'_>) -> Result<(), ()> {
+                    unsafe { rtfm::export::log(&mut *#producer.as_mut_ptr(), args) }
+                }
+            }
+        ));
+    }
+
+    let consumers = levels
+        .iter()
+        .map(|&(core, level)| util::log_consumer_ident(core, level))
+        .collect::<Vec<_>>();
+    const_app.push(quote!(
+        /// Where the log records go
+        static LOG_FD: rtfm::export::Fd = rtfm::export::Fd::uninit();
+
+        /// Writes the log records out
+        extern "C" fn logger() -> ! {
+            unsafe {
+                rtfm::export::register_thread(Some("rtfm-log"), None, Some(0), 0);
+                rtfm::export::fault_stack().unwrap_or_else(rtfm::export::fatal);
+
+                let fd = LOG_FD.get();
+
+                loop {
+                    let mut written = 0;
+                    #(written += rtfm::export::log_drain(fd, &mut *#consumers.as_mut_ptr());)*
+
+                    if written == 0 {
+                        rtfm::export::log_sleep();
+                    }
+                }
+            }
+        }
+    ));
+
+    let path = if output.value() == "stderr" {
+        quote!(None)
+    } else {
+        quote!(Some(#output))
+    };
+    stmts.push(quote!(
+        LOG_FD.init(rtfm::export::log_open(#path).unwrap_or_else(rtfm::export::fatal));
+
+        // NOTE the writer thread inherits the signal mask of this thread so it will never run any
+        // of the signal handlers
+        let tid = rtfm::export::spawn(logger, #LOG_STACK_SIZE, "the log writer thread")
+            .unwrap_or_else(rtfm::export::fatal);
+        rtfm::export::set_normal(tid).unwrap_or_else(rtfm::export::fatal);
+    ));
+
+    (const_app, stmts)
+}
+
+/// Waits until the writer thread has written out all the log records
+///
+/// NOTE this must run after the producers have stopped
+pub fn flush(analysis: &Analysis) -> Option<TokenStream2> {
+    analysis.extensions.app.log.as_ref()?;
+
+    Some(quote!(rtfm::export::log_flush();))
+}
+
+/// Priority levels that have a log queue; `init` and `idle` log at level 0
+fn levels(app: &App) -> BTreeSet<(Core, Priority)> {
+    app.inits
+        .keys()
+        .chain(app.idles.keys())
+        .map(|&core| (core, 0))
+        .chain(
+            app.software_tasks
+                .values()
+                .map(|task| (task.args.core, task.args.priority)),
+        )
+        .collect()
+}
@@ -0,0 +1,46 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+
+use crate::analyze::Analysis;
+
+/// Stack size of the metrics thread, in bytes
+const METRICS_STACK_SIZE: usize = 256 * 1024;
+
+/// Creates the thread that serves the metrics endpoint
+pub fn codegen(analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    let addr = if let Some(addr) = &analysis.extensions.app.metrics {
+        addr
+    } else {
+        return (const_app, stmts);
+    };
+
+    const_app.push(quote!(
+        /// Socket of the metrics endpoint
+        static METRICS_FD: rtfm::export::Fd = rtfm::export::Fd::uninit();
+
+        /// Serves the metrics of the application
+        extern "C" fn metrics() -> ! {
+            unsafe {
+                rtfm::export::register_thread(Some("rtfm-metrics"), None, Some(0), 0);
+                rtfm::export::fault_stack().unwrap_or_else(rtfm::export::fatal);
+
+                rtfm::export::serve_metrics(METRICS_FD.get())
+            }
+        }
+    ));
+
+    stmts.push(quote!(
+        METRICS_FD.init(rtfm::export::metrics_bind(#addr).unwrap_or_else(rtfm::export::fatal));
+
+        // NOTE the metrics thread inherits the signal mask of this thread so it will never run any
+        // of the signal handlers
+        let tid = rtfm::export::spawn(metrics, #METRICS_STACK_SIZE, "the metrics thread")
+            .unwrap_or_else(rtfm::export::fatal);
+        rtfm::export::set_normal(tid).unwrap_or_else(rtfm::export::fatal);
+    ));
+
+    (const_app, stmts)
+}
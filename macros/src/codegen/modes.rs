@@ -0,0 +1,171 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::ast::App;
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Generates the `Mode` enum and the `switch` method of the tasks with `mode_switch = true`
+pub fn codegen(
+    app: &App,
+    analysis: &Analysis,
+) -> (
+    // mod_app
+    Vec<TokenStream2>,
+    // const_app
+    Vec<TokenStream2>,
+) {
+    let modes = util::modes(analysis);
+    if modes.is_empty() {
+        return (vec![], vec![]);
+    }
+
+    let indices = (0..modes.len()).map(|i| i as u8).collect::<Vec<_>>();
+    let mod_app = vec![quote!(
+        /// The modes of the application
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        pub enum Mode {
+            #(#modes,)*
+        }
+
+        impl Mode {
+            /// The mode the application is in
+            pub fn current() -> Self {
+                Mode::from_index(rtfm::export::mode())
+            }
+
+            fn from_index(index: u8) -> Self {
+                match index {
+                    #(#indices => Mode::#modes,)*
+                    _ => unreachable!(),
+                }
+            }
+        }
+    )];
+
+    let mut const_app = vec![];
+    let (start, end) = util::signal_range(0, analysis);
+    for name in app
+        .software_tasks
+        .keys()
+        .filter(|name| util::mode_switch(name, analysis))
+    {
+        let cfgs = &app.software_tasks[name].cfgs;
+        const_app.push(quote!(
+            #(#cfgs)*
+            impl #name::ModeSwitch {
+                /// Moves the application to `mode` and returns the mode it was in
+                ///
+                /// The periodic tasks whose period changes are released right away. The tasks that
+                /// `mode` disables are no longer released and their `schedule`-d messages are
+                /// dropped; the instances already released still run
+                pub fn switch(&self, mode: Mode) -> Mode {
+                    unsafe {
+                        rtfm::export::free(#start..#end, || {
+                            let previous = rtfm::export::set_mode(mode as u8);
+                            if previous != mode as u8 {
+                                change_mode(previous, mode as u8);
+                            }
+
+                            Mode::from_index(previous)
+                        })
+                    }
+                }
+            }
+        ));
+    }
+
+    // only the tasks with `mode_switch = true` change modes
+    if const_app.is_empty() {
+        return (mod_app, const_app);
+    }
+
+    let mut stmts = vec![];
+
+    // re-arm the timers of the periodic tasks whose period changes; `0` disables the task
+    let monotonic = util::monotonic(analysis);
+    let mut now = None;
+    for name in analysis.periodic_tasks.keys() {
+        let periods = util::mode_periods(name, analysis);
+        if periods.iter().all(|&period| period == periods[0]) {
+            continue;
+        }
+
+        now = Some(quote!(let now = <#monotonic as rtfm::Monotonic>::now();));
+
+        let timer = util::period_timer_ident(name);
+        let len = periods.len();
+        stmts.push(quote!({
+            const PERIODS: [u64; #len] = [#(#periods),*];
+            let (old, new) = (PERIODS[usize::from(previous)], PERIODS[usize::from(mode)]);
+            if new != old {
+                if new == 0 {
+                    rtfm::export::timer_stop(#timer.get())
+                } else {
+                    rtfm::export::timer_start::<#monotonic>(#timer.get(), now, new)
+                }
+                .unwrap_or_else(rtfm::export::fatal);
+            }
+        }));
+    }
+
+    // drop the `schedule`-d messages of the disabled tasks and free their slots
+    if let Some(timer_queue) = analysis.timer_queues.get(&0) {
+        let t = util::schedule_t_ident(0);
+        let (keep, discard): (Vec<_>, Vec<_>) = timer_queue
+            .tasks
+            .iter()
+            .filter_map(|name| {
+                let mask = util::mode_mask(name, analysis)?;
+                let inputs = util::inputs_ident(name);
+                let fq = util::fq_ident_(name, 0);
+                let release = util::release_slot(name, app, analysis);
+
+                Some((
+                    quote!(#t::#name => rtfm::export::mode_enabled(#mask),),
+                    quote!(#t::#name => {
+                        core::ptr::drop_in_place(
+                            #inputs.get_unchecked_mut(usize::from(nr.index)).as_mut_ptr(),
+                        );
+                        #fq.split().0.enqueue_unchecked(nr.index);
+                        #release
+                    }),
+                ))
+            })
+            .unzip();
+
+        if !keep.is_empty() {
+            let mut tqs = vec![util::tq_ident(0)];
+            if util::wall_clock(analysis) {
+                tqs.push(util::wall_tq_ident(0));
+            }
+
+            for tq in tqs {
+                stmts.push(quote!(
+                    #tq.retain(
+                        |task| match *task {
+                            #(#keep)*
+                            _ => true,
+                        },
+                        |nr| match nr.task {
+                            #(#discard)*
+                            _ => {}
+                        },
+                    );
+                ));
+            }
+        }
+    }
+
+    const_app.push(quote!(
+        /// Re-arms the periodic tasks and quiesces the tasks that the new mode disables
+        #[allow(unreachable_patterns, unused_variables)]
+        unsafe fn change_mode(previous: u8, mode: u8) {
+            #now
+
+            #(#stmts)*
+        }
+    ));
+
+    (mod_app, const_app)
+}
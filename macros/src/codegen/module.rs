@@ -2,7 +2,10 @@ use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use rtfm_syntax::{ast::App, Context};
 
-use crate::codegen::util;
+use crate::{
+    analyze::{self, Analysis},
+    codegen::{spawner, util},
+};
 
 pub fn codegen(
     ctxt: Context,
@@ -11,10 +14,12 @@ pub fn codegen(
     spawn: bool,
     late_resources: bool,
     app: &App,
+    analysis: &Analysis,
 ) -> TokenStream2 {
     let mut items = vec![];
     let mut fields = vec![];
     let mut values = vec![];
+    let mut methods = vec![];
 
     let name = ctxt.ident(app);
 
@@ -26,7 +31,42 @@ pub fn codegen(
 
         Context::HardwareTask(_) => unreachable!(),
 
-        Context::SoftwareTask(_) => {
+        Context::SoftwareTask(name) => {
+            let task = name.to_string();
+            let task_core = if analyze::is_background(name, &analysis.extensions) {
+                quote!(None)
+            } else {
+                quote!(Some(#core))
+            };
+            items.push(quote!(
+                /// This task, for `rtfm::set_task_priority` and `rtfm::set_task_cpu`
+                pub const TASK: rtfm::Task = rtfm::Task::new(#task, #task_core);
+            ));
+
+            if util::has_schedule_handle(name, app) {
+                items.push(quote!(
+                    /// Handle to a `schedule`-d instance of this task
+                    pub struct ScheduleHandle {
+                        #[doc(hidden)]
+                        pub marker: u32,
+                        _not_send: core::marker::PhantomData<*mut ()>,
+                    }
+
+                    impl ScheduleHandle {
+                        #[doc(hidden)]
+                        #[inline(always)]
+                        pub unsafe fn new(marker: u32) -> Self {
+                            ScheduleHandle {
+                                marker,
+                                _not_send: core::marker::PhantomData,
+                            }
+                        }
+                    }
+                ));
+            }
+
+            items.extend(spawner::module(name, app, analysis));
+
             if app.uses_schedule(core) {
                 fields.push(quote!(
                     /// The time at which this task was scheduled to run
@@ -37,6 +77,55 @@ pub fn codegen(
 
                 needs_instant = true;
             }
+
+            if analysis.periodic_tasks.contains_key(name) {
+                fields.push(quote!(
+                    /// How many releases of this task were skipped, since the previous one,
+                    /// because it or a higher priority task ran for longer than its period; `0`
+                    /// if this instance was `spawn`-ed
+                    pub overruns: u32
+                ));
+
+                values.push(quote!(overruns: 0));
+
+                methods.push(quote!(
+                    #[doc(hidden)]
+                    #[inline(always)]
+                    pub fn with_overruns(self, overruns: u32) -> Self {
+                        Context { overruns, ..self }
+                    }
+                ));
+            }
+
+            if util::mode_switch(name, analysis) {
+                items.push(quote!(
+                    /// Moves the application to another mode
+                    #[derive(Clone, Copy)]
+                    pub struct ModeSwitch {
+                        _not_send: core::marker::PhantomData<*mut ()>,
+                    }
+                ));
+
+                fields.push(quote!(
+                    /// Moves the application to another mode
+                    pub mode_switch: ModeSwitch
+                ));
+
+                values.push(quote!(
+                    mode_switch: ModeSwitch { _not_send: core::marker::PhantomData }
+                ));
+            }
+
+            if let Some(index) = util::heartbeat_index(name, analysis) {
+                methods.push(quote!(
+                    /// Tells the heartbeat monitor that this task is alive; it must be called at
+                    /// least once per `heartbeat` interval
+                    #[inline(always)]
+                    pub fn heartbeat(&self) {
+                        rtfm::export::heartbeat(#index)
+                    }
+                ));
+            }
         }
     }
 
@@ -46,7 +135,81 @@ pub fn codegen(
         pub use super::#ident as Locals;
     ));
 
-    if resources.0 {
+    if ctxt.is_init() {
+        fields.push(quote!(
+            /// The command line arguments of the process
+            pub args: rtfm::Args
+        ));
+
+        values.push(quote!(args: rtfm::Args::new()));
+
+        fields.push(quote!(
+            /// The environment the process started with
+            pub env: rtfm::Env
+        ));
+
+        values.push(quote!(env: rtfm::Env::new()));
+    }
+
+    if let (true, Some(device)) = (ctxt.is_init(), &analysis.extensions.app.device) {
+        fields.push(quote!(
+            /// Core (Cortex-M) peripherals; there are none on Linux
+            pub core: rtfm::compat::CorePeripherals
+        ));
+
+        values.push(quote!(core: rtfm::compat::CorePeripherals::steal()));
+
+        if analysis.extensions.app.peripherals.map(|(value, _)| value) == Some(true) {
+            fields.push(quote!(
+                /// Device specific peripherals
+                pub device: #device::Peripherals
+            ));
+
+            values.push(quote!(device: #device::Peripherals::steal()));
+        }
+    }
+
+    if resources.0 && analysis.extensions.model.is_some() {
+        lt = Some(quote!('a));
+
+        let local = util::local_resources_ident(ctxt, app);
+        items.push(quote!(
+            #[doc(inline)]
+            pub use super::#local as LocalResources;
+        ));
+
+        fields.push(quote!(
+            /// Resources that only this context uses
+            pub local: LocalResources<'a>
+        ));
+
+        values.push(quote!(local: LocalResources::new()));
+
+        if ctxt.is_init() {
+            items.push(quote!(
+                /// The monotonic timers of the application; the clock of the `schedule` API is
+                /// `#[app(monotonic = ..)]` so this is empty
+                pub struct Monotonics();
+            ));
+        } else {
+            let shared = util::shared_resources_ident(ctxt, app);
+            let locked = util::locked_resources_ident(ctxt, app);
+            items.push(quote!(
+                #[doc(inline)]
+                pub use super::#shared as SharedResources;
+
+                #[doc(inline)]
+                pub use super::#locked as LockedResources;
+            ));
+
+            fields.push(quote!(
+                /// Resources shared with other contexts; they must be `lock`-ed
+                pub shared: SharedResources<'a>
+            ));
+
+            values.push(quote!(shared: SharedResources::new(priority)));
+        }
+    } else if resources.0 {
         let ident = util::resources_ident(ctxt, app);
         let lt = if resources.1 {
             lt = Some(quote!('a));
@@ -75,6 +238,8 @@ pub fn codegen(
 
     if schedule {
         let doc = "Tasks that can be `schedule`-d from this context";
+        let after_doc = "Tasks that can be `spawn_after`-ed from this context";
+        let wall_doc = "Tasks that can be scheduled at a wall-clock time from this context";
         if ctxt.is_init() {
             items.push(quote!(
                 #[doc = #doc]
@@ -82,6 +247,12 @@ pub fn codegen(
                 pub struct Schedule {
                     _not_send: core::marker::PhantomData<*mut ()>,
                 }
+
+                #[doc = #after_doc]
+                #[derive(Clone, Copy)]
+                pub struct SpawnAfter {
+                    _not_send: core::marker::PhantomData<*mut ()>,
+                }
             ));
 
             fields.push(quote!(
@@ -89,9 +260,37 @@ pub fn codegen(
                 pub schedule: Schedule
             ));
 
+            fields.push(quote!(
+                #[doc = #after_doc]
+                pub spawn_after: SpawnAfter
+            ));
+
             values.push(quote!(
                 schedule: Schedule { _not_send: core::marker::PhantomData }
             ));
+
+            values.push(quote!(
+                spawn_after: SpawnAfter { _not_send: core::marker::PhantomData }
+            ));
+
+            if util::wall_clock(analysis) {
+                items.push(quote!(
+                    #[doc = #wall_doc]
+                    #[derive(Clone, Copy)]
+                    pub struct ScheduleWall {
+                        _not_send: core::marker::PhantomData<*mut ()>,
+                    }
+                ));
+
+                fields.push(quote!(
+                    #[doc = #wall_doc]
+                    pub schedule_wall: ScheduleWall
+                ));
+
+                values.push(quote!(
+                    schedule_wall: ScheduleWall { _not_send: core::marker::PhantomData }
+                ));
+            }
         } else {
             lt = Some(quote!('a));
 
@@ -109,6 +308,20 @@ pub fn codegen(
                         &self.priority
                     }
                 }
+
+                #[doc = #after_doc]
+                #[derive(Clone, Copy)]
+                pub struct SpawnAfter<'a> {
+                    priority: &'a rtfm::export::Priority,
+                }
+
+                impl<'a> SpawnAfter<'a> {
+                    #[doc(hidden)]
+                    #[inline(always)]
+                    pub unsafe fn priority(&self) -> &rtfm::export::Priority {
+                        &self.priority
+                    }
+                }
             ));
 
             fields.push(quote!(
@@ -116,9 +329,45 @@ pub fn codegen(
                 pub schedule: Schedule<'a>
             ));
 
+            fields.push(quote!(
+                #[doc = #after_doc]
+                pub spawn_after: SpawnAfter<'a>
+            ));
+
             values.push(quote!(
                 schedule: Schedule { priority }
             ));
+
+            values.push(quote!(
+                spawn_after: SpawnAfter { priority }
+            ));
+
+            if util::wall_clock(analysis) {
+                items.push(quote!(
+                    #[doc = #wall_doc]
+                    #[derive(Clone, Copy)]
+                    pub struct ScheduleWall<'a> {
+                        priority: &'a rtfm::export::Priority,
+                    }
+
+                    impl<'a> ScheduleWall<'a> {
+                        #[doc(hidden)]
+                        #[inline(always)]
+                        pub unsafe fn priority(&self) -> &rtfm::export::Priority {
+                            &self.priority
+                        }
+                    }
+                ));
+
+                fields.push(quote!(
+                    #[doc = #wall_doc]
+                    pub schedule_wall: ScheduleWall<'a>
+                ));
+
+                values.push(quote!(
+                    schedule_wall: ScheduleWall { priority }
+                ));
+            }
         }
     }
 
@@ -139,6 +388,24 @@ pub fn codegen(
             ));
 
             values.push(quote!(spawn: Spawn { _not_send: core::marker::PhantomData }));
+
+            if !util::broadcast_groups(ctxt, app, analysis).is_empty() {
+                let doc = "Groups of tasks that can be sent the same message from this context";
+                items.push(quote!(
+                    #[doc = #doc]
+                    #[derive(Clone, Copy)]
+                    pub struct Broadcast {
+                        _not_send: core::marker::PhantomData<*mut ()>,
+                    }
+                ));
+
+                fields.push(quote!(
+                    #[doc = #doc]
+                    pub broadcast: Broadcast
+                ));
+
+                values.push(quote!(broadcast: Broadcast { _not_send: core::marker::PhantomData }));
+            }
         } else {
             lt = Some(quote!('a));
 
@@ -148,6 +415,7 @@ pub fn codegen(
             ));
 
             let mut instant_method = None;
+            let spawn_value;
             if ctxt.is_idle() {
                 items.push(quote!(
                     #[doc = #doc]
@@ -158,6 +426,7 @@ pub fn codegen(
                 ));
 
                 values.push(quote!(spawn: Spawn { priority }));
+                spawn_value = quote!(Spawn { priority });
             } else {
                 let instant_field = if app.uses_schedule(core) {
                     needs_instant = true;
@@ -188,6 +457,7 @@ pub fn codegen(
                 values.push(quote!(
                     spawn: Spawn { priority #_instant }
                 ));
+                spawn_value = quote!(Spawn { priority #_instant });
             }
 
             items.push(quote!(
@@ -201,13 +471,85 @@ pub fn codegen(
                     #instant_method
                 }
             ));
+
+            if !util::blocking_spawnees(ctxt, app, analysis).is_empty() {
+                let doc = "Tasks that can be `spawn`-ed from this context once they have a free \
+                           message slot";
+                items.push(quote!(
+                    #[doc = #doc]
+                    #[derive(Clone, Copy)]
+                    pub struct SpawnBlocking<'a> {
+                        spawn: Spawn<'a>,
+                    }
+
+                    impl<'a> SpawnBlocking<'a> {
+                        #[doc(hidden)]
+                        #[inline(always)]
+                        pub fn spawn(&self) -> &Spawn<'a> {
+                            &self.spawn
+                        }
+                    }
+                ));
+
+                fields.push(quote!(
+                    #[doc = #doc]
+                    pub spawn_blocking: SpawnBlocking<'a>
+                ));
+
+                values.push(quote!(spawn_blocking: SpawnBlocking { spawn: #spawn_value }));
+            }
+
+            if !util::broadcast_groups(ctxt, app, analysis).is_empty() {
+                let doc = "Groups of tasks that can be sent the same message from this context";
+                items.push(quote!(
+                    #[doc = #doc]
+                    #[derive(Clone, Copy)]
+                    pub struct Broadcast<'a> {
+                        spawn: Spawn<'a>,
+                    }
+
+                    impl<'a> Broadcast<'a> {
+                        #[doc(hidden)]
+                        #[inline(always)]
+                        pub fn spawn(&self) -> &Spawn<'a> {
+                            &self.spawn
+                        }
+                    }
+                ));
+
+                fields.push(quote!(
+                    #[doc = #doc]
+                    pub broadcast: Broadcast<'a>
+                ));
+
+                values.push(quote!(broadcast: Broadcast { spawn: #spawn_value }));
+            }
         }
     }
 
+    if analysis.extensions.app.log.is_some() {
+        items.push(quote!(
+            /// Signal-safe logger; use it with `write!` / `writeln!`
+            #[derive(Clone, Copy)]
+            pub struct Log {
+                _not_send: core::marker::PhantomData<*mut ()>,
+            }
+        ));
+
+        fields.push(quote!(
+            /// Signal-safe logger
+            pub log: Log
+        ));
+
+        values.push(quote!(log: Log { _not_send: core::marker::PhantomData }));
+    }
+
     if late_resources {
+        let late_resources = util::late_resources_ident(&name);
+
         items.push(quote!(
             #[doc(inline)]
-            pub use super::initLateResources as LateResources;
+            pub use super::#late_resources as LateResources;
         ));
     }
 
@@ -243,11 +585,15 @@ pub fn codegen(
                     #(#values,)*
                 }
             }
+
+            #(#methods)*
         }
     ));
 
     if !items.is_empty() {
+        let cfgs = util::cfgs(ctxt, app);
         quote!(
+            #(#cfgs)*
             #[allow(non_snake_case)]
             #[doc = #doc]
             pub mod #name {
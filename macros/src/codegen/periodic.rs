@@ -0,0 +1,186 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::{analyze::Priority, ast::App, Core};
+use syn::Ident;
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Creates the timers of the periodic tasks
+pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    analysis
+        .periodic_tasks
+        .keys()
+        .map(|name| {
+            let timer = util::period_timer_ident(name);
+            let doc = format!("Timer that releases `{}`", name);
+            let cfgs = &app.software_tasks[name].cfgs;
+
+            quote!(
+                #[doc = #doc]
+                #(#cfgs)*
+                static #timer: rtfm::export::Timer = rtfm::export::Timer::uninit();
+            )
+        })
+        .collect()
+}
+
+/// Creates and arms the timers of the periodic tasks of `core`
+///
+/// All the periodic tasks of a core are first released at the same instant, right before the
+/// tasks are enabled; with `modes` only the ones that the initial mode enables, with the period of
+/// that mode
+pub fn start(core: Core, app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let monotonic = util::monotonic(analysis);
+
+    let timers = analysis
+        .periodic_tasks
+        .iter()
+        .filter(|(name, _)| app.software_tasks[*name].args.core == core)
+        .map(|(name, &id)| {
+            let task = &app.software_tasks[name];
+            let signo = analysis.signals[&core].map[&task.args.priority];
+            // NOTE `0` if the initial mode disables the task
+            let period = util::mode_periods(name, analysis)
+                .first()
+                .cloned()
+                .unwrap_or_else(|| period(name, analysis));
+
+            let tid = if util::process_directed(app, analysis) {
+                quote!(None)
+            } else {
+                let tid = util::tid_ident(core);
+                quote!(Some(#tid.get()))
+            };
+
+            let timer = util::period_timer_ident(name);
+            let cfgs = &task.cfgs;
+            let start = if period != 0 {
+                Some(quote!(
+                    #(#cfgs)*
+                    rtfm::export::timer_start::<#monotonic>(#timer.get(), now, #period)
+                        .unwrap_or_else(rtfm::export::fatal);
+                ))
+            } else {
+                None
+            };
+
+            quote!(
+                #(#cfgs)*
+                #timer.init(rtfm::export::periodic_timer(
+                    #tid,
+                    #signo,
+                    <#monotonic as rtfm::Monotonic>::TIMER_CLOCK,
+                    #id,
+                ).unwrap_or_else(rtfm::export::fatal));
+                #start
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if timers.is_empty() {
+        return timers;
+    }
+
+    // NOTE `now` is unused if every periodic task is `#[cfg]`-ed out
+    vec![quote!(
+        #[allow(unused_variables)]
+        let now = <#monotonic as rtfm::Monotonic>::now();
+        #(#timers)*
+    )]
+}
+
+/// Deletes the timers of the periodic tasks of `core`
+pub fn stop(core: Core, app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    analysis
+        .periodic_tasks
+        .keys()
+        .filter(|name| app.software_tasks[*name].args.core == core)
+        .map(|name| {
+            let timer = util::period_timer_ident(name);
+            let cfgs = &app.software_tasks[name].cfgs;
+
+            quote!(
+                #(#cfgs)*
+                rtfm::export::timer_delete(#timer.get());
+            )
+        })
+        .collect()
+}
+
+/// Runs the periodic tasks that are dispatched at priority `level`
+///
+/// Returns `None` if there are no such tasks
+pub fn dispatch(
+    core: Core,
+    level: Priority,
+    app: &App,
+    analysis: &Analysis,
+) -> Option<TokenStream2> {
+    let arms = analysis
+        .periodic_tasks
+        .iter()
+        .filter(|(name, _)| {
+            let task = &app.software_tasks[*name];
+
+            task.args.core == core && task.args.priority == level
+        })
+        .map(|(name, id)| {
+            let (let_instant, instant) = if app.uses_schedule(core) {
+                let monotonic = util::monotonic(analysis);
+                (
+                    Some(quote!(let instant = <#monotonic as rtfm::Monotonic>::now();)),
+                    Some(quote!(, instant)),
+                )
+            } else {
+                (None, None)
+            };
+
+            let call = util::run_task(
+                name,
+                core,
+                app,
+                analysis,
+                quote!(#name(
+                    #name::Locals::new(),
+                    #name::Context::new(priority #instant).with_overruns(overruns)
+                )),
+            );
+
+            let timer = util::period_timer_ident(name);
+            let cfgs = &app.software_tasks[name].cfgs;
+            quote!(
+                #(#cfgs)*
+                #id => {
+                    let overruns = rtfm::export::timer_getoverrun(#timer.get());
+                    #let_instant
+                    #call
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if arms.is_empty() {
+        return None;
+    }
+
+    // NOTE the timer signals carry the same `sigval` as the `rt_sigqueueinfo`-ed ones
+    Some(quote!(
+        let si_value = si.siginfo.sifields.rt.sigval.sival_ptr;
+        if si_value & rtfm::export::PERIOD_EVENT != 0 {
+            let priority = &rtfm::export::Priority::new(PRIORITY);
+            match si_value as u8 {
+                #(#arms)*
+                _ => {}
+            }
+
+            return;
+        }
+    ))
+}
+
+fn period(name: &Ident, analysis: &Analysis) -> u64 {
+    analysis.extensions.tasks[name]
+        .period
+        .map(|(nanos, _)| nanos)
+        .expect("UNREACHABLE")
+}
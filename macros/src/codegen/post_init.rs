@@ -3,22 +3,37 @@ use std::collections::BTreeSet;
 
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
+use rtfm_syntax::ast::App;
 
-use crate::{analyze::Analysis, codegen::util};
+use crate::{
+    analyze::Analysis,
+    codegen::{background, epoll, periodic, shm, shutdown, threads, throttling, util, watchdog},
+};
 
-pub fn codegen(analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
     let mut const_app = vec![];
     let mut stmts = vec![];
 
     // initialize late resources
     if let Some(late_resources) = analysis.late_resources.get(&0) {
         for name in late_resources {
+            let cfgs = &app.late_resources[name].cfgs;
             stmts.push(quote!(
+                #(#cfgs)*
                 #name.as_mut_ptr().write(late.#name);
             ));
         }
     }
 
+    // watch the file descriptors bound to tasks
+    stmts.extend(epoll::register(0, app, analysis));
+
+    // release the periodic tasks
+    stmts.extend(periodic::start(0, app, analysis));
+
+    // let other processes feed the tasks
+    stmts.extend(shm::open(0, app, analysis));
+
     // synchronization barriers
     let all_senders = analysis
         .initialization_barriers
@@ -26,10 +41,15 @@ pub fn codegen(analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
         .flat_map(|senders| senders.iter().cloned())
         .collect::<BTreeSet<_>>();
 
+    let (ty, expr) = util::shared_static(
+        util::processes(analysis),
+        quote!(rtfm::export::Barrier),
+        quote!(rtfm::export::Barrier::new()),
+    );
     for &sender in &all_senders {
         let b = util::b_ident(sender);
         const_app.push(quote!(
-            static #b: rtfm::export::Barrier = rtfm::export::Barrier::new();
+            static #b: #ty = #expr;
         ));
     }
 
@@ -49,13 +69,42 @@ pub fn codegen(analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
         }
     }
 
+    // run the messages that `init` sent to the background tasks
+    stmts.extend(background::start(analysis));
+
+    // watch the thermal zones and the cpufreq policies
+    stmts.extend(throttling::start(app, analysis));
+
+    // NOTE a no-op unless the runtime is built with the `record` feature
+    stmts.push(quote!(rtfm::export::record_start();));
+
     // `interrupt::enable()`
-    let signals = &analysis.signals[&0];
-    let max = signals.map.len() as u8;
-    let Range { start, end } = signals.range();
-    stmts.push(quote!(
-        rtfm::export::mask(#start..#end, 0, #max, false);
-    ));
+    // NOTE the privileges, and the system calls, go before the tasks run but after the threads of
+    // the priority levels, which need them, are spawned
+    if util::threads(analysis) {
+        stmts.extend(threads::spawn(0, app, analysis));
+        stmts.push(util::drop_privileges());
+        stmts.extend(util::install_seccomp(analysis));
+    } else {
+        stmts.push(util::drop_privileges());
+        stmts.extend(util::install_seccomp(analysis));
+
+        let signals = &analysis.signals[&0];
+        let max = signals.map.len() as u8;
+        let Range { start, end } = signals.range();
+        stmts.push(quote!(
+            rtfm::export::mask(#start..#end, 0, #max, false);
+        ));
+    }
+    stmts.push(shutdown::enable(0, analysis));
+
+    // let systemd supervise the application
+    stmts.extend(watchdog::ready(analysis));
+
+    // the replay driver takes the place of `idle`
+    if analysis.extensions.app.replay.is_some() {
+        stmts.push(quote!(rtfm::export::replay();));
+    }
 
     (const_app, stmts)
 }
@@ -1,38 +1,140 @@
-use core::ops::Range;
+use core::{cmp, ops::Range};
+use std::collections::BTreeSet;
 
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use rtfm_syntax::ast::App;
 
-use crate::{analyze::Analysis, codegen::util};
+use crate::{
+    analyze::Analysis,
+    codegen::{
+        compat, defer, epoll, introspect, latency, log, metrics, periodic, shm, shutdown, spawner,
+        stats, threads, util, watchdog,
+    },
+};
 
 pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
     let mut const_app = vec![];
     let mut stmts = vec![];
 
-    let signo_max = match analysis
-        .signals
+    // NOTE the shutdown signal of each core comes after the signals of its tasks
+    let signals = match (
+        analysis.signals.values().map(|signals| signals.start).min(),
+        analysis
+            .signals
+            .values()
+            .map(|signals| signals.shutdown)
+            .max(),
+    ) {
+        (Some(start), Some(shutdown)) => {
+            let end = shutdown + 1;
+            quote!(Some(#start..#end))
+        }
+        _ => quote!(None),
+    };
+
+    // the `#[pre_init]` function runs first, with the privileges and the scheduling policy the
+    // process started with
+    let config = if let Some(f) = &analysis.extensions.pre_init {
+        let pre_init = &f.ident;
+
+        quote!(#pre_init())
+    } else {
+        let cpu_dma_latency = match analysis.extensions.app.cpu_dma_latency {
+            Some(latency) => quote!(Some(#latency)),
+            None => quote!(None),
+        };
+        let shield_cores = match &analysis.extensions.app.shield_cores {
+            Some(cpus) => quote!(Some(&[#(#cpus),*])),
+            None => quote!(None),
+        };
+
+        // the environment overrides what was compiled in
+        quote!(rtfm::RuntimeConfig {
+            cpu_dma_latency: #cpu_dma_latency,
+            shield_cores: #shield_cores,
+            ..Default::default()
+        }
+        .from_env())
+    };
+    stmts.push(quote!(
+        let config: rtfm::RuntimeConfig = #config;
+        rtfm::export::configure(&config);
+    ));
+
+    let disable = analysis.extensions.app.disable_rt_throttling == Some(true);
+    stmts.push(quote!(rtfm::export::init_throttling(#disable);));
+
+    // NOTE a no-op unless the runtime is built with the `trace` feature
+    stmts.push(quote!(rtfm::export::trace_open();));
+
+    if let Some((path, _)) = &analysis.extensions.app.replay {
+        stmts.push(quote!(
+            rtfm::export::replay_open(#path).unwrap_or_else(rtfm::export::fatal);
+        ));
+    }
+
+    // fix what can be fixed without privileges, then report everything that would still keep the
+    // runtime from starting before trying to start it
+    let rtprio = rtprio(app, analysis);
+    let cores = app.args.cores;
+    stmts.push(quote!(
+        rtfm::export::raise_limits(#rtprio);
+        rtfm::export::preflight(#rtprio, #cores);
+    ));
+
+    // NOTE this must be known before the scheduling policy changes
+    if util::threads(analysis) {
+        stmts.push(quote!(rtfm::export::use_threads();));
+    }
+
+    if util::processes(analysis) {
+        stmts.push(quote!(rtfm::export::use_processes();));
+    }
+
+    stmts.push(quote!(
+        rtfm::export::init_runtime(#signals, &config).unwrap_or_else(rtfm::export::fatal);
+    ));
+
+    if analysis
+        .extensions
+        .tasks
         .values()
-        .flat_map(|signals| signals.map.values())
-        .max()
+        .any(|args| args.budget.is_some())
     {
-        Some(signo) => quote!(Some(#signo)),
-        None => quote!(None),
+        stmts.push(quote!(rtfm::export::init_budgets().unwrap_or_else(rtfm::export::fatal);));
+    }
+
+    // NOTE the main thread keeps its name, the name of the process
+    let (cpu, priority) = if analysis.reservations.contains_key(&0) {
+        (quote!(None), 0u8)
+    } else {
+        (quote!(Some(0)), 1u8)
     };
-    stmts.push(quote!(rtfm::export::init_runtime(#signo_max);));
+    stmts.push(quote!(rtfm::export::register_thread(None, Some(0), #cpu, #priority);));
+
+    // the (nested) signal handlers of core #0 run on their own stack
+    let stack_size = analysis.stack_sizes[&0];
+    stmts.push(quote!(
+        rtfm::export::sigaltstack(rtfm::export::stack_size(#stack_size), "the tasks of core #0")
+            .unwrap_or_else(rtfm::export::fatal);
+    ));
 
     // populate the `FreeQueue`s
     for (name, senders) in &analysis.free_queues {
-        let cap = app.software_tasks[name].args.capacity;
+        let cap = util::capacity(name, app, analysis);
+        let cfgs = &app.software_tasks[name].cfgs;
 
         // NOTE all free queues share the same INPUTS / INSTANTS buffers
         stmts.push(quote!(
+            #(#cfgs)*
             let mut index = 0;
         ));
         for &sender in senders.keys() {
             let fq = util::fq_ident_(name, sender);
 
             stmts.push(quote!(
+                #(#cfgs)*
                 for _ in 0..#cap {
                     #fq.enqueue_unchecked(index);
                     index += 1;
@@ -51,52 +153,119 @@ pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenS
     ));
 
     // initialize `TIMER0`
-    if let Some(tq) = analysis.timer_queues.get(&0) {
-        let timer = util::timer_ident(0);
-        let signo = analysis.signals[&0].map[&tq.priority];
+    let tid = if util::process_directed(app, analysis) {
+        quote!(None)
+    } else {
+        quote!(Some(tgid))
+    };
+    stmts.extend(timers(0, tid, analysis));
 
-        let tid = if app.args.cores == 1 {
-            quote!(None)
-        } else {
-            quote!(Some(tgid))
-        };
-        stmts.push(quote!(
-            #timer.init(rtfm::export::timer_create(#tid, #signo));
-        ));
-    }
+    if util::threads(analysis) {
+        // the threads of the priority levels call the signal handlers
+        const_app.extend(threads::codegen(app, analysis));
+    } else {
+        // register signal handlers
+        // NOTE iterating analysis.channels instead of analysis.signals avoid referring to
+        // non-existent (not codegen-ed) signal handlers
+        for (&core, dispatchers) in &analysis.channels {
+            let signals = &analysis.signals[&core];
 
-    // register signal handlers
-    // NOTE iterating analysis.channels instead of analysis.signals avoid referring to non-existent
-    // (not codegen-ed) signal handlers
-    for (&core, dispatchers) in &analysis.channels {
-        let signals = &analysis.signals[&core];
+            let Range { start, end } = signals.range();
+            for priority in dispatchers.keys() {
+                let rt = util::rt_ident(signals.map[priority]);
 
-        let Range { start, end } = signals.range();
-        for priority in dispatchers.keys() {
-            let rt = util::rt_ident(signals.map[priority]);
+                stmts.push(quote!(
+                    rtfm::export::register(#start..#end, #priority, #rt)
+                        .unwrap_or_else(rtfm::export::fatal);
+                ));
+            }
 
-            stmts.push(quote!(
-                rtfm::export::register(#start..#end, #priority, #rt);
-            ));
+            // the timer handler may be its own signal handler
+            if let Some(tq) = analysis.timer_queues.get(&core) {
+                if !dispatchers.contains_key(&tq.priority) {
+                    let priority = tq.priority;
+                    let rt = util::rt_ident(signals.map[&priority]);
+
+                    stmts.push(quote!(
+                        rtfm::export::register(#start..#end, #priority, #rt)
+                        .unwrap_or_else(rtfm::export::fatal);
+                    ));
+                }
+            }
         }
 
-        // the timer handler may be its own signal handler
-        if let Some(tq) = analysis.timer_queues.get(&core) {
-            if !dispatchers.contains_key(&tq.priority) {
-                let priority = tq.priority;
-                let rt = util::rt_ident(signals.map[&priority]);
+        // priority levels that only dispatch tasks bound to file descriptors, periodic, external,
+        // shared memory and `Spawner` tasks
+        for (core, level) in util::exclusive_levels(app, analysis) {
+            let signals = &analysis.signals[&core];
+            let Range { start, end } = signals.range();
+            let rt = util::rt_ident(signals.map[&level]);
 
-                stmts.push(quote!(
-                    rtfm::export::register(#start..#end, #priority, #rt);
-                ));
-            }
+            stmts.push(quote!(
+                rtfm::export::register(#start..#end, #level, #rt)
+                    .unwrap_or_else(rtfm::export::fatal);
+            ));
         }
     }
 
+    const_app.extend(periodic::codegen(app, analysis));
+    const_app.extend(shm::codegen(app, analysis));
+
+    // NOTE the queues must be ready before `init` can hand out `Spawner`s
+    let (spawner_const_app, spawner_stmts) = spawner::codegen(app, analysis);
+    const_app.extend(spawner_const_app);
+    stmts.extend(spawner_stmts);
+    stmts.extend(compat::bind(analysis));
+
+    // NOTE `init` may already `defer` closures
+    stmts.extend(defer::codegen(app, analysis));
+
+    let (shutdown_const_app, shutdown_stmts) = shutdown::codegen(app, analysis);
+    const_app.extend(shutdown_const_app);
+    stmts.extend(shutdown_stmts);
+
+    let (stats_const_app, stats_stmts) = stats::codegen(app, analysis);
+    const_app.extend(stats_const_app);
+    stmts.extend(stats_stmts);
+
+    let (introspect_const_app, introspect_stmts) = introspect::codegen(app, analysis);
+    const_app.extend(introspect_const_app);
+    stmts.extend(introspect_stmts);
+
+    let (log_const_app, log_stmts) = log::codegen(app, analysis);
+    const_app.extend(log_const_app);
+    stmts.extend(log_stmts);
+
+    let (metrics_const_app, metrics_stmts) = metrics::codegen(analysis);
+    const_app.extend(metrics_const_app);
+    stmts.extend(metrics_stmts);
+
+    let (latency_const_app, latency_stmts) = latency::codegen(app, analysis);
+    const_app.extend(latency_const_app);
+    stmts.extend(latency_stmts);
+
+    let (watchdog_const_app, watchdog_stmts) = watchdog::codegen(analysis);
+    const_app.extend(watchdog_const_app);
+    stmts.extend(watchdog_stmts);
+
+    let (heartbeats_const_app, heartbeats_stmts) = watchdog::heartbeats(analysis);
+    const_app.extend(heartbeats_const_app);
+    stmts.extend(heartbeats_stmts);
+
+    // NOTE the epoll instance must exist before the other threads are spawned
+    let (epoll_const_app, epoll_stmts) = epoll::codegen(app, analysis);
+    const_app.extend(epoll_const_app);
+    stmts.extend(epoll_stmts);
+
     if app.args.cores > 1 {
         let tid = util::tid_ident(0);
+        let (ty, expr) = util::shared_static(
+            util::processes(analysis),
+            quote!(rtfm::export::Pid),
+            quote!(rtfm::export::Pid::uninit()),
+        );
         const_app.push(quote!(
-            static #tid: rtfm::export::Pid = rtfm::export::Pid::uninit();
+            static #tid: #ty = #expr;
         ));
 
         stmts.push(quote!(
@@ -104,6 +273,11 @@ pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenS
         ));
     }
 
+    // NOTE the processes of the other cores get a copy of everything else
+    if util::processes(analysis) {
+        stmts.extend(share(app, analysis));
+    }
+
     // initialize the other threads, their timers and the `TID`s
     for &core in &analysis.used_cores {
         if core == 0 {
@@ -114,31 +288,197 @@ pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenS
         let tid = util::tid_ident(core);
         let child = util::child_ident(core);
 
+        let (ty, expr) = util::shared_static(
+            util::processes(analysis),
+            quote!(rtfm::export::Pid),
+            quote!(rtfm::export::Pid::uninit()),
+        );
         const_app.push(quote!(
-            static #tid: rtfm::export::Pid = rtfm::export::Pid::uninit();
+            static #tid: #ty = #expr;
         ));
 
-        stmts.push(quote!(
-            let tid = rtfm::export::spawn(#child);
-        ));
+        let stack_size = analysis.stack_sizes[&core];
+        let name = format!("the thread of core #{}", core);
+        if util::processes(analysis) {
+            stmts.push(quote!(
+                let tid = rtfm::export::fork(#child).unwrap_or_else(rtfm::export::fatal);
+            ));
+        } else {
+            stmts.push(quote!(
+                let tid = rtfm::export::spawn(#child, rtfm::export::stack_size(#stack_size), #name)
+                    .unwrap_or_else(rtfm::export::fatal);
+            ));
+        }
 
-        // create timer
-        if let Some(tq) = analysis.timer_queues.get(&core) {
-            let timer = util::timer_ident(core);
-            let signo = analysis.signals[&core].map[&tq.priority];
+        // create timer; the process of a core creates its own
+        if !util::processes(analysis) {
+            let tid = if util::threads(analysis) {
+                quote!(None)
+            } else {
+                quote!(Some(tid))
+            };
+            stmts.extend(timers(core, tid, analysis));
+        }
+
+        if let Some(r) = analysis.reservations.get(&core) {
+            let (runtime, deadline, period) = (r.runtime, r.deadline, r.period);
             stmts.push(quote!(
-                #timer.init(rtfm::export::timer_create(Some(tid), #signo));
+                // `SCHED_DEADLINE` threads can't be pinned to a single core
+                rtfm::export::sched_deadline(tid, #runtime, #deadline, #period)
+                    .unwrap_or_else(rtfm::export::fatal);
+            ));
+        } else {
+            stmts.push(quote!(
+                // migrate the thread to a different core
+                rtfm::export::set_affinity(tid, #core).unwrap_or_else(rtfm::export::fatal);
             ));
         }
 
         stmts.push(quote!(
-            // migrate the thread to a different core
-            rtfm::export::set_affinity(tid, #core);
-
             // unblock the thread
             #tid.init(tid);
         ));
     }
 
+    // NOTE this must come last because `SCHED_DEADLINE` threads can't spawn other threads
+    if let Some(r) = analysis.reservations.get(&0) {
+        let (runtime, deadline, period) = (r.runtime, r.deadline, r.period);
+        stmts.push(quote!(
+            rtfm::export::sched_deadline(0, #runtime, #deadline, #period)
+                .unwrap_or_else(rtfm::export::fatal);
+        ));
+    }
+
     (const_app, stmts)
 }
+
+/// Moves the statics that the cores share to shared memory (`processes = true`)
+fn share(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let mut statics = vec![];
+
+    let children = analysis
+        .used_cores
+        .iter()
+        .cloned()
+        .filter(|&core| core != 0)
+        .collect::<Vec<_>>();
+
+    // thread IDs and the barriers of the initialization and of the shutdown
+    statics.push((vec![], util::tid_ident(0)));
+    statics.extend(children.iter().map(|&core| (vec![], util::tid_ident(core))));
+    statics.extend(children.iter().map(|&core| (vec![], util::sd_ident(core))));
+    statics.extend(
+        analysis
+            .initialization_barriers
+            .values()
+            .flat_map(|senders| senders.iter().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|sender| (vec![], util::b_ident(sender))),
+    );
+
+    // the message slots of the tasks that other cores spawn
+    for (name, senders) in &analysis.free_queues {
+        let task = &app.software_tasks[name];
+        let core = task.args.core;
+        let cfgs = &task.cfgs;
+
+        if util::shared_messages(name, app, analysis) {
+            statics.push((cfgs.clone(), util::inputs_ident(name)));
+
+            if app.uses_schedule(core) {
+                statics.push((cfgs.clone(), util::instants_ident(name)));
+            }
+
+            for &sender in senders.keys().filter(|&&sender| sender != core) {
+                statics.push((cfgs.clone(), util::fq_ident_(name, sender)));
+            }
+        }
+    }
+
+    // resources shared between cores
+    for name in analysis.extensions.pi_futex.keys() {
+        let (res, _) = app.resource(name).expect("UNREACHABLE");
+        statics.push((res.cfgs.clone(), name.clone()));
+    }
+
+    statics
+        .into_iter()
+        .map(|(cfgs, name)| {
+            quote!(
+                #(#cfgs)*
+                #name.share().unwrap_or_else(rtfm::export::fatal);
+            )
+        })
+        .collect()
+}
+
+/// Creates the timer(s) of the timer queue of `core`, if it has one; their signals go to thread
+/// `tid`, an `Option`
+pub fn timers(core: u8, tid: TokenStream2, analysis: &Analysis) -> Vec<TokenStream2> {
+    let mut stmts = vec![];
+
+    if let Some(tq) = analysis.timer_queues.get(&core) {
+        let monotonic = util::monotonic(analysis);
+        let timer = util::timer_ident(core);
+        let signo = analysis.signals[&core].map[&tq.priority];
+        stmts.push(quote!(
+            #timer.init(rtfm::export::timer_create(
+                #tid,
+                #signo,
+                <#monotonic as rtfm::Monotonic>::TIMER_CLOCK,
+            ).unwrap_or_else(rtfm::export::fatal));
+        ));
+
+        if util::wall_clock(analysis) {
+            let timer = util::wall_timer_ident(core);
+            stmts.push(quote!(
+                #timer.init(rtfm::export::timer_create(
+                    #tid,
+                    #signo,
+                    <rtfm::export::ClockRealtime as rtfm::Monotonic>::TIMER_CLOCK,
+                ).unwrap_or_else(rtfm::export::fatal));
+            ));
+        }
+    }
+
+    stmts
+}
+
+/// Highest `SCHED_FIFO` priority used by the threads of the application
+fn rtprio(app: &App, analysis: &Analysis) -> u8 {
+    let mut rtprio = 1;
+
+    if !analysis.fd_tasks.is_empty() {
+        rtprio = cmp::max(
+            rtprio,
+            analysis
+                .extensions
+                .app
+                .epoll_priority
+                .unwrap_or(epoll::EPOLL_PRIORITY),
+        );
+    }
+
+    if analysis.extensions.app.latency_probe.is_some() {
+        rtprio = latency::PROBE_PRIORITY;
+    }
+
+    if analysis.extensions.app.watchdog.is_some() {
+        rtprio = watchdog::WATCHDOG_PRIORITY;
+    }
+
+    if util::threads(analysis) {
+        if let Some(level) = app
+            .software_tasks
+            .values()
+            .map(|task| task.args.priority)
+            .chain(analysis.timer_queues.values().map(|tq| tq.priority))
+            .max()
+        {
+            rtprio = cmp::max(rtprio, 1 + level);
+        }
+    }
+
+    rtprio
+}
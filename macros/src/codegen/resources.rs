@@ -21,7 +21,33 @@ pub fn codegen(
         let attrs = &res.attrs;
         let ty = &res.ty;
 
-        if let Some(expr) = expr {
+        if let (Some((ty, _)), Some(expr)) = (analysis.extensions.per_core.get(name), expr) {
+            // one instance per core
+            let slots = util::per_core_ident(name);
+            let cores = usize::from(app.args.cores);
+            let exprs = (0..cores).map(|_| quote!(rtfm::export::PerCoreSlot::new(#expr)));
+            let doc = format!("Instances of `{}`, one per core", name);
+            const_app.push(quote!(
+                #[doc = #doc]
+                #(#cfgs)*
+                static #slots: [rtfm::export::PerCoreSlot<#ty>; #cores] = [#(#exprs),*];
+
+                #(#attrs)*
+                #(#cfgs)*
+                static mut #name: rtfm::export::PerCore<#ty> =
+                    rtfm::export::PerCore::new(&#slots);
+            ));
+        } else if let (true, Some(expr)) = (
+            util::processes(analysis) && analysis.extensions.pi_futex.contains_key(name),
+            expr,
+        ) {
+            // NOTE moved to shared memory before the other cores are forked
+            const_app.push(quote!(
+                #(#attrs)*
+                #(#cfgs)*
+                static mut #name: rtfm::export::Shared<#ty> = rtfm::export::Shared::new(#expr);
+            ));
+        } else if let Some(expr) = expr {
             const_app.push(quote!(
                 #(#attrs)*
                 #(#cfgs)*
@@ -46,10 +72,12 @@ pub fn codegen(
                 };
 
                 mod_resources.push(quote!(
+                    #(#cfgs)*
                     pub struct #name<'a> {
                         priority: &'a Priority,
                     }
 
+                    #(#cfgs)*
                     impl<'a> #name<'a> {
                         #[inline(always)]
                         pub unsafe fn new(priority: &'a Priority) -> Self {
@@ -72,6 +100,7 @@ pub fn codegen(
                     *ceiling,
                     range.clone(),
                     ptr,
+                    util::lock_stats(&[name], app, analysis),
                 ));
             }
         }
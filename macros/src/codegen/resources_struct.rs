@@ -1,8 +1,8 @@
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
-use rtfm_syntax::{ast::App, Context};
+use rtfm_syntax::{analyze::Ownership, ast::App, Context};
 
-use crate::{analyze::Analysis, codegen::util};
+use crate::{analyze::Analysis, codegen::util, parse::Model};
 
 pub fn codegen(
     context: Context,
@@ -11,6 +11,12 @@ pub fn codegen(
     app: &App,
     analysis: &Analysis,
 ) -> (TokenStream2, TokenStream2) {
+    if let Some(model) = &analysis.extensions.model {
+        *needs_lt = true;
+
+        return shared_local(context, priority, model, app, analysis);
+    }
+
     let mut lt = None;
 
     let resources = match context {
@@ -58,6 +64,55 @@ pub fn codegen(
                     #name: &mut #name
                 ));
             }
+        } else if let Some((ty, _)) = analysis.extensions.per_core.get(name) {
+            // this core's instance; see `rtfm::export::PerCore`
+            lt = Some(quote!('a));
+
+            let core = context.core(app);
+            let ceiling = util::core_ceiling(name, core, app);
+            let (start, end) = util::signal_range(core, analysis);
+            let name_str = name.to_string();
+
+            fields.push(quote!(
+                #(#cfgs)*
+                pub #name: rtfm::export::PerCoreProxy<'a, #ty>
+            ));
+
+            values.push(quote!(
+                #(#cfgs)*
+                #name: rtfm::export::PerCoreProxy::new(
+                    &#name,
+                    #core,
+                    #name_str,
+                    priority,
+                    #ceiling,
+                    #start..#end,
+                )
+            ));
+        } else if let Some((ty, _)) = analysis.extensions.pi_futex.get(name) {
+            // shared with other cores; see `rtfm::export::PiFutex`
+            lt = Some(quote!('a));
+
+            let core = context.core(app);
+            let ceiling = util::core_ceiling(name, core, app);
+            let (start, end) = util::signal_range(core, analysis);
+            let name_str = name.to_string();
+
+            fields.push(quote!(
+                #(#cfgs)*
+                pub #name: rtfm::export::PiFutexProxy<'a, #ty>
+            ));
+
+            values.push(quote!(
+                #(#cfgs)*
+                #name: rtfm::export::PiFutexProxy::new(
+                    &#name,
+                    #name_str,
+                    priority,
+                    #ceiling,
+                    #start..#end,
+                )
+            ));
         } else {
             let ownership = &analysis.ownerships[name];
 
@@ -143,7 +198,9 @@ pub fn codegen(
 
     let doc = format!("Resources `{}` has access to", context.ident(app));
     let ident = util::resources_ident(context, app);
+    let ctxt_cfgs = util::cfgs(context, app);
     let item = quote!(
+        #(#ctxt_cfgs)*
         #[allow(non_snake_case)]
         #[doc = #doc]
         pub struct #ident<#lt> {
@@ -156,6 +213,7 @@ pub fn codegen(
         Some(quote!(priority: &#lt rtfm::export::Priority))
     };
     let constructor = quote!(
+        #(#ctxt_cfgs)*
         impl<#lt> #ident<#lt> {
             #[inline(always)]
             unsafe fn new(#arg) -> Self {
@@ -167,3 +225,212 @@ pub fn codegen(
     );
     (item, constructor)
 }
+
+// `SharedResources`, whose resources all have a `lock` method, `LockedResources`, what
+// `SharedResources::lock` hands out, and `LocalResources`
+fn shared_local(
+    context: Context,
+    priority: u8,
+    model: &Model,
+    app: &App,
+    analysis: &Analysis,
+) -> (TokenStream2, TokenStream2) {
+    let resources = match context {
+        Context::Init(core) => &app.inits[&core].args.resources,
+        Context::Idle(core) => &app.idles[&core].args.resources,
+        Context::HardwareTask(name) => &app.hardware_tasks[name].args.resources,
+        Context::SoftwareTask(name) => &app.software_tasks[name].args.resources,
+    };
+
+    let mut shared_fields = vec![];
+    let mut shared_values = vec![];
+    let mut locked_fields = vec![];
+    let mut locked_values = vec![];
+    let mut local_fields = vec![];
+    let mut local_values = vec![];
+    let mut ceiling = priority;
+    // the resources whose contention counters the `lock` of `shared` bumps
+    let mut contended = vec![];
+    for name in resources {
+        let (res, expr) = app.resource(name).expect("UNREACHABLE");
+
+        let cfgs = &res.cfgs;
+        let mut_ = res.mutability;
+        let ty = &res.ty;
+        let field = model.field(name);
+        let ptr = match (expr.is_none(), mut_.is_some()) {
+            (true, true) => quote!(#name.as_mut_ptr()),
+            (true, false) => quote!(#name.as_ptr()),
+            (false, true) => quote!(&mut #name),
+            (false, false) => quote!(&#name),
+        };
+
+        if !model.is_shared(name) {
+            let lt = if context.runs_once() {
+                quote!('static)
+            } else {
+                quote!('a)
+            };
+
+            local_fields.push(quote!(
+                #(#cfgs)*
+                pub #field: &#lt #mut_ #ty
+            ));
+
+            local_values.push(quote!(
+                #(#cfgs)*
+                #field: &#mut_ *#ptr
+            ));
+
+            continue;
+        }
+
+        let ownership = &analysis.ownerships[name];
+        if mut_.is_none() {
+            shared_fields.push(quote!(
+                #(#cfgs)*
+                pub #field: &'a #ty
+            ));
+
+            shared_values.push(quote!(
+                #(#cfgs)*
+                #field: &*#ptr
+            ));
+        } else if ownership.needs_lock(priority) {
+            if let Ownership::Shared { ceiling: c } = ownership {
+                ceiling = ceiling.max(*c);
+            }
+            contended.push(name);
+
+            shared_fields.push(quote!(
+                #(#cfgs)*
+                pub #field: resources::#name<'a>
+            ));
+
+            shared_values.push(quote!(
+                #(#cfgs)*
+                #field: resources::#name::new(priority)
+            ));
+        } else {
+            // no other context can preempt this one and access the resource; `lock` is a no-op
+            shared_fields.push(quote!(
+                #(#cfgs)*
+                pub #field: rtfm::Exclusive<'a, #ty>
+            ));
+
+            shared_values.push(quote!(
+                #(#cfgs)*
+                #field: rtfm::Exclusive(&mut *#ptr)
+            ));
+        }
+
+        locked_fields.push(quote!(
+            #(#cfgs)*
+            pub #field: &'a #mut_ #ty
+        ));
+
+        locked_values.push(quote!(
+            #(#cfgs)*
+            #field: &#mut_ *#ptr
+        ));
+    }
+
+    let name = context.ident(app);
+    let ctxt_cfgs = util::cfgs(context, app);
+    let local = util::local_resources_ident(context, app);
+    let local_doc = format!("Local resources of `{}`", name);
+    let mut item = quote!(
+        #(#ctxt_cfgs)*
+        #[allow(non_snake_case)]
+        #[doc = #local_doc]
+        pub struct #local<'a> {
+            #(#local_fields,)*
+            #[doc(hidden)]
+            pub __marker__: core::marker::PhantomData<&'a ()>,
+        }
+    );
+    let mut constructor = quote!(
+        #(#ctxt_cfgs)*
+        impl<'a> #local<'a> {
+            #[inline(always)]
+            unsafe fn new() -> Self {
+                #local {
+                    #(#local_values,)*
+                    __marker__: core::marker::PhantomData,
+                }
+            }
+        }
+    );
+
+    // `init` runs before the other contexts; it initializes the shared resources
+    if context.is_init() {
+        return (item, constructor);
+    }
+
+    let shared = util::shared_resources_ident(context, app);
+    let locked = util::locked_resources_ident(context, app);
+    let shared_doc = format!("Shared resources `{}` has access to", name);
+    let locked_doc = format!("The shared resources of `{}`, locked", name);
+    item.extend(quote!(
+        #(#ctxt_cfgs)*
+        #[allow(non_snake_case)]
+        #[doc = #shared_doc]
+        pub struct #shared<'a> {
+            #(#shared_fields,)*
+            priority: &'a rtfm::export::Priority,
+        }
+
+        #(#ctxt_cfgs)*
+        #[allow(non_snake_case)]
+        #[doc = #locked_doc]
+        pub struct #locked<'a> {
+            #(#locked_fields,)*
+            #[doc(hidden)]
+            pub __marker__: core::marker::PhantomData<&'a ()>,
+        }
+    ));
+
+    let range = analysis.signals[&context.core(app)].range();
+    let (start, end) = (range.start, range.end);
+    let name_str = format!("{}::shared", name);
+    let stats = util::lock_stats(&contended, app, analysis);
+    constructor.extend(quote!(
+        #(#ctxt_cfgs)*
+        impl<'a> #shared<'a> {
+            #[inline(always)]
+            unsafe fn new(priority: &'a rtfm::export::Priority) -> Self {
+                #shared {
+                    #(#shared_values,)*
+                    priority,
+                }
+            }
+
+            /// Locks all the shared resources of this context at once, at the highest of their
+            /// ceilings
+            #[inline(always)]
+            pub fn lock<R>(&mut self, f: impl FnOnce(#locked<'_>) -> R) -> R {
+                /// Priority ceiling
+                const CEILING: u8 = #ceiling;
+
+                unsafe {
+                    rtfm::export::lock(
+                        &mut (),
+                        #name_str,
+                        #stats,
+                        self.priority,
+                        CEILING,
+                        #start..#end,
+                        |_| {
+                            f(#locked {
+                                #(#locked_values,)*
+                                __marker__: core::marker::PhantomData,
+                            })
+                        },
+                    )
+                }
+            }
+        }
+    ));
+
+    (item, constructor)
+}
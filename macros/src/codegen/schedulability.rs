@@ -0,0 +1,61 @@
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use rtfm_syntax::ast::App;
+use syn::Ident;
+
+use crate::{
+    analyze::{self, Analysis},
+    parse::Schedulability,
+};
+
+/// Reports the tasks that can miss their deadlines as warnings, under
+/// `#[app(schedulability = "warn")]`
+///
+/// NOTE there's no stable API to emit a warning from a procedural macro so this uses a deprecated
+/// item; the warning points at the `wcet` of the task
+pub fn warnings(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let schedulability = analysis.extensions.app.schedulability.map(|(s, _)| s);
+    if schedulability != Some(Schedulability::Warn) {
+        return vec![];
+    }
+
+    analysis
+        .timings
+        .iter()
+        .filter(|(_, timing)| timing.response.is_none())
+        .map(|(name, timing)| {
+            let core = app.software_tasks[name].args.core;
+            let utilization = analyze::utilization(core, app, analysis)
+                .values()
+                .sum::<f64>();
+            let note = format!(
+                "`{}` can miss its deadline ({}); the utilization of core #{} is {:.0}%",
+                name,
+                timing.describe(),
+                core,
+                utilization * 100.
+            );
+            let span = analysis.extensions.tasks[name]
+                .wcet
+                .map(|(_, span)| span)
+                .unwrap_or_else(Span::call_site);
+
+            let ident = Ident::new(&format!("{}_can_miss_its_deadline", name), span);
+            let user = Ident::new(&format!("__{}_schedulability", name), span);
+            let cfgs = &app.software_tasks[name].cfgs;
+            let use_ = quote_spanned!(span=> let _ = #ident;);
+            quote!(
+                #(#cfgs)*
+                #[deprecated(note = #note)]
+                #[allow(non_upper_case_globals)]
+                const #ident: () = ();
+
+                #(#cfgs)*
+                #[allow(dead_code)]
+                fn #user() {
+                    #use_
+                }
+            )
+        })
+        .collect()
+}
@@ -12,6 +12,8 @@ use crate::{
 pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
     let mut items = vec![];
 
+    let monotonic = util::monotonic(analysis);
+
     let mut seen = BTreeSet::new();
     for (scheduler, schedulees) in app.schedule_callers() {
         if schedulees.is_empty() {
@@ -19,6 +21,8 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
         }
 
         let mut methods = vec![];
+        let mut after_methods = vec![];
+        let mut wall_methods = vec![];
 
         for name in schedulees {
             let schedulee = &app.software_tasks[name];
@@ -27,22 +31,60 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
 
             let cfgs = &schedulee.cfgs;
 
+            let ok = if util::has_schedule_handle(name, app) {
+                quote!(#name::ScheduleHandle)
+            } else {
+                quote!(())
+            };
+
             let schedule = util::schedule_ident(name);
             if scheduler.is_init() {
-                let body = schedule_body::codegen(scheduler, name, app, analysis);
+                let body = schedule_body::codegen(scheduler, name, false, app, analysis);
 
                 let args = args.clone();
                 methods.push(quote!(
                     #(#cfgs)*
-                    fn #name(&self, instant: rtfm::Instant #(,#args)*) -> Result<(), #ty> {
+                    fn #name(
+                        &self,
+                        instant: rtfm::Instant
+                            #(,#args)*
+                    ) -> Result<#ok, rtfm::SpawnError<#ty>> {
+                        #body
+                    }
+                ));
+
+                after_methods.push(quote!(
+                    #(#cfgs)*
+                    fn #name(
+                        &self,
+                        duration: core::time::Duration
+                            #(,#args)*
+                    ) -> Result<#ok, rtfm::SpawnError<#ty>> {
+                        let instant = <#monotonic as rtfm::Monotonic>::now() + duration;
+
                         #body
                     }
                 ));
+
+                if util::wall_clock(analysis) {
+                    let body = schedule_body::codegen(scheduler, name, true, app, analysis);
+
+                    wall_methods.push(quote!(
+                        #(#cfgs)*
+                        fn #name(
+                            &self,
+                            time: std::time::SystemTime
+                                #(,#args)*
+                        ) -> Result<(), rtfm::SpawnError<#ty>> {
+                            #body
+                        }
+                    ));
+                }
             } else {
                 if !seen.contains(name) {
                     seen.insert(name);
 
-                    let body = schedule_body::codegen(scheduler, name, app, analysis);
+                    let body = schedule_body::codegen(scheduler, name, false, app, analysis);
                     let args = args.clone();
 
                     items.push(quote!(
@@ -51,21 +93,74 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
                             priority: &rtfm::export::Priority,
                             instant: rtfm::Instant
                                 #(,#args)*
-                        ) -> Result<(), #ty> {
+                        ) -> Result<#ok, rtfm::SpawnError<#ty>> {
                             #body
                         }
                     ));
+
+                    if util::wall_clock(analysis) {
+                        let body = schedule_body::codegen(scheduler, name, true, app, analysis);
+                        let schedule_wall = util::schedule_wall_ident(name);
+                        let args = args.clone();
+
+                        items.push(quote!(
+                            #(#cfgs)*
+                            fn #schedule_wall(
+                                priority: &rtfm::export::Priority,
+                                time: std::time::SystemTime
+                                    #(,#args)*
+                            ) -> Result<(), rtfm::SpawnError<#ty>> {
+                                #body
+                            }
+                        ));
+                    }
                 }
 
                 methods.push(quote!(
                     #(#cfgs)*
                     #[inline(always)]
-                    fn #name(&self, instant: rtfm::Instant #(,#args)*) -> Result<(), #ty> {
+                    fn #name(
+                        &self,
+                        instant: rtfm::Instant
+                            #(,#args)*
+                    ) -> Result<#ok, rtfm::SpawnError<#ty>> {
+                        let priority = unsafe { self.priority() };
+
+                        #schedule(priority, instant #(,#untupled)*)
+                    }
+                ));
+
+                after_methods.push(quote!(
+                    #(#cfgs)*
+                    #[inline(always)]
+                    fn #name(
+                        &self,
+                        duration: core::time::Duration
+                            #(,#args)*
+                    ) -> Result<#ok, rtfm::SpawnError<#ty>> {
                         let priority = unsafe { self.priority() };
+                        let instant = <#monotonic as rtfm::Monotonic>::now() + duration;
 
                         #schedule(priority, instant #(,#untupled)*)
                     }
                 ));
+
+                if util::wall_clock(analysis) {
+                    let schedule_wall = util::schedule_wall_ident(name);
+                    wall_methods.push(quote!(
+                        #(#cfgs)*
+                        #[inline(always)]
+                        fn #name(
+                            &self,
+                            time: std::time::SystemTime
+                                #(,#args)*
+                        ) -> Result<(), rtfm::SpawnError<#ty>> {
+                            let priority = unsafe { self.priority() };
+
+                            #schedule_wall(priority, time #(,#untupled)*)
+                        }
+                    ));
+                }
             }
         }
 
@@ -74,11 +169,98 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
         } else {
             Some(quote!('a))
         };
+        let scheduler_cfgs = util::cfgs(scheduler, app);
         let scheduler = scheduler.ident(app);
         items.push(quote!(
+            #(#scheduler_cfgs)*
             impl<#lt> #scheduler::Schedule<#lt> {
                 #(#methods)*
             }
+
+            #(#scheduler_cfgs)*
+            impl<#lt> #scheduler::SpawnAfter<#lt> {
+                #(#after_methods)*
+            }
+        ));
+
+        if util::wall_clock(analysis) {
+            items.push(quote!(
+                #(#scheduler_cfgs)*
+                impl<#lt> #scheduler::ScheduleWall<#lt> {
+                    #(#wall_methods)*
+                }
+            ));
+        }
+    }
+
+    for (name, task) in &app.software_tasks {
+        if !util::has_schedule_handle(name, app) {
+            continue;
+        }
+
+        let core = task.args.core;
+        let (_, _, _, ty) = util::regroup_inputs(&task.inputs);
+        let cfgs = &task.cfgs;
+
+        let fq = util::fq_ident_(name, core);
+        let tq = util::tq_ident(core);
+        let inputs = util::inputs_ident(name);
+        let instants = util::instants_ident(name);
+        let release = util::release_slot(name, app, analysis);
+
+        let signo = analysis.signals[&core].map[&analysis.timer_queues[&core].priority];
+        let tgid_tid = if util::process_directed(app, analysis) {
+            quote!(None)
+        } else {
+            let tid = util::tid_ident(core);
+            let tgid = util::tgid(core, analysis);
+
+            quote!(Some((#tgid, #tid.get())))
+        };
+
+        // NOTE this critical section also covers the dispatcher, the other producer of `#fq`
+        let range = analysis.signals[&core].range();
+        let (start, end) = (range.start, range.end);
+        items.push(quote!(
+            #(#cfgs)*
+            impl #name::ScheduleHandle {
+                /// Cancels the scheduled task and returns its payload
+                ///
+                /// Returns `Err` if the task has already been dispatched
+                pub fn cancel(self) -> Result<#ty, ()> {
+                    unsafe {
+                        rtfm::export::free(#start..#end, || {
+                            let nr = #tq.cancel(self.marker).ok_or(())?;
+
+                            let input = #inputs.get_unchecked(usize::from(nr.index)).as_ptr().read();
+                            #fq.split().0.enqueue_unchecked(nr.index);
+                            #release
+
+                            Ok(input)
+                        })
+                    }
+                }
+
+                /// Moves the scheduled task to `instant`
+                ///
+                /// Returns `Err` if the task has already been dispatched
+                pub fn reschedule_at(self, instant: rtfm::Instant) -> Result<Self, ()> {
+                    unsafe {
+                        rtfm::export::free(#start..#end, || {
+                            let nr = #tq.cancel(self.marker).ok_or(())?;
+
+                            #instants.get_unchecked_mut(usize::from(nr.index)).as_mut_ptr().write(instant);
+                            let marker = #tq.enqueue_unchecked(
+                                rtfm::export::NotReady { instant, ..nr },
+                                #tgid_tid,
+                                #signo,
+                            );
+
+                            Ok(Self::new(marker))
+                        })
+                    }
+                }
+            }
         ));
     }
 
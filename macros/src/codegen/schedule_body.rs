@@ -3,9 +3,17 @@ use quote::quote;
 use rtfm_syntax::{ast::App, Context};
 use syn::Ident;
 
-use crate::{analyze::Analysis, codegen::util};
+use crate::{analyze::Analysis, codegen::util, parse::PastSchedule};
 
-pub fn codegen(ctxt: Context, name: &Ident, app: &App, analysis: &Analysis) -> TokenStream2 {
+/// Body of the `schedule` methods; `wall` selects the `schedule_wall` variant, which takes a
+/// `time: SystemTime` instead of an `instant`
+pub fn codegen(
+    ctxt: Context,
+    name: &Ident,
+    wall: bool,
+    app: &App,
+    analysis: &Analysis,
+) -> TokenStream2 {
     let sender = ctxt.core(app);
     let schedulee = &app.software_tasks[name];
     let receiver = schedulee.args.core;
@@ -13,66 +21,160 @@ pub fn codegen(ctxt: Context, name: &Ident, app: &App, analysis: &Analysis) -> T
     let (_, tupled, _, _) = util::regroup_inputs(&schedulee.inputs);
 
     let fq = util::fq_ident_(name, sender);
-    let tq = util::tq_ident(sender);
+    let tq = if wall {
+        util::wall_tq_ident(sender)
+    } else {
+        util::tq_ident(sender)
+    };
     let inputs = util::inputs_ident(name);
 
     let signo = analysis.signals[&sender].map[&analysis.timer_queues[&sender].priority];
-    let tgid_tid = if app.args.cores == 1 {
+    let tgid_tid = if util::process_directed(app, analysis) {
         quote!(None)
     } else {
         let tid = util::tid_ident(sender);
+        let tgid = util::tgid(sender, analysis);
 
         quote!(Some((
-            TGID.get(),
+            #tgid,
             #tid.get(),
         )))
     };
-    let (dequeue, enqueue) = if ctxt.is_init() {
+    let claim = util::claim_slot(name, app, analysis);
+    let release = util::release_slot(name, app, analysis);
+    let (dequeue, enqueue, free_slot) = if ctxt.is_init() {
         // `init` has exclusive access to these queues so we can bypass the resources AND
         // the consumer / producer split
         (
             quote!(#fq.dequeue()),
-            quote!(#tq.enqueue_unchecked(nr, #tgid_tid, #signo);),
+            quote!(#tq.enqueue(nr, #tgid_tid, #signo)),
+            quote!(#fq.enqueue_unchecked(index);),
         )
     } else {
+        // NOTE like `cancel`, this critical section also covers the dispatcher, the other
+        // producer of `#fq`
+        let range = analysis.signals[&sender].range();
+        let (start, end) = (range.start, range.end);
         (
             quote!((#fq { priority }).lock(|fq| fq.split().1.dequeue())),
             quote!((#tq { priority }).lock(|tq| {
-                tq.enqueue_unchecked(nr, #tgid_tid, #signo)
-            });),
+                tq.enqueue(nr, #tgid_tid, #signo)
+            })),
+            quote!(rtfm::export::free(#start..#end, || #fq.split().0.enqueue_unchecked(index));),
         )
     };
 
     let instants_write = if app.uses_schedule(receiver) {
         let instants = util::instants_ident(name);
+        let instant = if wall {
+            let monotonic = util::monotonic(analysis);
 
-        Some(quote!(#instants.get_unchecked_mut(usize::from(index)).as_mut_ptr().write(instant);))
+            quote!(rtfm::export::wall_to_monotonic::<#monotonic>(time))
+        } else {
+            quote!(instant)
+        };
+
+        Some(quote!(#instants.get_unchecked_mut(usize::from(index)).as_mut_ptr().write(#instant);))
     } else {
         None
     };
 
+    let instant = if wall {
+        quote!(rtfm::export::wall_instant(time))
+    } else {
+        quote!(instant)
+    };
+
+    // NOTE wall-clock entries can't be cancelled; their markers come from another queue
+    let (marker, ok) = if util::has_schedule_handle(name, app) && !wall {
+        (
+            quote!(marker),
+            quote!(Ok(#name::ScheduleHandle::new(marker))),
+        )
+    } else {
+        (quote!(_), quote!(Ok(())))
+    };
+
+    let (time, now) = if wall {
+        (quote!(time), quote!(std::time::SystemTime::now()))
+    } else {
+        let monotonic = util::monotonic(analysis);
+
+        (
+            quote!(instant),
+            quote!(<#monotonic as rtfm::Monotonic>::now()),
+        )
+    };
+    let (clamp, reject) = match util::past_schedule(name, analysis) {
+        PastSchedule::Fire => (None, None),
+
+        PastSchedule::Reject => (
+            None,
+            Some(quote!(
+                else if #time < #now {
+                    Err(rtfm::SpawnError::InstantPassed(input))
+                }
+            )),
+        ),
+
+        PastSchedule::Clamp => {
+            let margin = analysis
+                .extensions
+                .app
+                .past_schedule_margin
+                .map(|(nanos, _)| nanos)
+                .unwrap_or(0);
+
+            (
+                Some(quote!(
+                    let #time = core::cmp::max(
+                        #time,
+                        #now + core::time::Duration::from_nanos(#margin),
+                    );
+                )),
+                None,
+            )
+        }
+    };
+
+    let disabled = util::reject_disabled(name, analysis);
+
     let t = util::schedule_t_ident(sender);
     quote!(
         unsafe {
             use rtfm::Mutex as _;
 
+            #clamp
+
             let input = #tupled;
-            if let Some(index) = #dequeue {
+            if rtfm::export::shutting_down() {
+                Err(rtfm::SpawnError::ShuttingDown(input))
+            } #disabled #reject else if let Some(index) = #dequeue {
+                #claim
+
                 #instants_write
 
                 #inputs.get_unchecked_mut(usize::from(index)).as_mut_ptr().write(input);
 
                 let nr = rtfm::export::NotReady {
-                    instant,
+                    instant: #instant,
                     index,
                     task: #t::#name,
+                    marker: 0,
                 };
 
-                #enqueue
+                match #enqueue {
+                    Ok(#marker) => #ok,
+                    Err(_) => {
+                        let input = #inputs.get_unchecked(usize::from(index)).as_ptr().read();
+                        #free_slot
+                        #release
 
-                Ok(())
+                        Err(rtfm::SpawnError::TimerQueueFull(input))
+                    }
+                }
             } else {
-                Err(input)
+                Err(rtfm::SpawnError::Full(input))
             }
         }
     )
@@ -0,0 +1,163 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::{analyze::Priority, ast::App, Core};
+use syn::Ident;
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Declares the consumer ends of the shared memory channels
+pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    analysis
+        .shm_tasks
+        .keys()
+        .map(|name| {
+            let ty = &app.software_tasks[name].inputs[0].ty;
+            let channel = util::shm_ident(name);
+            let doc = format!("Shared memory channel that feeds `{}`", name);
+            let cfgs = &app.software_tasks[name].cfgs;
+
+            quote!(
+                #[doc = #doc]
+                #(#cfgs)*
+                static #channel: rtfm::export::ShmChannel<#ty> =
+                    rtfm::export::ShmChannel::uninit();
+            )
+        })
+        .collect()
+}
+
+/// Creates the shared memory channels of the tasks of `core`
+pub fn open(core: Core, app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    analysis
+        .shm_tasks
+        .iter()
+        .filter(|(name, _)| app.software_tasks[*name].args.core == core)
+        .map(|(name, &id)| {
+            let task = &app.software_tasks[name];
+            let signo = analysis.signals[&core].map[&task.args.priority];
+            let cap = task.args.capacity;
+            let path = shm_name(name, analysis);
+
+            // NOTE the producer is another process so it can't look the thread up
+            let tid = if util::process_directed(app, analysis) {
+                quote!(None)
+            } else {
+                let tid = util::tid_ident(core);
+                quote!(Some(#tid.get()))
+            };
+
+            let tgid = util::tgid(core, analysis);
+            let channel = util::shm_ident(name);
+            let cfgs = &task.cfgs;
+            quote!(
+                #(#cfgs)*
+                #channel.init(
+                    #path,
+                    #cap,
+                    #tgid,
+                    #tid,
+                    #signo,
+                    rtfm::export::SHM_EVENT | #id,
+                ).unwrap_or_else(rtfm::export::fatal);
+            )
+        })
+        .collect()
+}
+
+/// Removes the shared memory channels of the tasks of `core`
+pub fn close(core: Core, app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    analysis
+        .shm_tasks
+        .keys()
+        .filter(|name| app.software_tasks[*name].args.core == core)
+        .map(|name| {
+            let path = shm_name(name, analysis);
+            let cfgs = &app.software_tasks[name].cfgs;
+
+            quote!(
+                #(#cfgs)*
+                rtfm::export::shm_unlink(#path);
+            )
+        })
+        .collect()
+}
+
+/// Runs the tasks that are dispatched at priority `level` once per message in their channel
+///
+/// Returns `None` if there are no such tasks
+pub fn dispatch(
+    core: Core,
+    level: Priority,
+    app: &App,
+    analysis: &Analysis,
+) -> Option<TokenStream2> {
+    let arms = analysis
+        .shm_tasks
+        .iter()
+        .filter(|(name, _)| {
+            let task = &app.software_tasks[*name];
+
+            task.args.core == core && task.args.priority == level
+        })
+        .map(|(name, id)| {
+            let (let_instant, instant) = if app.uses_schedule(core) {
+                let monotonic = util::monotonic(analysis);
+                (
+                    Some(quote!(let instant = <#monotonic as rtfm::Monotonic>::now();)),
+                    Some(quote!(, instant)),
+                )
+            } else {
+                (None, None)
+            };
+
+            let call = util::run_task(
+                name,
+                core,
+                app,
+                analysis,
+                quote!(#name(
+                    #name::Locals::new(),
+                    #name::Context::new(priority #instant),
+                    message
+                )),
+            );
+
+            // NOTE a signal may find the channel empty if an earlier one already drained it
+            let channel = util::shm_ident(name);
+            let cfgs = &app.software_tasks[name].cfgs;
+            quote!(
+                #(#cfgs)*
+                #id => {
+                    while let Some(message) = #channel.pop() {
+                        #let_instant
+                        #call
+                    }
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if arms.is_empty() {
+        return None;
+    }
+
+    Some(quote!(
+        if si_value & rtfm::export::SHM_EVENT != 0 {
+            let priority = &rtfm::export::Priority::new(PRIORITY);
+            match si_value as u8 {
+                #(#arms)*
+                _ => {}
+            }
+
+            return;
+        }
+    ))
+}
+
+fn shm_name(name: &Ident, analysis: &Analysis) -> String {
+    analysis.extensions.tasks[name]
+        .shm
+        .as_ref()
+        .map(|lit| lit.value())
+        .expect("UNREACHABLE")
+}
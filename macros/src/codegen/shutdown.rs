@@ -0,0 +1,156 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::ast::App;
+
+use crate::{
+    analyze::Analysis,
+    codegen::{latency, log, periodic, shm, timer_queue, util},
+};
+
+/// Generates the shutdown handlers and the statements that register them
+pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    let children = analysis
+        .used_cores
+        .iter()
+        .cloned()
+        .filter(|&core| core != 0)
+        .collect::<Vec<_>>();
+
+    for core in (0..app.args.cores).filter(|&core| core == 0 || children.contains(&core)) {
+        let signo = analysis.signals[&core].shutdown;
+        let mut body = vec![quote!(
+            let _dispatched = rtfm::export::Dispatched::new(#signo, si);
+        )];
+
+        if core == 0 {
+            // the other cores drain their tasks and exit first
+            for &child in &children {
+                let tid = util::tid_ident(child);
+                let signo = analysis.signals[&child].shutdown;
+                let sd = util::sd_ident(child);
+
+                let tgid = util::tgid(child, analysis);
+                let (ty, expr) = util::shared_static(
+                    util::processes(analysis),
+                    quote!(rtfm::export::Barrier),
+                    quote!(rtfm::export::Barrier::new()),
+                );
+
+                const_app.push(quote!(
+                    static #sd: #ty = #expr;
+                ));
+
+                body.push(quote!(
+                    rtfm::export::request_shutdown(#tgid, #tid.get(), #signo);
+                    #sd.wait();
+                ));
+            }
+        }
+
+        body.extend(periodic::stop(core, app, analysis));
+        body.extend(shm::close(core, app, analysis));
+
+        if analysis.timer_queues.contains_key(&core) {
+            let timer = util::timer_ident(core);
+
+            body.push(quote!(
+                rtfm::export::timer_delete(#timer.get());
+            ));
+
+            if util::wall_clock(analysis) {
+                let timer = util::wall_timer_ident(core);
+
+                body.push(quote!(
+                    rtfm::export::timer_delete(#timer.get());
+                ));
+            }
+        }
+
+        body.extend(timer_queue::discard(core, app, analysis));
+
+        // drop the resources owned by this core; core #0 also drops the ones shared between cores
+        for (name, res, expr, loc) in app.resources(analysis) {
+            if loc.core().unwrap_or(0) != core {
+                continue;
+            }
+
+            let cfgs = &res.cfgs;
+            let ptr = if expr.is_none() {
+                quote!(#name.as_mut_ptr())
+            } else {
+                quote!(&mut #name)
+            };
+
+            body.push(quote!(
+                #(#cfgs)*
+                core::ptr::drop_in_place(#ptr);
+            ));
+        }
+
+        if core == 0 {
+            // all the other threads are gone by now
+            if let Some(f) = &analysis.extensions.deinit {
+                let deinit = &f.ident;
+                body.push(quote!(#deinit();));
+            }
+
+            body.extend(log::flush(analysis));
+            body.extend(latency::dump(analysis));
+            body.push(quote!(rtfm::export::save_on_exit();));
+
+            body.push(quote!(
+                rtfm::export::exit_group(rtfm::export::exit_code());
+            ));
+        } else {
+            let sd = util::sd_ident(core);
+
+            body.push(quote!(
+                #sd.release();
+                rtfm::export::exit(0);
+            ));
+        }
+
+        let handler = util::shutdown_ident(core);
+        let doc = format!("Core #{} shutdown handler", core);
+        const_app.push(quote!(
+            #[allow(non_snake_case)]
+            #[doc = #doc]
+            extern "C" fn #handler(
+                _: i32,
+                si: &mut rtfm::export::siginfo_t,
+                _: usize,
+            ) {
+                unsafe {
+                    #(#body)*
+                }
+            }
+        ));
+
+        let range = analysis.signals[&core].range();
+        let (start, end) = (range.start, range.end);
+        stmts.push(quote!(
+            rtfm::export::register_shutdown(#start..#end, #handler)
+                .unwrap_or_else(rtfm::export::fatal);
+        ));
+    }
+
+    let signo = analysis.signals[&0].shutdown;
+    stmts.push(quote!(
+        rtfm::export::init_shutdown(#signo);
+    ));
+
+    (const_app, stmts)
+}
+
+/// Unblocks the shutdown signal of `core`
+pub fn enable(core: u8, analysis: &Analysis) -> TokenStream2 {
+    let start = analysis.signals[&core].shutdown;
+    let end = start + 1;
+
+    quote!(
+        rtfm::export::mask(#start..#end, 0, 1, false);
+    )
+}
@@ -2,7 +2,8 @@ use std::collections::BTreeSet;
 
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
-use rtfm_syntax::ast::App;
+use rtfm_syntax::{ast::App, Context};
+use syn::Ident;
 
 use crate::{
     analyze::Analysis,
@@ -12,6 +13,8 @@ use crate::{
 pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
     let mut items = vec![];
 
+    let monotonic = util::monotonic(analysis);
+
     let mut seen = BTreeSet::new();
     for (spawner, spawnees) in app.spawn_callers() {
         let sender = spawner.core(app);
@@ -31,13 +34,13 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
                 let body = spawn_body::codegen(spawner, &name, app, analysis);
 
                 let let_instant = if app.uses_schedule(sender) {
-                    Some(quote!(let instant = rtfm::Instant::now();))
+                    Some(quote!(let instant = <#monotonic as rtfm::Monotonic>::now();))
                 } else {
                     None
                 };
                 methods.push(quote!(
                     #(#cfgs)*
-                    fn #name(&self #(,#args)*) -> Result<(), #ty> {
+                    fn #name(&self #(,#args)*) -> Result<(), rtfm::SpawnError<#ty>> {
                         #let_instant
                         #body
                     }
@@ -62,7 +65,7 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
                             priority: &rtfm::export::Priority
                             #instant
                             #(,#args)*
-                        ) -> Result<(), #ty> {
+                        ) -> Result<(), rtfm::SpawnError<#ty>> {
                             #body
                         }
                     ));
@@ -71,7 +74,7 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
                 let (let_instant, instant) = if app.uses_schedule(receiver) {
                     (
                         Some(if spawner.is_idle() {
-                            quote!(let instant = rtfm::Instant::now();)
+                            quote!(let instant = <#monotonic as rtfm::Monotonic>::now();)
                         } else {
                             quote!(let instant = self.instant();)
                         }),
@@ -84,7 +87,7 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
                 methods.push(quote!(
                     #(#cfgs)*
                     #[inline(always)]
-                    fn #name(&self #(,#args)*) -> Result<(), #ty> {
+                    fn #name(&self #(,#args)*) -> Result<(), rtfm::SpawnError<#ty>> {
                         unsafe {
                             #let_instant
                             #spawn(self.priority() #instant #(,#untupled)*)
@@ -99,12 +102,99 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
         } else {
             Some(quote!('a))
         };
+        let spawner_cfgs = util::cfgs(spawner, app);
         items.push(quote!(
+            #(#spawner_cfgs)*
             impl<#lt> #spawner_ident::Spawn<#lt> {
                 #(#methods)*
             }
         ));
+
+        let blocking_methods = util::blocking_spawnees(spawner, app, analysis)
+            .into_iter()
+            .map(|name| blocking_method(spawner, name, app, analysis))
+            .collect::<Vec<_>>();
+        if !blocking_methods.is_empty() {
+            items.push(quote!(
+                #(#spawner_cfgs)*
+                impl<'a> #spawner_ident::SpawnBlocking<'a> {
+                    #(#blocking_methods)*
+                }
+            ));
+        }
     }
 
     items
 }
+
+// `spawn_blocking.${name}` retries `spawn_${name}` every time the dispatcher frees one of the
+// message slots of `name`
+//
+// NOTE the caller can only wait if the spawnee is able to run, and free a slot, in the meantime.
+// A spawnee on the same core must be able to preempt the caller at its current priority; if it
+// can't the payload is handed back right away, like `spawn` does
+fn blocking_method(spawner: Context, name: &Ident, app: &App, analysis: &Analysis) -> TokenStream2 {
+    let spawnee = &app.software_tasks[name];
+    let cfgs = &spawnee.cfgs;
+    let (args, tupled, untupled, ty) = util::regroup_inputs(&spawnee.inputs);
+
+    let spawn = util::spawn_ident(name);
+    let free = util::free_slots_ident(name);
+
+    let (let_instant, instant) = if app.uses_schedule(spawnee.args.core) {
+        (
+            Some(if spawner.is_idle() {
+                let monotonic = util::monotonic(analysis);
+                quote!(let instant = <#monotonic as rtfm::Monotonic>::now();)
+            } else {
+                quote!(let instant = spawn.instant();)
+            }),
+            Some(quote!(, instant)),
+        )
+    } else {
+        (None, None)
+    };
+
+    let cannot_wait = if spawner.core(app) == spawnee.args.core {
+        let priority = spawnee.args.priority;
+        Some(quote!(
+            if !priority.is_below(#priority) {
+                return Err(rtfm::SpawnError::Full(input));
+            }
+        ))
+    } else {
+        None
+    };
+
+    quote!(
+        #(#cfgs)*
+        /// Waits for a free message slot instead of returning `Err` if the task has too many
+        /// messages pending
+        ///
+        /// Returns `Err` if the application is shutting down or if the task can't preempt this
+        /// context (at its current priority)
+        fn #name(&self #(,#args)*) -> Result<(), rtfm::SpawnError<#ty>> {
+            unsafe {
+                let spawn = self.spawn();
+                let priority = spawn.priority();
+
+                let mut input = #tupled;
+                loop {
+                    let token = #free.token();
+
+                    #let_instant
+                    let #tupled = input;
+                    input = match #spawn(priority #instant #(,#untupled)*) {
+                        Ok(()) => return Ok(()),
+                        Err(rtfm::SpawnError::Full(input)) => input,
+                        Err(e) => return Err(e),
+                    };
+
+                    #cannot_wait
+
+                    #free.wait(token);
+                }
+            }
+        }
+    )
+}
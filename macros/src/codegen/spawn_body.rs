@@ -3,7 +3,10 @@ use quote::quote;
 use rtfm_syntax::{ast::App, Context};
 use syn::Ident;
 
-use crate::{analyze::Analysis, codegen::util};
+use crate::{
+    analyze::Analysis,
+    codegen::{background, util},
+};
 
 /// Creates the body of `spawn_${name}`
 pub fn codegen<'a>(
@@ -12,6 +15,10 @@ pub fn codegen<'a>(
     app: &'a App,
     analysis: &Analysis,
 ) -> TokenStream2 {
+    if analysis.background.contains_key(name) {
+        return background::spawn_body(context, name, app, analysis);
+    }
+
     let sender = context.core(app);
     let spawnee = &app.software_tasks[name];
     let receiver = spawnee.args.core;
@@ -34,38 +41,50 @@ pub fn codegen<'a>(
         None
     };
 
+    let claim = util::claim_slot(name, app, analysis);
+
     let variant = util::task_ident(name, sender);
     let signo = analysis.signals[&receiver].map[&priority];
-    let enqueue = if app.args.cores == 1 {
+    let enqueue = if util::process_directed(app, analysis) {
         quote!(
-            rtfm::export::enqueue(
+            rtfm::export::try_enqueue(
                 TGID.get(),
                 None,
                 #signo,
                 #t::#variant as u8,
                 index,
-            );
+            )
         )
     } else {
         let tid = util::tid_ident(receiver);
+        let tgid = util::tgid(receiver, analysis);
 
         quote!(
-            rtfm::export::enqueue(
-                TGID.get(),
+            rtfm::export::try_enqueue(
+                #tgid,
                 Some(#tid.get()),
                 #signo,
                 #t::#variant as u8,
                 index,
-            );
+            )
         )
     };
 
-    let dequeue = if context.is_init() {
+    let release = util::release_slot(name, app, analysis);
+    let reject = util::reject_disabled(name, analysis);
+    let (dequeue, free_slot) = if context.is_init() {
         // `init` has exclusive access to these queues so we can bypass the resources AND
         // the consumer / producer split
-        quote!(#fq.dequeue())
+        (quote!(#fq.dequeue()), quote!(#fq.enqueue_unchecked(index);))
     } else {
-        quote!((#fq { priority }).lock(|fq| fq.split().1.dequeue()))
+        // NOTE like in `schedule`, this critical section also covers the dispatcher, the other
+        // producer of `#fq`
+        let range = analysis.signals[&sender].range();
+        let (start, end) = (range.start, range.end);
+        (
+            quote!((#fq { priority }).lock(|fq| fq.split().1.dequeue())),
+            quote!(rtfm::export::free(#start..#end, || #fq.split().0.enqueue_unchecked(index));),
+        )
     };
 
     quote!(
@@ -73,16 +92,26 @@ pub fn codegen<'a>(
             use rtfm::Mutex as _;
 
             let input = #tupled;
-            if let Some(index) = #dequeue {
+            if rtfm::export::shutting_down() {
+                Err(rtfm::SpawnError::ShuttingDown(input))
+            } #reject else if let Some(index) = #dequeue {
+                #claim
+
                 #inputs.get_unchecked_mut(usize::from(index)).as_mut_ptr().write(input);
 
                 #write_instant
 
-                #enqueue
+                if #enqueue {
+                    Ok(())
+                } else {
+                    let input = #inputs.get_unchecked(usize::from(index)).as_ptr().read();
+                    #free_slot
+                    #release
 
-                Ok(())
+                    Err(rtfm::SpawnError::SignalQueueFull(input))
+                }
             } else {
-                Err(input)
+                Err(rtfm::SpawnError::Full(input))
             }
         }
     )
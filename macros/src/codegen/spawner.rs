@@ -0,0 +1,152 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::{analyze::Priority, ast::App, Core};
+use syn::Ident;
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Creates the queues of the messages sent through `Spawner`s
+pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    for name in analysis.spawner_tasks.keys() {
+        let task = &app.software_tasks[name];
+        let (_, _, _, ty) = util::regroup_inputs(&task.inputs);
+        let cap = usize::from(util::capacity(name, app, analysis));
+
+        let queue = util::spawner_ident(name);
+        let doc = format!("Messages sent to `{}` through its `Spawner`s", name);
+        let cfgs = &task.cfgs;
+        const_app.push(quote!(
+            #[doc = #doc]
+            #(#cfgs)*
+            static #queue: rtfm::export::SpawnQueue<#ty, [rtfm::export::SpawnSlot<#ty>; #cap]> =
+                rtfm::export::SpawnQueue::uninit();
+        ));
+
+        stmts.push(quote!(
+            #(#cfgs)*
+            #queue.init();
+        ));
+    }
+
+    (const_app, stmts)
+}
+
+/// The `Spawner` type of task `name` and the function that returns one
+pub fn module(name: &Ident, app: &App, analysis: &Analysis) -> Option<TokenStream2> {
+    let id = *analysis.spawner_tasks.get(name)?;
+
+    let task = &app.software_tasks[name];
+    let core = task.args.core;
+    let signo = analysis.signals[&core].map[&task.args.priority];
+    let (args, tupled, _, ty) = util::regroup_inputs(&task.inputs);
+
+    let tid = if util::process_directed(app, analysis) {
+        quote!(None)
+    } else {
+        let tid = util::tid_ident(core);
+        quote!(Some(&super::#tid))
+    };
+
+    let queue = util::spawner_ident(name);
+    Some(quote!(
+        /// Spawns this task from threads that the runtime doesn't manage
+        pub type Spawner = rtfm::Spawner<#ty>;
+
+        /// Returns a `Spawner` of this task; it can be cloned and sent to other threads
+        pub fn spawner() -> Spawner {
+            unsafe {
+                rtfm::export::Spawner::new(
+                    &super::#queue,
+                    &super::TGID,
+                    #tid,
+                    #signo,
+                    rtfm::export::SPAWNER_EVENT | #id,
+                )
+            }
+        }
+
+        /// Spawns this task from any context or thread, like `spawn` does in RTIC
+        pub fn spawn(#(#args),*) -> Result<(), #ty> {
+            spawner().spawn(#tupled).map_err(rtfm::SpawnError::into_payload)
+        }
+    ))
+}
+
+/// Runs the tasks that are dispatched at priority `level` once per message sent through their
+/// `Spawner`s
+///
+/// Returns `None` if there are no such tasks
+pub fn dispatch(
+    core: Core,
+    level: Priority,
+    app: &App,
+    analysis: &Analysis,
+) -> Option<TokenStream2> {
+    let arms = analysis
+        .spawner_tasks
+        .iter()
+        .filter(|(name, _)| {
+            let task = &app.software_tasks[*name];
+
+            task.args.core == core && task.args.priority == level
+        })
+        .map(|(name, id)| {
+            let task = &app.software_tasks[name];
+            let (_, tupled, pats, _) = util::regroup_inputs(&task.inputs);
+
+            let (let_instant, instant) = if app.uses_schedule(core) {
+                let monotonic = util::monotonic(analysis);
+                (
+                    Some(quote!(let instant = <#monotonic as rtfm::Monotonic>::now();)),
+                    Some(quote!(, instant)),
+                )
+            } else {
+                (None, None)
+            };
+
+            let call = util::run_task(
+                name,
+                core,
+                app,
+                analysis,
+                quote!(#name(
+                    #name::Locals::new(),
+                    #name::Context::new(priority #instant)
+                    #(,#pats)*
+                )),
+            );
+
+            // NOTE a signal may find the queue empty if an earlier one already drained it
+            let queue = util::spawner_ident(name);
+            let cfgs = &task.cfgs;
+            quote!(
+                #(#cfgs)*
+                #id => {
+                    while let Some(#tupled) = #queue.dequeue() {
+                        #let_instant
+                        #call
+                    }
+                }
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if arms.is_empty() {
+        return None;
+    }
+
+    Some(quote!(
+        if si_value & rtfm::export::SPAWNER_EVENT != 0 {
+            let priority = &rtfm::export::Priority::new(PRIORITY);
+            match si_value as u8 {
+                #(#arms)*
+                _ => {}
+            }
+
+            return;
+        }
+    ))
+}
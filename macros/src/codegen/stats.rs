@@ -0,0 +1,58 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::ast::App;
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Creates the tables of task execution-time statistics and lock contention counters
+pub fn codegen(app: &App, analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    if analysis.extensions.app.stats != Some(true) {
+        return (const_app, stmts);
+    }
+
+    // NOTE `util::run_task` indexes this table using the position of the task in `software_tasks`
+    let names = app
+        .software_tasks
+        .keys()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let len = names.len();
+    const_app.push(quote!(
+        /// Execution-time statistics of the software tasks
+        static STATS: [rtfm::export::TaskStats; #len] = [
+            #(rtfm::export::TaskStats::new(#names),)*
+        ];
+    ));
+
+    let locks = util::locked_resources(app, analysis)
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let len = locks.len();
+    const_app.push(quote!(
+        /// Lock contention counters of the resources that need a lock
+        static LOCKS: [rtfm::export::LockStats; #len] = [
+            #(rtfm::export::LockStats::new(#locks),)*
+        ];
+    ));
+
+    for core in 0..app.args.cores {
+        let busy = util::busy_ident(core);
+        let doc = format!("CPU time spent by the tasks of core #{}", core);
+
+        const_app.push(quote!(
+            #[doc = #doc]
+            static #busy: rtfm::export::Busy = rtfm::export::Busy::new();
+        ));
+    }
+
+    let perf_counters = analysis.extensions.app.perf_counters.map(|(on, _)| on) == Some(true);
+    stmts.push(quote!(
+        rtfm::export::init_stats(&STATS, &LOCKS, #perf_counters);
+    ));
+
+    (const_app, stmts)
+}
@@ -4,7 +4,7 @@ use rtfm_syntax::{ast::App, Context};
 
 use crate::{
     analyze::Analysis,
-    codegen::{locals, module, resources_struct, util},
+    codegen::{locals, lock_groups, module, resources_struct, util},
 };
 
 pub fn codegen(
@@ -30,65 +30,100 @@ pub fn codegen(
 
     for (name, task) in &app.software_tasks {
         let core = task.args.core;
+        let cfgs = &task.cfgs;
         let inputs = &task.inputs;
 
         if let Some(free_queues) = analysis.free_queues.get(name) {
             let (_, _, _, ty) = util::regroup_inputs(inputs);
 
-            let cap = task.args.capacity * free_queues.len() as u8;
+            let cap = util::capacity(name, app, analysis) * free_queues.len() as u16;
             let cap_lit = util::capacity_literal(cap);
 
             let elems = (0..cap)
                 .map(|_| quote!(core::mem::MaybeUninit::uninit()))
                 .collect::<Vec<_>>();
 
+            // NOTE with `processes = true` the cores that spawn the task may run in other processes
+            let shared = util::shared_messages(name, app, analysis);
+
             if app.uses_schedule(core) {
                 let task_instants = util::instants_ident(name);
 
-                let elems = elems.clone();
+                let (instants_ty, instants_expr) = util::shared_static(
+                    shared,
+                    quote!([core::mem::MaybeUninit<rtfm::Instant>; #cap_lit]),
+                    quote!([#(#elems,)*]),
+                );
                 const_app.push(quote!(
                     /// Buffer that holds the instants associated to the inputs of a task
-                    static mut #task_instants: [core::mem::MaybeUninit<rtfm::Instant>; #cap_lit] =
-                        [#(#elems,)*];
+                    #(#cfgs)*
+                    static mut #task_instants: #instants_ty = #instants_expr;
                 ));
             }
 
             let task_inputs = util::inputs_ident(name);
+            let (inputs_ty, inputs_expr) = util::shared_static(
+                shared,
+                quote!([core::mem::MaybeUninit<#ty>; #cap_lit]),
+                quote!([#(#elems,)*]),
+            );
             const_app.push(quote!(
                 /// Buffer that holds the inputs of a task
-                static mut #task_inputs: [core::mem::MaybeUninit<#ty>; #cap_lit] =
-                    [#(#elems,)*];
+                #(#cfgs)*
+                static mut #task_inputs: #inputs_ty = #inputs_expr;
             ));
 
-            let cap = task.args.capacity;
+            if util::backpressure(name, analysis) {
+                let free = util::free_slots_ident(name);
+                const_app.push(quote!(
+                    /// Wakes up the contexts that wait for a free slot in the previous buffer(s)
+                    #(#cfgs)*
+                    static #free: rtfm::export::FreeSlots = rtfm::export::FreeSlots::new();
+                ));
+            }
+
+            let cap = util::capacity(name, app, analysis);
             let cap_ty = util::typenum_capacity(cap, true);
             for (&sender, ceiling) in free_queues {
                 let task_fq = util::fq_ident_(name, sender);
 
                 let doc = "Queue version of a free-list that keeps track of empty slots in the previous buffer(s)";
                 let fq_ty = quote!(rtfm::export::FreeQueue<#cap_ty>);
+                let shared = shared && sender != core;
+                let (static_ty, expr) = util::shared_static(
+                    shared,
+                    fq_ty.clone(),
+                    quote!(rtfm::export::Queue(rtfm::export::iQueue::u16_sc())),
+                );
                 const_app.push(quote!(
                     #[doc = #doc]
-                    static mut #task_fq: #fq_ty = unsafe {
-                        rtfm::export::Queue(rtfm::export::iQueue::u8_sc())
-                    };
+                    #(#cfgs)*
+                    static mut #task_fq: #static_ty = unsafe { #expr };
                 ));
-                let ptr = quote!(&mut #task_fq);
+                let ptr = if shared {
+                    quote!(&mut *#task_fq)
+                } else {
+                    quote!(&mut #task_fq)
+                };
 
                 if let Some(ceil) = ceiling {
-                    const_app.push(quote!(struct #task_fq<'a> {
-                        priority: &'a rtfm::export::Priority,
-                    }));
+                    const_app.push(quote!(
+                        #(#cfgs)*
+                        struct #task_fq<'a> {
+                            priority: &'a rtfm::export::Priority,
+                        }
+                    ));
 
                     let range = analysis.signals[&core].range();
                     const_app.push(util::impl_mutex(
-                        &[],
+                        cfgs,
                         false,
                         &task_fq,
                         fq_ty,
                         *ceil,
                         range.clone(),
                         ptr,
+                        quote!(&[]),
                     ));
                 }
             }
@@ -97,7 +132,9 @@ pub fn codegen(
         }
 
         let mut needs_lt = false;
-        if !task.args.resources.is_empty() {
+        // NOTE with `#[shared]` and `#[local]` every context has `shared` and `local` fields
+        let has_resources = !task.args.resources.is_empty() || analysis.extensions.model.is_some();
+        if has_resources {
             let (item, constructor) = resources_struct::codegen(
                 Context::SoftwareTask(name),
                 task.args.priority,
@@ -112,26 +149,55 @@ pub fn codegen(
         }
         mods.push(module::codegen(
             Context::SoftwareTask(name),
-            (!task.args.resources.is_empty(), needs_lt),
+            (has_resources, needs_lt),
             !task.args.schedule.is_empty(),
             !task.args.spawn.is_empty(),
             false,
             app,
+            analysis,
         ));
 
         let attrs = &task.attrs;
         let context = &task.context;
-        let stmts = &task.stmts;
+        let stmts = lock_groups::rewrite(
+            Context::SoftwareTask(name),
+            task.args.priority,
+            &task.stmts,
+            app,
+            analysis,
+        );
         let (locals_struct, locals_pat) =
             locals::codegen(Context::SoftwareTask(name), &task.locals, app);
         locals_structs.push(locals_struct);
+        let output = analysis
+            .extensions
+            .tasks
+            .get(name)
+            .and_then(|args| args.output.as_ref());
+
+        // NOTE the body of an `extern` task is the function `#name` that the module of `#[app]`
+        // imports; the wrapper lives in `const APP` so that it doesn't collide with the import
+        if analysis.extensions.extern_tasks.contains_key(name) {
+            let (args, _, untupled, _) = util::regroup_inputs(inputs);
+            const_app.push(quote!(
+                #(#cfgs)*
+                #[allow(non_snake_case)]
+                fn #name(#locals_pat, context: #name::Context #(,#args)*) #output {
+                    self::#name(context #(,#untupled)*)
+                }
+            ));
+
+            continue;
+        }
+
         user_code.push(quote!(
             #(#attrs)*
+            #(#cfgs)*
             #[allow(non_snake_case)]
-            fn #name(#locals_pat, #context: #name::Context #(,#inputs)*) {
+            fn #name(#locals_pat, #context: #name::Context #(,#inputs)*) #output {
                 use rtfm::Mutex as _;
 
-                #(#stmts)*
+                #stmts
             }
         ));
     }
@@ -0,0 +1,100 @@
+use std::collections::BTreeSet;
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::{analyze::Priority, ast::App, Core};
+
+use crate::{analyze::Analysis, codegen::util};
+
+/// Generates the threads that run the priority levels when `dispatch = "threads"`
+///
+/// Each thread waits for the signal of its priority level and then calls the signal handler that
+/// would have been registered in the default mode
+pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let mut const_app = vec![];
+
+    if !util::threads(analysis) {
+        return const_app;
+    }
+
+    for &core in &analysis.used_cores {
+        for level in levels(core, app, analysis) {
+            let thread = util::level_ident(core, level);
+            let signo = analysis.signals[&core].map[&level];
+            let handler = util::rt_ident(signo);
+
+            let doc = format!("Core #{} priority level {}", core, level);
+            let name = format!("rtfm-c{}-p{}", core, level);
+            const_app.push(quote!(
+                #[allow(non_snake_case)]
+                #[doc = #doc]
+                extern "C" fn #thread() -> ! {
+                    unsafe {
+                        rtfm::export::register_thread(
+                            Some(#name),
+                            Some(#core),
+                            Some(#core),
+                            rtfm::export::level_priority(#level),
+                        );
+                        rtfm::export::fault_stack().unwrap_or_else(rtfm::export::fatal);
+
+                        loop {
+                            let mut si = rtfm::export::sigwait(#signo);
+                            #handler(0, &mut si, 0);
+                        }
+                    }
+                }
+            ));
+        }
+    }
+
+    const_app
+}
+
+/// Spawns the threads of the priority levels of `core`; this is `interrupt::enable` in the
+/// `dispatch = "threads"` mode
+///
+/// NOTE this must run on the thread of `core`: the new threads inherit its CPU affinity and its
+/// signal mask, which blocks all the task signals
+pub fn spawn(core: Core, app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let stack_size = analysis.stack_sizes[&core];
+
+    // NOTE highest priority first so that a task never runs before the levels that can preempt it
+    levels(core, app, analysis)
+        .into_iter()
+        .rev()
+        .map(|level| {
+            let thread = util::level_ident(core, level);
+            let name = format!("priority level {} of core #{}", level, core);
+
+            quote!(
+                let tid = rtfm::export::spawn(#thread, rtfm::export::stack_size(#stack_size), #name)
+                    .unwrap_or_else(rtfm::export::fatal);
+                rtfm::export::set_priority(tid, rtfm::export::level_priority(#level))
+                    .unwrap_or_else(rtfm::export::fatal);
+            )
+        })
+        .collect()
+}
+
+/// Priority levels of `core` that have a signal handler
+fn levels(core: Core, app: &App, analysis: &Analysis) -> BTreeSet<Priority> {
+    let mut levels = analysis
+        .channels
+        .get(&core)
+        .map(|dispatchers| dispatchers.keys().cloned().collect::<BTreeSet<_>>())
+        .unwrap_or_default();
+
+    if let Some(tq) = analysis.timer_queues.get(&core) {
+        levels.insert(tq.priority);
+    }
+
+    levels.extend(
+        util::exclusive_levels(app, analysis)
+            .into_iter()
+            .filter(|(c, _)| *c == core)
+            .map(|(_, level)| level),
+    );
+
+    levels
+}
@@ -0,0 +1,82 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use rtfm_syntax::ast::App;
+
+use crate::analyze::{self, Analysis};
+
+/// How often the sysfs attributes are read again when `throttling_poll` is not specified, in
+/// nanoseconds
+const THROTTLING_POLL: u64 = 1_000_000_000;
+
+/// Stack size of the throttling monitor, in bytes
+const MONITOR_STACK_SIZE: usize = 64 * 1024;
+
+/// Creates the thread that spawns the throttling task
+pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let mut const_app = vec![];
+
+    let name = if let Some((name, _)) = analysis
+        .extensions
+        .tasks
+        .iter()
+        .find(|(_, args)| analyze::is_throttling(args))
+    {
+        name
+    } else {
+        return const_app;
+    };
+
+    let poll = analysis
+        .extensions
+        .app
+        .throttling_poll
+        .map(|(nanos, _)| nanos)
+        .unwrap_or(THROTTLING_POLL);
+
+    let cfgs = &app.software_tasks[name].cfgs;
+    const_app.push(quote!(
+        /// Spawns the throttling task when the CPUs start or stop being throttled
+        #(#cfgs)*
+        extern "C" fn throttling_monitor() -> ! {
+            unsafe {
+                rtfm::export::register_thread(Some("rtfm-throttling"), None, None, 0);
+                rtfm::export::fault_stack().unwrap_or_else(rtfm::export::fatal);
+
+                rtfm::export::monitor_throttling(
+                    #name::spawner(),
+                    core::time::Duration::from_nanos(#poll),
+                )
+            }
+        }
+    ));
+
+    const_app
+}
+
+/// Starts the throttling monitor
+///
+/// NOTE the monitor inherits the signal mask of the thread of core #0 so this must run before the
+/// thread unblocks its signals
+pub fn start(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    analysis
+        .extensions
+        .tasks
+        .iter()
+        .filter(|(_, args)| analyze::is_throttling(args))
+        .map(|(name, _)| {
+            let cfgs = &app.software_tasks[name].cfgs;
+            quote!(
+                #(#cfgs)*
+                {
+                    let tid = rtfm::export::spawn(
+                        throttling_monitor,
+                        #MONITOR_STACK_SIZE,
+                        "the throttling monitor",
+                    )
+                    .unwrap_or_else(rtfm::export::fatal);
+                    rtfm::export::set_normal(tid).unwrap_or_else(rtfm::export::fatal);
+                }
+            )
+        })
+        .collect()
+}
@@ -10,6 +10,7 @@ pub fn codegen(
     app: &App,
     analysis: &Analysis,
 ) -> TokenStream2 {
+    let monotonic = util::monotonic(analysis);
     let timer = util::timer_ident(sender);
     let tq = util::tq_ident(sender);
     let arms = timer_queue
@@ -24,7 +25,7 @@ pub fn codegen(
             let pt = util::spawn_t_ident(receiver, task.args.priority);
             let pname = util::task_ident(name, sender);
 
-            let tid = if app.args.cores == 1 {
+            let tid = if util::process_directed(app, analysis) {
                 quote!(None)
             } else {
                 let tid = util::tid_ident(receiver);
@@ -40,16 +41,48 @@ pub fn codegen(
         })
         .collect::<Vec<_>>();
 
+    // NOTE both timers send the same signal so each expiration services both queues
+    let wall = if util::wall_clock(analysis) {
+        let timer = util::wall_timer_ident(sender);
+        let wtq = util::wall_tq_ident(sender);
+        let arms = &arms;
+
+        Some(quote!(
+            let timer = #timer.get();
+
+            ready.clear();
+            (#wtq {
+                priority: &rtfm::export::Priority::new(PRIORITY),
+            }).lock(|tq| tq.dequeue::<rtfm::export::ClockRealtime>(timer, &mut ready));
+
+            for &(task, index) in ready.iter() {
+                match task {
+                    #(#arms)*
+                }
+            }
+        ))
+    } else {
+        None
+    };
+
+    // NOTE the entries are taken out under a single lock and then posted outside of it
+    let cap = util::typenum_capacity(util::tq_capacity(sender, app, analysis), false);
+    let tgid = util::tgid(sender, analysis);
     quote!(
-        let tgid = TGID.get();
+        let tgid = #tgid;
         let timer = #timer.get();
 
-        while let Some((task, index)) = (#tq {
+        let mut ready = rtfm::export::Vec::<_, #cap>::new();
+        (#tq {
             priority: &rtfm::export::Priority::new(PRIORITY),
-        }).lock(|tq| tq.dequeue(timer)) {
+        }).lock(|tq| tq.dequeue::<#monotonic>(timer, &mut ready));
+
+        for &(task, index) in ready.iter() {
             match task {
                 #(#arms)*
             }
         }
+
+        #wall
     )
 }
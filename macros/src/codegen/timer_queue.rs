@@ -1,6 +1,6 @@
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
-use rtfm_syntax::ast::App;
+use rtfm_syntax::{ast::App, Core};
 
 use crate::{analyze::Analysis, codegen::util};
 
@@ -32,15 +32,18 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
             }
         ));
 
-        let cap = util::typenum_capacity(timer_queue.capacity, false);
+        let cap = util::typenum_capacity(util::tq_capacity(sender, app, analysis), false);
         let ty = quote!(rtfm::export::TimerQueue<#t, #cap>);
         let doc = format!("Core #{} timer queue", sender);
         let tq = util::tq_ident(sender);
+        let info = info(sender, false, analysis);
         items.push(quote!(
             #[doc = #doc]
-            static mut #tq: #ty = rtfm::export::TimerQueue(
-                rtfm::export::BinaryHeap(rtfm::export::iBinaryHeap::new())
-            );
+            static mut #tq: #ty = rtfm::export::TimerQueue {
+                heap: rtfm::export::BinaryHeap(rtfm::export::iBinaryHeap::new()),
+                marker: 0,
+                info: #info,
+            };
         ));
 
         let timer = util::timer_ident(sender);
@@ -61,12 +64,109 @@ pub fn codegen(app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
             &[],
             false,
             &tq,
-            ty,
+            ty.clone(),
             timer_queue.ceiling,
-            range,
+            range.clone(),
             quote!(&mut #tq),
+            quote!(&[]),
         ));
+
+        // `schedule_wall` entries; these reuse the free slots, and thus the capacity, of `#tq`
+        if util::wall_clock(analysis) {
+            let doc = format!("Core #{} wall-clock timer queue", sender);
+            let wtq = util::wall_tq_ident(sender);
+            let info = info(sender, true, analysis);
+            items.push(quote!(
+                #[doc = #doc]
+                static mut #wtq: #ty = rtfm::export::TimerQueue {
+                    heap: rtfm::export::BinaryHeap(rtfm::export::iBinaryHeap::new()),
+                    marker: 0,
+                    info: #info,
+                };
+            ));
+
+            let timer = util::wall_timer_ident(sender);
+            let doc = format!("{} timer", wtq.to_string());
+            items.push(quote!(
+                #[doc = #doc]
+                static #timer: rtfm::export::Timer = rtfm::export::Timer::uninit();
+            ));
+
+            items.push(quote!(
+                struct #wtq<'a> {
+                    priority: &'a rtfm::export::Priority,
+                }
+            ));
+
+            items.push(util::impl_mutex(
+                &[],
+                false,
+                &wtq,
+                ty,
+                timer_queue.ceiling,
+                range,
+                quote!(&mut #wtq),
+                quote!(&[]),
+            ));
+        }
     }
 
     items
 }
+
+/// Drops the payloads of the entries that are still in the timer queue(s) of `core`
+///
+/// NOTE the shutdown handler runs this after deleting the timer(s); the payloads need not be `Copy`
+/// so they must not be leaked
+pub fn discard(core: Core, app: &App, analysis: &Analysis) -> Vec<TokenStream2> {
+    let timer_queue = if let Some(timer_queue) = analysis.timer_queues.get(&core) {
+        timer_queue
+    } else {
+        return vec![];
+    };
+
+    let t = util::schedule_t_ident(core);
+    let arms = timer_queue
+        .tasks
+        .iter()
+        .map(|name| {
+            let cfgs = &app.software_tasks[name].cfgs;
+            let inputs = util::inputs_ident(name);
+
+            quote!(
+                #(#cfgs)*
+                #t::#name => core::ptr::drop_in_place(
+                    #inputs.get_unchecked_mut(usize::from(nr.index)).as_mut_ptr(),
+                ),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut tqs = vec![util::tq_ident(core)];
+    if util::wall_clock(analysis) {
+        tqs.push(util::wall_tq_ident(core));
+    }
+
+    tqs.into_iter()
+        .map(|tq| {
+            quote!(
+                while let Some(nr) = #tq.pop() {
+                    match nr.task {
+                        #(#arms)*
+                    }
+                }
+            )
+        })
+        .collect()
+}
+
+// where the timer queue publishes its length and head; see `codegen::introspect`
+fn info(sender: Core, wall: bool, analysis: &Analysis) -> TokenStream2 {
+    if util::introspect(analysis) {
+        let info = util::tq_info_ident(sender, wall);
+
+        quote!(Some(&#info))
+    } else {
+        quote!(None)
+    }
+}
@@ -1,10 +1,20 @@
 use core::ops::Range;
+use std::collections::BTreeSet;
 
 use proc_macro2::{Span, TokenStream as TokenStream2};
 use quote::quote;
-use rtfm_syntax::{ast::App, Context};
+use rtfm_syntax::{
+    analyze::{Ownership, Priority},
+    ast::App,
+    Context, Core,
+};
 use syn::{ArgCaptured, Attribute, Ident, IntSuffix, LitInt};
 
+use crate::{
+    analyze::Analysis,
+    parse::{BudgetPolicy, Dispatch, PastSchedule},
+};
+
 pub fn impl_mutex(
     cfgs: &[Attribute],
     resources_prefix: bool,
@@ -13,6 +23,7 @@ pub fn impl_mutex(
     ceiling: u8,
     Range { start, end }: Range<u8>,
     ptr: TokenStream2,
+    stats: TokenStream2,
 ) -> TokenStream2 {
     let (path, priority) = if resources_prefix {
         (quote!(resources::#name), quote!(self.priority()))
@@ -20,7 +31,36 @@ pub fn impl_mutex(
         (quote!(#name), quote!(self.priority))
     };
 
+    let name_str = name.to_string();
+    // resource proxies can also be locked at the ceiling of a lock group; see `lock_groups`
+    let group = if resources_prefix {
+        quote!(
+            #(#cfgs)*
+            impl<'a> #path<'a> {
+                #[doc(hidden)]
+                #[inline(always)]
+                pub fn __lock_group<R>(&mut self, ceiling: u8, f: impl FnOnce(&mut #ty) -> R) -> R {
+                    unsafe {
+                        rtfm::export::lock(
+                            #ptr,
+                            #name_str,
+                            #stats,
+                            #priority,
+                            ceiling,
+                            #start..#end,
+                            f,
+                        )
+                    }
+                }
+            }
+        )
+    } else {
+        quote!()
+    };
+
     quote!(
+        #group
+
         #(#cfgs)*
         impl<'a> rtfm::Mutex for #path<'a> {
             type T = #ty;
@@ -33,6 +73,8 @@ pub fn impl_mutex(
                 unsafe {
                     rtfm::export::lock(
                         #ptr,
+                        #name_str,
+                        #stats,
                         #priority,
                         CEILING,
                         #start..#end,
@@ -93,19 +135,606 @@ pub fn regroup_inputs(
     }
 }
 
-/// `u8` -> (unsuffixed) `LitInt`
-pub fn capacity_literal(capacity: u8) -> LitInt {
+/// The clock that drives the `schedule` API
+pub fn monotonic(analysis: &Analysis) -> TokenStream2 {
+    if let Some(path) = &analysis.extensions.app.monotonic {
+        quote!(#path)
+    } else {
+        quote!(rtfm::time::ClockMonotonic)
+    }
+}
+
+/// Runs task `name` with the message that core `sender` left in message slot `index`, and returns
+/// the slot to the free queue of `sender`
+pub fn run_message(name: &Ident, sender: Core, app: &App, analysis: &Analysis) -> TokenStream2 {
+    let task = &app.software_tasks[name];
+    let receiver = task.args.core;
+    let (_, tupled, pats, _) = regroup_inputs(&task.inputs);
+
+    let inputs = inputs_ident(name);
+    let fq = fq_ident_(name, sender);
+
+    let input = quote!(#inputs.get_unchecked(usize::from(index)).as_ptr().read());
+
+    let (let_instant, instant) = if app.uses_schedule(receiver) {
+        let instants = instants_ident(name);
+        let instant = quote!(#instants.get_unchecked(usize::from(index)).as_ptr().read());
+
+        (
+            Some(quote!(let instant = #instant;)),
+            Some(quote!(, instant)),
+        )
+    } else {
+        (None, None)
+    };
+
+    let call = run_task(
+        name,
+        receiver,
+        app,
+        analysis,
+        quote!(#name(
+            #name::Locals::new(),
+            #name::Context::new(priority #instant)
+            #(,#pats)*
+        )),
+    );
+
+    let release = release_slot(name, app, analysis);
+
+    quote!(
+        let #tupled = #input;
+        #let_instant
+        #fq.split().0.enqueue_unchecked(index);
+        #release
+        let priority = &rtfm::export::Priority::new(PRIORITY);
+        #call
+    )
+}
+
+/// What a panic in a context of core `core` leads to: the `#[panic_task]` or an abort
+pub fn on_panic(core: u8, analysis: &Analysis) -> TokenStream2 {
+    if let Some(f) = &analysis.extensions.panic_task {
+        let panic_task = &f.ident;
+        let Range { start, end } = analysis.signals[&core].range();
+
+        quote!(|task| rtfm::export::free(#start..#end, || #panic_task(task)))
+    } else {
+        quote!(rtfm::export::abort)
+    }
+}
+
+/// Calls a task such that a panic doesn't unwind through the signal handler
+///
+/// A panic aborts the process unless there's a `#[panic_task]`, in which case it's called with all
+/// the signals of the core blocked. With `stats = true` the execution time of the task is recorded.
+/// Tasks with a `deadline` report to the `#[deadline_miss]` function if they complete late, and
+/// tasks with a `budget` are stopped from using more CPU time than that
+pub fn run_task(
+    name: &Ident,
+    core: u8,
+    app: &App,
+    analysis: &Analysis,
+    call: TokenStream2,
+) -> TokenStream2 {
+    let task = name.to_string();
+
+    // `then = bar`: the value the task returns becomes the message of `bar`
+    let then = analysis
+        .extensions
+        .tasks
+        .get(name)
+        .and_then(|args| args.then.as_ref());
+    let call = if let Some(then) = then {
+        let spawnee = &app.software_tasks[then];
+        let (_, tupled, untupled, _) = regroup_inputs(&spawnee.inputs);
+        let spawn = spawn_ident(then);
+        let instant = if app.uses_schedule(spawnee.args.core) {
+            Some(quote!(, instant))
+        } else {
+            None
+        };
+        let then = then.to_string();
+
+        quote!({
+            let #tupled = #call;
+            if let Err(error) = #spawn(priority #instant #(,#untupled)*) {
+                rtfm::export::lost_output(#task, #then, error);
+            }
+        })
+    } else {
+        call
+    };
+
+    let on_panic = on_panic(core, analysis);
+    let run = quote!(rtfm::export::run(#task, PRIORITY, || #call, #on_panic));
+    let run = if analysis.extensions.app.stats == Some(true) {
+        let index = task_index(name, app);
+        let busy = busy_ident(core);
+
+        quote!(rtfm::export::measure(&STATS[#index], &#busy, || #run);)
+    } else {
+        quote!(#run;)
+    };
+
+    // every task of a core that has budgets is accounted for so that the time of the tasks that
+    // preempt a task with a budget isn't charged to it
+    let run = if budgets(core, app, analysis) {
+        let budget = match analysis
+            .extensions
+            .tasks
+            .get(name)
+            .and_then(|args| args.budget)
+        {
+            Some((nanos, _)) => quote!(Some(#nanos)),
+            None => quote!(None),
+        };
+        let on_overrun = on_overrun(analysis);
+
+        quote!(rtfm::export::budget(#task, #budget, #on_overrun, || { #run });)
+    } else {
+        run
+    };
+
+    // NOTE `instant` is the instant the task was scheduled at; see `check::app`
+    let deadline = analysis
+        .extensions
+        .tasks
+        .get(name)
+        .and_then(|args| args.deadline);
+    if let Some((nanos, _)) = deadline {
+        let monotonic = monotonic(analysis);
+        let deadline_miss = &analysis
+            .extensions
+            .deadline_miss
+            .as_ref()
+            .expect("UNREACHABLE")
+            .ident;
+
+        let count = if introspect(analysis) {
+            let index = task_index(name, app);
+
+            Some(quote!(TASK_INFO[#index].missed();))
+        } else {
+            None
+        };
+
+        quote!(
+            #run
+
+            if let Some(lateness) = rtfm::export::lateness::<#monotonic>(instant, #nanos) {
+                #count
+                #deadline_miss(#task, lateness);
+            }
+        )
+    } else {
+        run
+    }
+}
+
+/// Whether some task of `core` has a `budget`
+pub fn budgets(core: Core, app: &App, analysis: &Analysis) -> bool {
+    analysis.extensions.tasks.iter().any(|(name, args)| {
+        args.budget.is_some()
+            && app.software_tasks.get(name).map(|task| task.args.core) == Some(core)
+    })
+}
+
+/// What happens to a task that exceeds its budget: the `#[budget_overrun]` function or the
+/// `budget_policy`
+fn on_overrun(analysis: &Analysis) -> TokenStream2 {
+    if let Some(f) = &analysis.extensions.budget_overrun {
+        let budget_overrun = &f.ident;
+        quote!(#budget_overrun)
+    } else {
+        match analysis.extensions.app.budget_policy {
+            Some((BudgetPolicy::Demote, _)) => quote!(rtfm::export::budget_demote),
+            _ => quote!(rtfm::export::budget_abort),
+        }
+    }
+}
+
+/// Position of task `name` in the table of heartbeats; `None` if it has no `heartbeat`
+pub fn heartbeat_index(name: &Ident, analysis: &Analysis) -> Option<usize> {
+    analysis
+        .extensions
+        .tasks
+        .iter()
+        .filter(|(_, args)| args.heartbeat.is_some())
+        .position(|(task, _)| task == name)
+}
+
+/// Whether the `schedule_wall` API is enabled (`wall_clock = true`)
+pub fn wall_clock(analysis: &Analysis) -> bool {
+    analysis.extensions.app.wall_clock == Some(true)
+}
+
+/// Whether each priority level runs on a thread of its own (`dispatch = "threads"`)
+pub fn threads(analysis: &Analysis) -> bool {
+    analysis.extensions.app.dispatch == Some(Dispatch::Threads)
+}
+
+/// Switches the process to the user of `RuntimeConfig::drop_privileges`, if any
+///
+/// NOTE the cores that start later keep working without the privileges because `raise_limits`
+/// lifted `RLIMIT_RTPRIO` and `RLIMIT_MEMLOCK` while the process had them
+pub fn drop_privileges() -> TokenStream2 {
+    quote!(rtfm::export::drop_privileges().unwrap_or_else(rtfm::export::fatal);)
+}
+
+/// Installs the seccomp filter, if the application asked for it (`seccomp = true`)
+pub fn install_seccomp(analysis: &Analysis) -> Option<TokenStream2> {
+    if let Some((true, _)) = analysis.extensions.app.seccomp {
+        Some(quote!(rtfm::export::install_seccomp().unwrap_or_else(rtfm::export::fatal);))
+    } else {
+        None
+    }
+}
+
+/// Whether every core but #0 runs in a process of its own (`processes = true`)
+pub fn processes(analysis: &Analysis) -> bool {
+    analysis
+        .extensions
+        .app
+        .processes
+        .map(|(processes, _)| processes)
+        == Some(true)
+}
+
+/// The thread group of the thread of `core`: the process of the application, or the process of
+/// the core if each core has its own
+pub fn tgid(core: u8, analysis: &Analysis) -> TokenStream2 {
+    if processes(analysis) {
+        let tid = tid_ident(core);
+        quote!(#tid.get())
+    } else {
+        quote!(TGID.get())
+    }
+}
+
+/// Whether the message slots of task `name` must be shared between processes: the cores run in
+/// processes of their own and another core spawns the task
+pub fn shared_messages(name: &Ident, app: &App, analysis: &Analysis) -> bool {
+    let core = app.software_tasks[name].args.core;
+
+    processes(analysis)
+        && analysis
+            .free_queues
+            .get(name)
+            .map(|senders| senders.keys().any(|&sender| sender != core))
+            .unwrap_or(false)
+}
+
+/// The type and the initializer of a static, wrapped in `rtfm::export::Shared` if `shared`
+pub fn shared_static(
+    shared: bool,
+    ty: TokenStream2,
+    expr: TokenStream2,
+) -> (TokenStream2, TokenStream2) {
+    if shared {
+        (
+            quote!(rtfm::export::Shared<#ty>),
+            quote!(rtfm::export::Shared::new(#expr)),
+        )
+    } else {
+        (ty, expr)
+    }
+}
+
+/// Whether the task signals are sent to the whole process rather than to the thread of a core
+///
+/// With `dispatch = "threads"` the signals are consumed by the thread of the priority level
+/// blocked in `sigtimedwait` so they can't be directed at the thread of the core
+pub fn process_directed(app: &App, analysis: &Analysis) -> bool {
+    app.args.cores == 1 || threads(analysis)
+}
+
+/// Priority levels that only dispatch tasks bound to file descriptors, periodic, external, shared
+/// memory and `Spawner` tasks
+///
+/// These levels have neither a task dispatcher nor the timer queue handler so they need a signal
+/// handler of their own
+pub fn exclusive_levels(app: &App, analysis: &Analysis) -> BTreeSet<(Core, Priority)> {
+    analysis
+        .fd_tasks
+        .keys()
+        .chain(analysis.periodic_tasks.keys())
+        .chain(analysis.external_tasks.keys())
+        .chain(analysis.shm_tasks.keys())
+        .chain(analysis.spawner_tasks.keys())
+        .map(|name| {
+            let task = &app.software_tasks[name];
+            (task.args.core, task.args.priority)
+        })
+        .filter(|(core, level)| {
+            let has_dispatcher = analysis
+                .channels
+                .get(core)
+                .map(|dispatchers| dispatchers.contains_key(level))
+                .unwrap_or(false);
+            let has_timer_queue = analysis
+                .timer_queues
+                .get(core)
+                .map(|tq| tq.priority == *level)
+                .unwrap_or(false);
+
+            !has_dispatcher && !has_timer_queue
+        })
+        .collect()
+}
+
+/// Whether `schedule`-ing `task` returns a `ScheduleHandle`
+///
+/// Only tasks that are exclusively scheduled from their own core get one: cancelling an entry
+/// hands its index back to the free queue, which must happen on the same core as the dispatcher
+pub fn has_schedule_handle(task: &Ident, app: &App) -> bool {
+    let core = app.software_tasks[task].args.core;
+
+    let mut scheduled = false;
+    for (scheduler, schedulees) in app.schedule_callers() {
+        if schedulees.contains(task) {
+            if scheduler.core(app) != core {
+                return false;
+            }
+
+            scheduled = true;
+        }
+    }
+
+    scheduled
+}
+
+/// How many messages of task `name` can be pending, per sender
+pub fn capacity(name: &Ident, app: &App, analysis: &Analysis) -> u16 {
+    analysis
+        .extensions
+        .tasks
+        .get(name)
+        .and_then(|args| args.capacity)
+        .map(|(capacity, _)| capacity)
+        .unwrap_or_else(|| u16::from(app.software_tasks[name].args.capacity))
+}
+
+/// The logical priority of task `name`; its priority level can be lower under
+/// `#[app(signal_levels = ..)]`
+pub fn logical_priority(name: &Ident, app: &App, analysis: &Analysis) -> u8 {
+    analysis
+        .extensions
+        .tasks
+        .get(name)
+        .and_then(|args| args.priority)
+        .map(|(priority, _)| priority)
+        .unwrap_or(app.software_tasks[name].args.priority)
+}
+
+/// What `schedule` does with an instant in the past for task `name`
+pub fn past_schedule(name: &Ident, analysis: &Analysis) -> PastSchedule {
+    analysis
+        .extensions
+        .tasks
+        .get(name)
+        .and_then(|args| args.past_schedule)
+        .or(analysis.extensions.app.past_schedule)
+        .unwrap_or(PastSchedule::Fire)
+}
+
+/// Whether the application tracks its queues; see `rtfm::introspect`
+///
+/// NOTE the metrics endpoint serves these tables so it enables them too
+pub fn introspect(analysis: &Analysis) -> bool {
+    analysis.extensions.app.introspect == Some(true) || analysis.extensions.app.metrics.is_some()
+}
+
+/// Bookkeeping done after a message slot of task `name` is taken by `spawn` or `schedule`
+pub fn claim_slot(name: &Ident, app: &App, analysis: &Analysis) -> Option<TokenStream2> {
+    if introspect(analysis) {
+        let index = task_index(name, app);
+
+        Some(quote!(TASK_INFO[#index].claim();))
+    } else {
+        None
+    }
+}
+
+/// Bookkeeping done after a message slot of task `name` is returned to its free queue
+pub fn release_slot(name: &Ident, app: &App, analysis: &Analysis) -> TokenStream2 {
+    let release = if introspect(analysis) {
+        let index = task_index(name, app);
+
+        Some(quote!(TASK_INFO[#index].release();))
+    } else {
+        None
+    };
+
+    let notify = if backpressure(name, analysis) {
+        let free = free_slots_ident(name);
+
+        Some(quote!(#free.notify();))
+    } else {
+        None
+    };
+
+    quote!(#release #notify)
+}
+
+/// Position of task `name` in `software_tasks`; it indexes the `STATS` and `TASK_INFO` tables
+pub fn task_index(name: &Ident, app: &App) -> usize {
+    app.software_tasks
+        .keys()
+        .position(|task| task == name)
+        .expect("UNREACHABLE")
+}
+
+/// The resources that get a lock contention counter, in the order of the `LOCKS` table
+pub fn locked_resources<'a>(app: &'a App, analysis: &'a Analysis) -> Vec<&'a Ident> {
+    app.resources(analysis)
+        .filter(|(name, ..)| match analysis.ownerships.get(*name) {
+            Some(Ownership::Shared { .. }) => true,
+            _ => false,
+        })
+        .map(|(name, ..)| name)
+        .collect()
+}
+
+/// The lock contention counters of `resources`, as the `stats` argument of `rtfm::export::lock`
+///
+/// Empty without `stats = true`; the internal locks, e.g. of the timer queues, pass no resources
+pub fn lock_stats(resources: &[&Ident], app: &App, analysis: &Analysis) -> TokenStream2 {
+    if analysis.extensions.app.stats != Some(true) {
+        return quote!(&[]);
+    }
+
+    let table = locked_resources(app, analysis);
+    let indices = resources.iter().filter_map(|name| {
+        table
+            .iter()
+            .position(|res| res == name)
+            .map(|i| quote!(&LOCKS[#i]))
+    });
+
+    quote!(&[#(#indices),*])
+}
+
+/// Whether task `name` was declared with `backpressure = true`
+pub fn backpressure(name: &Ident, analysis: &Analysis) -> bool {
+    analysis
+        .extensions
+        .tasks
+        .get(name)
+        .map(|args| args.backpressure == Some(true))
+        .unwrap_or(false)
+}
+
+/// The modes of the application, the initial one first; empty without `#[app(modes = ..)]`
+pub fn modes(analysis: &Analysis) -> &[Ident] {
+    analysis
+        .extensions
+        .app
+        .modes
+        .as_ref()
+        .map(|(modes, _)| &modes[..])
+        .unwrap_or(&[])
+}
+
+/// Bit mask of the indices of the modes that task `name` runs in; `None` if it runs in all of
+/// them
+pub fn mode_mask(name: &Ident, analysis: &Analysis) -> Option<u32> {
+    let task_modes = &analysis.extensions.tasks.get(name)?.modes.as_ref()?.0;
+
+    Some(
+        modes(analysis)
+            .iter()
+            .enumerate()
+            .filter(|(_, mode)| task_modes.iter().any(|(task_mode, _)| task_mode == *mode))
+            .fold(0, |mask, (i, _)| mask | 1 << i),
+    )
+}
+
+/// The period of the periodic task `name` in each mode, in nanoseconds; `0` in the modes that
+/// disable it
+pub fn mode_periods(name: &Ident, analysis: &Analysis) -> Vec<u64> {
+    let args = &analysis.extensions.tasks[name];
+    let period = args.period.map(|(nanos, _)| nanos).expect("UNREACHABLE");
+
+    modes(analysis)
+        .iter()
+        .map(|mode| match &args.modes {
+            Some((task_modes, _)) => task_modes
+                .iter()
+                .find(|(task_mode, _)| task_mode == mode)
+                .map(|(_, mode_period)| mode_period.unwrap_or(period))
+                .unwrap_or(0),
+            None => period,
+        })
+        .collect()
+}
+
+/// Whether task `name` can switch modes (`mode_switch = true`)
+pub fn mode_switch(name: &Ident, analysis: &Analysis) -> bool {
+    analysis
+        .extensions
+        .tasks
+        .get(name)
+        .map(|args| args.mode_switch.map(|(value, _)| value) == Some(true))
+        .unwrap_or(false)
+}
+
+/// Rejects the message with `SpawnError::Disabled` if the current mode disables task `name`; an
+/// `else if` branch of `spawn` and `schedule`
+pub fn reject_disabled(name: &Ident, analysis: &Analysis) -> Option<TokenStream2> {
+    mode_mask(name, analysis).map(|mask| {
+        quote!(
+            else if !rtfm::export::mode_enabled(#mask) {
+                Err(rtfm::SpawnError::Disabled(input))
+            }
+        )
+    })
+}
+
+/// The tasks that `ctxt` can `spawn_blocking`
+///
+/// NOTE `init` can't wait for a message slot because no task runs before it returns
+pub fn blocking_spawnees<'a>(ctxt: Context, app: &'a App, analysis: &Analysis) -> Vec<&'a Ident> {
+    let spawnees = match ctxt {
+        Context::Init(_) | Context::HardwareTask(_) => return vec![],
+        Context::Idle(core) => &app.idles[&core].args.spawn,
+        Context::SoftwareTask(name) => &app.software_tasks[name].args.spawn,
+    };
+
+    spawnees
+        .iter()
+        .filter(|name| backpressure(name, analysis))
+        .collect()
+}
+
+/// The broadcast groups whose tasks `ctxt` can all spawn
+pub fn broadcast_groups<'a>(ctxt: Context, app: &App, analysis: &'a Analysis) -> Vec<&'a Ident> {
+    let spawnees = match ctxt {
+        Context::HardwareTask(_) => return vec![],
+        Context::Init(core) => &app.inits[&core].args.spawn,
+        Context::Idle(core) => &app.idles[&core].args.spawn,
+        Context::SoftwareTask(name) => &app.software_tasks[name].args.spawn,
+    };
+
+    analysis
+        .extensions
+        .app
+        .broadcast
+        .iter()
+        .flatten()
+        .filter(|(_, tasks)| tasks.iter().all(|task| spawnees.contains(task)))
+        .map(|(group, _)| group)
+        .collect()
+}
+
+/// e.g. `0` -> `BROADCAST0_INDEXES`; the message slots of all but the first task of broadcast `id`
+pub fn broadcast_indexes_ident(id: usize) -> Ident {
+    Ident::new(&format!("BROADCAST{}_INDEXES", id), Span::call_site())
+}
+
+/// How many entries the timer queue of `sender` needs: one per message slot of its schedulees
+pub fn tq_capacity(sender: Core, app: &App, analysis: &Analysis) -> u16 {
+    analysis.timer_queues[&sender]
+        .tasks
+        .iter()
+        .map(|name| capacity(name, app, analysis))
+        .sum()
+}
+
+/// `u16` -> (unsuffixed) `LitInt`
+pub fn capacity_literal(capacity: u16) -> LitInt {
     LitInt::new(u64::from(capacity), IntSuffix::None, Span::call_site())
 }
 
-/// e.g. `4u8` -> `U4`
-pub fn typenum_capacity(capacity: u8, power_of_two: bool) -> TokenStream2 {
-    let capacity = if power_of_two {
-        capacity
-            .checked_next_power_of_two()
-            .expect("capacity.next_power_of_two()")
+/// e.g. `4u16` -> `U4`
+///
+/// NOTE `typenum` only names every number up to 1024; larger capacities are rounded up to a power
+/// of two
+pub fn typenum_capacity(capacity: u16, power_of_two: bool) -> TokenStream2 {
+    let capacity = if power_of_two || capacity > 1024 {
+        u32::from(capacity).next_power_of_two()
     } else {
-        capacity
+        u32::from(capacity)
     };
 
     let ident = Ident::new(&format!("U{}", capacity), Span::call_site());
@@ -118,11 +747,51 @@ pub fn inputs_ident(base: &Ident) -> Ident {
     Ident::new(&format!("{}_INPUTS", base), Span::call_site())
 }
 
+/// e.g. `foo` -> `foo_FD`
+pub fn fd_ident(task: &Ident) -> Ident {
+    Ident::new(&format!("{}_FD", task), Span::call_site())
+}
+
+/// e.g. `foo` -> `foo_SHM`
+pub fn shm_ident(task: &Ident) -> Ident {
+    Ident::new(&format!("{}_SHM", task), Span::call_site())
+}
+
+/// e.g. `foo` -> `foo_SPAWNER`
+pub fn spawner_ident(task: &Ident) -> Ident {
+    Ident::new(&format!("{}_SPAWNER", task), Span::call_site())
+}
+
+/// e.g. `0` -> `TQ0_INFO`; `WTQ0_INFO` for the wall-clock timer queue
+pub fn tq_info_ident(sender: Core, wall: bool) -> Ident {
+    let prefix = if wall { "W" } else { "" };
+
+    Ident::new(&format!("{}TQ{}_INFO", prefix, sender), Span::call_site())
+}
+
+/// e.g. `foo` -> `foo_FREE`
+pub fn free_slots_ident(task: &Ident) -> Ident {
+    Ident::new(&format!("{}_FREE", task), Span::call_site())
+}
+
+/// e.g. `foo` -> `foo_TIMER`
+pub fn period_timer_ident(task: &Ident) -> Ident {
+    Ident::new(&format!("{}_TIMER", task), Span::call_site())
+}
+
 /// e.g. `foo` -> `foo_INSTANTS`
 pub fn instants_ident(base: &Ident) -> Ident {
     Ident::new(&format!("{}_INSTANTS", base), Span::call_site())
 }
 
+/// The `#[cfg]` attributes of a context; only software tasks can have them
+pub fn cfgs<'a>(ctxt: Context, app: &'a App) -> &'a [Attribute] {
+    match ctxt {
+        Context::SoftwareTask(name) => &app.software_tasks[name].cfgs,
+        _ => &[],
+    }
+}
+
 pub fn locals_ident(ctxt: Context, app: &App) -> Ident {
     let mut s = match ctxt {
         Context::Init(core) => app.inits[&core].name.to_string(),
@@ -147,6 +816,61 @@ pub fn resources_ident(ctxt: Context, app: &App) -> Ident {
     Ident::new(&s, Span::call_site())
 }
 
+/// e.g. `foo` -> `fooSharedResources`
+pub fn shared_resources_ident(ctxt: Context, app: &App) -> Ident {
+    suffixed(ctxt, app, "SharedResources")
+}
+
+/// e.g. `foo` -> `fooLockedResources`
+pub fn locked_resources_ident(ctxt: Context, app: &App) -> Ident {
+    suffixed(ctxt, app, "LockedResources")
+}
+
+/// e.g. `foo` -> `fooLocalResources`
+pub fn local_resources_ident(ctxt: Context, app: &App) -> Ident {
+    suffixed(ctxt, app, "LocalResources")
+}
+
+fn suffixed(ctxt: Context, app: &App, suffix: &str) -> Ident {
+    Ident::new(&format!("{}{}", ctxt.ident(app), suffix), Span::call_site())
+}
+
+/// e.g. `0` -> `BUSY0`
+pub fn busy_ident(core: u8) -> Ident {
+    Ident::new(&format!("BUSY{}", core), Span::call_site())
+}
+
+/// e.g. `1` -> `latency_probe1`
+pub fn latency_probe_ident(core: u8) -> Ident {
+    Ident::new(&format!("latency_probe{}", core), Span::call_site())
+}
+
+/// e.g. `(0, 2)` -> `LOG0_2`
+pub fn log_ident(core: u8, level: u8) -> Ident {
+    Ident::new(&format!("LOG{}_{}", core, level), Span::call_site())
+}
+
+/// e.g. `(0, 2)` -> `LOG_PRODUCER0_2`
+pub fn log_producer_ident(core: u8, level: u8) -> Ident {
+    Ident::new(
+        &format!("LOG_PRODUCER{}_{}", core, level),
+        Span::call_site(),
+    )
+}
+
+/// e.g. `(0, 2)` -> `LOG_CONSUMER0_2`
+pub fn log_consumer_ident(core: u8, level: u8) -> Ident {
+    Ident::new(
+        &format!("LOG_CONSUMER{}_{}", core, level),
+        Span::call_site(),
+    )
+}
+
+/// e.g. `(0, 2)` -> `level0_2`
+pub fn level_ident(core: u8, level: u8) -> Ident {
+    Ident::new(&format!("level{}_{}", core, level), Span::call_site())
+}
+
 /// e.g. `3` -> `RT3`
 pub fn rt_ident(i: u8) -> Ident {
     Ident::new(&format!("RT{}", i), Span::call_site())
@@ -156,6 +880,21 @@ pub fn schedule_ident(task: &Ident) -> Ident {
     Ident::new(&format!("schedule_{}", task), Span::call_site())
 }
 
+/// e.g. `foo` -> `schedule_wall_foo`
+pub fn schedule_wall_ident(task: &Ident) -> Ident {
+    Ident::new(&format!("schedule_wall_{}", task), Span::call_site())
+}
+
+/// e.g. `0` -> `SHUTDOWN0`
+pub fn shutdown_ident(core: u8) -> Ident {
+    Ident::new(&format!("SHUTDOWN{}", core), Span::call_site())
+}
+
+/// e.g. `1` -> `SD1`
+pub fn sd_ident(core: u8) -> Ident {
+    Ident::new(&format!("SD{}", core), Span::call_site())
+}
+
 pub fn spawn_ident(task: &Ident) -> Ident {
     Ident::new(&format!("spawn_{}", task), Span::call_site())
 }
@@ -171,6 +910,45 @@ pub fn fq_ident_(task: &Ident, sender: u8) -> Ident {
     )
 }
 
+/// e.g. `foo`, `1` -> `foo_S1_BQ`; the messages that core #1 sends to the background task `foo`
+pub fn bq_ident(task: &Ident, sender: u8) -> Ident {
+    Ident::new(&format!("{}_S{}_BQ", task, sender), Span::call_site())
+}
+
+/// Ceiling, on `core`, of the `pi_futex` or `#[per_core]` resource `name`: the highest priority
+/// of the tasks of the core that share it
+pub fn core_ceiling(name: &Ident, core: Core, app: &App) -> u8 {
+    app.software_tasks
+        .values()
+        .map(|task| (task.args.core, task.args.priority, &task.args.resources))
+        .chain(
+            app.hardware_tasks
+                .values()
+                .map(|task| (task.args.core, task.args.priority, &task.args.resources)),
+        )
+        .filter(|(task_core, _, resources)| *task_core == core && resources.contains(name))
+        .map(|(_, priority, _)| priority)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The signals of `core`, as `(start, end)`; `(0, 0)` if the core has no tasks
+pub fn signal_range(core: Core, analysis: &Analysis) -> (u8, u8) {
+    analysis
+        .signals
+        .get(&core)
+        .map(|signals| {
+            let range = signals.range();
+            (range.start, range.end)
+        })
+        .unwrap_or((0, 0))
+}
+
+/// e.g. `HITS` -> `HITS_PER_CORE`, the instances of `#[per_core]` resource `HITS`
+pub fn per_core_ident(name: &Ident) -> Ident {
+    Ident::new(&format!("{}_PER_CORE", name), Span::call_site())
+}
+
 pub fn tid_ident(core: u8) -> Ident {
     Ident::new(&format!("TID{}", core), Span::call_site())
 }
@@ -205,3 +983,13 @@ pub fn task_ident(task: &Ident, sender: u8) -> Ident {
 pub fn tq_ident(sender: u8) -> Ident {
     Ident::new(&format!("TQ{}", sender), Span::call_site())
 }
+
+/// e.g. `0` -> `WTQ0`
+pub fn wall_tq_ident(sender: u8) -> Ident {
+    Ident::new(&format!("WTQ{}", sender), Span::call_site())
+}
+
+/// e.g. `0` -> `WALL_TIMER0`
+pub fn wall_timer_ident(sender: u8) -> Ident {
+    Ident::new(&format!("WALL_TIMER{}", sender), Span::call_site())
+}
@@ -0,0 +1,162 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+
+use crate::{analyze::Analysis, parse::WatchdogPolicy};
+
+/// `SCHED_FIFO` priority of the watchdog thread; it must preempt every task
+pub const WATCHDOG_PRIORITY: u8 = 99;
+
+/// Stack size of the watchdog thread, in bytes
+const WATCHDOG_STACK_SIZE: usize = 64 * 1024;
+
+/// Creates the watchdog thread
+pub fn codegen(analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    let window = if let Some((nanos, _)) = analysis.extensions.app.watchdog {
+        nanos
+    } else {
+        return (const_app, stmts);
+    };
+
+    let on_starved = if let Some(f) = &analysis.extensions.watchdog_starved {
+        let watchdog_starved = &f.ident;
+        quote!(#watchdog_starved)
+    } else {
+        match analysis.extensions.app.watchdog_policy {
+            Some((WatchdogPolicy::Log, _)) => quote!(rtfm::export::watchdog_log),
+            _ => quote!(rtfm::export::watchdog_abort),
+        }
+    };
+
+    const_app.push(quote!(
+        /// Checks that the tasks feed the watchdog and reports to systemd
+        extern "C" fn watchdog() -> ! {
+            unsafe {
+                rtfm::export::register_thread(
+                    Some("rtfm-watchdog"),
+                    None,
+                    Some(0),
+                    #WATCHDOG_PRIORITY,
+                );
+                rtfm::export::fault_stack().unwrap_or_else(rtfm::export::fatal);
+
+                rtfm::export::watchdog(core::time::Duration::from_nanos(#window), #on_starved)
+            }
+        }
+    ));
+
+    stmts.push(quote!(
+        // NOTE the watchdog thread inherits the signal mask of this thread so it will never run
+        // any of the signal handlers
+        let tid = rtfm::export::spawn(watchdog, #WATCHDOG_STACK_SIZE, "the watchdog thread")
+            .unwrap_or_else(rtfm::export::fatal);
+        rtfm::export::set_priority(tid, #WATCHDOG_PRIORITY).unwrap_or_else(rtfm::export::fatal);
+    ));
+
+    (const_app, stmts)
+}
+
+/// Creates the heartbeat monitor thread
+pub fn heartbeats(analysis: &Analysis) -> (Vec<TokenStream2>, Vec<TokenStream2>) {
+    let mut const_app = vec![];
+    let mut stmts = vec![];
+
+    let heartbeats = analysis
+        .extensions
+        .tasks
+        .iter()
+        .filter_map(|(name, args)| {
+            let (interval, _) = args.heartbeat?;
+            let name = name.to_string();
+
+            Some(quote!(rtfm::export::Heartbeat::new(#name, #interval)))
+        })
+        .collect::<Vec<_>>();
+    if heartbeats.is_empty() {
+        return (const_app, stmts);
+    }
+
+    // the `#[heartbeat_missed]` function can be followed by the abort of the default policy
+    let policy = analysis
+        .extensions
+        .app
+        .heartbeat_policy
+        .map(|(policy, _)| policy);
+    let on_missed = match (&analysis.extensions.heartbeat_missed, policy) {
+        (Some(f), Some(WatchdogPolicy::Abort)) => {
+            let heartbeat_missed = &f.ident;
+            quote!(|task, silent| {
+                #heartbeat_missed(task, silent);
+                rtfm::export::heartbeat_abort(task, silent)
+            })
+        }
+        (Some(f), _) => {
+            let heartbeat_missed = &f.ident;
+            quote!(#heartbeat_missed)
+        }
+        (None, Some(WatchdogPolicy::Log)) => quote!(rtfm::export::heartbeat_log),
+        (None, _) => quote!(rtfm::export::heartbeat_abort),
+    };
+
+    let len = heartbeats.len();
+    const_app.push(quote!(
+        static HEARTBEATS: [rtfm::export::Heartbeat; #len] = [#(#heartbeats,)*];
+
+        /// Checks that the tasks with a `heartbeat` beat within their intervals
+        extern "C" fn heartbeat_monitor() -> ! {
+            unsafe {
+                rtfm::export::register_thread(
+                    Some("rtfm-heartbeat"),
+                    None,
+                    Some(0),
+                    #WATCHDOG_PRIORITY,
+                );
+                rtfm::export::fault_stack().unwrap_or_else(rtfm::export::fatal);
+
+                rtfm::export::monitor_heartbeats(#on_missed)
+            }
+        }
+    ));
+
+    stmts.push(quote!(
+        rtfm::export::init_heartbeats(&HEARTBEATS);
+
+        // NOTE like the watchdog thread, the monitor never runs any of the signal handlers
+        let tid = rtfm::export::spawn(
+            heartbeat_monitor,
+            #WATCHDOG_STACK_SIZE,
+            "the heartbeat monitor thread",
+        )
+        .unwrap_or_else(rtfm::export::fatal);
+        rtfm::export::set_priority(tid, #WATCHDOG_PRIORITY).unwrap_or_else(rtfm::export::fatal);
+    ));
+
+    (const_app, stmts)
+}
+
+/// Tells systemd that the application is up and, if there's a watchdog, starts expecting the tasks
+/// to feed it; the same goes for the heartbeats
+///
+/// NOTE this must run after the tasks of all the cores have been enabled
+pub fn ready(analysis: &Analysis) -> Vec<TokenStream2> {
+    let mut stmts = vec![];
+
+    if analysis.extensions.app.watchdog.is_some() {
+        stmts.push(quote!(rtfm::export::arm_watchdog();));
+    }
+
+    if analysis
+        .extensions
+        .tasks
+        .values()
+        .any(|args| args.heartbeat.is_some())
+    {
+        stmts.push(quote!(rtfm::export::arm_heartbeats();));
+    }
+
+    stmts.push(quote!(rtfm::export::sd_notify("READY=1");));
+
+    stmts
+}
@@ -4,13 +4,20 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use std::{fs, path::Path};
+use proc_macro2::Span;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
 
-use rtfm_syntax::Settings;
+use rtfm_syntax::{ast::App, Settings};
+
+use crate::analyze::Analysis;
 
 mod analyze;
 mod check;
 mod codegen;
+mod parse;
 
 #[proc_macro_attribute]
 pub fn app(args: TokenStream, input: TokenStream) -> TokenStream {
@@ -18,20 +25,82 @@ pub fn app(args: TokenStream, input: TokenStream) -> TokenStream {
     settings.parse_cores = true;
     settings.parse_schedule = true;
 
-    let (app, analysis) = match rtfm_syntax::parse(args, input, settings) {
+    let (args, input, extensions) = match parse::app(args.into(), input.into()) {
         Err(e) => return e.to_compile_error().into(),
         Ok(x) => x,
     };
 
-    if let Err(e) = check::app(&app, &analysis) {
+    let (app, analysis) = match rtfm_syntax::parse(args.into(), input.into(), settings) {
+        Err(e) => return e.to_compile_error().into(),
+        Ok(x) => x,
+    };
+
+    if let Err(e) = check::app(&app, &analysis, &extensions) {
         return e.to_compile_error().into();
     }
 
-    let analysis = analyze::app(analysis, &app);
+    let analysis = analyze::app(analysis, &app, extensions);
+
+    if let Err(e) = check::schedulability(&app, &analysis) {
+        return e.to_compile_error().into();
+    }
 
     // Code generation
     let ts = codegen::app(&app, &analysis);
 
+    // Write out the C headers of the external and FFI tasks
+    let headers = [
+        (
+            &analysis.extensions.app.external_header,
+            codegen::external::header as fn(&App, &Analysis) -> String,
+        ),
+        (&analysis.extensions.app.ffi_header, codegen::ffi::header),
+    ];
+    for (path, header) in &headers {
+        if let Some(path) = path {
+            if let Err(e) = fs::write(path.value(), header(&app, &analysis)) {
+                return syn::Error::new(path.span(), format!("couldn't write the C header: {}", e))
+                    .to_compile_error()
+                    .into();
+            }
+        }
+    }
+
+    // Write out the task and resource graph; `RTFM_GRAPH_DIR` does it for every application built
+    // with it set
+    let graph = match (
+        &analysis.extensions.app.graph,
+        env::var_os("RTFM_GRAPH_DIR"),
+    ) {
+        (Some(path), _) => Some((
+            env::var_os("OUT_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_default()
+                .join(path.value()),
+            path.span(),
+        )),
+        (None, Some(dir)) => {
+            let name = env::var("CARGO_CRATE_NAME")
+                .or_else(|_| env::var("CARGO_PKG_NAME"))
+                .unwrap_or_else(|_| String::from("app"));
+            Some((PathBuf::from(dir).join(name), Span::call_site()))
+        }
+        (None, None) => None,
+    };
+    if let Some((path, span)) = graph {
+        let files = [
+            ("json", codegen::graph::json(&app, &analysis)),
+            ("dot", codegen::graph::dot(&app, &analysis)),
+        ];
+        for (extension, contents) in &files {
+            if let Err(e) = fs::write(path.with_extension(extension), contents) {
+                return syn::Error::new(span, format!("couldn't write the graph: {}", e))
+                    .to_compile_error()
+                    .into();
+            }
+        }
+    }
+
     // Try to write the expanded code to disk
     if Path::new("target").exists() {
         fs::write("target/rtfm-expansion.rs", ts.to_string()).ok();
@@ -0,0 +1,1944 @@
+//! Linux specific arguments
+//!
+//! `rtfm-syntax` rejects arguments it doesn't know about so these are parsed here and stripped from
+//! the input before it reaches `rtfm_syntax::parse`
+
+use std::{collections::BTreeMap, mem, net::SocketAddr, ops::Range, slice};
+
+use proc_macro2::{Delimiter, Group, Span, TokenStream as TokenStream2, TokenTree};
+use quote::quote;
+use syn::{
+    parse::{self, Parse, ParseStream},
+    punctuated::Punctuated,
+    Attribute, Block, Expr, ForeignItem, Ident, Item, ItemConst, ItemFn, ItemForeignMod, ItemMod,
+    ItemStatic, LitBool, LitInt, LitStr, Path, ReturnType, Stmt, Token, Type, Visibility,
+};
+
+pub use self::shared_local::Model;
+
+mod compat;
+mod hybrid;
+mod shared_local;
+
+/// Arguments that `rtfm-syntax` doesn't know about
+#[derive(Default)]
+pub struct Extensions {
+    /// Extra `#[app]` arguments
+    pub app: AppArgs,
+
+    /// Extra `#[task]` arguments
+    pub tasks: BTreeMap<Ident, TaskArgs>,
+
+    /// Extra `#[idle]` arguments
+    pub idles: BTreeMap<Ident, IdleArgs>,
+
+    /// `#[panic_task] fn on_panic(task: &'static str)`; called when a task panics
+    pub panic_task: Option<ItemFn>,
+
+    /// `#[deadline_miss] fn on_miss(task: &'static str, lateness: Duration)`; called when a task
+    /// completes after its `deadline`
+    pub deadline_miss: Option<ItemFn>,
+
+    /// `#[watchdog_starved] fn on_starved(starved: Duration)`; called when the tasks stop feeding
+    /// the watchdog
+    pub watchdog_starved: Option<ItemFn>,
+
+    /// `#[budget_overrun] fn on_overrun(task: &'static str, budget: Duration)`; called when a task
+    /// uses more CPU time than its `budget`
+    pub budget_overrun: Option<ItemFn>,
+
+    /// `#[heartbeat_missed] fn on_missed(task: &'static str, silent: Duration)`; called when a
+    /// task doesn't beat within its `heartbeat` interval
+    pub heartbeat_missed: Option<ItemFn>,
+
+    /// `#[pre_init] fn setup() -> rtfm::RuntimeConfig`; called before the runtime starts
+    pub pre_init: Option<ItemFn>,
+
+    /// `#[deinit] fn teardown()`; called on shutdown, once the resources have been dropped
+    pub deinit: Option<ItemFn>,
+
+    /// `#[lock_free] static mut X: T = ..;` resources; only contexts of the same priority may
+    /// share them
+    pub lock_free: BTreeMap<Ident, Span>,
+
+    /// `#[lock(policy = "pi_futex")] static mut X: T = ..;` resources, by the type `T` they had
+    /// before becoming a `static X: rtfm::export::PiFutex<T>`; they can be shared between cores
+    pub pi_futex: BTreeMap<Ident, (Box<Type>, Span)>,
+
+    /// `#[per_core] static mut X: T = e;` resources, by the type `T` of their instances; they
+    /// become a `static X: rtfm::export::PerCore<T>` with one instance per core
+    pub per_core: BTreeMap<Ident, (Box<Type>, Span)>,
+
+    /// Tasks declared in an `extern "Rust" { .. }` block; their bodies are defined elsewhere
+    pub extern_tasks: BTreeMap<Ident, Span>,
+
+    /// `#[shared]` and `#[local]` resources; `None` if the application uses `struct Resources`
+    pub model: Option<Model>,
+
+    /// `mod app { .. }` without its RTFM items; the generated code goes inside it
+    pub module: Option<ItemMod>,
+}
+
+/// Extra `#[app]` arguments
+#[derive(Default)]
+pub struct AppArgs {
+    /// `epoll_priority = 2`; `SCHED_FIFO` priority of the thread that runs the epoll loop
+    pub epoll_priority: Option<u8>,
+
+    /// `stack_size = "8MiB"`; base stack size of the spawned threads, in bytes
+    pub stack_size: Option<usize>,
+
+    /// `monotonic = path::to::Clock`; clock that drives the `schedule` API
+    pub monotonic: Option<Path>,
+
+    /// `log = "stderr"` or `log = "/path/to/file"`; enables `rtfm::log` and sets its output
+    pub log: Option<LitStr>,
+
+    /// `stats = true`; records the execution time of the tasks
+    pub stats: Option<bool>,
+
+    /// `perf_counters = true`; adds `perf_event_open` counters to the `stats` of the tasks
+    pub perf_counters: Option<(bool, Span)>,
+
+    /// `introspect = true`; tracks the message queues and timer queues; see `rtfm::introspect`
+    pub introspect: Option<bool>,
+
+    /// `metrics = "0.0.0.0:9100"`; serves the metrics of the application over HTTP in the
+    /// Prometheus text format; implies `introspect = true`
+    pub metrics: Option<LitStr>,
+
+    /// `latency_probe = "1ms"`; measures the wakeup latency of each core with a probe thread
+    /// that wakes up once per period; see `rtfm::latency_probe`
+    pub latency_probe: Option<u64>,
+
+    /// `replay = "rtfm.rec"`; re-executes the tasks in the order of a recording made with the
+    /// `record` feature
+    pub replay: Option<(LitStr, Span)>,
+
+    /// `simulate = true`; under `cfg(test)` the application runs in `rtfm::simulate`, on a virtual
+    /// clock, instead of in `main`
+    pub simulate: Option<(bool, Span)>,
+
+    /// `cpu_dma_latency = "10us"`; `/dev/cpu_dma_latency` target, in microseconds
+    pub cpu_dma_latency: Option<u32>,
+
+    /// `shield_cores = [1, 2]`; CPUs that the other processes, the interrupts and the kernel
+    /// workqueues are moved off at start-up
+    pub shield_cores: Option<Vec<u8>>,
+
+    /// `disable_rt_throttling = true`; turns the real-time throttling of the kernel off at start-up
+    pub disable_rt_throttling: Option<bool>,
+
+    /// `dispatch = "signals"` or `dispatch = "threads"`; how the priority levels are implemented
+    pub dispatch: Option<Dispatch>,
+
+    /// `signals = 4..12`; real-time signals the runtime may use, as offsets from `SIGRTMIN`
+    pub signals: Option<(Range<u8>, Span)>,
+
+    /// `external_header = "path/to/app.h"`; where to write the C header of the external tasks
+    pub external_header: Option<LitStr>,
+
+    /// `ffi_header = "path/to/app.h"`; where to write the C header of the FFI tasks
+    pub ffi_header: Option<LitStr>,
+
+    /// `graph = "path/to/app"`; where to write `app.json` and `app.dot`, the task and resource
+    /// graph. Relative paths are relative to `OUT_DIR`, if the crate has a build script
+    pub graph: Option<LitStr>,
+
+    /// `schedulability = "deny"` or `schedulability = "warn"`; what a task that can miss its
+    /// deadline does to the build
+    pub schedulability: Option<(Schedulability, Span)>,
+
+    /// `signal_levels = 8`; the priorities of the tasks are folded onto at most this many priority
+    /// levels, and signals; see `rtfm::export::hybrid`
+    pub signal_levels: Option<(u8, Span)>,
+
+    /// `lock_groups = true`; a `lock` whose closure locks resources with higher ceilings raises the
+    /// priority to the highest of them at once; see `codegen::lock_groups`
+    pub lock_groups: Option<bool>,
+
+    /// `processes = true`; every core but #0 runs in a process of its own; see `rtfm::export::fork`
+    pub processes: Option<(bool, Span)>,
+
+    /// `wall_clock = true`; adds the `schedule_wall` API, driven by `CLOCK_REALTIME`
+    pub wall_clock: Option<bool>,
+
+    /// `watchdog = "100ms"`; how often the tasks must feed the watchdog, in nanoseconds
+    pub watchdog: Option<(u64, Span)>,
+
+    /// `watchdog_policy = "abort"` or `watchdog_policy = "log"`; what the watchdog does when it's
+    /// starved
+    pub watchdog_policy: Option<(WatchdogPolicy, Span)>,
+
+    /// `past_schedule = "fire"`, `"reject"` or `"clamp"`; what `schedule` does with an instant
+    /// that has already passed; tasks can override it
+    pub past_schedule: Option<PastSchedule>,
+
+    /// `past_schedule_margin = "50us"`; how far into the future `"clamp"` moves an instant that
+    /// has already passed, in nanoseconds
+    pub past_schedule_margin: Option<(u64, Span)>,
+
+    /// `broadcast(sensors = [fuse, log])`; groups of tasks that `c.broadcast.sensors(..)` sends
+    /// the same message to
+    pub broadcast: Option<Vec<(Ident, Vec<Ident>)>>,
+
+    /// `defer = 8`; number of closures that `rtfm::defer` can hold at a time
+    pub defer: Option<(u8, Span)>,
+
+    /// `device = path::to::pac`; the device crate, or its `rtfm::compat::device!` stub
+    pub device: Option<Path>,
+
+    /// `peripherals = true`; `init::Context` gets the peripherals of `device`
+    pub peripherals: Option<(bool, Span)>,
+
+    /// `dispatchers = [EXTI0, ..]`; the interrupts that RTIC dispatches software tasks from. Each
+    /// priority level has a signal of its own so these are not used
+    pub dispatchers: Option<Vec<Ident>>,
+
+    /// `background_workers = 2`; number of `SCHED_OTHER` threads that run the background tasks
+    pub background_workers: Option<(u8, Span)>,
+
+    /// `throttling_poll = "1s"`; how often the sysfs attributes behind the `throttling` task are
+    /// read again when no notification comes, in nanoseconds
+    pub throttling_poll: Option<(u64, Span)>,
+
+    /// `seccomp = true`; restricts the process to the system calls of the runtime once the tasks
+    /// are enabled
+    pub seccomp: Option<(bool, Span)>,
+
+    /// `std_main = true`; the application gets a regular `fn main`, which `std` calls, instead of
+    /// the C entry point of `#![no_main]`
+    pub std_main: Option<(bool, Span)>,
+
+    /// `modes = [startup, normal, degraded]`; the modes the application can be in, the first one
+    /// at start-up
+    pub modes: Option<(Vec<Ident>, Span)>,
+
+    /// `budget_policy = "abort"` or `budget_policy = "demote"`; what happens to a task that uses
+    /// more CPU time than its `budget`
+    pub budget_policy: Option<(BudgetPolicy, Span)>,
+
+    /// `heartbeat_policy = "abort"` or `heartbeat_policy = "log"`; what the heartbeat monitor
+    /// does when a task misses its heartbeat. With a `#[heartbeat_missed]` function, `"abort"`
+    /// aborts once the function returns
+    pub heartbeat_policy: Option<(WatchdogPolicy, Span)>,
+}
+
+/// What `schedule` does with an instant that has already passed
+#[derive(Clone, Copy, PartialEq)]
+pub enum PastSchedule {
+    /// Enqueue it as is; the task runs right away (default)
+    Fire,
+    /// Return `SpawnError::InstantPassed`
+    Reject,
+    /// Move the instant to now plus `past_schedule_margin`
+    Clamp,
+}
+
+/// What the watchdog does when the tasks stop feeding it, and what the heartbeat monitor does
+/// when a task stops beating
+#[derive(Clone, Copy, PartialEq)]
+pub enum WatchdogPolicy {
+    /// Report it and abort the process (default)
+    Abort,
+    /// Report it and keep running
+    Log,
+}
+
+/// What happens to a task that exceeds its execution budget
+#[derive(Clone, Copy, PartialEq)]
+pub enum BudgetPolicy {
+    /// Report it and abort the process (default)
+    Abort,
+    /// Report it and run its thread under `SCHED_OTHER` until the task returns
+    Demote,
+}
+
+/// What the response-time analysis does when a task can miss its deadline
+#[derive(Clone, Copy, PartialEq)]
+pub enum Schedulability {
+    /// Fail the build (default)
+    Deny,
+    /// Report it as a warning
+    Warn,
+}
+
+/// How the priority levels of a core are implemented
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dispatch {
+    /// Nested signal handlers on a single thread (default)
+    Signals,
+    /// One `SCHED_FIFO` thread per priority level, blocked in `sigtimedwait`
+    Threads,
+}
+
+/// Extra `#[task]` arguments
+#[derive(Default)]
+pub struct TaskArgs {
+    /// `sched_deadline(runtime = "..", deadline = "..", period = "..")`
+    pub sched_deadline: Option<SchedDeadline>,
+
+    /// `binds_fd = RESOURCE`
+    pub binds_fd: Option<Ident>,
+
+    /// `fd_events = "readable|writable"`; `EPOLL*` flags
+    pub fd_events: Option<(u32, Span)>,
+
+    /// `stack_size = "64KiB"`; stack space this task needs, in bytes
+    pub stack_size: Option<usize>,
+
+    /// `deadline = "5ms"`; relative to the instant the task was scheduled at, in nanoseconds
+    pub deadline: Option<(u64, Span)>,
+
+    /// `period = "10ms"`; the runtime releases the task every period, in nanoseconds
+    pub period: Option<(u64, Span)>,
+
+    /// `external = true`; other processes can spawn the task; see `rtfm::external`
+    pub external: Option<bool>,
+
+    /// `shm = "/name"`; the input of the task comes from a shared memory channel; see `rtfm::shm`
+    pub shm: Option<LitStr>,
+
+    /// `spawner = true`; threads the runtime doesn't manage can spawn the task; see `rtfm::Spawner`
+    pub spawner: Option<bool>,
+
+    /// `ffi = true`; C code can spawn the task through `rtfm_spawn_foo`; implies `spawner = true`
+    pub ffi: Option<bool>,
+
+    /// `capacity = 1024`; capacities that don't fit in the `u8` that `rtfm-syntax` accepts
+    pub capacity: Option<(u16, Span)>,
+
+    /// `backpressure = true`; other contexts can wait for a free message slot with `spawn_blocking`
+    pub backpressure: Option<bool>,
+
+    /// `past_schedule = "reject"`; overrides `#[app(past_schedule = ..)]` for this task
+    pub past_schedule: Option<PastSchedule>,
+
+    /// `then = bar`; the value the task returns is spawned as the message of `bar`
+    pub then: Option<Ident>,
+
+    /// The return type of a task with `then`; `rtfm-syntax` only accepts tasks that return `()`
+    pub output: Option<ReturnType>,
+
+    /// `binds = USART1`; `rtfm::compat::pend` releases the task in place of this interrupt
+    pub binds: Option<Ident>,
+
+    /// `wcet = "200us"`; worst-case execution time of the task, in nanoseconds
+    pub wcet: Option<(u64, Span)>,
+
+    /// `interarrival = "10ms"`; minimum time between two releases of a task that is not
+    /// periodic, in nanoseconds
+    pub interarrival: Option<(u64, Span)>,
+
+    /// `priority = 2`; the logical priority of the task and the span of its value. `rtfm-syntax`
+    /// parses the argument, or the priority level it's folded onto; see `signal_levels`
+    pub priority: Option<(u8, Span)>,
+
+    /// `class = "background"`; the task runs on the worker pool instead of a priority level
+    pub class: Option<(TaskClass, Span)>,
+
+    /// `throttling = true`; the runtime spawns the task when the CPUs start or stop being
+    /// throttled; see `rtfm::CpuThrottling`. Implies `spawner = true`
+    pub throttling: Option<(bool, Span)>,
+
+    /// `modes = [normal, degraded = "50ms"]`; the modes the task runs in, all of them by default.
+    /// A periodic task can have a different period, in nanoseconds, in each
+    pub modes: Option<(Vec<(Ident, Option<u64>)>, Span)>,
+
+    /// `mode_switch = true`; the task can move the application to another mode
+    pub mode_switch: Option<(bool, Span)>,
+
+    /// `budget = "200us"`; CPU time the task may use per run, in nanoseconds
+    pub budget: Option<(u64, Span)>,
+
+    /// `heartbeat = "50ms"`; the task must call `c.heartbeat()` at least this often, in
+    /// nanoseconds
+    pub heartbeat: Option<(u64, Span)>,
+}
+
+/// What runs a task
+#[derive(Clone, Copy, PartialEq)]
+pub enum TaskClass {
+    /// The dispatcher of its priority level, under `SCHED_FIFO` (default)
+    RealTime,
+    /// A `SCHED_OTHER` worker thread
+    Background,
+}
+
+/// Extra `#[idle]` arguments
+#[derive(Default)]
+pub struct IdleArgs {
+    /// `policy = "idle"` or `policy = "other"`; what `idle` runs under outside of `lock`, instead
+    /// of `SCHED_FIFO`
+    pub policy: Option<(IdlePolicy, Span)>,
+}
+
+/// Scheduling policy of a demoted `idle`
+#[derive(Clone, Copy, PartialEq)]
+pub enum IdlePolicy {
+    /// `SCHED_IDLE`
+    Idle,
+    /// `SCHED_OTHER`
+    Other,
+}
+
+/// `SCHED_DEADLINE` parameters, in nanoseconds
+#[derive(Clone, Copy)]
+pub struct SchedDeadline {
+    pub runtime: u64,
+    pub deadline: u64,
+    pub period: u64,
+    pub span: Span,
+}
+
+pub fn app(
+    args: TokenStream2,
+    input: TokenStream2,
+) -> parse::Result<(TokenStream2, TokenStream2, Extensions)> {
+    let mut extensions = Extensions::default();
+
+    let args = strip_args(args, |key, value| app_arg(&mut extensions.app, key, value))?;
+
+    let mut item = match syn::parse2::<ItemConst>(input.clone()) {
+        Ok(item) => item,
+        Err(_) => match syn::parse2::<ItemMod>(input.clone()) {
+            // the application of RTIC 0.6
+            Ok(module) => {
+                let (item, module) = compat::module(module)?;
+                extensions.module = Some(module);
+                item
+            }
+            // let `rtfm-syntax` report the error
+            Err(_) => return Ok((args, input, extensions)),
+        },
+    };
+
+    if let Expr::Block(block) = &mut *item.expr {
+        // `#[panic_task]`, `#[deadline_miss]`, `#[watchdog_starved]`, `#[budget_overrun]`,
+        // `#[heartbeat_missed]`, `#[pre_init]` and `#[deinit]` functions are not RTFM items; take
+        // them out of the input
+        let mut stmts = vec![];
+        let mut structs = vec![];
+        let mut legacy = None;
+        for stmt in block.block.stmts.drain(..) {
+            match stmt {
+                Stmt::Item(Item::Fn(f)) if hook_attr(&f).is_some() => hook(&mut extensions, f)?,
+                // the `#[shared]` and `#[local]` structs are rewritten into `struct Resources`
+                Stmt::Item(Item::Struct(s)) if shared_local::is_model_struct(&s) => structs.push(s),
+                // `rtfm-syntax` sees `extern "Rust"` tasks as tasks with empty bodies
+                Stmt::Item(Item::ForeignMod(block)) if is_extern_tasks(&block) => {
+                    for f in extern_tasks(&mut extensions, block)? {
+                        stmts.push(Stmt::Item(Item::Fn(f)));
+                    }
+                }
+                stmt => {
+                    match &stmt {
+                        Stmt::Item(Item::Struct(s)) if s.ident == "Resources" => {
+                            legacy = Some(s.ident.span())
+                        }
+                        Stmt::Item(Item::Static(s)) => legacy = Some(s.ident.span()),
+                        _ => {}
+                    }
+
+                    stmts.push(stmt)
+                }
+            }
+        }
+        block.block.stmts = stmts;
+
+        extensions.model = shared_local::model(structs, legacy)?;
+        if let (None, Some(span)) = (&extensions.model, legacy) {
+            if !cfg!(feature = "legacy-resources") {
+                return Err(shared_local::legacy(span));
+            }
+        }
+
+        for stmt in &mut block.block.stmts {
+            match stmt {
+                Stmt::Item(Item::Fn(f)) => {
+                    for attr in &mut f.attrs {
+                        if is(attr, "init") || is(attr, "idle") || is(attr, "task") {
+                            shared_local::context(extensions.model.as_mut(), attr, &f.ident)?;
+                        }
+
+                        if is(attr, "task") {
+                            let mut args = TaskArgs::default();
+                            strip(attr, |key, value| task_arg(&mut args, key, value))?;
+                            if let Some(then) = &args.then {
+                                // so that `rtfm-syntax` accounts for the messages of `then`
+                                extend_list(attr, "spawn", slice::from_ref(then));
+                            }
+                            if args.binds.is_some() || extensions.module.is_some() {
+                                // `pend` and `foo::spawn` go through the `Spawner`
+                                args.spawner.get_or_insert(true);
+                            }
+                            extensions.tasks.insert(f.ident.clone(), args);
+                        }
+
+                        if is(attr, "idle") {
+                            let mut args = IdleArgs::default();
+                            strip(attr, |key, value| idle_arg(&mut args, key, value))?;
+                            extensions.idles.insert(f.ident.clone(), args);
+                        }
+                    }
+
+                    if let Some(args) = extensions.tasks.get_mut(&f.ident) {
+                        if args.then.is_some() {
+                            args.output =
+                                Some(mem::replace(&mut f.decl.output, ReturnType::Default));
+                        }
+                    }
+                }
+
+                Stmt::Item(Item::Static(s)) => {
+                    if let Some(i) = s.attrs.iter().position(|attr| is(attr, "lock_free")) {
+                        let attr = s.attrs.remove(i);
+                        let span = attr.path.segments[0].ident.span();
+                        if !attr.tts.is_empty() {
+                            return Err(parse::Error::new(
+                                span,
+                                "this attribute takes no arguments",
+                            ));
+                        }
+
+                        extensions.lock_free.insert(s.ident.clone(), span);
+                    }
+
+                    if let Some(i) = s.attrs.iter().position(|attr| is(attr, "lock")) {
+                        let attr = s.attrs.remove(i);
+                        pi_futex(&mut extensions, s, attr)?;
+                    }
+
+                    if let Some(i) = s.attrs.iter().position(|attr| is(attr, "per_core")) {
+                        let attr = s.attrs.remove(i);
+                        per_core(&mut extensions, s, attr)?;
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        if let Some((levels, _)) = extensions.app.signal_levels {
+            hybrid::fold(levels, &mut block.block.stmts, &extensions.tasks);
+        }
+
+        if let Some(model) = &extensions.model {
+            shared_local::desugar(model, &mut block.block.stmts)?;
+        }
+    }
+
+    Ok((args, quote!(#item), extensions))
+}
+
+// `#[lock(policy = "pi_futex")] static mut X: T = e;` becomes
+// `static X: rtfm::export::PiFutex<T> = rtfm::export::PiFutex::new(e);`, a read-only resource that
+// the contexts of all the cores can share
+fn pi_futex(
+    extensions: &mut Extensions,
+    s: &mut ItemStatic,
+    mut attr: Attribute,
+) -> parse::Result<()> {
+    let span = attr.path.segments[0].ident.span();
+    let mut policy = None;
+    strip(&mut attr, |key, value| {
+        if key != "policy" {
+            return Ok(false);
+        }
+
+        once(key, &policy)?;
+
+        let lit = syn::parse2::<StrArg>(value)?.lit;
+        if lit.value() != "pi_futex" {
+            return Err(parse::Error::new(lit.span(), "expected `\"pi_futex\"`"));
+        }
+        policy = Some(lit.span());
+
+        Ok(true)
+    })?;
+
+    if !attr.tts.is_empty() {
+        return Err(parse::Error::new(
+            span,
+            "expected `#[lock(policy = \"pi_futex\")]`",
+        ));
+    }
+
+    if policy.is_none() {
+        return Err(parse::Error::new(span, "expected a `policy` argument"));
+    }
+
+    if s.mutability.is_none() {
+        return Err(parse::Error::new(
+            s.ident.span(),
+            "only `static mut` resources can have a lock policy",
+        ));
+    }
+
+    if is_late(s) {
+        return Err(parse::Error::new(
+            s.ident.span(),
+            "`pi_futex` resources need an initial value",
+        ));
+    }
+
+    let ty = s.ty.clone();
+    let expr = &s.expr;
+    s.expr = Box::new(syn::parse_quote!(rtfm::export::PiFutex::new(#expr)));
+    s.ty = Box::new(syn::parse_quote!(rtfm::export::PiFutex<#ty>));
+    s.mutability = None;
+    extensions.pi_futex.insert(s.ident.clone(), (ty, span));
+
+    Ok(())
+}
+
+// `#[per_core] static mut X: T = e;` becomes `static X: rtfm::export::PerCore<T> = e;`; the
+// initializer is only type checked later, when `codegen::resources` gives each core an instance
+// initialized with `e`
+fn per_core(extensions: &mut Extensions, s: &mut ItemStatic, attr: Attribute) -> parse::Result<()> {
+    let span = attr.path.segments[0].ident.span();
+    if !attr.tts.is_empty() {
+        return Err(parse::Error::new(span, "this attribute takes no arguments"));
+    }
+
+    if s.mutability.is_none() {
+        return Err(parse::Error::new(
+            s.ident.span(),
+            "only `static mut` resources can be `#[per_core]`",
+        ));
+    }
+
+    if is_late(s) {
+        return Err(parse::Error::new(
+            s.ident.span(),
+            "`#[per_core]` resources need an initial value",
+        ));
+    }
+
+    if extensions.pi_futex.contains_key(&s.ident) {
+        return Err(parse::Error::new(
+            span,
+            "`#[per_core]` resources can't have a lock policy",
+        ));
+    }
+
+    let ty = s.ty.clone();
+    s.ty = Box::new(syn::parse_quote!(rtfm::export::PerCore<#ty>));
+    s.mutability = None;
+    extensions.per_core.insert(s.ident.clone(), (ty, span));
+
+    Ok(())
+}
+
+// `static mut X: T = ();` is a late resource
+fn is_late(s: &ItemStatic) -> bool {
+    match &*s.expr {
+        Expr::Tuple(tuple) => tuple.elems.is_empty(),
+        _ => false,
+    }
+}
+
+/// Attributes that mark functions called by the runtime
+const HOOKS: &[&str] = &[
+    "panic_task",
+    "deadline_miss",
+    "watchdog_starved",
+    "budget_overrun",
+    "heartbeat_missed",
+    "pre_init",
+    "deinit",
+];
+
+fn hook_attr(f: &ItemFn) -> Option<usize> {
+    f.attrs
+        .iter()
+        .position(|attr| HOOKS.iter().any(|hook| is(attr, hook)))
+}
+
+fn hook(extensions: &mut Extensions, mut f: ItemFn) -> parse::Result<()> {
+    let attr = f.attrs.remove(hook_attr(&f).expect("UNREACHABLE"));
+    let name = &attr.path.segments[0].ident;
+    if !attr.tts.is_empty() {
+        return Err(parse::Error::new(
+            name.span(),
+            "this attribute takes no arguments",
+        ));
+    }
+
+    let slot = if name == "panic_task" {
+        &mut extensions.panic_task
+    } else if name == "deadline_miss" {
+        &mut extensions.deadline_miss
+    } else if name == "watchdog_starved" {
+        &mut extensions.watchdog_starved
+    } else if name == "budget_overrun" {
+        &mut extensions.budget_overrun
+    } else if name == "heartbeat_missed" {
+        &mut extensions.heartbeat_missed
+    } else if name == "pre_init" {
+        &mut extensions.pre_init
+    } else {
+        &mut extensions.deinit
+    };
+
+    if slot.is_some() {
+        return Err(parse::Error::new(
+            f.ident.span(),
+            format!("only one `#[{}]` function can be declared", name),
+        ));
+    }
+
+    *slot = Some(f);
+
+    Ok(())
+}
+
+fn is_extern_tasks(block: &ItemForeignMod) -> bool {
+    block.attrs.iter().any(|attr| is(attr, "task"))
+        || block.items.iter().any(|item| match item {
+            ForeignItem::Fn(f) => f.attrs.iter().any(|attr| is(attr, "task")),
+            _ => false,
+        })
+}
+
+/// `#[task(..)] extern "Rust" { fn foo(c: foo::Context, n: u32); }`; a `#[task]` on the block
+/// applies to the functions that don't have their own
+fn extern_tasks(extensions: &mut Extensions, block: ItemForeignMod) -> parse::Result<Vec<ItemFn>> {
+    let span = block.abi.extern_token.span;
+    if block.abi.name.as_ref().map(|name| name.value()) != Some("Rust".to_string()) {
+        return Err(parse::Error::new(
+            span,
+            "tasks can only be declared in `extern \"Rust\"` blocks",
+        ));
+    }
+
+    let mut shared = None;
+    for attr in block.attrs {
+        if is(&attr, "task") && shared.is_none() {
+            shared = Some(attr);
+        } else {
+            return Err(parse::Error::new(
+                span,
+                "`extern` blocks in `#[app]` take at most one attribute, `#[task]`",
+            ));
+        }
+    }
+
+    let mut tasks = vec![];
+    for item in block.items {
+        let f = match item {
+            ForeignItem::Fn(f) => f,
+            _ => {
+                return Err(parse::Error::new(
+                    span,
+                    "`extern` blocks in `#[app]` can only declare tasks",
+                ))
+            }
+        };
+
+        let mut attrs = f.attrs;
+        if !attrs.iter().any(|attr| is(attr, "task")) {
+            match &shared {
+                Some(attr) => attrs.push(attr.clone()),
+                None => {
+                    return Err(parse::Error::new(
+                        f.ident.span(),
+                        "this function needs a `#[task]` attribute",
+                    ))
+                }
+            }
+        }
+
+        extensions
+            .extern_tasks
+            .insert(f.ident.clone(), f.ident.span());
+        tasks.push(ItemFn {
+            attrs,
+            vis: Visibility::Inherited,
+            constness: None,
+            unsafety: None,
+            asyncness: None,
+            abi: None,
+            ident: f.ident,
+            decl: f.decl,
+            block: Box::new(Block {
+                brace_token: Default::default(),
+                stmts: vec![],
+            }),
+        });
+    }
+
+    Ok(tasks)
+}
+
+fn app_arg(args: &mut AppArgs, key: &Ident, value: TokenStream2) -> parse::Result<bool> {
+    match &*key.to_string() {
+        "epoll_priority" => {
+            once(key, &args.epoll_priority)?;
+
+            let lit = syn::parse2::<IntArg>(value)?.lit;
+            match lit.value() {
+                priority @ 1..=99 => args.epoll_priority = Some(priority as u8),
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "`SCHED_FIFO` priorities must be in the range 1..=99",
+                    ))
+                }
+            }
+        }
+
+        "stack_size" => {
+            once(key, &args.stack_size)?;
+
+            args.stack_size = Some(syn::parse2::<SizeArg>(value)?.bytes);
+        }
+
+        "monotonic" => {
+            once(key, &args.monotonic)?;
+
+            args.monotonic = Some(syn::parse2::<PathArg>(value)?.path);
+        }
+
+        "log" => {
+            once(key, &args.log)?;
+
+            args.log = Some(syn::parse2::<StrArg>(value)?.lit);
+        }
+
+        "stats" => {
+            once(key, &args.stats)?;
+
+            args.stats = Some(syn::parse2::<BoolArg>(value)?.value);
+        }
+
+        "perf_counters" => {
+            once(key, &args.perf_counters)?;
+
+            args.perf_counters = Some((syn::parse2::<BoolArg>(value)?.value, key.span()));
+        }
+
+        "introspect" => {
+            once(key, &args.introspect)?;
+
+            args.introspect = Some(syn::parse2::<BoolArg>(value)?.value);
+        }
+
+        "metrics" => {
+            once(key, &args.metrics)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            if lit.value().parse::<SocketAddr>().is_err() {
+                return Err(parse::Error::new(
+                    lit.span(),
+                    "expected an address and a port, e.g. `\"0.0.0.0:9100\"`",
+                ));
+            }
+
+            args.metrics = Some(lit);
+        }
+
+        "replay" => {
+            once(key, &args.replay)?;
+
+            args.replay = Some((syn::parse2::<StrArg>(value)?.lit, key.span()));
+        }
+
+        "simulate" => {
+            once(key, &args.simulate)?;
+
+            args.simulate = Some((syn::parse2::<BoolArg>(value)?.value, key.span()));
+        }
+
+        "latency_probe" => {
+            once(key, &args.latency_probe)?;
+
+            let nanos = syn::parse2::<DurationArg>(value)?.nanos;
+            if nanos == 0 {
+                return Err(parse::Error::new(
+                    key.span(),
+                    "the period must be greater than zero",
+                ));
+            }
+
+            args.latency_probe = Some(nanos);
+        }
+
+        "cpu_dma_latency" => {
+            once(key, &args.cpu_dma_latency)?;
+
+            let micros = syn::parse2::<DurationArg>(value)?.nanos / 1_000;
+            if micros > i32::max_value() as u64 {
+                return Err(parse::Error::new(key.span(), "this latency is too long"));
+            }
+
+            args.cpu_dma_latency = Some(micros as u32);
+        }
+
+        "shield_cores" => {
+            once(key, &args.shield_cores)?;
+
+            let list = syn::parse2::<ListArg<LitInt>>(value)?;
+            let mut cpus = vec![];
+            for lit in list.items {
+                let cpu = lit.value();
+                if cpu > u64::from(u8::max_value()) {
+                    return Err(parse::Error::new(lit.span(), "expected a CPU number"));
+                }
+
+                let cpu = cpu as u8;
+                if cpus.contains(&cpu) {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "this CPU is already shielded",
+                    ));
+                }
+                cpus.push(cpu);
+            }
+
+            if cpus.is_empty() {
+                return Err(parse::Error::new(key.span(), "expected at least one CPU"));
+            }
+
+            args.shield_cores = Some(cpus);
+        }
+
+        "disable_rt_throttling" => {
+            once(key, &args.disable_rt_throttling)?;
+
+            args.disable_rt_throttling = Some(syn::parse2::<BoolArg>(value)?.value);
+        }
+
+        "signals" => {
+            once(key, &args.signals)?;
+
+            let arg = syn::parse2::<RangeArg>(value)?;
+            let (start, end) = (arg.start.value(), arg.end.value());
+            if start >= end || end > 32 {
+                return Err(parse::Error::new(
+                    arg.start.span(),
+                    "expected a non-empty range of signals within `0..32`",
+                ));
+            }
+
+            args.signals = Some((start as u8..end as u8, arg.start.span()));
+        }
+
+        "external_header" => {
+            once(key, &args.external_header)?;
+
+            args.external_header = Some(syn::parse2::<StrArg>(value)?.lit);
+        }
+
+        "ffi_header" => {
+            once(key, &args.ffi_header)?;
+
+            args.ffi_header = Some(syn::parse2::<StrArg>(value)?.lit);
+        }
+
+        "graph" => {
+            once(key, &args.graph)?;
+
+            args.graph = Some(syn::parse2::<StrArg>(value)?.lit);
+        }
+
+        "signal_levels" => {
+            once(key, &args.signal_levels)?;
+
+            let lit = syn::parse2::<IntArg>(value)?.lit;
+            let levels = lit.value();
+            if levels == 0 || levels > 32 {
+                return Err(parse::Error::new(
+                    lit.span(),
+                    "expected a number of priority levels within `1..=32`",
+                ));
+            }
+
+            args.signal_levels = Some((levels as u8, key.span()));
+        }
+
+        "lock_groups" => {
+            once(key, &args.lock_groups)?;
+
+            args.lock_groups = Some(syn::parse2::<BoolArg>(value)?.value);
+        }
+
+        "processes" => {
+            once(key, &args.processes)?;
+
+            args.processes = Some((syn::parse2::<BoolArg>(value)?.value, key.span()));
+        }
+
+        "schedulability" => {
+            once(key, &args.schedulability)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            let schedulability = match &*lit.value() {
+                "deny" => Schedulability::Deny,
+                "warn" => Schedulability::Warn,
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "expected `\"deny\"` or `\"warn\"`",
+                    ))
+                }
+            };
+
+            args.schedulability = Some((schedulability, key.span()));
+        }
+
+        "wall_clock" => {
+            once(key, &args.wall_clock)?;
+
+            args.wall_clock = Some(syn::parse2::<BoolArg>(value)?.value);
+        }
+
+        "watchdog" => {
+            once(key, &args.watchdog)?;
+
+            let nanos = syn::parse2::<DurationArg>(value)?.nanos;
+            if nanos == 0 {
+                return Err(parse::Error::new(key.span(), "the window can't be empty"));
+            }
+
+            args.watchdog = Some((nanos, key.span()));
+        }
+
+        "watchdog_policy" => {
+            once(key, &args.watchdog_policy)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            let policy = match &*lit.value() {
+                "abort" => WatchdogPolicy::Abort,
+                "log" => WatchdogPolicy::Log,
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "expected `\"abort\"` or `\"log\"`",
+                    ))
+                }
+            };
+
+            args.watchdog_policy = Some((policy, key.span()));
+        }
+
+        "heartbeat_policy" => {
+            once(key, &args.heartbeat_policy)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            let policy = match &*lit.value() {
+                "abort" => WatchdogPolicy::Abort,
+                "log" => WatchdogPolicy::Log,
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "expected `\"abort\"` or `\"log\"`",
+                    ))
+                }
+            };
+
+            args.heartbeat_policy = Some((policy, key.span()));
+        }
+
+        "budget_policy" => {
+            once(key, &args.budget_policy)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            let policy = match &*lit.value() {
+                "abort" => BudgetPolicy::Abort,
+                "demote" => BudgetPolicy::Demote,
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "expected `\"abort\"` or `\"demote\"`",
+                    ))
+                }
+            };
+
+            args.budget_policy = Some((policy, key.span()));
+        }
+
+        "past_schedule" => {
+            once(key, &args.past_schedule)?;
+
+            args.past_schedule = Some(past_schedule(value)?);
+        }
+
+        "past_schedule_margin" => {
+            once(key, &args.past_schedule_margin)?;
+
+            let nanos = syn::parse2::<DurationArg>(value)?.nanos;
+            args.past_schedule_margin = Some((nanos, key.span()));
+        }
+
+        "broadcast" => {
+            once(key, &args.broadcast)?;
+
+            args.broadcast = Some(syn::parse2::<BroadcastArg>(value)?.groups);
+        }
+
+        "defer" => {
+            once(key, &args.defer)?;
+
+            let lit = syn::parse2::<IntArg>(value)?.lit;
+            match lit.value() {
+                slots @ 1..=64 => args.defer = Some((slots as u8, key.span())),
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "the number of `defer` slots must be in the range 1..=64",
+                    ))
+                }
+            }
+        }
+
+        "dispatch" => {
+            once(key, &args.dispatch)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            args.dispatch = Some(match &*lit.value() {
+                "signals" => Dispatch::Signals,
+                "threads" => Dispatch::Threads,
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "expected `\"signals\"` or `\"threads\"`",
+                    ))
+                }
+            });
+        }
+
+        "device" => {
+            once(key, &args.device)?;
+
+            args.device = Some(syn::parse2::<PathArg>(value)?.path);
+        }
+
+        "peripherals" => {
+            once(key, &args.peripherals)?;
+
+            args.peripherals = Some((syn::parse2::<BoolArg>(value)?.value, key.span()));
+        }
+
+        "dispatchers" => {
+            once(key, &args.dispatchers)?;
+
+            let list = syn::parse2::<ListArg<Ident>>(value)?;
+            args.dispatchers = Some(list.items.into_iter().collect());
+        }
+
+        "throttling_poll" => {
+            once(key, &args.throttling_poll)?;
+
+            let nanos = syn::parse2::<DurationArg>(value)?.nanos;
+            if nanos == 0 {
+                return Err(parse::Error::new(
+                    key.span(),
+                    "the polling period must be greater than zero",
+                ));
+            }
+
+            args.throttling_poll = Some((nanos, key.span()));
+        }
+
+        "seccomp" => {
+            once(key, &args.seccomp)?;
+
+            args.seccomp = Some((syn::parse2::<BoolArg>(value)?.value, key.span()));
+        }
+
+        "modes" => {
+            once(key, &args.modes)?;
+
+            let list = syn::parse2::<ListArg<Ident>>(value)?;
+            let mut modes: Vec<Ident> = vec![];
+            for mode in list.items {
+                if modes.contains(&mode) {
+                    return Err(parse::Error::new(
+                        mode.span(),
+                        "this mode is already declared",
+                    ));
+                }
+                modes.push(mode);
+            }
+
+            if modes.is_empty() || modes.len() > 32 {
+                return Err(parse::Error::new(
+                    key.span(),
+                    "expected between 1 and 32 modes",
+                ));
+            }
+
+            args.modes = Some((modes, key.span()));
+        }
+
+        "std_main" => {
+            once(key, &args.std_main)?;
+
+            args.std_main = Some((syn::parse2::<BoolArg>(value)?.value, key.span()));
+        }
+
+        "background_workers" => {
+            once(key, &args.background_workers)?;
+
+            let lit = syn::parse2::<IntArg>(value)?.lit;
+            match lit.value() {
+                workers @ 1..=64 => args.background_workers = Some((workers as u8, key.span())),
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "the number of background workers must be in the range 1..=64",
+                    ))
+                }
+            }
+        }
+
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+fn task_arg(args: &mut TaskArgs, key: &Ident, value: TokenStream2) -> parse::Result<bool> {
+    match &*key.to_string() {
+        "priority" => {
+            if let Ok(arg) = syn::parse2::<IntArg>(value) {
+                if arg.lit.value() <= u64::from(u8::max_value()) {
+                    args.priority = Some((arg.lit.value() as u8, arg.lit.span()));
+                }
+            }
+
+            return Ok(false);
+        }
+
+        "sched_deadline" => {
+            once(key, &args.sched_deadline)?;
+
+            args.sched_deadline = Some(sched_deadline(key, value)?);
+        }
+
+        "binds_fd" => {
+            once(key, &args.binds_fd)?;
+
+            args.binds_fd = Some(syn::parse2::<IdentArg>(value)?.ident);
+        }
+
+        "fd_events" => {
+            once(key, &args.fd_events)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            let mut events = 0;
+            for event in lit.value().split('|') {
+                events |=
+                    match event.trim() {
+                        // EPOLLIN
+                        "readable" => 0x001,
+                        // EPOLLOUT
+                        "writable" => 0x004,
+                        _ => return Err(parse::Error::new(
+                            lit.span(),
+                            "expected `\"readable\"`, `\"writable\"` or `\"readable|writable\"`",
+                        )),
+                    };
+            }
+
+            args.fd_events = Some((events, lit.span()));
+        }
+
+        "stack_size" => {
+            once(key, &args.stack_size)?;
+
+            args.stack_size = Some(syn::parse2::<SizeArg>(value)?.bytes);
+        }
+
+        "deadline" => {
+            once(key, &args.deadline)?;
+
+            args.deadline = Some((syn::parse2::<DurationArg>(value)?.nanos, key.span()));
+        }
+
+        "budget" => {
+            once(key, &args.budget)?;
+
+            let nanos = syn::parse2::<DurationArg>(value)?.nanos;
+            if nanos == 0 {
+                return Err(parse::Error::new(key.span(), "the budget can't be empty"));
+            }
+
+            args.budget = Some((nanos, key.span()));
+        }
+
+        "heartbeat" => {
+            once(key, &args.heartbeat)?;
+
+            let nanos = syn::parse2::<DurationArg>(value)?.nanos;
+            if nanos == 0 {
+                return Err(parse::Error::new(key.span(), "the interval can't be empty"));
+            }
+
+            args.heartbeat = Some((nanos, key.span()));
+        }
+
+        // NOTE small capacities are left to `rtfm-syntax`
+        "capacity" => {
+            let lit = match syn::parse2::<IntArg>(value) {
+                Ok(arg) if arg.lit.value() > u64::from(u8::max_value()) => arg.lit,
+                _ => return Ok(false),
+            };
+
+            once(key, &args.capacity)?;
+
+            if lit.value() > MAX_CAPACITY {
+                return Err(parse::Error::new(
+                    lit.span(),
+                    format!("the capacity of a task can't exceed {}", MAX_CAPACITY),
+                ));
+            }
+
+            args.capacity = Some((lit.value() as u16, lit.span()));
+        }
+
+        "period" => {
+            once(key, &args.period)?;
+
+            let nanos = syn::parse2::<DurationArg>(value)?.nanos;
+            if nanos == 0 {
+                return Err(parse::Error::new(
+                    key.span(),
+                    "the period must be greater than zero",
+                ));
+            }
+
+            args.period = Some((nanos, key.span()));
+        }
+
+        "modes" => {
+            once(key, &args.modes)?;
+
+            let list = syn::parse2::<ListArg<ModeArg>>(value)?;
+            let mut modes: Vec<(Ident, Option<u64>)> = vec![];
+            for arg in list.items {
+                if modes.iter().any(|(mode, _)| *mode == arg.mode) {
+                    return Err(parse::Error::new(
+                        arg.mode.span(),
+                        "this mode is already listed",
+                    ));
+                }
+                modes.push((arg.mode, arg.period));
+            }
+
+            args.modes = Some((modes, key.span()));
+        }
+
+        "mode_switch" => {
+            once(key, &args.mode_switch)?;
+
+            args.mode_switch = Some((syn::parse2::<BoolArg>(value)?.value, key.span()));
+        }
+
+        "external" => {
+            once(key, &args.external)?;
+
+            args.external = Some(syn::parse2::<BoolArg>(value)?.value);
+        }
+
+        "shm" => {
+            once(key, &args.shm)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            let name = lit.value();
+            if !name.starts_with('/') || name[1..].is_empty() || name[1..].contains('/') {
+                return Err(parse::Error::new(
+                    lit.span(),
+                    "expected a name like `\"/foo\"`: a slash followed by no other slashes",
+                ));
+            }
+
+            args.shm = Some(lit);
+        }
+
+        "spawner" => {
+            once(key, &args.spawner)?;
+
+            args.spawner = Some(syn::parse2::<BoolArg>(value)?.value);
+        }
+
+        "ffi" => {
+            once(key, &args.ffi)?;
+
+            args.ffi = Some(syn::parse2::<BoolArg>(value)?.value);
+        }
+
+        "backpressure" => {
+            once(key, &args.backpressure)?;
+
+            args.backpressure = Some(syn::parse2::<BoolArg>(value)?.value);
+        }
+
+        "past_schedule" => {
+            once(key, &args.past_schedule)?;
+
+            args.past_schedule = Some(past_schedule(value)?);
+        }
+
+        "then" => {
+            once(key, &args.then)?;
+
+            args.then = Some(syn::parse2::<IdentArg>(value)?.ident);
+        }
+
+        "binds" => {
+            once(key, &args.binds)?;
+
+            args.binds = Some(syn::parse2::<IdentArg>(value)?.ident);
+        }
+
+        "wcet" => {
+            once(key, &args.wcet)?;
+
+            let nanos = syn::parse2::<DurationArg>(value)?.nanos;
+            if nanos == 0 {
+                return Err(parse::Error::new(
+                    key.span(),
+                    "the WCET must be greater than zero",
+                ));
+            }
+
+            args.wcet = Some((nanos, key.span()));
+        }
+
+        "interarrival" => {
+            once(key, &args.interarrival)?;
+
+            let nanos = syn::parse2::<DurationArg>(value)?.nanos;
+            if nanos == 0 {
+                return Err(parse::Error::new(
+                    key.span(),
+                    "the inter-arrival time must be greater than zero",
+                ));
+            }
+
+            args.interarrival = Some((nanos, key.span()));
+        }
+
+        "class" => {
+            once(key, &args.class)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            let class = match &*lit.value() {
+                "realtime" => TaskClass::RealTime,
+                "background" => TaskClass::Background,
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "expected `\"realtime\"` or `\"background\"`",
+                    ))
+                }
+            };
+            args.class = Some((class, key.span()));
+        }
+
+        "throttling" => {
+            once(key, &args.throttling)?;
+
+            args.throttling = Some((syn::parse2::<BoolArg>(value)?.value, key.span()));
+        }
+
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+fn idle_arg(args: &mut IdleArgs, key: &Ident, value: TokenStream2) -> parse::Result<bool> {
+    match &*key.to_string() {
+        "policy" => {
+            once(key, &args.policy)?;
+
+            let lit = syn::parse2::<StrArg>(value)?.lit;
+            let policy = match &*lit.value() {
+                "idle" => IdlePolicy::Idle,
+                "other" => IdlePolicy::Other,
+                _ => {
+                    return Err(parse::Error::new(
+                        lit.span(),
+                        "expected `\"idle\"` or `\"other\"`",
+                    ))
+                }
+            };
+            args.policy = Some((policy, key.span()));
+        }
+
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+/// Adds `items` to the `key = [..]` list of `attr`, creating the list if needed
+fn extend_list(attr: &mut Attribute, name: &str, items: &[Ident]) {
+    if items.is_empty() {
+        return;
+    }
+
+    let stream = match attr.tts.clone().into_iter().next() {
+        Some(TokenTree::Group(group)) => group.stream(),
+        _ => TokenStream2::new(),
+    };
+
+    let mut found = false;
+    let mut args = split(stream);
+    for arg in &mut args {
+        let tokens = arg.clone().into_iter().collect::<Vec<_>>();
+        if let (Some(TokenTree::Ident(key)), Some(TokenTree::Group(list))) =
+            (tokens.get(0), tokens.get(2))
+        {
+            if key != name {
+                continue;
+            }
+
+            found = true;
+            let mut elems = split(list.stream());
+            for item in items {
+                if elems
+                    .iter()
+                    .all(|elem| elem.to_string() != item.to_string())
+                {
+                    elems.push(quote!(#item));
+                }
+            }
+
+            let eq = &tokens[1];
+            let mut list = Group::new(Delimiter::Bracket, quote!(#(#elems),*));
+            list.set_span(tokens[2].span());
+            *arg = quote!(#key #eq #list);
+        }
+    }
+
+    if !found {
+        let key = Ident::new(name, Span::call_site());
+        args.push(quote!(#key = [#(#items),*]));
+    }
+
+    attr.tts = quote!((#(#args),*));
+}
+
+fn past_schedule(value: TokenStream2) -> parse::Result<PastSchedule> {
+    let lit = syn::parse2::<StrArg>(value)?.lit;
+
+    match &*lit.value() {
+        "fire" => Ok(PastSchedule::Fire),
+        "reject" => Ok(PastSchedule::Reject),
+        "clamp" => Ok(PastSchedule::Clamp),
+        _ => Err(parse::Error::new(
+            lit.span(),
+            "expected `\"fire\"`, `\"reject\"` or `\"clamp\"`",
+        )),
+    }
+}
+
+/// Largest `capacity`; the message slots of a task are indexed with a `u16`
+const MAX_CAPACITY: u64 = 1 << 15;
+
+fn once<T>(key: &Ident, slot: &Option<T>) -> parse::Result<()> {
+    if slot.is_some() {
+        Err(parse::Error::new(
+            key.span(),
+            "argument appears more than once",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn sched_deadline(key: &Ident, value: TokenStream2) -> parse::Result<SchedDeadline> {
+    let group =
+        match value.into_iter().next() {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => group,
+            _ => return Err(parse::Error::new(
+                key.span(),
+                "expected `sched_deadline(runtime = \"..\", deadline = \"..\", period = \"..\")`",
+            )),
+        };
+
+    let (mut runtime, mut deadline, mut period) = (None, None, None);
+    for arg in split(group.stream()) {
+        let mut tokens = arg.into_iter();
+        let param = match tokens.next() {
+            Some(TokenTree::Ident(param)) => param,
+            _ => return Err(parse::Error::new(group.span(), "expected an identifier")),
+        };
+
+        let slot = match &*param.to_string() {
+            "runtime" => &mut runtime,
+            "deadline" => &mut deadline,
+            "period" => &mut period,
+            _ => {
+                return Err(parse::Error::new(
+                    param.span(),
+                    "expected one of `runtime`, `deadline` or `period`",
+                ))
+            }
+        };
+
+        if slot.is_some() {
+            return Err(parse::Error::new(
+                param.span(),
+                "argument appears more than once",
+            ));
+        }
+
+        *slot = Some(syn::parse2::<DurationArg>(tokens.collect())?.nanos);
+    }
+
+    match (runtime, deadline, period) {
+        (Some(runtime), Some(deadline), Some(period)) => {
+            if runtime > deadline || deadline > period {
+                return Err(parse::Error::new(
+                    key.span(),
+                    "`SCHED_DEADLINE` requires `runtime <= deadline <= period`",
+                ));
+            }
+
+            Ok(SchedDeadline {
+                runtime,
+                deadline,
+                period,
+                span: key.span(),
+            })
+        }
+
+        _ => Err(parse::Error::new(
+            key.span(),
+            "`runtime`, `deadline` and `period` must all be specified",
+        )),
+    }
+}
+
+/// `= FOO`
+struct IdentArg {
+    ident: Ident,
+}
+
+impl Parse for IdentArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        input.parse::<Token![=]>()?;
+
+        Ok(IdentArg {
+            ident: input.parse()?,
+        })
+    }
+}
+
+/// `(sensors = [fuse, log], ..)`
+struct BroadcastArg {
+    groups: Vec<(Ident, Vec<Ident>)>,
+}
+
+impl Parse for BroadcastArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        let content;
+        syn::parenthesized!(content in input);
+
+        let groups = content.parse_terminated::<_, Token![,]>(broadcast_group)?;
+
+        Ok(BroadcastArg {
+            groups: groups.into_iter().collect(),
+        })
+    }
+}
+
+/// `sensors = [fuse, log]`
+fn broadcast_group(input: ParseStream<'_>) -> parse::Result<(Ident, Vec<Ident>)> {
+    let name = input.parse::<Ident>()?;
+    input.parse::<Token![=]>()?;
+
+    let tasks;
+    syn::bracketed!(tasks in input);
+    let tasks = tasks.parse_terminated::<_, Token![,]>(Ident::parse)?;
+
+    Ok((name, tasks.into_iter().collect()))
+}
+
+/// `normal` or `degraded = "50ms"`
+struct ModeArg {
+    mode: Ident,
+    period: Option<u64>,
+}
+
+impl Parse for ModeArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        let mode = input.parse()?;
+        let period = if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            let lit = input.parse::<LitStr>()?;
+            let nanos = nanos(&lit)?;
+            if nanos == 0 {
+                return Err(parse::Error::new(
+                    lit.span(),
+                    "the period must be greater than zero",
+                ));
+            }
+
+            Some(nanos)
+        } else {
+            None
+        };
+
+        Ok(ModeArg { mode, period })
+    }
+}
+
+/// `= [a, b, c]`
+struct ListArg<T> {
+    items: Punctuated<T, Token![,]>,
+}
+
+impl<T> Parse for ListArg<T>
+where
+    T: Parse,
+{
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        let _eq_token: Token![=] = input.parse()?;
+
+        let content;
+        syn::bracketed!(content in input);
+
+        Ok(ListArg {
+            items: Punctuated::parse_terminated(&content)?,
+        })
+    }
+}
+
+/// `= path::to::Foo`
+struct PathArg {
+    path: Path,
+}
+
+impl Parse for PathArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        input.parse::<Token![=]>()?;
+
+        Ok(PathArg {
+            path: input.parse()?,
+        })
+    }
+}
+
+/// `= 42`
+struct IntArg {
+    lit: LitInt,
+}
+
+impl Parse for IntArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        input.parse::<Token![=]>()?;
+
+        Ok(IntArg {
+            lit: input.parse()?,
+        })
+    }
+}
+
+/// `= 4..12`
+struct RangeArg {
+    start: LitInt,
+    end: LitInt,
+}
+
+impl Parse for RangeArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        input.parse::<Token![=]>()?;
+        let start = input.parse()?;
+        input.parse::<Token![..]>()?;
+        let end = input.parse()?;
+
+        Ok(RangeArg { start, end })
+    }
+}
+
+/// `= "foo"`
+struct StrArg {
+    lit: LitStr,
+}
+
+impl Parse for StrArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        input.parse::<Token![=]>()?;
+
+        Ok(StrArg {
+            lit: input.parse()?,
+        })
+    }
+}
+
+/// `= true`
+struct BoolArg {
+    value: bool,
+}
+
+impl Parse for BoolArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        input.parse::<Token![=]>()?;
+
+        Ok(BoolArg {
+            value: input.parse::<LitBool>()?.value,
+        })
+    }
+}
+
+/// `= "64KiB"` or `= 65536`
+struct SizeArg {
+    bytes: usize,
+}
+
+impl Parse for SizeArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        input.parse::<Token![=]>()?;
+
+        let (bytes, span) = if input.peek(LitInt) {
+            let lit = input.parse::<LitInt>()?;
+            (Some(lit.value()), lit.span())
+        } else {
+            let lit = input.parse::<LitStr>()?;
+            (bytes(&lit.value()), lit.span())
+        };
+
+        match bytes {
+            Some(bytes) if bytes <= usize::max_value() as u64 => Ok(SizeArg {
+                bytes: bytes as usize,
+            }),
+            _ => Err(parse::Error::new(
+                span,
+                "expected a size like \"64KiB\"; valid units are `B`, `KiB` and `MiB`",
+            )),
+        }
+    }
+}
+
+/// e.g. `"64KiB"` -> `65536`
+fn bytes(s: &str) -> Option<u64> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(end);
+
+    let scale = match unit.trim() {
+        "" | "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        _ => return None,
+    };
+
+    value.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+/// `= "10ms"`
+struct DurationArg {
+    nanos: u64,
+}
+
+impl Parse for DurationArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        input.parse::<Token![=]>()?;
+        let lit = input.parse::<LitStr>()?;
+
+        Ok(DurationArg {
+            nanos: nanos(&lit)?,
+        })
+    }
+}
+
+/// e.g. `"10ms"` -> `10_000_000`
+fn nanos(lit: &LitStr) -> parse::Result<u64> {
+    let s = lit.value();
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(end);
+
+    let scale = match unit.trim() {
+        "ns" => Some(1),
+        "us" => Some(1_000),
+        "ms" => Some(1_000_000),
+        "s" => Some(1_000_000_000),
+        _ => None,
+    };
+
+    value
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(scale?))
+        .ok_or_else(|| {
+            parse::Error::new(
+                lit.span(),
+                "expected a duration like \"10ms\"; valid units are `ns`, `us`, `ms` and `s`",
+            )
+        })
+}
+
+fn is(attr: &Attribute, name: &str) -> bool {
+    attr.path.segments.len() == 1 && attr.path.segments[0].ident == name
+}
+
+/// Removes the arguments consumed by `f` from a list of comma separated arguments
+fn strip_args(
+    args: TokenStream2,
+    mut f: impl FnMut(&Ident, TokenStream2) -> parse::Result<bool>,
+) -> parse::Result<TokenStream2> {
+    let mut kept = vec![];
+    for arg in split(args) {
+        let mut tokens = arg.clone().into_iter();
+        let consumed = match tokens.next() {
+            Some(TokenTree::Ident(key)) => f(&key, tokens.collect())?,
+            _ => false,
+        };
+
+        if !consumed {
+            kept.push(arg);
+        }
+    }
+
+    Ok(quote!(#(#kept),*))
+}
+
+/// Removes the arguments consumed by `f` from `attr`
+fn strip(
+    attr: &mut Attribute,
+    f: impl FnMut(&Ident, TokenStream2) -> parse::Result<bool>,
+) -> parse::Result<()> {
+    let group = match attr.tts.clone().into_iter().next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => group,
+        _ => return Ok(()),
+    };
+
+    let kept = strip_args(group.stream(), f)?;
+    attr.tts = if kept.is_empty() {
+        TokenStream2::new()
+    } else {
+        let mut stripped = Group::new(Delimiter::Parenthesis, kept);
+        stripped.set_span(group.span());
+        quote!(#stripped)
+    };
+
+    Ok(())
+}
+
+/// Splits a stream of comma separated arguments
+fn split(stream: TokenStream2) -> Vec<TokenStream2> {
+    let mut args = vec![];
+    let mut arg = vec![];
+
+    for tt in stream {
+        if let TokenTree::Punct(punct) = &tt {
+            if punct.as_char() == ',' {
+                args.push(arg.drain(..).collect());
+                continue;
+            }
+        }
+
+        arg.push(tt);
+    }
+
+    if !arg.is_empty() {
+        args.push(arg.into_iter().collect());
+    }
+
+    args
+}
@@ -0,0 +1,51 @@
+//! `mod app { .. }`, the application of RTIC 0.6
+//!
+//! `rtfm-syntax` only parses `const APP: () = { .. };` so the items that it knows about are moved
+//! into one; the rest, e.g. `use` items and helper functions, stay in the module, which is emitted
+//! around the generated code.
+
+use quote::quote;
+use syn::{parse, spanned::Spanned, Item, ItemConst, ItemMod};
+
+use super::{hook_attr, is, is_extern_tasks, shared_local};
+
+/// Splits `module` into the `const APP` that `rtfm-syntax` parses and the module without its
+/// RTFM items
+pub fn module(mut module: ItemMod) -> parse::Result<(ItemConst, ItemMod)> {
+    let items = match module.content.take() {
+        Some((_, items)) => items,
+        None => {
+            return Err(parse::Error::new(
+                module.span(),
+                "the application must be declared inline: `mod app { .. }`",
+            ))
+        }
+    };
+
+    let (rtfm, other): (Vec<_>, Vec<_>) = items.into_iter().partition(is_rtfm_item);
+    let item = syn::parse2(quote!(
+        const APP: () = {
+            #(#rtfm)*
+        };
+    ))?;
+
+    module.content = Some((Default::default(), other));
+
+    Ok((item, module))
+}
+
+// items that go in `const APP`
+fn is_rtfm_item(item: &Item) -> bool {
+    match item {
+        Item::Fn(f) => {
+            hook_attr(f).is_some()
+                || f.attrs
+                    .iter()
+                    .any(|attr| is(attr, "init") || is(attr, "idle") || is(attr, "task"))
+        }
+        Item::Struct(s) => shared_local::is_model_struct(s) || s.ident == "Resources",
+        Item::ForeignMod(block) => is_extern_tasks(block),
+        Item::Static(_) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,66 @@
+//! `#[app(signal_levels = N)]`: more priorities than signals
+//!
+//! The priorities of the tasks are folded onto the levels `1..=N` before `rtfm-syntax` sees them,
+//! so the signals, the ceilings and the `lock`s all work on levels. The logical priorities stay
+//! in `TaskArgs.priority`; the dispatchers use them to order the releases of a level.
+
+use std::collections::BTreeMap;
+
+use proc_macro2::{Span, TokenTree};
+use quote::quote;
+use syn::{Ident, IntSuffix, Item, LitInt, Stmt};
+
+use super::{is, split, strip, TaskArgs};
+
+/// Rewrites the `priority` of every task into the level it's folded onto
+pub fn fold(levels: u8, stmts: &mut [Stmt], tasks: &BTreeMap<Ident, TaskArgs>) {
+    let highest = tasks.values().map(priority).max().unwrap_or(1);
+
+    for stmt in stmts {
+        if let Stmt::Item(Item::Fn(f)) = stmt {
+            let args = match tasks.get(&f.ident) {
+                Some(args) => args,
+                None => continue,
+            };
+
+            for attr in &mut f.attrs {
+                if !is(attr, "task") {
+                    continue;
+                }
+
+                // NOTE `task_arg` keeps `priority` so this can't fail
+                strip(attr, |key, _| Ok(*key == "priority")).ok();
+
+                let level = LitInt::new(
+                    u64::from(level(priority(args), highest, levels)),
+                    IntSuffix::None,
+                    args.priority
+                        .map(|(_, span)| span)
+                        .unwrap_or_else(Span::call_site),
+                );
+                let mut args = match attr.tts.clone().into_iter().next() {
+                    Some(TokenTree::Group(group)) => split(group.stream()),
+                    _ => vec![],
+                };
+                args.push(quote!(priority = #level));
+                attr.tts = quote!((#(#args),*));
+            }
+        }
+    }
+}
+
+/// The logical priority of a task; `1` if it doesn't have one
+pub fn priority(args: &TaskArgs) -> u8 {
+    args.priority.map(|(priority, _)| priority).unwrap_or(1)
+}
+
+// Level of logical priority `priority` when `1..=highest` is folded onto `1..=levels`; the order
+// of the priorities is kept
+fn level(priority: u8, highest: u8, levels: u8) -> u8 {
+    if highest <= levels {
+        return priority;
+    }
+
+    let scaled = u16::from(priority) * u16::from(levels);
+    ((scaled + u16::from(highest) - 1) / u16::from(highest)) as u8
+}
@@ -0,0 +1,378 @@
+//! `#[shared]` and `#[local]` resources, the resource model of RTIC 0.6
+//!
+//! `rtfm-syntax` only knows the `struct Resources` model so the new one is rewritten into it:
+//!
+//! - the fields of `Shared` and `Local` become late resources
+//! - the `shared` and `local` lists of a context become its `resources` list
+//! - `local = [x: T = e]` becomes a resource with an initial value that only its context uses
+//! - `init` returns `(Shared, Local)`, or `(Shared, Local, init::Monotonics)`; it's wrapped in a
+//!   function that returns `init::LateResources`
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{
+    parse::{self, Parse, ParseStream},
+    Attribute, Expr, Fields, Ident, Item, ItemFn, ItemStruct, ReturnType, Stmt, Token, Type,
+};
+
+use super::{extend_list, is, strip, ListArg};
+
+/// The `#[shared]` / `#[local]` resources of the application
+pub struct Model {
+    /// `#[shared] struct Shared { .. }`, without the attribute
+    pub shared: ItemStruct,
+
+    /// `#[local] struct Local { .. }`, without the attribute
+    pub local: ItemStruct,
+
+    // fields of `Shared`
+    shared_fields: BTreeSet<Ident>,
+
+    // fields of `Local` and the context that uses each
+    local_fields: BTreeMap<Ident, Option<Ident>>,
+
+    // `local = [x: T = e]` resources and the name of their field in `LocalResources`
+    declared: BTreeMap<Ident, Ident>,
+
+    // `#[init(e)] x: T` fields of `struct Resources`
+    initialized: Vec<TokenStream2>,
+}
+
+impl Model {
+    /// Whether `resource` is a field of `Shared`
+    pub fn is_shared(&self, resource: &Ident) -> bool {
+        self.shared_fields.contains(resource)
+    }
+
+    /// Name of the field of `resource` in the `SharedResources` or `LocalResources` structs
+    pub fn field<'a>(&'a self, resource: &'a Ident) -> &'a Ident {
+        self.declared.get(resource).unwrap_or(resource)
+    }
+}
+
+/// Whether `item` is the `#[shared]` or the `#[local]` struct
+pub fn is_model_struct(item: &ItemStruct) -> bool {
+    item.attrs
+        .iter()
+        .any(|attr| is(attr, "shared") || is(attr, "local"))
+}
+
+/// Builds the model from the `#[shared]` and `#[local]` structs, if there are any
+pub fn model(structs: Vec<ItemStruct>, legacy: Option<Span>) -> parse::Result<Option<Model>> {
+    if structs.is_empty() {
+        return Ok(None);
+    }
+
+    if let Some(span) = legacy {
+        return Err(parse::Error::new(
+            span,
+            "`struct Resources` can't be used together with `#[shared]` and `#[local]`",
+        ));
+    }
+
+    let mut shared = None;
+    let mut local = None;
+    for mut item in structs {
+        let i = item
+            .attrs
+            .iter()
+            .position(|attr| is(attr, "shared") || is(attr, "local"))
+            .expect("UNREACHABLE");
+        let attr = item.attrs.remove(i);
+        let kind = &attr.path.segments[0].ident;
+        if !attr.tts.is_empty() {
+            return Err(parse::Error::new(
+                kind.span(),
+                "this attribute takes no arguments",
+            ));
+        }
+
+        match &item.fields {
+            Fields::Named(_) => {}
+            _ => {
+                return Err(parse::Error::new(
+                    item.ident.span(),
+                    "resources must be declared as named fields",
+                ))
+            }
+        }
+
+        let slot = if kind == "shared" {
+            &mut shared
+        } else {
+            &mut local
+        };
+        if slot.is_some() {
+            return Err(parse::Error::new(
+                item.ident.span(),
+                format!("only one `#[{}]` struct can be declared", kind),
+            ));
+        }
+        *slot = Some(item);
+    }
+
+    let (shared, local) = match (shared, local) {
+        (Some(shared), Some(local)) => (shared, local),
+        (Some(item), None) | (None, Some(item)) => {
+            return Err(parse::Error::new(
+                item.ident.span(),
+                "`#[shared]` and `#[local]` structs must be declared together",
+            ))
+        }
+        (None, None) => unreachable!(),
+    };
+
+    let shared_fields = shared
+        .fields
+        .iter()
+        .map(|field| field.ident.clone().expect("UNREACHABLE"))
+        .collect::<BTreeSet<_>>();
+    let mut local_fields = BTreeMap::new();
+    for field in &local.fields {
+        let name = field.ident.clone().expect("UNREACHABLE");
+        if shared_fields.contains(&name) {
+            return Err(parse::Error::new(
+                name.span(),
+                "a resource can't be both shared and local",
+            ));
+        }
+        local_fields.insert(name, None);
+    }
+
+    Ok(Some(Model {
+        shared,
+        local,
+        shared_fields,
+        local_fields,
+        declared: BTreeMap::new(),
+        initialized: vec![],
+    }))
+}
+
+/// Turns the `shared` and `local` arguments of the `#[init]`, `#[idle]` or `#[task]` attribute
+/// `attr` of context `ctxt` into a `resources` list
+pub fn context(model: Option<&mut Model>, attr: &mut Attribute, ctxt: &Ident) -> parse::Result<()> {
+    let mut shared = vec![];
+    let mut local = vec![];
+    let has_model = model.is_some();
+    strip(attr, |key, value| match &*key.to_string() {
+        "shared" if has_model => {
+            shared.extend(syn::parse2::<ListArg<Ident>>(value)?.items);
+            Ok(true)
+        }
+        "local" if has_model => {
+            local.extend(syn::parse2::<ListArg<LocalArg>>(value)?.items);
+            Ok(true)
+        }
+        "shared" | "local" => Err(parse::Error::new(
+            key.span(),
+            "this argument needs the `#[shared]` and `#[local]` structs",
+        )),
+        "resources" if has_model => Err(parse::Error::new(
+            key.span(),
+            "use `shared` and `local` with the `#[shared]` and `#[local]` structs",
+        )),
+        "resources" if !cfg!(feature = "legacy-resources") => Err(legacy(key.span())),
+        _ => Ok(false),
+    })?;
+
+    let model = match model {
+        Some(model) => model,
+        None => return Ok(()),
+    };
+
+    let is_init = is(attr, "init");
+    let mut resources = vec![];
+    for name in shared {
+        if !model.shared_fields.contains(&name) {
+            return Err(parse::Error::new(
+                name.span(),
+                "this is not a field of the `#[shared]` struct",
+            ));
+        }
+
+        if is_init {
+            return Err(parse::Error::new(
+                name.span(),
+                "`init` initializes the shared resources; it can't use them",
+            ));
+        }
+
+        resources.push(name);
+    }
+
+    for arg in local {
+        match arg {
+            LocalArg::Resource(name) => {
+                let user =
+                    match model.local_fields.get_mut(&name) {
+                        Some(user) => user,
+                        None => return Err(parse::Error::new(
+                            name.span(),
+                            "this is not a field of the `#[local]` struct; declare it here with \
+                             `name: Type = value`",
+                        )),
+                    };
+
+                if is_init {
+                    return Err(parse::Error::new(
+                        name.span(),
+                        "`init` initializes the `#[local]` resources; it can't use them",
+                    ));
+                }
+
+                if user.is_some() {
+                    return Err(parse::Error::new(
+                        name.span(),
+                        "a local resource can only be used by one context",
+                    ));
+                }
+                *user = Some(ctxt.clone());
+
+                resources.push(name);
+            }
+
+            LocalArg::Declared { name, ty, expr } => {
+                let resource = Ident::new(&format!("__{}_{}", ctxt, name), name.span());
+                if model.declared.contains_key(&resource) {
+                    return Err(parse::Error::new(
+                        name.span(),
+                        "this local is declared more than once",
+                    ));
+                }
+
+                model.initialized.push(quote!(
+                    #[init(#expr)]
+                    #resource: #ty
+                ));
+                model.declared.insert(resource.clone(), name);
+
+                resources.push(resource);
+            }
+        }
+    }
+
+    extend_list(attr, "resources", &resources);
+
+    Ok(())
+}
+
+/// Adds the `struct Resources` of the model to `stmts` and makes `init` return its late
+/// resources
+pub fn desugar(model: &Model, stmts: &mut Vec<Stmt>) -> parse::Result<()> {
+    let fields = model.shared.fields.iter().chain(&model.local.fields);
+    let initialized = &model.initialized;
+    stmts.push(Stmt::Item(Item::Struct(syn::parse2(quote!(
+        struct Resources {
+            #(#fields,)*
+            #(#initialized,)*
+        }
+    ))?)));
+
+    for stmt in stmts {
+        if let Stmt::Item(Item::Fn(f)) = stmt {
+            if f.attrs.iter().any(|attr| is(attr, "init")) {
+                init(model, f)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// `fn init(c: init::Context) -> (Shared, Local) { .. }` becomes
+// `fn init(c: init::Context) -> init::LateResources { .. }`
+fn init(model: &Model, f: &mut ItemFn) -> parse::Result<()> {
+    let ty = match &f.decl.output {
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Tuple(tuple) if tuple.elems.len() == 2 || tuple.elems.len() == 3 => ty.clone(),
+            _ => return Err(init_error(f)),
+        },
+        ReturnType::Default => return Err(init_error(f)),
+    };
+
+    let late = model
+        .shared
+        .fields
+        .iter()
+        .map(|field| (field, quote!(0)))
+        .chain(model.local.fields.iter().map(|field| (field, quote!(1))))
+        .map(|(field, i)| {
+            let cfgs = field.attrs.iter().filter(|attr| is(attr, "cfg"));
+            let name = &field.ident;
+
+            quote!(
+                #(#cfgs)*
+                #name: resources.#i.#name
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // NOTE the closure keeps the `return`s of the body in the body
+    let name = &f.ident;
+    let stmts = &f.block.stmts;
+    let (output, block) = if late.is_empty() {
+        (
+            ReturnType::Default,
+            quote!({
+                let _: #ty = (move || -> #ty { #(#stmts)* })();
+            }),
+        )
+    } else {
+        (
+            syn::parse2(quote!(-> #name::LateResources))?,
+            quote!({
+                let resources: #ty = (move || -> #ty { #(#stmts)* })();
+
+                #name::LateResources {
+                    #(#late,)*
+                }
+            }),
+        )
+    };
+
+    f.decl.output = output;
+    f.block = Box::new(syn::parse2(block)?);
+
+    Ok(())
+}
+
+fn init_error(f: &ItemFn) -> parse::Error {
+    parse::Error::new(
+        f.ident.span(),
+        "`init` must return `(Shared, Local)` or `(Shared, Local, init::Monotonics)`",
+    )
+}
+
+/// The error of the legacy syntax when the `legacy-resources` feature is disabled
+pub fn legacy(span: Span) -> parse::Error {
+    parse::Error::new(
+        span,
+        "`struct Resources` and `resources = [..]` need the `legacy-resources` feature; use the \
+         `#[shared]` and `#[local]` structs instead",
+    )
+}
+
+// `b` or `c: u32 = 0`
+enum LocalArg {
+    Resource(Ident),
+    Declared { name: Ident, ty: Type, expr: Expr },
+}
+
+impl Parse for LocalArg {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        let name = input.parse()?;
+        if !input.peek(Token![:]) {
+            return Ok(LocalArg::Resource(name));
+        }
+
+        let _colon_token: Token![:] = input.parse()?;
+        let ty = input.parse()?;
+        let _eq_token: Token![=] = input.parse()?;
+        let expr = input.parse()?;
+
+        Ok(LocalArg::Declared { name, ty, expr })
+    }
+}
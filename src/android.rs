@@ -0,0 +1,88 @@
+//! Running inside an Android native service
+//!
+//! Bionic, and the way `init` starts a service, differ from a desktop Linux in three ways that
+//! matter to the runtime:
+//!
+//! - bionic reserves the first `RESERVED_SIGNALS` real-time signals of the kernel in every
+//!   process (POSIX timers, debuggerd, the profilers, fdtrack, ..) so the signals of the runtime
+//!   start after them; see `export::SIGRTMIN`
+//! - `init` puts the service in a cpuset (`/dev/cpuset/*`; `system-background`, for example, only
+//!   has the little cores) and pinning a thread to a CPU outside of it fails with `EINVAL`. Core
+//!   #N runs on the N-th CPU that the process was allowed to run on at start-up instead of on CPU
+//!   #N
+//! - the service gets its capabilities and resource limits from the `capabilities` and `rlimit`
+//!   options of its `.rc` file, not from `setcap` or `ulimit`, and its SELinux domain needs the
+//!   `sys_nice` and `ipc_lock` capabilities and the `setsched` permission
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::fs;
+
+use crate::shield;
+
+/// Real-time signals of the kernel that bionic keeps for itself (`__SIGRT_RESERVED`)
+pub(crate) const RESERVED_SIGNALS: i32 = 9;
+
+/// `.rc` options that give the service `SCHED_FIFO` and `mlockall`
+pub(crate) const FIX_CAPABILITIES: &str = "capabilities SYS_NICE IPC_LOCK";
+
+/// `.rc` option that lets the service lock all its memory without `CAP_IPC_LOCK`
+pub(crate) const FIX_MEMLOCK: &str = "rlimit memlock unlimited unlimited";
+
+// hints of the start-up errors; see `Error`
+pub(crate) const HINT_MEMLOCK: &str =
+    "add `capabilities IPC_LOCK`, or `rlimit memlock unlimited unlimited`, to the `.rc` file of \
+     the service";
+
+pub(crate) const HINT_SYS_NICE: &str =
+    "add `capabilities SYS_NICE` to the `.rc` file of the service and allow the `sys_nice` \
+     capability to its SELinux domain";
+
+pub(crate) const HINT_SETSCHED: &str =
+    "SELinux denied it; allow `setsched` on `self:process` to the domain of the service";
+
+pub(crate) const HINT_CPUSET: &str =
+    "the cpuset of the service has fewer CPUs than the application has cores; move the service \
+     to a larger cpuset with `task_profiles` in its `.rc` file";
+
+/// Its `Cpus_allowed_list` is the affinity of the process
+const STATUS: &str = "/proc/self/status";
+
+/// The CPUs the process was allowed to run on at start-up, as a bit mask; `0` until read
+static CPUS: AtomicU64 = AtomicU64::new(0);
+
+/// The CPU that core #`core` runs on: the `core`-th CPU the process was allowed to run on at
+/// start-up, or CPU #`core` if that's unknown; `None` if the service has fewer CPUs than that
+///
+/// NOTE the affinity is read on the first call, which happens before the runtime pins the first
+/// thread
+pub(crate) fn cpu(core: u8) -> Option<u8> {
+    let mut cpus = CPUS.load(Ordering::Relaxed);
+    if cpus == 0 {
+        cpus = allowed();
+        CPUS.store(cpus, Ordering::Relaxed);
+    }
+
+    if cpus == 0 {
+        Some(core)
+    } else {
+        (0..64)
+            .filter(|cpu| cpus & (1 << cpu) != 0)
+            .nth(usize::from(core))
+    }
+}
+
+// `0` if the affinity couldn't be read
+fn allowed() -> u64 {
+    fs::read_to_string(STATUS)
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find(|line| line.starts_with("Cpus_allowed_list:"))
+                .and_then(|line| shield::parse_list(line["Cpus_allowed_list:".len()..].trim()))
+        })
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|&cpu| cpu < 64)
+        .fold(0, |mask, cpu| mask | 1 << cpu)
+}
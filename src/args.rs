@@ -0,0 +1,144 @@
+//! The command line and the environment that `main` received
+//!
+//! The generated `main` is the C entry point (`#![no_main]`), so the arguments reach it from the C
+//! library, not from `std`. `std::env::args` happens to work with glibc, which passes them to the
+//! initializers of the program too, but it's empty with musl; these work with either. With
+//! `#[app(std_main = true)]` `std` calls `main`, and they are rebuilt from `std::env`
+
+use core::{iter, ptr, slice};
+use std::{
+    env,
+    ffi::{CStr, CString},
+    os::unix::ffi::OsStringExt,
+};
+
+use cty::{c_char, c_int};
+
+/// `argc`, `argv` and `envp` of `main`; only written by `set`, before `init` runs
+static mut ARGC: usize = 0;
+static mut ARGV: *const *const c_char = ptr::null();
+static mut ENVP: *const *const c_char = ptr::null();
+
+/// Keeps the arguments of `main` around
+///
+/// NOTE the processes of the cores are forked later so they inherit them
+pub unsafe fn set(argc: c_int, argv: *const *const c_char, envp: *const *const c_char) {
+    ARGC = if argv.is_null() {
+        0
+    } else {
+        argc.max(0) as usize
+    };
+    ARGV = argv;
+    ENVP = envp;
+}
+
+/// Rebuilds the arguments of `main` from `std::env`, for a `main` that `std` called
+pub unsafe fn set_from_std() {
+    let argv = leak(env::args_os().map(|arg| arg.into_vec()));
+    let envp = leak(env::vars_os().map(|(name, value)| {
+        let mut var = name.into_vec();
+        var.push(b'=');
+        var.extend(value.into_vec());
+        var
+    }));
+
+    set((argv.len() - 1) as c_int, argv.as_ptr(), envp.as_ptr());
+}
+
+// a null terminated array of NUL terminated strings that live as long as the process
+fn leak(strings: impl Iterator<Item = Vec<u8>>) -> &'static [*const c_char] {
+    let strings = strings
+        .filter_map(|string| CString::new(string).ok())
+        .map(|string| CString::into_raw(string) as *const c_char)
+        .chain(iter::once(ptr::null()))
+        .collect::<Vec<_>>();
+
+    Box::leak(strings.into_boxed_slice())
+}
+
+/// The command line arguments of the process, the name of the program first
+#[derive(Clone, Copy)]
+pub struct Args {
+    argv: &'static [*const c_char],
+}
+
+impl Args {
+    #[doc(hidden)]
+    pub unsafe fn new() -> Self {
+        Args {
+            argv: if ARGV.is_null() {
+                &[]
+            } else {
+                slice::from_raw_parts(ARGV, ARGC)
+            },
+        }
+    }
+
+    /// The `i`-th argument
+    pub fn get(&self, i: usize) -> Option<&'static CStr> {
+        // NOTE(unsafe) the C library passes `argc` NUL terminated strings that live as long as
+        // the process
+        self.argv.get(i).map(|&arg| unsafe { CStr::from_ptr(arg) })
+    }
+
+    /// The number of arguments, including the name of the program
+    pub fn len(&self) -> usize {
+        self.argv.len()
+    }
+
+    /// Whether there are no arguments, not even the name of the program
+    pub fn is_empty(&self) -> bool {
+        self.argv.is_empty()
+    }
+
+    /// Iterates over the arguments
+    pub fn iter(&self) -> impl Iterator<Item = &'static CStr> {
+        // NOTE(unsafe) see `get`
+        self.argv.iter().map(|&arg| unsafe { CStr::from_ptr(arg) })
+    }
+}
+
+/// The environment the process started with, as `NAME=value` strings
+///
+/// NOTE `std::env::set_var` can change the entries that this iterates over
+#[derive(Clone, Copy)]
+pub struct Env {
+    envp: *const *const c_char,
+}
+
+impl Env {
+    #[doc(hidden)]
+    pub unsafe fn new() -> Self {
+        Env { envp: ENVP }
+    }
+
+    /// The value of the variable `name`, if it's set
+    pub fn get(&self, name: &str) -> Option<&'static CStr> {
+        self.iter().find_map(|var| {
+            let var = var.to_bytes_with_nul();
+            if var.starts_with(name.as_bytes()) && var.get(name.len()) == Some(&b'=') {
+                // NOTE the value is the NUL terminated rest of the entry
+                CStr::from_bytes_with_nul(&var[name.len() + 1..]).ok()
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Iterates over the variables, as `NAME=value` strings
+    pub fn iter(&self) -> impl Iterator<Item = &'static CStr> {
+        let envp = self.envp;
+        (0..)
+            // NOTE(unsafe) `envp` is terminated by a null pointer
+            .map(move |i| unsafe {
+                if envp.is_null() {
+                    ptr::null()
+                } else {
+                    *envp.add(i)
+                }
+            })
+            .take_while(|var| !var.is_null())
+            // NOTE(unsafe) the entries are NUL terminated strings that live as long as the process
+            .map(|var| unsafe { CStr::from_ptr(var) })
+    }
+}
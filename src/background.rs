@@ -0,0 +1,45 @@
+//! Background tasks: `#[task(class = "background")]`
+//!
+//! Background tasks don't run at a priority level of a core but on a pool of worker threads that
+//! run under `SCHED_OTHER`, on any CPU. Each core that spawns a background task gets a queue of
+//! its own for the messages of the task, which it fills under a `lock` like any other free queue,
+//! and then rings the doorbell of the workers. Neither step can block the sender: the workers
+//! never hold anything the real-time tasks wait for.
+//!
+//! A worker takes the messages of one task at a time, one message per task per round, so a slow
+//! background task doesn't starve the others. A task is never run by two workers at once, which
+//! keeps its `static mut` variables exclusive. The messages that are still queued when the
+//! application shuts down are dropped.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Lets a single worker at a time run a background task
+pub struct TaskClaim {
+    running: AtomicBool,
+}
+
+impl TaskClaim {
+    pub const fn new() -> Self {
+        TaskClaim {
+            running: AtomicBool::new(false),
+        }
+    }
+
+    /// Runs `f` unless another worker is running the task; returns `false` if it didn't run `f`
+    /// and, otherwise, what `f` returns
+    pub fn try_run(&self, f: impl FnOnce() -> bool) -> bool {
+        if self.running.swap(true, Ordering::Acquire) {
+            return false;
+        }
+
+        let ran = f();
+        self.running.store(false, Ordering::Release);
+        ran
+    }
+}
+
+impl Default for TaskClaim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
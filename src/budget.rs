@@ -0,0 +1,238 @@
+//! Execution budgets: `#[task(budget = "200us")]`
+//!
+//! A task with a budget may use that much CPU time per run; the time of the tasks that preempt it
+//! is not charged to it. Each thread that runs such a task gets a timer on its own CPU-time clock
+//! (`CLOCK_THREAD_CPUTIME_ID`), armed for the innermost task it's running, that sends `SIGXCPU` to
+//! the thread when the task runs out of budget. The handler of `SIGXCPU` then calls the overrun
+//! policy of the application with the name of the task:
+//!
+//! - `budget_abort` reports it and aborts the process (default)
+//! - `budget_demote` reports it and moves the thread to `SCHED_OTHER` until the task returns so
+//!   that a runaway loop no longer freezes the CPU
+//! - a `#[budget_overrun]` function; the task keeps running once it returns
+//!
+//! Every task of a core that has budgets runs inside `budget`, with or without a budget of its
+//! own, so that the time of the tasks that preempt another one can be told apart.
+
+use core::{
+    cell::Cell,
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use std::process;
+
+use nc::{
+    sched_param_t, sigev_un_t, sigevent_t, sighandler_t, siginfo_t, sigset_t, sigval_t, timer_t,
+    timespec_t, SIGXCPU,
+};
+
+use crate::{export, restorer, sim, stack, stats, Error};
+
+// A task running inside `budget`; it lives on the stack of the thread
+struct Frame {
+    name: &'static str,
+    // in nanoseconds; `None` if the task has no budget
+    budget: Option<u64>,
+    on_overrun: fn(&'static str, Duration),
+    // CPU time of the thread when the task started
+    start: u64,
+    // `BUSY` when the task started
+    busy: u64,
+    // the task this one preempted
+    outer: *const Frame,
+    overran: Cell<bool>,
+}
+
+impl Frame {
+    // CPU time the task has used so far, without the tasks that preempted it
+    fn used(&self, now: u64) -> u64 {
+        let preempted = BUSY.with(|busy| busy.load(Ordering::Relaxed)) - self.busy;
+
+        (now - self.start).saturating_sub(preempted)
+    }
+}
+
+thread_local! {
+    // The innermost task this thread is running
+    static TOP: Cell<*const Frame> = Cell::new(ptr::null());
+
+    // CPU time this thread has spent in tasks that returned; the time a task is preempted for is
+    // the increase of this counter while it runs
+    //
+    // NOTE an atomic add so that a task that preempts the update doesn't undo its own
+    static BUSY: AtomicU64 = AtomicU64::new(0);
+
+    // The timer on the CPU-time clock of this thread; created by the first task with a budget
+    static TIMER: Cell<Option<timer_t>> = Cell::new(None);
+
+    // The `SCHED_FIFO` priority this thread had before `budget_demote`
+    static DEMOTED: Cell<Option<u8>> = Cell::new(None);
+}
+
+/// Installs the `SIGXCPU` handler that applies the overrun policy
+pub unsafe fn init() -> Result<(), Error> {
+    restorer::sigaction(SIGXCPU, on_expiry as sighandler_t, sigset_t::default())
+        .map_err(|errno| Error::BudgetHandler { errno })
+}
+
+/// Runs the task `name`, `f`; if it uses more than `budget` nanoseconds of CPU time it's reported
+/// to `on_overrun`
+pub fn budget(
+    name: &'static str,
+    budget: Option<u64>,
+    on_overrun: fn(&'static str, Duration),
+    f: impl FnOnce(),
+) {
+    // CPU time is not simulated
+    if sim::active() {
+        return f();
+    }
+
+    let frame = Frame {
+        name,
+        budget,
+        on_overrun,
+        start: stats::thread_cputime(),
+        busy: BUSY.with(|busy| busy.load(Ordering::Relaxed)),
+        outer: TOP.with(Cell::get),
+        overran: Cell::new(false),
+    };
+    TOP.with(|top| top.set(&frame));
+    arm(&frame, frame.start);
+
+    f();
+
+    let now = stats::thread_cputime();
+    let used = frame.used(now);
+    TOP.with(|top| top.set(frame.outer));
+    BUSY.with(|busy| busy.fetch_add(used, Ordering::Relaxed));
+
+    if frame.overran.get() {
+        restore();
+    }
+
+    // NOTE(unsafe) the preempted task is further down the stack
+    match unsafe { frame.outer.as_ref() } {
+        Some(outer) => arm(outer, now),
+        None => settime(0),
+    }
+}
+
+/// Default overrun policy: take the whole process down
+pub fn budget_abort(task: &'static str, _: Duration) {
+    // NOTE `eprintln!` takes a lock; this may have interrupted its owner
+    unsafe { stack::report(&["error: task `", task, "` exceeded its budget; aborting\n"]) }
+
+    process::abort()
+}
+
+/// Lets the task run to completion under `SCHED_OTHER`; the thread gets its priority back when
+/// the task returns
+pub fn budget_demote(task: &'static str, _: Duration) {
+    unsafe {
+        stack::report(&[
+            "warning: task `",
+            task,
+            "` exceeded its budget; it runs under `SCHED_OTHER` until it returns\n",
+        ]);
+
+        let mut param = sched_param_t::default();
+        // NOTE a degraded runtime (`soft-rt`) is not on `SCHED_FIFO` to begin with
+        if nc::sched_getparam(0, &mut param).is_ok() && param.sched_priority > 0 {
+            DEMOTED.with(|demoted| demoted.set(Some(param.sched_priority as u8)));
+            export::set_nice(0, 0);
+        }
+    }
+}
+
+// Gives the thread back the priority it had before `budget_demote`
+fn restore() {
+    if let Some(priority) = DEMOTED.with(Cell::take) {
+        unsafe { export::set_priority(0, priority).unwrap_or_else(export::fatal) }
+    }
+}
+
+// Arms the timer for the rest of the budget of `frame`, or disarms it if `frame` has none left to
+// enforce
+fn arm(frame: &Frame, now: u64) {
+    match frame.budget {
+        Some(budget) if !frame.overran.get() => {
+            // NOTE a budget that already ran out expires right away
+            settime(now + budget.saturating_sub(frame.used(now)).max(1))
+        }
+        _ => settime(0),
+    }
+}
+
+// Sets the expiry of the timer of this thread to the absolute CPU time `expiry`; `0` disarms it
+fn settime(expiry: u64) {
+    let timer = match TIMER.with(Cell::get) {
+        Some(timer) => timer,
+        // nothing to disarm
+        None if expiry == 0 => return,
+        None => {
+            let timer = unsafe { create() }.unwrap_or_else(export::fatal);
+            TIMER.with(|cell| cell.set(Some(timer)));
+            timer
+        }
+    };
+
+    nc::timer_settime(
+        timer,
+        nc::TIMER_ABSTIME,
+        &nc::itimerspec_t {
+            it_interval: timespec_t {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: timespec_t {
+                tv_sec: (expiry / 1_000_000_000) as _,
+                tv_nsec: (expiry % 1_000_000_000) as _,
+            },
+        },
+        None,
+    )
+    .map_err(|errno| Error::Timer { errno })
+    .unwrap_or_else(export::fatal);
+}
+
+// A timer on the CPU-time clock of the calling thread that sends it `SIGXCPU`
+unsafe fn create() -> Result<timer_t, Error> {
+    let mut timer = 0;
+    nc::timer_create(
+        nc::CLOCK_THREAD_CPUTIME_ID,
+        Some(&mut sigevent_t {
+            sigev_value: sigval_t { sival_ptr: 0 },
+            sigev_signo: SIGXCPU,
+            sigev_notify: nc::SIGEV_THREAD_ID,
+            sigev_un: sigev_un_t { tid: nc::gettid() },
+        }),
+        &mut timer,
+    )
+    .map_err(|errno| Error::Timer { errno })?;
+
+    Ok(timer)
+}
+
+extern "C" fn on_expiry(_: i32, _: &mut siginfo_t, _: usize) {
+    // NOTE(unsafe) the innermost task is further down the stack
+    let frame = match unsafe { TOP.with(Cell::get).as_ref() } {
+        Some(frame) => frame,
+        None => return,
+    };
+
+    let budget = match frame.budget {
+        Some(budget) if !frame.overran.get() => budget,
+        _ => return,
+    };
+
+    // the timer may have been armed for a task that returned in the meantime
+    let now = stats::thread_cputime();
+    if frame.used(now) < budget {
+        return arm(frame, now);
+    }
+
+    frame.overran.set(true);
+    (frame.on_overrun)(frame.name, Duration::from_nanos(budget));
+}
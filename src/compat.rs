@@ -0,0 +1,145 @@
+//! Running RTIC applications written for Cortex-M devices on Linux
+//!
+//! `#[rtfm::app]` also accepts the surface of RTIC 0.6: the application is a `mod app { .. }`, the
+//! `device`, `peripherals` and `dispatchers` arguments are accepted, resources are `#[shared]` and
+//! `#[local]` and any context, or thread, spawns a software task with `foo::spawn(..)`.
+//!
+//! There are no interrupts on Linux. A hardware task, `#[task(binds = USART1)]`, becomes a
+//! software task without inputs that `pend(Interrupt::USART1)` releases, like pending the
+//! interrupt in the NVIC does. Pending it again before the task runs has no effect.
+//!
+//! The device crate is replaced with a stub that only has the `Interrupt` enum and an empty
+//! `Peripherals` struct; declare it with `rtfm::compat::device!` and select it with `cfg`. `rtic`
+//! can then be an alias of this module:
+//!
+//! ``` ignore
+//! #[cfg(target_os = "linux")]
+//! use rtfm::compat as rtic;
+//!
+//! #[cfg(target_os = "linux")]
+//! rtfm::compat::device!(pub pac: USART1 = 37, EXTI0 = 6);
+//!
+//! #[cfg(not(target_os = "linux"))]
+//! use stm32f4::stm32f411 as pac;
+//!
+//! #[rtic::app(device = crate::pac, dispatchers = [EXTI0])]
+//! mod app {
+//!     // ..
+//! }
+//! ```
+//!
+//! NOTE `device` is used from the modules the application generates so it must be an absolute
+//! path, e.g. `crate::pac` or `stm32f4::stm32f411`
+
+pub use crate::{device, Exclusive, Mutex};
+pub use linux_rtfm_macros::app;
+
+/// An interrupt of a device
+pub trait InterruptNumber: Copy {
+    /// Position of the interrupt in the vector table
+    fn number(self) -> u16;
+}
+
+/// Most interrupts a Cortex-M device can have
+const MAX_INTERRUPTS: usize = 496;
+
+// NOTE only written at start-up, before any task can run
+static mut HANDLERS: [Option<fn()>; MAX_INTERRUPTS] = [None; MAX_INTERRUPTS];
+
+/// Pends `interrupt`: the task bound to it runs as soon as its priority allows
+///
+/// # Panics
+///
+/// If no task is bound to `interrupt`
+pub fn pend<I>(interrupt: I)
+where
+    I: InterruptNumber,
+{
+    let nr = interrupt.number();
+
+    // NOTE(unsafe) no longer written; see `bind`
+    match unsafe { HANDLERS.get(usize::from(nr)).and_then(|handler| *handler) } {
+        Some(handler) => handler(),
+        None => panic!("no task is bound to interrupt #{}", nr),
+    }
+}
+
+/// Makes `pend(interrupt)` call `handler`
+///
+/// NOTE this must run before any context can call `pend`
+pub unsafe fn bind<I>(interrupt: I, handler: fn())
+where
+    I: InterruptNumber,
+{
+    let nr = usize::from(interrupt.number());
+    assert!(nr < MAX_INTERRUPTS, "interrupt #{} doesn't exist", nr);
+
+    HANDLERS[nr] = Some(handler);
+}
+
+/// The core peripherals of a Cortex-M device, `init::Context.core`; there are none on Linux
+pub struct CorePeripherals {
+    _0: (),
+}
+
+impl CorePeripherals {
+    #[doc(hidden)]
+    pub unsafe fn steal() -> Self {
+        CorePeripherals { _0: () }
+    }
+}
+
+/// Declares the stub of a device crate: a module with the `Interrupt` enum, an empty
+/// `Peripherals` struct and `NVIC_PRIO_BITS`
+///
+/// ``` ignore
+/// rtfm::compat::device!(pub pac: USART1 = 37, EXTI0 = 6);
+/// ```
+#[macro_export]
+macro_rules! device {
+    ($(#[$attr:meta])* $vis:vis $name:ident: $($interrupt:ident = $nr:expr),* $(,)?) => {
+        $(#[$attr])*
+        $vis mod $name {
+            /// The interrupts of the device
+            #[allow(non_camel_case_types)]
+            #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+            pub enum Interrupt {
+                $($interrupt = $nr,)*
+            }
+
+            impl $crate::compat::InterruptNumber for Interrupt {
+                fn number(self) -> u16 {
+                    self as u16
+                }
+            }
+
+            /// Number of priority bits of the NVIC
+            pub const NVIC_PRIO_BITS: u8 = 4;
+
+            /// The peripherals of the device; there are none on Linux
+            pub struct Peripherals {
+                _0: (),
+            }
+
+            impl Peripherals {
+                /// Returns the peripherals once
+                pub fn take() -> Option<Self> {
+                    use core::sync::atomic::{AtomicBool, Ordering};
+
+                    static TAKEN: AtomicBool = AtomicBool::new(false);
+
+                    if TAKEN.swap(true, Ordering::AcqRel) {
+                        None
+                    } else {
+                        Some(Peripherals { _0: () })
+                    }
+                }
+
+                /// Returns the peripherals, even if they were already taken
+                pub unsafe fn steal() -> Self {
+                    Peripherals { _0: () }
+                }
+            }
+        }
+    };
+}
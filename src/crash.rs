@@ -0,0 +1,212 @@
+//! Crash reports
+//!
+//! `SIGSEGV`, `SIGBUS` and `SIGFPE` are caught on the alternate signal stack of the thread and
+//! reported to stderr along with the task that was running: its name, its priority and its
+//! dispatch depth, i.e. how many tasks were nested on the thread. A backtrace follows, one
+//! `module+offset` per frame, which `addr2line -e module offset` turns into a source location.
+//! Then the signal is raised again with its default action so that the process still dumps core.
+//! The kernel restores that action as it enters the handler (`SA_RESETHAND`): the handler doesn't
+//! call `rt_sigaction`, which the seccomp filter doesn't allow, and a second crash, e.g. in the
+//! handler itself, takes the process down without a report.
+//!
+//! A `SIGSEGV` in the guard page of a stack is reported as an overflow of that stack instead.
+//!
+//! NOTE the report doesn't allocate or lock, but the unwinder and `dladdr` may take the lock of
+//! the dynamic loader; a crash inside the loader could hang instead of being reported
+
+// there's no backtrace on 32-bit ARM
+#![cfg_attr(target_arch = "arm", allow(dead_code, unused_imports))]
+
+use core::{cell::Cell, ffi::c_void, mem, str};
+use std::{ffi::CStr, process};
+
+use cty::{c_char, c_int};
+use nc::{sighandler_t, siginfo_t, sigset_t, SIGBUS, SIGFPE, SIGSEGV};
+
+use crate::{restorer, stack, Error};
+
+/// How many frames the backtrace shows at most
+const MAX_FRAMES: usize = 64;
+
+thread_local! {
+    // The innermost task this thread is running, and its priority
+    static TASK: Cell<Option<(&'static str, u8)>> = Cell::new(None);
+
+    // How many tasks are nested on this thread
+    static DEPTH: Cell<u8> = Cell::new(0);
+}
+
+/// Installs the handlers that report crashes, and stack overflows
+pub(crate) unsafe fn install() -> Result<(), Error> {
+    for &signo in &[SIGSEGV, SIGBUS, SIGFPE] {
+        restorer::sigaction_once(signo, on_crash as sighandler_t, sigset_t::default())
+            .map_err(|errno| Error::FaultHandler { errno })?;
+    }
+
+    Ok(())
+}
+
+/// Runs `f`, the task `name` of priority `priority`, as the task that a crash is blamed on
+pub(crate) fn enter<R>(name: &'static str, priority: u8, f: impl FnOnce() -> R) -> R {
+    let outer = TASK.with(|task| task.replace(Some((name, priority))));
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+
+    let r = f();
+
+    DEPTH.with(|depth| depth.set(depth.get() - 1));
+    TASK.with(|task| task.set(outer));
+
+    r
+}
+
+extern "C" fn on_crash(signo: i32, si: &mut siginfo_t, _: usize) {
+    unsafe {
+        let addr = si.siginfo.sifields.sigfault.addr;
+        let mut buf = [0; 18];
+
+        // NOTE `eprintln!` takes a lock; this may have interrupted its owner
+        if let Some(name) = stack::overflowed(addr).filter(|_| signo == SIGSEGV) {
+            stack::report(&["error: stack overflow in ", name, "\n"]);
+            context();
+            process::abort();
+        }
+
+        let signal = match signo {
+            SIGSEGV => "segmentation fault (SIGSEGV)",
+            SIGBUS => "bus error (SIGBUS)",
+            _ => "arithmetic exception (SIGFPE)",
+        };
+        stack::report(&["error: ", signal, " at address ", hex(addr, &mut buf), "\n"]);
+        context();
+        backtrace();
+
+        // the signal stays blocked while this handler runs; it's delivered again, with the default
+        // action that `SA_RESETHAND` restored, once the handler returns
+        let _ = nc::tgkill(nc::getpid(), nc::gettid(), signo);
+    }
+}
+
+// Reports the task that was running
+unsafe fn context() {
+    let task = TASK.try_with(Cell::get).ok().flatten();
+    let depth = DEPTH.try_with(Cell::get).unwrap_or(0);
+
+    if let Some((name, priority)) = task {
+        let (mut priority_buf, mut depth_buf) = ([0; 3], [0; 3]);
+        stack::report(&[
+            "  in task `",
+            name,
+            "` of priority ",
+            decimal(priority, &mut priority_buf),
+            ", dispatch depth ",
+            decimal(depth, &mut depth_buf),
+            "\n",
+        ]);
+    } else {
+        stack::report(&["  outside of the tasks (in `init`, `idle` or a runtime thread)\n"]);
+    }
+}
+
+// NOTE on 32-bit ARM `_Unwind_GetIP` is a macro over `_Unwind_VRS_Get`, not a function
+#[cfg(target_arch = "arm")]
+unsafe fn backtrace() {}
+
+#[cfg(not(target_arch = "arm"))]
+unsafe fn backtrace() {
+    stack::report(&["  backtrace:\n"]);
+
+    let mut frames = 0usize;
+    _Unwind_Backtrace(trace, &mut frames as *mut usize as *mut c_void);
+}
+
+/// `_URC_NO_REASON`; go on with the next frame
+const URC_NO_REASON: c_int = 0;
+
+/// `_URC_END_OF_STACK`; stop
+const URC_END_OF_STACK: c_int = 5;
+
+// Reports one frame of the backtrace
+//
+// NOTE the runtime's `__restorer` trampoline is encoded the way the unwinder expects a signal
+// frame to be so the walk continues into the code that crashed
+extern "C" fn trace(context: *mut c_void, frames: *mut c_void) -> c_int {
+    unsafe {
+        let frames = &mut *(frames as *mut usize);
+        let ip = _Unwind_GetIP(context);
+        if ip == 0 || *frames == MAX_FRAMES {
+            return URC_END_OF_STACK;
+        }
+        *frames += 1;
+
+        let (mut ip_buf, mut offset_buf) = ([0; 18], [0; 18]);
+        let mut info: DlInfo = mem::zeroed();
+        if dladdr(ip as *const c_void, &mut info) != 0 && !info.dli_fname.is_null() {
+            let module = CStr::from_ptr(info.dli_fname).to_str().unwrap_or("?");
+            stack::report(&[
+                "    ",
+                hex(ip, &mut ip_buf),
+                " ",
+                if module.is_empty() { "?" } else { module },
+                "+",
+                hex(ip - info.dli_fbase as usize, &mut offset_buf),
+                "\n",
+            ]);
+        } else {
+            stack::report(&["    ", hex(ip, &mut ip_buf), "\n"]);
+        }
+
+        URC_NO_REASON
+    }
+}
+
+// `value` in hexadecimal, with a `0x` prefix
+fn hex(value: usize, buf: &mut [u8; 18]) -> &str {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let len = 2 * mem::size_of::<usize>();
+    buf[0] = b'0';
+    buf[1] = b'x';
+    for i in 0..len {
+        buf[2 + i] = DIGITS[(value >> (4 * (len - 1 - i))) & 0xf];
+    }
+
+    // NOTE(unsafe) all ASCII
+    unsafe { str::from_utf8_unchecked(&buf[..2 + len]) }
+}
+
+// `value` in decimal
+fn decimal(mut value: u8, buf: &mut [u8; 3]) -> &str {
+    let mut start = buf.len();
+    loop {
+        start -= 1;
+        buf[start] = b'0' + value % 10;
+        value /= 10;
+
+        if value == 0 {
+            break;
+        }
+    }
+
+    // NOTE(unsafe) all ASCII
+    unsafe { str::from_utf8_unchecked(&buf[start..]) }
+}
+
+/// `Dl_info`
+#[repr(C)]
+struct DlInfo {
+    dli_fname: *const c_char,
+    dli_fbase: *mut c_void,
+    dli_sname: *const c_char,
+    dli_saddr: *mut c_void,
+}
+
+extern "C" {
+    fn dladdr(addr: *const c_void, info: *mut DlInfo) -> c_int;
+
+    fn _Unwind_Backtrace(
+        trace: extern "C" fn(*mut c_void, *mut c_void) -> c_int,
+        frames: *mut c_void,
+    ) -> c_int;
+
+    fn _Unwind_GetIP(context: *mut c_void) -> usize;
+}
@@ -0,0 +1,169 @@
+use core::{
+    mem::{self, MaybeUninit},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crate::{
+    export::{self, Pid},
+    SpawnError,
+};
+
+/// How many bytes a closure passed to `rtfm::defer` may capture
+pub const DEFER_SLOT_SIZE: usize = 64;
+
+/// Most slots an application can ask for; one bit each in `FREE`
+const MAX_SLOTS: usize = 64;
+
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct Storage([MaybeUninit<u8>; DEFER_SLOT_SIZE]);
+
+const STORAGE: Storage = Storage([MaybeUninit::uninit(); DEFER_SLOT_SIZE]);
+
+/// Where the dispatchers of the application are
+struct Config {
+    tgid: &'static Pid,
+    tid: Option<&'static Pid>,
+    // (priority, signal) of each dispatcher
+    levels: &'static [(u8, u8)],
+}
+
+static READY: AtomicBool = AtomicBool::new(false);
+static mut CONFIG: Option<Config> = None;
+
+// one bit per slot; set if the slot is free
+static FREE: AtomicU64 = AtomicU64::new(0);
+
+// NOTE a slot belongs to the context that cleared its `FREE` bit until the dispatcher sets it
+static mut CALLS: [Option<unsafe fn(*mut u8)>; MAX_SLOTS] = [None; MAX_SLOTS];
+static mut STORAGES: [Storage; MAX_SLOTS] = [STORAGE; MAX_SLOTS];
+
+/// Runs `f` once, at `priority`, on the dispatcher of that priority level
+///
+/// `f` is moved into one of the `#[app(defer = N)]` slots and, like a task without a name, runs
+/// as soon as no context of priority `priority` or higher is running. It can't `lock` resources.
+///
+/// Returns `f` back if every slot is taken, if the process has too many signals pending or if
+/// the application is shutting down.
+///
+/// # Panics
+///
+/// If the application doesn't declare `#[app(defer = N)]`, if no software task runs at
+/// `priority` (only those levels have a dispatcher) or if `f` captures more than
+/// `DEFER_SLOT_SIZE` bytes
+pub fn defer<F>(priority: u8, f: F) -> Result<(), SpawnError<F>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    assert!(
+        mem::size_of::<F>() <= DEFER_SLOT_SIZE && mem::align_of::<F>() <= 16,
+        "the closure captures more than `DEFER_SLOT_SIZE` bytes"
+    );
+
+    if !READY.load(Ordering::Acquire) {
+        panic!("`rtfm::defer` requires `#[app(defer = N)]`");
+    }
+
+    // NOTE(unsafe) no longer written; see `defer_init`
+    let config = unsafe { CONFIG.as_ref().expect("UNREACHABLE") };
+    let signo = match config.levels.iter().find(|(level, _)| *level == priority) {
+        Some(&(_, signo)) => signo,
+        None => panic!(
+            "no task runs at priority {} so there's no dispatcher to `defer` to",
+            priority
+        ),
+    };
+
+    if export::shutting_down() {
+        return Err(SpawnError::ShuttingDown(f));
+    }
+
+    let index = match claim() {
+        Some(index) => index,
+        None => return Err(SpawnError::Full(f)),
+    };
+
+    unsafe {
+        let storage = STORAGES[index].0.as_mut_ptr() as *mut F;
+        ptr::write(storage, f);
+        CALLS[index] = Some(call::<F>);
+
+        let tgid = config.tgid.wait();
+        let tid = config.tid.map(Pid::wait);
+        match export::try_sigqueue(tgid, tid, signo, export::DEFER_EVENT | index) {
+            Ok(()) => Ok(()),
+            Err(nc::EAGAIN) => {
+                CALLS[index] = None;
+                let f = ptr::read(storage);
+                release(index);
+
+                Err(SpawnError::SignalQueueFull(f))
+            }
+            Err(_) => panic!("error: couldn't enqueue signal\n"),
+        }
+    }
+}
+
+unsafe fn call<F>(f: *mut u8)
+where
+    F: FnOnce(),
+{
+    ptr::read(f as *mut F)()
+}
+
+fn claim() -> Option<usize> {
+    let mut free = FREE.load(Ordering::Relaxed);
+    loop {
+        if free == 0 {
+            return None;
+        }
+
+        let index = free.trailing_zeros();
+        match FREE.compare_exchange_weak(
+            free,
+            free & !(1 << index),
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return Some(index as usize),
+            Err(now) => free = now,
+        }
+    }
+}
+
+fn release(index: usize) {
+    FREE.fetch_or(1 << index, Ordering::Release);
+}
+
+/// Makes `slots` slots available to `rtfm::defer`
+///
+/// NOTE this must run before any context can call `rtfm::defer`
+pub unsafe fn defer_init(
+    slots: u8,
+    tgid: &'static Pid,
+    tid: Option<&'static Pid>,
+    levels: &'static [(u8, u8)],
+) {
+    CONFIG = Some(Config { tgid, tid, levels });
+    FREE.store(
+        u64::max_value() >> (MAX_SLOTS - usize::from(slots)),
+        Ordering::Relaxed,
+    );
+    READY.store(true, Ordering::Release);
+}
+
+/// Runs the closure in slot `index` and frees the slot
+///
+/// NOTE only the dispatcher that received the signal of `index` may call this
+pub unsafe fn run_deferred(index: u8) {
+    let index = usize::from(index);
+
+    if let Some(call) = CALLS[index].take() {
+        // NOTE move the closure out of the slot before the slot can be reused
+        let mut f = STORAGES[index];
+        release(index);
+
+        call(f.0.as_mut_ptr() as *mut u8)
+    }
+}
@@ -0,0 +1,319 @@
+use core::fmt;
+
+use nc::{EACCES, EADDRINUSE, EAGAIN, EBUSY, EINVAL, ENOMEM, ENOSYS, EPERM};
+
+#[cfg(target_os = "android")]
+use crate::android;
+
+/// An error that prevents the runtime from starting
+///
+/// Each variant carries the `errno` reported by the failing system call
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// Pinning a thread to a CPU failed
+    Affinity { cpu: Option<u8>, errno: i32 },
+
+    /// Locking the process memory in RAM (`mlockall`) failed
+    MemoryLock { errno: i32 },
+
+    /// Moving a thread to `SCHED_FIFO` failed
+    RealTimePriority { priority: u8, errno: i32 },
+
+    /// Moving a thread back to `SCHED_NORMAL` failed
+    NormalPriority { errno: i32 },
+
+    /// Moving the thread of `idle` to `SCHED_IDLE` or `SCHED_OTHER` failed
+    IdlePolicy { errno: i32 },
+
+    /// The kernel rejected a `SCHED_DEADLINE` reservation
+    Deadline { errno: i32 },
+
+    /// Blocking the real-time signals failed
+    SignalMask { errno: i32 },
+
+    /// Registering a signal handler failed
+    SignalHandler { signo: u8, errno: i32 },
+
+    /// Allocating the stack of a thread (`mmap`) failed
+    Stack { size: usize, errno: i32 },
+
+    /// Installing the handlers that report crashes and stack overflows failed
+    FaultHandler { errno: i32 },
+
+    /// Installing the alternate signal stack of a thread failed
+    SignalStack { errno: i32 },
+
+    /// Installing the handler that enforces the execution budgets failed
+    BudgetHandler { errno: i32 },
+
+    /// Spawning a thread (`pthread_create`) failed
+    Thread { errno: i32 },
+
+    /// Sharing memory with, or forking, the process of a core failed (`processes = true`)
+    Process { errno: i32 },
+
+    /// Creating or arming a POSIX timer failed
+    Timer { errno: i32 },
+
+    /// Creating the epoll instance or watching a file descriptor failed
+    Epoll { errno: i32 },
+
+    /// Opening the log file failed
+    LogFile { errno: i32 },
+
+    /// Creating the shared memory object of a channel (`/dev/shm`) failed
+    Shm { errno: i32 },
+
+    /// Listening on the address of the metrics endpoint failed
+    Metrics { errno: i32 },
+
+    /// Reading the recording to replay failed
+    Recording { errno: i32 },
+
+    /// Switching to the user and group of `RuntimeConfig::drop_privileges` failed
+    Privileges { errno: i32 },
+
+    /// Installing the seccomp filter failed (`seccomp = true`)
+    Seccomp { errno: i32 },
+}
+
+impl Error {
+    /// The `errno` reported by the failing system call
+    pub fn errno(&self) -> i32 {
+        match *self {
+            Error::Affinity { errno, .. }
+            | Error::MemoryLock { errno }
+            | Error::RealTimePriority { errno, .. }
+            | Error::NormalPriority { errno }
+            | Error::IdlePolicy { errno }
+            | Error::Deadline { errno }
+            | Error::SignalMask { errno }
+            | Error::SignalHandler { errno, .. }
+            | Error::Stack { errno, .. }
+            | Error::FaultHandler { errno }
+            | Error::SignalStack { errno }
+            | Error::BudgetHandler { errno }
+            | Error::Thread { errno }
+            | Error::Process { errno }
+            | Error::Timer { errno }
+            | Error::Epoll { errno }
+            | Error::LogFile { errno }
+            | Error::Shm { errno }
+            | Error::Metrics { errno }
+            | Error::Recording { errno }
+            | Error::Privileges { errno }
+            | Error::Seccomp { errno } => errno,
+        }
+    }
+
+    // Hint on how to fix the error, if there's a well-known cause
+    fn hint(&self) -> Option<&'static str> {
+        #[cfg(target_os = "android")]
+        {
+            if let Some(hint) = self.android_hint() {
+                return Some(hint);
+            }
+        }
+
+        Some(match (*self, self.errno()) {
+            (Error::Affinity { cpu: Some(_), .. }, EINVAL) => {
+                "the CPU doesn't exist or is offline; reduce the number of `cores` or check `nproc`"
+            }
+            (Error::MemoryLock { .. }, EPERM) => "run `sudo setcap cap_ipc_lock+ep $binary` first",
+            (Error::MemoryLock { .. }, ENOMEM) | (Error::MemoryLock { .. }, EAGAIN) => {
+                "`RLIMIT_MEMLOCK` is too low; raise it with `ulimit -l unlimited` or \
+                 run `sudo setcap cap_ipc_lock+ep $binary` first"
+            }
+            (Error::SignalMask { .. }, EINVAL) => {
+                "the application needs more real-time signals than the C library leaves to it; \
+                 use fewer priority levels or fewer cores"
+            }
+            (Error::RealTimePriority { .. }, EPERM) => {
+                "the process lacks `CAP_SYS_NICE` or `RLIMIT_RTPRIO` is too low; \
+                 run `sudo setcap cap_sys_nice+ep $binary` or `ulimit -r 99` first"
+            }
+            (Error::Deadline { .. }, EPERM) => "run `sudo setcap cap_sys_nice+ep $binary` first",
+            (Error::Deadline { .. }, EBUSY) => {
+                "the kernel's admission control rejected the reservation; \
+                 reduce the runtime or lengthen the period of the `sched_deadline` tasks"
+            }
+            (Error::Stack { .. }, ENOMEM) => {
+                "not enough memory for the thread stacks; reduce `stack_size`"
+            }
+            (Error::Timer { .. }, EPERM) => {
+                "timers on `CLOCK_BOOTTIME_ALARM` need `CAP_WAKE_ALARM`; \
+                 run `sudo setcap cap_wake_alarm+ep $binary` first"
+            }
+            (Error::Timer { .. }, EAGAIN) => {
+                "the process has too many pending signals; raise `RLIMIT_SIGPENDING` with \
+                 `ulimit -i`"
+            }
+            (Error::Metrics { .. }, EADDRINUSE) => {
+                "another process listens on the address; change `#[app(metrics = ..)]`"
+            }
+            (Error::Metrics { .. }, EACCES) => {
+                "ports below 1024 need `CAP_NET_BIND_SERVICE`; pick a higher port"
+            }
+            (Error::Recording { .. }, ENOSYS) => {
+                "replaying needs the `record` feature of `linux-rtfm`"
+            }
+            (Error::Privileges { .. }, EPERM) => {
+                "only root, or a process with `CAP_SETUID` and `CAP_SETGID`, can switch to \
+                 another user"
+            }
+            (Error::Seccomp { .. }, EINVAL) => "the kernel lacks `CONFIG_SECCOMP_FILTER`",
+            (Error::Seccomp { .. }, ENOSYS) => "the filter only supports x86_64 and aarch64",
+            (Error::Recording { .. }, EINVAL) => {
+                "the file isn't a recording or its oldest events were overwritten"
+            }
+            _ => return None,
+        })
+    }
+
+    // The fixes of an Android native service go in its `.rc` file and its SELinux policy
+    #[cfg(target_os = "android")]
+    fn android_hint(&self) -> Option<&'static str> {
+        Some(match (*self, self.errno()) {
+            (Error::MemoryLock { .. }, EPERM)
+            | (Error::MemoryLock { .. }, ENOMEM)
+            | (Error::MemoryLock { .. }, EAGAIN) => android::HINT_MEMLOCK,
+            (Error::RealTimePriority { .. }, EPERM) | (Error::Deadline { .. }, EPERM) => {
+                android::HINT_SYS_NICE
+            }
+            (Error::RealTimePriority { .. }, EACCES) | (Error::Deadline { .. }, EACCES) => {
+                android::HINT_SETSCHED
+            }
+            (Error::Affinity { cpu: Some(_), .. }, EINVAL) => android::HINT_CPUSET,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::Affinity { cpu: Some(cpu), .. } => {
+                write!(f, "couldn't pin a thread to CPU #{}", cpu)?
+            }
+            Error::Affinity { cpu: None, .. } => f.write_str("couldn't change CPU affinity")?,
+            Error::MemoryLock { .. } => f.write_str("couldn't lock the process memory")?,
+            Error::RealTimePriority { priority, .. } => write!(
+                f,
+                "couldn't change the scheduling policy to `SCHED_FIFO` with priority {}",
+                priority
+            )?,
+            Error::NormalPriority { .. } => {
+                f.write_str("couldn't change the scheduling policy to `SCHED_NORMAL`")?
+            }
+            Error::IdlePolicy { .. } => {
+                f.write_str("couldn't move `idle` to `SCHED_IDLE` or `SCHED_OTHER`")?
+            }
+            Error::Deadline { .. } => {
+                f.write_str("couldn't change the scheduling policy to `SCHED_DEADLINE`")?
+            }
+            Error::SignalMask { .. } => f.write_str("couldn't change the signal mask")?,
+            Error::SignalHandler { signo, .. } => write!(
+                f,
+                "couldn't register the handler of signal SIGRTMIN+{}",
+                signo
+            )?,
+            Error::Stack { size, .. } => {
+                write!(f, "couldn't allocate a thread stack of {} bytes", size)?
+            }
+            Error::FaultHandler { .. } => f.write_str("couldn't install the crash handlers")?,
+            Error::SignalStack { .. } => {
+                f.write_str("couldn't install the alternate signal stack of a thread")?
+            }
+            Error::BudgetHandler { .. } => {
+                f.write_str("couldn't install the execution budget overrun handler")?
+            }
+            Error::Thread { .. } => f.write_str("couldn't spawn a thread")?,
+            Error::Process { .. } => f.write_str("couldn't start the process of a core")?,
+            Error::Timer { .. } => f.write_str("couldn't set up a POSIX timer")?,
+            Error::Epoll { .. } => f.write_str("couldn't watch the file descriptors")?,
+            Error::LogFile { .. } => f.write_str("couldn't open the log file")?,
+            Error::Shm { .. } => f.write_str("couldn't create the shared memory of a channel")?,
+            Error::Metrics { .. } => f.write_str("couldn't listen on the metrics address")?,
+            Error::Recording { .. } => f.write_str("couldn't read the recording to replay")?,
+            Error::Privileges { .. } => {
+                f.write_str("couldn't drop the privileges of the process")?
+            }
+            Error::Seccomp { .. } => f.write_str("couldn't install the seccomp filter")?,
+        }
+
+        write!(f, " (errno = {})", self.errno())?;
+
+        if let Some(hint) = self.hint() {
+            write!(f, "; {}", hint)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Why a task couldn't be spawned or scheduled
+///
+/// Every variant hands the payload of the message back
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpawnError<T> {
+    /// The task already has `capacity` messages pending
+    Full(T),
+
+    /// The timer queue has no room for another entry
+    TimerQueueFull(T),
+
+    /// The instant passed to `schedule` has already passed
+    InstantPassed(T),
+
+    /// The application is shutting down and no longer accepts messages
+    ShuttingDown(T),
+
+    /// The process has as many signals pending as `RLIMIT_SIGPENDING` allows
+    SignalQueueFull(T),
+
+    /// The current mode of the application disables the task; see `#[app(modes = ..)]`
+    Disabled(T),
+}
+
+impl<T> SpawnError<T> {
+    /// The payload of the message that couldn't be sent
+    pub fn payload(&self) -> &T {
+        match self {
+            SpawnError::Full(payload)
+            | SpawnError::TimerQueueFull(payload)
+            | SpawnError::InstantPassed(payload)
+            | SpawnError::ShuttingDown(payload)
+            | SpawnError::SignalQueueFull(payload)
+            | SpawnError::Disabled(payload) => payload,
+        }
+    }
+
+    /// Returns the payload of the message that couldn't be sent
+    pub fn into_payload(self) -> T {
+        match self {
+            SpawnError::Full(payload)
+            | SpawnError::TimerQueueFull(payload)
+            | SpawnError::InstantPassed(payload)
+            | SpawnError::ShuttingDown(payload)
+            | SpawnError::SignalQueueFull(payload)
+            | SpawnError::Disabled(payload) => payload,
+        }
+    }
+}
+
+impl<T> fmt::Display for SpawnError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SpawnError::Full(_) => "the task has too many messages pending",
+            SpawnError::TimerQueueFull(_) => "the timer queue is full",
+            SpawnError::InstantPassed(_) => "the scheduled instant has already passed",
+            SpawnError::ShuttingDown(_) => "the application is shutting down",
+            SpawnError::SignalQueueFull(_) => "the process has too many signals pending",
+            SpawnError::Disabled(_) => "the current mode disables the task",
+        })
+    }
+}
+
+impl<T> std::error::Error for SpawnError<T> where T: fmt::Debug {}
@@ -1,166 +1,777 @@
 use core::{
     cell::Cell,
+    ffi::c_void,
     ops::Range,
-    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+    time::Duration,
+};
+use std::{
+    env,
+    fs::OpenOptions,
+    io::Write,
+    mem::{self, size_of},
+    panic::{self, AssertUnwindSafe},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
 };
-use std::mem::size_of;
 
+use cty::c_ulong;
+pub use cty::{c_char, c_int};
 use heapless::spsc::SingleCore;
 pub use heapless::{
     consts,
     i::{BinaryHeap as iBinaryHeap, Queue as iQueue},
     spsc::Queue,
-    BinaryHeap,
+    BinaryHeap, Vec,
+};
+pub use nc::{
+    clockid_t, exit, exit_group, getpid, pid_t, sched_yield, siginfo_t, timer_t, SI_QUEUE,
 };
-pub use nc::{exit, getpid, pid_t, sched_yield, siginfo_t, timer_t, SI_QUEUE};
 use nc::{
-    mmap, rt_sigaction, rt_sigprocmask, sched_param_t, sched_setaffinity, sched_setscheduler,
-    sigaction_t, sigev_un_t, sigevent_t, sighandler_t, sigset_t, sigval_t, SCHED_FIFO, SIGRTMIN,
-    SIG_BLOCK,
+    epoll_event_t, mlockall, rt_sigprocmask, sched_attr_t, sched_param_t, sched_setaffinity,
+    sched_setattr, sched_setscheduler, sigev_un_t, sigevent_t, sighandler_t, sigset_t, sigval_t,
+    timespec_t, SCHED_DEADLINE, SCHED_FIFO, SIG_BLOCK,
 };
+pub use std::os::unix::io::AsRawFd;
 
-pub use crate::tq::{NotReady, TimerQueue};
+#[cfg(target_os = "android")]
+use crate::android;
+pub use crate::{
+    args::{set as set_args, set_from_std as set_std_args},
+    background::TaskClaim,
+    budget::{budget, budget_abort, budget_demote, init as init_budgets},
+    compat::bind as bind_interrupt,
+    defer::{defer_init, run_deferred},
+    futex::{PiFutex, PiFutexProxy},
+    heartbeat::{
+        arm_heartbeats, heartbeat, heartbeat_abort, heartbeat_log, init_heartbeats,
+        monitor_heartbeats, Heartbeat,
+    },
+    hybrid::hybrid,
+    introspect::{init_introspection, TaskInfo, TimerQueueInfo},
+    latency::{dump_latency_probe, init_latency_probe, run_latency_probe, LatencyHistogram},
+    log::{
+        log, log_drain, log_flush, log_open, log_sleep, LogConsumer, LogProducer, LogQueue,
+        LogRecord,
+    },
+    metrics::{metrics_bind, serve_metrics},
+    mode::{current as mode, enabled as mode_enabled, set as set_mode},
+    per_core::{PerCore, PerCoreProxy, PerCoreSlot},
+    privileges::drop as drop_privileges,
+    process::{fork, use_processes, Shared},
+    record::{record_start, replay, replay_open, save_on_exit, Dispatched},
+    seccomp::install as install_seccomp,
+    shm::{unlink as shm_unlink, Channel as ShmChannel},
+    shutdown::{exit_code, init_shutdown, request_shutdown, shutting_down},
+    spawner::{SpawnQueue, SpawnSlot, Spawner},
+    stats::{init_stats, measure, Busy, LockStats, TaskStats},
+    sync::{Barrier, Doorbell, FreeSlots, Pid},
+    thermal::monitor as monitor_throttling,
+    threads::register as register_thread,
+    throttling::init_throttling,
+    tq::{NotReady, TimerQueue},
+    trace::trace_open,
+    watchdog::{arm_watchdog, sd_notify, watchdog, watchdog_abort, watchdog_log},
+};
+use crate::{
+    crash,
+    faults::{self, Syscall},
+    privileges, record, restorer, shield, sim, stack, threads,
+    time::{self, Instant, Monotonic},
+    trace, Error, SpawnError,
+};
 
-pub struct Barrier {
-    inner: AtomicBool,
+pub struct Timer {
+    inner: AtomicI32,
 }
 
-impl Barrier {
-    pub const fn new() -> Self {
+impl Timer {
+    pub const fn uninit() -> Self {
         Self {
-            inner: AtomicBool::new(false),
+            inner: AtomicI32::new(0),
         }
     }
 
-    pub fn release(&self) {
-        self.inner.store(true, Ordering::Release)
+    pub fn get(&self) -> timer_t {
+        self.inner.load(Ordering::Relaxed)
     }
 
-    pub fn wait(&self) {
-        while !self.inner.load(Ordering::Acquire) {}
+    pub fn init(&self, timer: timer_t) {
+        self.inner.store(timer, Ordering::Relaxed)
     }
 }
 
-pub struct Pid {
+pub struct Fd {
     inner: AtomicI32,
 }
 
-impl Pid {
+impl Fd {
     pub const fn uninit() -> Self {
         Self {
-            inner: AtomicI32::new(0),
+            inner: AtomicI32::new(-1),
         }
     }
 
-    pub fn get(&self) -> pid_t {
+    pub fn get(&self) -> i32 {
         self.inner.load(Ordering::Relaxed)
     }
 
-    pub fn init(&self, pid: pid_t) {
-        self.inner.store(pid, Ordering::Relaxed)
+    pub fn init(&self, fd: i32) {
+        self.inner.store(fd, Ordering::Relaxed)
     }
+}
 
-    pub fn wait(&self) -> pid_t {
-        loop {
-            let pid = self.inner.load(Ordering::Relaxed);
+pub type FreeQueue<N> = Queue<u16, N, u16, SingleCore>;
 
-            if pid == 0 {
-                sched_yield().expect("Yield failed");
-            } else {
-                break pid;
+// The PID `0` represents the current process
+const OURSELVES: pid_t = 0;
+
+/// The first real-time signal the runtime may use; the offsets in `#[app(signals = ..)]`, and the
+/// signal numbers of the tasks, start here
+///
+/// On Android it comes after the signals that bionic reserves; see `android`
+#[cfg(target_os = "android")]
+pub const SIGRTMIN: i32 = nc::SIGRTMIN + android::RESERVED_SIGNALS;
+#[cfg(not(target_os = "android"))]
+pub const SIGRTMIN: i32 = nc::SIGRTMIN;
+
+/// Number of real-time signals from `SIGRTMIN` up to the last one of the kernel, `SIGRTMAX` (64)
+pub const SIGNALS: u8 = (64 - SIGRTMIN + 1) as u8;
+
+/// Start-up settings of the runtime
+///
+/// The generated `main` builds them from the `#[app]` arguments and the environment (see
+/// `from_env`), or gets them from the `#[pre_init]` function
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeConfig {
+    /// `/dev/cpu_dma_latency` target, in microseconds; keeps the CPUs out of the idle states that
+    /// take longer than this to wake up from
+    pub cpu_dma_latency: Option<u32>,
+
+    /// CPUs to keep the other processes, the interrupts and the kernel workqueues off; see
+    /// `#[app(shield_cores = ..)]`
+    pub shield_cores: Option<&'static [u8]>,
+
+    /// The user and group IDs the process switches to right before the tasks are enabled; see
+    /// `drop_privileges`
+    pub run_as: Option<(u32, u32)>,
+
+    /// The CPUs that the cores run on, core #0 first; by default core #N runs on CPU #N
+    pub cpus: Option<&'static [u8]>,
+
+    /// Size of the stacks of the tasks, in bytes; overrides `#[app(stack_size = ..)]`
+    pub stack_size: Option<usize>,
+
+    /// Whether to fall back to `SCHED_OTHER` when the process can't use `SCHED_FIFO`, as if the
+    /// runtime had been built with the `soft-rt` feature
+    pub soft_rt: bool,
+}
+
+impl RuntimeConfig {
+    /// Overrides the settings with the ones in the environment of the process
+    ///
+    /// - `RTFM_CORES=2,3` (or `2-3`): `cpus`
+    /// - `RTFM_STACK_KB=256`: `stack_size`, in KiB
+    /// - `RTFM_SOFT_RT=1` (or `0`): `soft_rt`
+    ///
+    /// The generated `main` applies them to the settings of the `#[app]` arguments; a `#[pre_init]`
+    /// function calls this itself. A malformed value is reported as a warning and ignored
+    pub fn from_env(mut self) -> Self {
+        if let Some(cores) = var("RTFM_CORES") {
+            match shield::parse_list(&cores) {
+                Some(ref cpus) if !cpus.is_empty() => {
+                    self.cpus = Some(Box::leak(cpus.clone().into_boxed_slice()))
+                }
+                _ => eprintln!(
+                    "warning: ignoring `RTFM_CORES={}`; expected a list of CPUs like `2,3`",
+                    cores
+                ),
+            }
+        }
+
+        if let Some(kb) = var("RTFM_STACK_KB") {
+            match kb.parse::<usize>() {
+                Ok(kb) if kb > 0 => self.stack_size = Some(kb * 1024),
+                _ => eprintln!(
+                    "warning: ignoring `RTFM_STACK_KB={}`; expected a size in KiB",
+                    kb
+                ),
+            }
+        }
+
+        if let Some(soft_rt) = var("RTFM_SOFT_RT") {
+            match &soft_rt[..] {
+                "1" | "true" => self.soft_rt = true,
+                "0" | "false" => self.soft_rt = false,
+                _ => eprintln!(
+                    "warning: ignoring `RTFM_SOFT_RT={}`; expected `1` or `0`",
+                    soft_rt
+                ),
             }
         }
+
+        self
+    }
+
+    /// Makes the process drop root, and all its capabilities, once the runtime has started by
+    /// switching to the user `uid` and the group `gid`
+    ///
+    /// The timers, stacks, memory locks and scheduling policies are set up with the privileges the
+    /// process started with; what needs them later on fails with `EPERM`. Not being able to drop
+    /// them is a start-up error
+    pub fn drop_privileges(mut self, uid: u32, gid: u32) -> Self {
+        self.run_as = Some((uid, gid));
+        self
     }
 }
 
-pub struct Timer {
-    inner: AtomicI32,
+// the value of the environment variable `name`, if it's set and not empty
+fn var(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
 }
 
-impl Timer {
-    pub const fn uninit() -> Self {
-        Self {
-            inner: AtomicI32::new(0),
+// the settings of `RuntimeConfig` that the runtime looks up later on; only written by `configure`
+static mut CPUS: Option<&'static [u8]> = None;
+static STACK_SIZE: AtomicUsize = AtomicUsize::new(0);
+static SOFT_RT: AtomicBool = AtomicBool::new(false);
+
+/// Makes the runtime use `config`; this must be called before anything else of the runtime
+///
+/// NOTE the processes of the cores are forked later so they inherit the settings
+pub unsafe fn configure(config: &RuntimeConfig) {
+    CPUS = config.cpus;
+    STACK_SIZE.store(config.stack_size.unwrap_or(0), Ordering::Relaxed);
+    SOFT_RT.store(config.soft_rt, Ordering::Relaxed);
+    privileges::set(config.run_as);
+}
+
+/// The size of the stacks of the tasks: `stack_size` unless `RuntimeConfig` overrides it
+pub fn stack_size(stack_size: usize) -> usize {
+    match STACK_SIZE.load(Ordering::Relaxed) {
+        0 => stack_size,
+        size => size,
+    }
+}
+
+/// Starts the runtime; `signals` are the real-time signals the application uses, as offsets from
+/// `SIGRTMIN`, which stay blocked until the tasks are enabled
+pub unsafe fn init_runtime(
+    signals: Option<Range<u8>>,
+    config: &RuntimeConfig,
+) -> Result<(), Error> {
+    // e.g. bionic leaves fewer signals to the application than glibc
+    if let Some(signals) = &signals {
+        if signals.end > SIGNALS {
+            return Err(Error::SignalMask { errno: nc::EINVAL });
         }
     }
 
-    pub fn get(&self) -> timer_t {
-        self.inner.load(Ordering::Relaxed)
+    // a simulation needs no privileges; it only blocks the signals
+    if sim::active() {
+        sim::init_signals(signals.clone());
+    } else {
+        init_scheduling(config)?;
     }
 
-    pub fn init(&self, timer: timer_t) {
-        self.inner.store(timer, Ordering::Relaxed)
+    // block all the used real-time signals; this is equivalent to `interrupt::disable`
+    // NOTE the signals outside the range may belong to someone else (e.g. a C library)
+    if let Some(Range { start, end }) = signals {
+        let mask = rt_sigset(start, end - start);
+        faults::inject(Syscall::Sigprocmask)
+            .and_then(|()| {
+                rt_sigprocmask(
+                    SIG_BLOCK,
+                    &mask,
+                    &mut sigset_t::default(),
+                    size_of::<sigset_t>(),
+                )
+            })
+            .map_err(|errno| Error::SignalMask { errno })?;
     }
-}
 
-pub type FreeQueue<N> = Queue<u8, N, u8, SingleCore>;
+    Ok(())
+}
 
-// The PID `0` represents the current process
-const OURSELVES: pid_t = 0;
+unsafe fn init_scheduling(config: &RuntimeConfig) -> Result<(), Error> {
+    // NOTE all threads spawned (`spawn`) from this one will inherit these settings
 
-pub unsafe fn init_runtime(signo_max: Option<u8>) {
-    // NOTE all threads spawned (`sys_clone`) from this one will inherit these settings
+    if let Some(cpus) = config.shield_cores {
+        shield::shield(cpus);
+    }
 
     // start by running all threads on a single core
-    set_affinity(OURSELVES, 0);
+    set_affinity(OURSELVES, 0)?;
+
+    // wake up from timed waits as precisely as possible; `SCHED_FIFO` threads already do but
+    // `SCHED_OTHER` ones, like the log writer, get up to 50 us of slack by default
+    // NOTE `0` would restore the default slack so `1` (ns) is the lowest value
+    let _ = nc::prctl(nc::PR_SET_TIMERSLACK, 1, 0, 0, 0);
+
+    if let Some(latency) = config.cpu_dma_latency {
+        request_dma_latency(latency);
+    }
+
+    // keep all the current and future pages in RAM; page faults would add unbounded latency
+    soften(
+        faults::inject(Syscall::Mlockall)
+            .and_then(|()| mlockall(nc::MCL_CURRENT | nc::MCL_FUTURE))
+            .map_err(|errno| Error::MemoryLock { errno }),
+    )?;
+    prefault_stack();
+
+    // report crashes, and stack overflows, instead of dying with a bare signal
+    crash::install()?;
 
     // raise the priority to the minimal real-time priority
-    sched_setscheduler(OURSELVES, SCHED_FIFO, &sched_param_t { sched_priority: 1 }).expect(
-        "error: couldn't change scheduling policy; \
-    run `sudo setcap cap_sys_nice+ep $binary` first",
+    set_priority(OURSELVES, 1)
+}
+
+// Keeps the CPUs out of the idle states with a wake-up latency longer than `latency` microseconds
+//
+// NOTE the request only holds while `/dev/cpu_dma_latency` is open so the file is never closed
+fn request_dma_latency(latency: u32) {
+    let request = OpenOptions::new()
+        .write(true)
+        .open("/dev/cpu_dma_latency")
+        .and_then(|mut file| {
+            file.write_all(&(latency as i32).to_ne_bytes())?;
+            Ok(file)
+        });
+
+    match request {
+        Ok(file) => mem::forget(file),
+        Err(e) => eprintln!(
+            "warning: couldn't set the CPU DMA latency ({}); \
+             the process needs write access to `/dev/cpu_dma_latency`",
+            e
+        ),
+    }
+}
+
+/// Raises the resource limits that keep unprivileged processes from using `SCHED_FIFO` priorities
+/// up to `rtprio`, from locking their memory and from queueing many signals, as far as the hard
+/// limits allow
+pub fn raise_limits(rtprio: u8) {
+    if !sim::active() {
+        crate::preflight::raise_limits(rtprio)
+    }
+}
+
+/// Prints the problems that `rtfm::preflight` finds; `rtprio` is the highest `SCHED_FIFO`
+/// priority the application uses and `cores` its number of cores
+pub fn preflight(rtprio: u8, cores: u8) {
+    // a simulation doesn't use any of it
+    if sim::active() {
+        return;
+    }
+
+    for problem in crate::preflight::check(rtprio, cores) {
+        eprintln!("warning: {}", problem);
+    }
+}
+
+/// Reports a runtime initialization error and exits the process
+pub fn fatal<T>(error: Error) -> T {
+    eprintln!("error: {}", error);
+    process::exit(1)
+}
+
+/// How much of the main thread stack gets pre-faulted by `init_runtime`
+const PREFAULT_SIZE: usize = 64 * 1024;
+
+// Touches the next `PREFAULT_SIZE` bytes of the stack so that they are mapped before the tasks start
+#[inline(never)]
+unsafe fn prefault_stack() {
+    let mut stack = [0u8; PREFAULT_SIZE];
+
+    for offset in (0..PREFAULT_SIZE).step_by(stack::PAGE_SIZE) {
+        ptr::write_volatile(stack.as_mut_ptr().add(offset), 0);
+    }
+}
+
+/// Spawns a thread with a stack of (at least) `stack_size` bytes and returns its thread ID
+///
+/// The stack has a guard page below it; an overflow is reported as an overflow of `name`
+///
+/// The thread is created with `pthread_create` so that it gets its own TLS block; `thread_local!`
+/// and the parts of `std` that rely on it work in the tasks. Like with `clone` the new thread
+/// inherits the scheduling policy, CPU affinity and signal mask of the calling thread
+pub unsafe fn spawn(
+    child: extern "C" fn() -> !,
+    stack_size: usize,
+    name: &'static str,
+) -> Result<pid_t, Error> {
+    // NOTE the C library places the thread descriptor and the TLS block at the top of the stack
+    let (stack_low, stack_size) = stack::map(stack_size + TLS_RESERVE, Some(name))?;
+
+    let mut attr = PthreadAttr([0; 64]);
+    pthread_attr_init(&mut attr);
+    pthread_attr_setstack(&mut attr, stack_low as *mut c_void, stack_size);
+    // the thread runs until the process exits; no one joins it
+    pthread_attr_setdetachstate(&mut attr, PTHREAD_CREATE_DETACHED);
+
+    let start = Start {
+        child,
+        tid: Pid::uninit(),
+    };
+    let mut thread = 0;
+    let errno = pthread_create(
+        &mut thread,
+        &attr,
+        trampoline,
+        &start as *const Start as *mut c_void,
     );
+    pthread_attr_destroy(&mut attr);
 
-    // block all the used real-time signals; this is equivalent to `interrupt::disable`
-    if let Some(signo) = signo_max {
-        let mask = ((1 << (signo + 1)) - 1) << (SIGRTMIN - 1);
-        let mask = sigset_t { sig: [mask] };
-        rt_sigprocmask(
-            SIG_BLOCK,
-            &mask,
-            &mut sigset_t::default(),
-            size_of::<sigset_t>(),
-        )
-        .expect("error: couldn't change the signal mask");
+    if errno != 0 {
+        return Err(Error::Thread { errno });
+    }
+
+    Ok(start.tid.wait())
+}
+
+/// Stack space set aside for the thread descriptor and the TLS block
+const TLS_RESERVE: usize = 16 * 1024;
+
+/// `PTHREAD_CREATE_DETACHED`
+const PTHREAD_CREATE_DETACHED: c_int = 1;
+
+/// `pthread_attr_t`: 56 bytes on x86_64 and 64 bytes on aarch64
+#[repr(C, align(8))]
+struct PthreadAttr([u8; 64]);
+
+extern "C" {
+    fn pthread_attr_init(attr: *mut PthreadAttr) -> c_int;
+    fn pthread_attr_setstack(attr: *mut PthreadAttr, addr: *mut c_void, size: usize) -> c_int;
+    fn pthread_attr_setdetachstate(attr: *mut PthreadAttr, state: c_int) -> c_int;
+    fn pthread_attr_destroy(attr: *mut PthreadAttr) -> c_int;
+    fn pthread_create(
+        thread: *mut c_ulong,
+        attr: *const PthreadAttr,
+        start: extern "C" fn(*mut c_void) -> *mut c_void,
+        arg: *mut c_void,
+    ) -> c_int;
+}
+
+// What `trampoline` needs; lives on the stack of the thread that calls `spawn`
+struct Start {
+    child: extern "C" fn() -> !,
+    tid: Pid,
+}
+
+extern "C" fn trampoline(start: *mut c_void) -> *mut c_void {
+    unsafe {
+        let start = &*(start as *const Start);
+        let child = start.child;
+
+        // NOTE `spawn` returns, and `start` goes away, as soon as the TID is stored; the futex wake
+        // up that follows only uses the address of `tid`
+        start.tid.init(nc::gettid());
+
+        child()
     }
 }
 
-pub unsafe fn spawn(_child: extern "C" fn() -> !) -> pid_t {
-    const PAGE_SIZE: usize = 4 * 1024; // 4 KiB (output of `getconf PAGESIZE`)
-    const STACK_SIZE: usize = 2 * 1024 * PAGE_SIZE; // 8 MiB (output of `ulimit -s`)
+/// Gives the calling thread an alternate signal stack of (at least) `size` bytes on which its
+/// signal handlers, and thus all its nested tasks, run
+///
+/// The stack has a guard page below it so that an overflow faults instead of overwriting the
+/// adjacent mapping; the fault is reported as an overflow of `name`
+pub unsafe fn sigaltstack(size: usize, name: &'static str) -> Result<(), Error> {
+    let (stack_low, size) = stack::map(size, Some(name))?;
+
+    set_sigaltstack(stack_low, size)
+}
+
+/// Size of the alternate signal stack of the threads that don't run signal handlers
+const FAULT_STACK_SIZE: usize = 16 * 1024;
+
+/// Gives the calling thread a small alternate signal stack on which the `SIGSEGV` handler can
+/// report an overflow of its regular stack
+pub unsafe fn fault_stack() -> Result<(), Error> {
+    let (stack_low, size) = stack::map(FAULT_STACK_SIZE, None)?;
 
-    let stack_low = mmap(
-        0,          // address; 0 means any page-aligned address
-        STACK_SIZE, // length of mapping
-        nc::PROT_READ | // read access
-        nc::PROT_WRITE, // write access
-        nc::MAP_ANONYMOUS | // mapping is not backed by any file
-        nc::MAP_PRIVATE | // mapping is private to other threads / processes
-        nc::MAP_GROWSDOWN, // mapping suitable for stacks
-        -1,         // file descriptor; needs to be `-1` because of MAP_ANONYMOUS
-        0,          // offset; ignored because of MAP_ANONYMOUS
+    set_sigaltstack(stack_low, size)
+}
+
+unsafe fn set_sigaltstack(stack_low: usize, size: usize) -> Result<(), Error> {
+    nc::sigaltstack(
+        &nc::sigaltstack_t {
+            ss_sp: stack_low,
+            ss_flags: 0,
+            ss_size: size,
+        },
+        &mut nc::sigaltstack_t::default(),
     )
-    .expect("MMAP failed in process spawn");
-
-    let stack_high = stack_low + STACK_SIZE;
-
-    // spin a new thread
-    nc::clone(
-        nc::CLONE_VM | // new thread shares memory with the parent
-        nc::CLONE_THREAD | // share thread group
-        nc::CLONE_SIGHAND, // shared signal handlers; required by `CLONE_THREAD`
-        stack_high,
-        &mut 0,
-        &mut 0,
-        0,
+    .map_err(|errno| Error::SignalStack { errno })
+}
+
+pub unsafe fn set_affinity(tid: pid_t, core: u8) -> Result<(), Error> {
+    if sim::active() {
+        return Ok(());
+    }
+
+    let cpu = core_cpu(core).ok_or(Error::Affinity {
+        cpu: Some(core),
+        errno: nc::EINVAL,
+    })?;
+
+    faults::inject(Syscall::SchedSetaffinity)
+        .and_then(|()| sched_setaffinity(tid, 1, &[1 << cpu]))
+        .map_err(|errno| Error::Affinity {
+            cpu: Some(cpu),
+            errno,
+        })
+}
+
+/// The CPU that core #`core` runs on; `None` if the process can't run on that many CPUs
+///
+/// That's CPU #`core` except when `RuntimeConfig::cpus` lists the CPUs, or on Android, where it's
+/// the `core`-th CPU of the cpuset of the service; see `android`
+pub(crate) fn core_cpu(core: u8) -> Option<u8> {
+    // NOTE(unsafe) only written at start-up
+    if let Some(cpus) = unsafe { CPUS } {
+        return cpus.get(usize::from(core)).cloned();
+    }
+
+    #[cfg(target_os = "android")]
+    {
+        android::cpu(core)
+    }
+
+    #[cfg(not(target_os = "android"))]
+    {
+        Some(core)
+    }
+}
+
+/// Changes the `SCHED_FIFO` priority of the thread `tid`
+///
+/// With the `soft-rt` feature, or `RuntimeConfig::soft_rt`, the thread falls back to
+/// `SCHED_OTHER` with a nice value that approximates `priority` if the process is not allowed to
+/// use `SCHED_FIFO`
+pub unsafe fn set_priority(tid: pid_t, priority: u8) -> Result<(), Error> {
+    // a simulation runs on the default policy; the priorities are simulated
+    if sim::active() {
+        return Ok(());
+    }
+
+    if !DEGRADED.load(Ordering::Relaxed) {
+        soften(
+            faults::inject(Syscall::SchedSetscheduler)
+                .and_then(|()| {
+                    sched_setscheduler(
+                        tid,
+                        SCHED_FIFO,
+                        &sched_param_t {
+                            sched_priority: i32::from(priority),
+                        },
+                    )
+                })
+                .map(drop)
+                .map_err(|errno| Error::RealTimePriority { priority, errno }),
+        )?;
+    }
+
+    if DEGRADED.load(Ordering::Relaxed) {
+        set_nice(tid, priority);
+    }
+
+    Ok(())
+}
+
+/// Set once the runtime has fallen back to `SCHED_OTHER`; only with the `soft-rt` feature or
+/// `RuntimeConfig::soft_rt`
+static DEGRADED: AtomicBool = AtomicBool::new(false);
+
+fn soft_rt() -> bool {
+    cfg!(feature = "soft-rt") || SOFT_RT.load(Ordering::Relaxed)
+}
+
+// With the `soft-rt` feature, or `RuntimeConfig::soft_rt`, permission errors degrade the runtime
+// instead of stopping it; a warning is printed the first time
+//
+// NOTE `dispatch = "threads"` can't be degraded: `lock` relies on `SCHED_FIFO` for mutual exclusion
+fn soften(r: Result<(), Error>) -> Result<(), Error> {
+    match r {
+        Err(error) if soft_rt() && !threads() => match error.errno() {
+            nc::EPERM | nc::ENOMEM | nc::EAGAIN => {
+                if !DEGRADED.swap(true, Ordering::Relaxed) {
+                    eprintln!(
+                        "warning: {}; continuing WITHOUT real-time guarantees (`soft-rt`)",
+                        error
+                    );
+                }
+
+                Ok(())
+            }
+            _ => Err(error),
+        },
+        r => r,
+    }
+}
+
+// `SCHED_OTHER` approximation of the `SCHED_FIFO` priority `priority`: higher priorities get lower
+// nice values. NOTE unprivileged processes can only raise their nice value so this is best effort
+pub(crate) unsafe fn set_nice(tid: pid_t, priority: u8) {
+    let nice = 19 - core::cmp::min(i32::from(priority), 39);
+
+    sched_setscheduler(tid, nc::SCHED_NORMAL, &sched_param_t { sched_priority: 0 }).ok();
+    nc::setpriority(nc::PRIO_PROCESS, tid, nice).ok();
+}
+
+/// Moves the thread `tid` back to the normal, non real-time, policy and lets it run on any CPU
+pub unsafe fn set_normal(tid: pid_t) -> Result<(), Error> {
+    if sim::active() {
+        return Ok(());
+    }
+
+    faults::inject(Syscall::SchedSetaffinity)
+        .and_then(|()| sched_setaffinity(tid, 1, &[!0]))
+        .map_err(|errno| Error::Affinity { cpu: None, errno })?;
+
+    faults::inject(Syscall::SchedSetscheduler)
+        .and_then(|()| {
+            sched_setscheduler(tid, nc::SCHED_NORMAL, &sched_param_t { sched_priority: 0 })
+        })
+        .map(drop)
+        .map_err(|errno| Error::NormalPriority { errno })
+}
+
+/// Moves the thread `tid` to the `SCHED_DEADLINE` policy; all the parameters are in nanoseconds
+///
+/// NOTE this must be done *after* the thread has been spawned because `SCHED_DEADLINE` threads
+/// are not allowed to `clone`
+pub unsafe fn sched_deadline(
+    tid: pid_t,
+    runtime: u64,
+    deadline: u64,
+    period: u64,
+) -> Result<(), Error> {
+    if sim::active() {
+        return Ok(());
+    }
+
+    // the kernel only admits `SCHED_DEADLINE` threads whose affinity spans the whole root domain
+    sched_setaffinity(tid, 1, &[!0]).map_err(|errno| Error::Affinity { cpu: None, errno })?;
+
+    let mut attr = sched_attr_t::default();
+    attr.size = size_of::<sched_attr_t>() as u32;
+    attr.sched_policy = SCHED_DEADLINE as u32;
+    attr.sched_runtime = runtime;
+    attr.sched_deadline = deadline;
+    attr.sched_period = period;
+
+    soften(
+        sched_setattr(tid, &mut attr, 0)
+            .map(drop)
+            .map_err(|errno| Error::Deadline { errno }),
     )
-    .expect("Process clone failed")
 }
 
-pub unsafe fn set_affinity(tid: pid_t, core: u8) {
-    sched_setaffinity(tid, 1, &[1 << core]).expect("error: couldn't change CPU affinity");
+pub unsafe fn timer_create(
+    tid: Option<pid_t>,
+    signo: u8,
+    clock: clockid_t,
+) -> Result<timer_t, Error> {
+    create_timer(tid, signo, clock, 0)
+}
+
+/// Tags the signals of the timers of periodic tasks
+pub const PERIOD_EVENT: usize = 1 << 17;
+
+/// Creates the timer of the periodic task with ID `id`; it will send `signo` with `PERIOD_EVENT`
+pub unsafe fn periodic_timer(
+    tid: Option<pid_t>,
+    signo: u8,
+    clock: clockid_t,
+    id: u8,
+) -> Result<timer_t, Error> {
+    create_timer(tid, signo, clock, PERIOD_EVENT | usize::from(id))
+}
+
+/// How many expirations of `timer` were lost because its signal was still pending; this is the
+/// number of releases of a periodic task that were skipped
+///
+/// NOTE this only refers to the last signal of the timer so it must be read by its handler
+pub unsafe fn timer_getoverrun(timer: timer_t) -> u32 {
+    nc::timer_getoverrun(timer).map(|n| n as u32).unwrap_or(0)
+}
+
+/// Arms the timer of a periodic task: it first fires at `start` and then every `period`
+/// nanoseconds
+///
+/// The expirations are absolute so the period doesn't drift, no matter when the task runs; `start`
+/// is an instant of `M`
+pub unsafe fn timer_start<M>(timer: timer_t, start: Instant, period: u64) -> Result<(), Error>
+where
+    M: Monotonic,
+{
+    if sim::active() {
+        sim::arm(timer, start, period);
+        return Ok(());
+    }
+
+    let (flags, it_value) = time::expiration::<M>(start, M::now());
+    faults::inject(Syscall::TimerSettime)
+        .and_then(|()| {
+            nc::timer_settime(
+                timer,
+                flags,
+                &nc::itimerspec_t {
+                    it_interval: timespec_t {
+                        tv_sec: (period / 1_000_000_000) as _,
+                        tv_nsec: (period % 1_000_000_000) as _,
+                    },
+                    it_value,
+                },
+                None,
+            )
+        })
+        .map(drop)
+        .map_err(|errno| Error::Timer { errno })
+}
+
+/// Disarms the timer of a periodic task; the task is no longer released until `timer_start`
+pub unsafe fn timer_stop(timer: timer_t) -> Result<(), Error> {
+    if sim::active() {
+        sim::disarm(timer);
+        return Ok(());
+    }
+
+    // NOTE a zero `it_value` disarms the timer
+    faults::inject(Syscall::TimerSettime)
+        .and_then(|()| {
+            nc::timer_settime(
+                timer,
+                0,
+                &nc::itimerspec_t {
+                    it_interval: timespec_t {
+                        tv_sec: 0,
+                        tv_nsec: 0,
+                    },
+                    it_value: timespec_t {
+                        tv_sec: 0,
+                        tv_nsec: 0,
+                    },
+                },
+                None,
+            )
+        })
+        .map(drop)
+        .map_err(|errno| Error::Timer { errno })
 }
 
-pub unsafe fn timer_create(tid: Option<pid_t>, signo: u8) -> timer_t {
+unsafe fn create_timer(
+    tid: Option<pid_t>,
+    signo: u8,
+    clock: clockid_t,
+    value: usize,
+) -> Result<timer_t, Error> {
     let (sigev_notify, sigev_un) = if let Some(tid) = tid {
         // multi-core application
         (nc::SIGEV_THREAD_ID, sigev_un_t { tid })
@@ -170,23 +781,68 @@ pub unsafe fn timer_create(tid: Option<pid_t>, signo: u8) -> timer_t {
     };
 
     let mut tid = 0;
-    nc::timer_create(
-        nc::CLOCK_MONOTONIC,
-        Some(&mut sigevent_t {
-            sigev_value: sigval_t { sival_int: 0 },
-            sigev_signo: SIGRTMIN + i32::from(signo),
-            sigev_notify,
-            sigev_un,
-        }),
-        &mut tid,
-    )
-    .expect("error: couldn't create a timer");
+    faults::inject(Syscall::TimerCreate)
+        .and_then(|()| {
+            nc::timer_create(
+                clock,
+                Some(&mut sigevent_t {
+                    sigev_value: sigval_t { sival_ptr: value },
+                    sigev_signo: SIGRTMIN + i32::from(signo),
+                    sigev_notify,
+                    sigev_un,
+                }),
+                &mut tid,
+            )
+        })
+        .map_err(|errno| Error::Timer { errno })?;
+    sim::timer_created(tid, signo, clock, value);
 
-    tid
+    Ok(tid)
 }
 
+/// `stats` are the contention counters of the resources behind `ptr`; they are empty unless the
+/// application has `stats = true`
 pub unsafe fn lock<T, R>(
     ptr: *mut T,
+    name: &'static str,
+    stats: &[&LockStats],
+    priority: &Priority,
+    ceiling: u8,
+    range: Range<u8>,
+    f: impl FnOnce(&mut T) -> R,
+) -> R {
+    if priority.get() < ceiling {
+        for stats in stats {
+            stats.record();
+        }
+    }
+
+    lock_with(&ThreadMask, ptr, name, priority, ceiling, range, f)
+}
+
+/// How `lock` keeps the tasks of the priorities it raises over out
+///
+/// `tests/loom.rs` implements it with a model of the signal mask of a core
+pub trait SignalMask {
+    /// Blocks, or unblocks, the signals of the priorities `current + 1 ..= ceiling`; see
+    /// `lock_signals`
+    unsafe fn mask(&self, range: Range<u8>, current: u8, ceiling: u8, block: bool);
+}
+
+/// The signal mask of the calling thread
+pub struct ThreadMask;
+
+impl SignalMask for ThreadMask {
+    unsafe fn mask(&self, range: Range<u8>, current: u8, ceiling: u8, block: bool) {
+        mask(range, current, ceiling, block)
+    }
+}
+
+/// `lock` on top of the signal mask `signals`
+pub unsafe fn lock_with<T, R>(
+    signals: &impl SignalMask,
+    ptr: *mut T,
+    name: &'static str,
     priority: &Priority,
     ceiling: u8,
     range: Range<u8>,
@@ -195,46 +851,517 @@ pub unsafe fn lock<T, R>(
     let current = priority.get();
 
     if current < ceiling {
+        trace::trace_lock(name, ceiling);
         priority.set(ceiling);
-        mask(range.clone(), current, ceiling, true);
-        let r = f(&mut *ptr);
-        mask(range, current, ceiling, false);
+        let r = if threads() {
+            // NOTE the task signals are blocked on every thread; only `idle` can be interrupted by
+            // the shutdown handler, which drops the resources
+            if current == 0 {
+                block_shutdown(range.end, true);
+            }
+            set_priority(OURSELVES, level_priority(ceiling)).unwrap_or_else(fatal);
+            let r = f(&mut *ptr);
+            set_level(current);
+            if current == 0 {
+                block_shutdown(range.end, false);
+            }
+            r
+        } else {
+            signals.mask(range.clone(), current, ceiling, true);
+            let r = f(&mut *ptr);
+            record::record_unlock(ceiling);
+            signals.mask(range, current, ceiling, false);
+            r
+        };
         priority.set(current);
+        trace::trace_end();
         r
     } else {
         f(&mut *ptr)
     }
 }
 
-pub unsafe fn mask(Range { start, end }: Range<u8>, current: u8, ceiling: u8, block: bool) {
-    let len = end.wrapping_sub(start);
-    let mask =
-        ((1 << (ceiling - current)) - 1) << (SIGRTMIN - 1 + i32::from(start + len - ceiling));
-    let mask = sigset_t { sig: [mask] };
+/// The signals, as offsets from `SIGRTMIN`, of the priorities `current + 1 ..= ceiling` of the
+/// core that owns the signals in `range`
+///
+/// The signal of priority `p` is `range.end - p` so higher priorities have lower signals. When
+/// `idle` locks (`current == 0`) this includes `range.end`, the shutdown signal: the shutdown
+/// handler drops the resources so it must not run while `idle` holds one
+pub fn lock_signals(Range { start, end }: Range<u8>, current: u8, ceiling: u8) -> Range<u8> {
+    let end = start + end.wrapping_sub(start);
+
+    end - ceiling..end - current + u8::from(current == 0)
+}
+
+// Blocks, or unblocks, the shutdown signal `signo` on the calling thread
+unsafe fn block_shutdown(signo: u8, block: bool) {
     rt_sigprocmask(
-        if block {
-            nc::SIG_BLOCK
+        if block { SIG_BLOCK } else { nc::SIG_UNBLOCK },
+        &rt_sigset(signo, 1),
+        &mut sigset_t::default(),
+        size_of::<sigset_t>(),
+    )
+    .expect("error: couldn't change the signal mask");
+}
+
+pub unsafe fn mask(range: Range<u8>, current: u8, ceiling: u8, block: bool) {
+    // NOTE during a replay the signals stay blocked; the replay driver dispatches them
+    if record::replaying() {
+        return;
+    }
+
+    // NOTE the same goes for a simulation; the simulated priority changes instead
+    if sim::active() {
+        return sim::mask(current, ceiling, block);
+    }
+
+    let Range { start, end } = lock_signals(range, current, ceiling);
+    let changed = shadow(start, end - start, block);
+    // NOTE the enclosing `lock`s or `free`s of this thread already (or still) block these signals
+    if changed == 0 {
+        return;
+    }
+
+    let mask = sigset(changed << (SIGRTMIN - 1));
+    faults::inject(Syscall::Sigprocmask)
+        .and_then(|()| {
+            rt_sigprocmask(
+                if block {
+                    nc::SIG_BLOCK
+                } else {
+                    nc::SIG_UNBLOCK
+                },
+                &mask,
+                &mut sigset_t::default(),
+                size_of::<sigset_t>(),
+            )
+        })
+        .expect("error: couldn't change the signal mask");
+}
+
+/// Runs `f` with all the signals in `range` blocked, regardless of the current priority
+pub unsafe fn free<R>(Range { start, end }: Range<u8>, f: impl FnOnce() -> R) -> R {
+    let len = end.wrapping_sub(start);
+
+    if threads() {
+        let mut param = sched_param_t::default();
+        nc::sched_getparam(OURSELVES, &mut param)
+            .expect("error: couldn't read the real-time priority");
+
+        set_priority(OURSELVES, level_priority(len)).unwrap_or_else(fatal);
+        let r = f();
+        if param.sched_priority == 0 {
+            // a demoted `idle`
+            set_level(0);
         } else {
-            nc::SIG_UNBLOCK
-        },
-        &mask,
+            set_priority(OURSELVES, param.sched_priority as u8).unwrap_or_else(fatal);
+        }
+
+        return r;
+    }
+
+    if sim::active() {
+        return sim::free(f);
+    }
+
+    // nothing to do if an enclosing `free` already blocks the signals
+    if shadow(start, len, true) == 0 {
+        let r = f();
+        shadow(start, len, false);
+        return r;
+    }
+
+    let mask = rt_sigset(start, len);
+    let mut old = sigset_t::default();
+    rt_sigprocmask(SIG_BLOCK, &mask, &mut old, size_of::<sigset_t>())
+        .expect("error: couldn't change the signal mask");
+
+    let r = f();
+
+    shadow(start, len, false);
+    rt_sigprocmask(
+        nc::SIG_SETMASK,
+        &old,
         &mut sigset_t::default(),
         size_of::<sigset_t>(),
     )
     .expect("error: couldn't change the signal mask");
+
+    r
+}
+
+thread_local! {
+    // Shadow of the signal mask of this thread: how many of the `lock`s and `free`s in progress
+    // block each real-time signal, indexed by offset from `SIGRTMIN`
+    //
+    // NOTE a signal handler leaves the shadow as it found it, because its `lock`s are balanced,
+    // and the signals the kernel blocks while it runs are not in it; the shadow only misses
+    // signals that are blocked, never the other way around
+    static BLOCKED: Cell<[u8; 32]> = Cell::new([0; 32]);
+}
+
+// Blocks, or unblocks, the `len` signals that start at `start` in the shadow of the signal mask;
+// returns the signals, as a bit mask of offsets from `SIGRTMIN`, that the real mask must change
+fn shadow(start: u8, len: u8, block: bool) -> u64 {
+    BLOCKED.with(|blocked| {
+        let mut depths = blocked.get();
+        let mut changed = 0;
+        for signo in usize::from(start)..usize::from(start) + usize::from(len) {
+            let depth = &mut depths[signo];
+            if block {
+                *depth += 1;
+                if *depth == 1 {
+                    changed |= 1 << signo;
+                }
+            } else {
+                *depth -= 1;
+                if *depth == 0 {
+                    changed |= 1 << signo;
+                }
+            }
+        }
+        blocked.set(depths);
+        changed
+    })
 }
 
-pub unsafe fn enqueue(tgid: i32, tid: Option<i32>, signo: u8, task: u8, index: u8) {
+/// Whether each priority level runs on a thread of its own (`dispatch = "threads"`)
+static THREADS: AtomicBool = AtomicBool::new(false);
+
+/// Switches `lock` from signal masking to `SCHED_FIFO` priority changes
+pub unsafe fn use_threads() {
+    THREADS.store(true, Ordering::Relaxed)
+}
+
+pub(crate) fn threads() -> bool {
+    THREADS.load(Ordering::Relaxed)
+}
+
+/// `SCHED_FIFO` priority of the thread of a priority level; `idle` runs at 1
+pub fn level_priority(level: u8) -> u8 {
+    1 + level
+}
+
+/// What `idle` runs under, outside of `lock`, when it leaves `SCHED_FIFO`
+#[derive(Clone, Copy)]
+pub enum IdlePolicy {
+    /// `SCHED_IDLE`; runs only when nothing else wants the CPU
+    Idle,
+    /// `SCHED_OTHER`; shares the CPU with the normal threads of the system
+    Other,
+}
+
+thread_local! {
+    // Policy of the thread if it runs a demoted `idle`
+    static IDLE_POLICY: Cell<Option<IdlePolicy>> = Cell::new(None);
+}
+
+/// Moves the calling thread, which is about to run `idle`, from `SCHED_FIFO` to `policy`
+///
+/// The threads of the priority levels stay on `SCHED_FIFO`. A `lock` in `idle` raises the thread
+/// to the `SCHED_FIFO` priority of the ceiling and moves it back to `policy` afterwards
+pub unsafe fn demote_idle(policy: IdlePolicy) -> Result<(), Error> {
+    IDLE_POLICY.with(|idle| idle.set(Some(policy)));
+    threads::reprioritize(0);
+
+    set_idle_policy(policy)
+}
+
+unsafe fn set_idle_policy(policy: IdlePolicy) -> Result<(), Error> {
+    if sim::active() {
+        return Ok(());
+    }
+
+    let policy = match policy {
+        IdlePolicy::Idle => nc::SCHED_IDLE,
+        IdlePolicy::Other => nc::SCHED_NORMAL,
+    };
+
+    faults::inject(Syscall::SchedSetscheduler)
+        .and_then(|()| sched_setscheduler(OURSELVES, policy, &sched_param_t { sched_priority: 0 }))
+        .map(drop)
+        .map_err(|errno| Error::IdlePolicy { errno })
+}
+
+// Moves the calling thread back to the priority level `level` at the end of a `lock` or `free`
+unsafe fn set_level(level: u8) {
+    match IDLE_POLICY.with(Cell::get) {
+        Some(policy) if level == 0 => set_idle_policy(policy),
+        _ => set_priority(OURSELVES, level_priority(level)),
+    }
+    .unwrap_or_else(fatal)
+}
+
+/// The real-time signals `start..start + len` of the runtime as a signal set
+pub(crate) fn rt_sigset(start: u8, len: u8) -> sigset_t {
+    sigset(((1 << len) - 1) << (SIGRTMIN - 1 + i32::from(start)))
+}
+
+/// The signal set whose bit `signo - 1` is signal `signo`
+///
+/// NOTE the kernel's set is 64 bits wide; `sigset_t` splits it in two words on 32-bit targets
+/// (armv7), where the real-time signals are all in the second one
+pub(crate) fn sigset(mask: u64) -> sigset_t {
+    #[cfg(target_pointer_width = "64")]
+    let sig = [mask as usize];
+    #[cfg(target_pointer_width = "32")]
+    let sig = [mask as u32 as usize, (mask >> 32) as usize];
+
+    sigset_t { sig }
+}
+
+/// Blocks until the signal `signo` arrives
+///
+/// NOTE the signal must be blocked by all the threads of the process
+pub unsafe fn sigwait(signo: u8) -> siginfo_t {
+    let set = rt_sigset(signo, 1);
+    let timeout = timespec_t {
+        tv_sec: 3600,
+        tv_nsec: 0,
+    };
+
     let mut si = siginfo_t::default();
-    si.siginfo.si_code = nc::SI_QUEUE;
-    si.siginfo.sifields.rt.sigval.sival_ptr = (usize::from(task) << 8) + usize::from(index);
+    loop {
+        match faults::inject(Syscall::Sigtimedwait)
+            .and_then(|()| nc::rt_sigtimedwait(&set, &mut si, &timeout, size_of::<sigset_t>()))
+        {
+            Ok(_) => break si,
+            // timed out or interrupted; keep waiting
+            Err(nc::EAGAIN) | Err(nc::EINTR) => {}
+            Err(_) => panic!("error: couldn't wait for a signal"),
+        }
+    }
+}
+
+/// Runs the task `name`, of priority `priority`; if it panics `on_panic` is called with its name
+///
+/// A crash while it runs is reported along with its name and priority
+///
+/// NOTE unwinding must stop here; it can't cross the signal handler that dispatched the task
+pub fn run(
+    name: &'static str,
+    priority: u8,
+    task: impl FnOnce(),
+    on_panic: impl FnOnce(&'static str),
+) {
+    trace::trace_begin(name);
+    let panicked = crash::enter(name, priority, || {
+        panic::catch_unwind(AssertUnwindSafe(task)).is_err()
+    });
+    trace::trace_end();
+
+    if panicked {
+        on_panic(name)
+    }
+}
+
+/// Reports that the value `task` returned couldn't be spawned as the message of `then`
+pub fn lost_output<T>(task: &'static str, then: &'static str, error: SpawnError<T>) {
+    // every message is turned away during a shutdown; that's expected
+    if let SpawnError::ShuttingDown(_) = error {
+        return;
+    }
+
+    eprintln!(
+        "warning: the output of task `{}` was dropped; `{}` couldn't be spawned: {}",
+        task, then, error
+    );
+}
+
+/// The default panic policy: take the whole process down
+pub fn abort(task: &'static str) {
+    eprintln!("error: task `{}` panicked; aborting", task);
+    record::save_on_exit();
 
-    if let Some(tid) = tid {
-        nc::rt_tgsigqueueinfo(tgid, tid, SIGRTMIN + i32::from(signo), &mut si)
-            .expect("error: couldn't enqueue signal\n");
+    process::abort()
+}
+
+/// How late a task that was scheduled at `scheduled` completes; `None` if it met its `deadline`
+pub fn lateness<M>(scheduled: Instant, deadline: u64) -> Option<Duration>
+where
+    M: Monotonic,
+{
+    let deadline = scheduled + Duration::from_nanos(deadline);
+    let now = M::now();
+
+    if now > deadline {
+        now.checked_duration_since(deadline)
     } else {
-        nc::rt_sigqueueinfo(tgid, SIGRTMIN + i32::from(signo), &mut si)
-            .expect("error: couldn't enqueue signal\n");
+        None
+    }
+}
+
+/// `CLOCK_REALTIME`; drives the wall-clock timer queues
+///
+/// NOTE this clock is not monotonic; it's only used to compare wall-clock instants with "now"
+pub struct ClockRealtime;
+
+impl Monotonic for ClockRealtime {
+    const TIMER_CLOCK: clockid_t = nc::CLOCK_REALTIME;
+
+    fn now() -> Instant {
+        time::clock_gettime(nc::CLOCK_REALTIME).into()
+    }
+}
+
+/// `time` as an instant of `ClockRealtime`; times before the Unix epoch become the epoch
+pub fn wall_instant(time: SystemTime) -> Instant {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    timespec_t {
+        tv_sec: since_epoch.as_secs() as _,
+        tv_nsec: since_epoch.subsec_nanos() as _,
+    }
+    .into()
+}
+
+/// The instant of the clock `M` that corresponds to the wall-clock `time`, according to the
+/// current offset between the two clocks
+pub fn wall_to_monotonic<M>(time: SystemTime) -> Instant
+where
+    M: Monotonic,
+{
+    let now = M::now();
+
+    match time.duration_since(SystemTime::now()) {
+        Ok(ahead) => now + ahead,
+        Err(e) => now.checked_sub(e.duration()).unwrap_or(now),
+    }
+}
+
+/// Posts the message in slot `index` to `task`
+///
+/// The `sival_ptr` payload holds `task` in bits 24..32 and `index` in bits 0..16, which leaves the
+/// bits of the `*_EVENT` tags clear
+pub unsafe fn enqueue(tgid: i32, tid: Option<i32>, signo: u8, task: u8, index: u16) {
+    sigqueue(
+        tgid,
+        tid,
+        signo,
+        (usize::from(task) << 24) | usize::from(index),
+    )
+}
+
+/// Like `enqueue` but returns `false`, instead of panicking, if the process already has as many
+/// signals pending as `RLIMIT_SIGPENDING` allows
+pub unsafe fn try_enqueue(tgid: i32, tid: Option<i32>, signo: u8, task: u8, index: u16) -> bool {
+    try_post(
+        tgid,
+        tid,
+        signo,
+        (usize::from(task) << 24) | usize::from(index),
+    )
+}
+
+/// Marks a `sival_ptr` payload as a broadcast; it holds the broadcast ID in bits 24..32 and the
+/// message slot of the first task of the broadcast in bits 0..16
+pub const BROADCAST_EVENT: usize = 1 << 21;
+
+/// Posts the messages of broadcast `id`; `index` is the message slot of its first task
+///
+/// Like `try_enqueue`, returns `false` if the process has too many signals pending
+pub unsafe fn try_broadcast(tgid: i32, tid: Option<i32>, signo: u8, id: u8, index: u16) -> bool {
+    try_post(
+        tgid,
+        tid,
+        signo,
+        BROADCAST_EVENT | (usize::from(id) << 24) | usize::from(index),
+    )
+}
+
+unsafe fn try_post(tgid: i32, tid: Option<i32>, signo: u8, value: usize) -> bool {
+    match try_sigqueue(tgid, tid, signo, value) {
+        Ok(()) => true,
+        Err(nc::EAGAIN) => false,
+        Err(_) => panic!("error: couldn't enqueue signal\n"),
+    }
+}
+
+/// Marks a `sival_ptr` payload as the readiness of a file descriptor bound to a task
+pub const FD_EVENT: usize = 1 << 16;
+
+/// Marks a `sival_ptr` payload as a message in the shared-memory channel of a task
+pub const SHM_EVENT: usize = 1 << 19;
+
+/// Marks a `sival_ptr` payload as a message sent through a `Spawner`
+pub const SPAWNER_EVENT: usize = 1 << 20;
+
+/// Marks a `sival_ptr` payload as a closure passed to `rtfm::defer`; bits 0..8 hold its slot
+pub const DEFER_EVENT: usize = 1 << 22;
+
+/// Notifies the dispatcher that the file descriptor of the task with epoll ID `id` is ready
+pub unsafe fn enqueue_fd_event(tgid: i32, tid: Option<i32>, signo: u8, id: u8) {
+    sigqueue(tgid, tid, signo, FD_EVENT | usize::from(id))
+}
+
+unsafe fn sigqueue(tgid: i32, tid: Option<i32>, signo: u8, value: usize) {
+    try_sigqueue(tgid, tid, signo, value).expect("error: couldn't enqueue signal\n");
+}
+
+pub(crate) unsafe fn try_sigqueue(
+    tgid: i32,
+    tid: Option<i32>,
+    signo: u8,
+    value: usize,
+) -> Result<(), i32> {
+    let mut si = siginfo_t::default();
+    si.siginfo.si_code = nc::SI_QUEUE;
+    si.siginfo.sifields.rt.sigval.sival_ptr = value;
+
+    let r = record::record_enqueue(signo, value, || {
+        faults::inject(Syscall::Sigqueue).and_then(|()| {
+            if let Some(tid) = tid {
+                nc::rt_tgsigqueueinfo(tgid, tid, SIGRTMIN + i32::from(signo), &mut si)
+            } else {
+                nc::rt_sigqueueinfo(tgid, SIGRTMIN + i32::from(signo), &mut si)
+            }
+        })
+    });
+
+    // NOTE a simulation runs the tasks that this signal lets preempt us right away
+    sim::preempt();
+
+    r
+}
+
+pub unsafe fn epoll_create() -> Result<i32, Error> {
+    nc::epoll_create1(nc::EPOLL_CLOEXEC).map_err(|errno| Error::Epoll { errno })
+}
+
+/// Starts watching `fd`; the task with epoll ID `id` will be notified once it becomes ready
+pub unsafe fn epoll_add(epfd: i32, fd: i32, events: u32, id: u8) -> Result<(), Error> {
+    epoll_ctl(epfd, nc::EPOLL_CTL_ADD, fd, events, id).map_err(|errno| Error::Epoll { errno })
+}
+
+/// Watches `fd` again after its task has handled the previous notification
+pub unsafe fn epoll_rearm(epfd: i32, fd: i32, events: u32, id: u8) {
+    epoll_ctl(epfd, nc::EPOLL_CTL_MOD, fd, events, id)
+        .expect("error: couldn't watch the file descriptor")
+}
+
+unsafe fn epoll_ctl(epfd: i32, op: i32, fd: i32, events: u32, id: u8) -> Result<(), i32> {
+    // one-shot: a file descriptor is not reported again until its task has run
+    let mut event = epoll_event_t {
+        events: events | nc::EPOLLONESHOT as u32,
+        data: u64::from(id),
+    };
+
+    nc::epoll_ctl(epfd, op, fd, &mut event).map(drop)
+}
+
+/// Blocks until one of the watched file descriptors becomes ready and returns its epoll ID
+pub unsafe fn epoll_wait(epfd: i32) -> u8 {
+    let mut events = [epoll_event_t { events: 0, data: 0 }];
+
+    loop {
+        match faults::inject(Syscall::EpollWait)
+            .and_then(|()| nc::epoll_wait(epfd, &mut events, 1, -1))
+        {
+            Ok(1) => break events[0].data as u8,
+            // interrupted by a (non-RTFM) signal
+            Ok(_) | Err(nc::EINTR) => continue,
+            Err(e) => panic!("error: couldn't wait for file descriptors (errno = {})", e),
+        }
     }
 }
 
@@ -242,27 +1369,52 @@ pub unsafe fn register(
     Range { start, end }: Range<u8>,
     priority: u8,
     sigaction: extern "C" fn(i32, &mut siginfo_t, usize),
-) {
-    extern "C" {
-        fn __restorer() -> !;
-    }
-
+) -> Result<(), Error> {
     let len = end.wrapping_sub(start);
-    let mask = (1 << len) - 1;
-    let mask = (mask ^ (mask >> (priority - 1))) << (i32::from(start) + SIGRTMIN - 1);
-    let mask = sigset_t { sig: [mask] };
-
-    rt_sigaction(
-        SIGRTMIN + i32::from(end.wrapping_sub(priority)),
-        &sigaction_t {
-            sa_handler: sigaction as sighandler_t,
-            sa_flags: nc::SA_SIGINFO,
-            sa_mask: mask,
-        },
-        &mut sigaction_t::default(),
-        size_of::<sigset_t>(),
-    )
-    .expect("error: couldn't register signal handler");
+    let mask: u64 = (1 << len) - 1;
+    // NOTE the shutdown signal, which comes right after `end`, is also blocked
+    let mask = (mask ^ (mask >> (priority - 1)) | (1 << len)) << (i32::from(start) + SIGRTMIN - 1);
+    let mask = sigset(mask);
+
+    set_handler(end.wrapping_sub(priority), sigaction, mask)
+}
+
+/// Registers the shutdown handler of the core that owns the signals in `range`
+///
+/// The handler runs on the signal that comes right after `range` with all the signals in `range`
+/// blocked so it only runs once the core has no pending tasks
+pub unsafe fn register_shutdown(
+    Range { start, end }: Range<u8>,
+    sigaction: extern "C" fn(i32, &mut siginfo_t, usize),
+) -> Result<(), Error> {
+    let mask = rt_sigset(start, end.wrapping_sub(start));
+
+    set_handler(end, sigaction, mask)
+}
+
+type Sigaction = extern "C" fn(i32, &mut siginfo_t, usize);
+
+// the registered handlers; the replay and the simulation call them themselves
+static mut HANDLERS: [Option<Sigaction>; 32] = [None; 32];
+
+/// The handler registered for the signal `signo`
+pub(crate) fn handler(signo: u8) -> Option<Sigaction> {
+    // NOTE(unsafe) handlers are registered before any task runs
+    unsafe { HANDLERS[usize::from(signo)] }
+}
+
+unsafe fn set_handler(signo: u8, sigaction: Sigaction, mask: sigset_t) -> Result<(), Error> {
+    HANDLERS[usize::from(signo)] = Some(sigaction);
+
+    faults::inject(Syscall::Sigaction)
+        .and_then(|()| {
+            restorer::sigaction(SIGRTMIN + i32::from(signo), sigaction as sighandler_t, mask)
+        })
+        .map_err(|errno| Error::SignalHandler { signo, errno })
+}
+
+pub unsafe fn timer_delete(timer: timer_t) {
+    nc::timer_delete(timer).expect("error: couldn't delete timer");
 }
 
 // Newtype over `Cell` that forbids mutation through a shared reference
@@ -288,15 +1440,26 @@ impl Priority {
     fn get(&self) -> u8 {
         self.inner.get()
     }
+
+    /// Whether a task of priority `level` can preempt this context, i.e. while it holds its locks
+    #[inline(always)]
+    pub fn is_below(&self, level: u8) -> bool {
+        self.get() < level
+    }
 }
 
+/// Sleeps until a signal handler has run
 pub fn pause() {
-    // ppoll(0, 0, 0, 0) in C.
-    #[cfg(target_arch = "aarch64")]
-    nc::rt_sigsuspend(&mut sigset_t { sig: [255] }, size_of::<sigset_t>()).ok();
+    extern "C" {
+        #[link_name = "pause"]
+        fn pause_() -> c_int;
+    }
 
-    #[cfg(not(target_arch = "aarch64"))]
-    nc::pause().expect("pause failed");
+    // NOTE aarch64 and riscv64 have no `pause` system call; the C library waits in `ppoll` there
+    // NOTE(unsafe) `pause` has no preconditions; it returns, with `EINTR`, once a handler has run
+    unsafe {
+        pause_();
+    }
 }
 
 pub fn assert_send<T>()
@@ -0,0 +1,62 @@
+//! Spawning tasks from other processes
+//!
+//! A task declared with `#[task(external = true)]` can be spawned by any process that is allowed to
+//! signal the application (same user, or `CAP_KILL`). The other process queues the real-time
+//! signal of the task's priority level with `rt_sigqueueinfo` / `sigqueue` and this `sigval`:
+//!
+//! ``` text
+//! bits 31..19  0
+//! bit  18      1 (`EVENT`)
+//! bits 17..16  0
+//! bits 15..8   payload; the `u8` input of the task, if it has one
+//! bits  7..0   ID of the task
+//! ```
+//!
+//! This encoding is stable. The signal and the ID of each external task are listed in the C
+//! header that `#[rtfm::app(external_header = "path/to/app.h")]` writes out.
+
+use nc::{pid_t, siginfo_t};
+
+/// Marks a `sigval` that spawns an external task
+pub const EVENT: usize = 1 << 18;
+
+/// A task that other processes can spawn
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Task {
+    signal: i32,
+    id: u8,
+}
+
+impl Task {
+    /// The task with ID `id` dispatched on `signal`; that's `RTFM_FOO_SIGNAL` and `RTFM_FOO_ID`
+    /// in the C header of the application
+    pub const fn new(signal: i32, id: u8) -> Self {
+        Task { signal, id }
+    }
+
+    /// Signal the task is dispatched on
+    pub fn signal(&self) -> i32 {
+        self.signal
+    }
+
+    /// ID of the task
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+}
+
+/// The `sigval` that spawns `task` with `payload` as its input
+pub fn sigval(task: Task, payload: u8) -> usize {
+    EVENT | usize::from(payload) << 8 | usize::from(task.id)
+}
+
+/// Spawns `task` in the application with process ID `pid`; returns the `errno` on failure
+///
+/// `EAGAIN` means that the application has too many pending signals
+pub fn spawn(pid: pid_t, task: Task, payload: u8) -> Result<(), i32> {
+    let mut si = siginfo_t::default();
+    si.siginfo.si_code = nc::SI_QUEUE;
+    si.siginfo.sifields.rt.sigval.sival_ptr = sigval(task, payload);
+
+    nc::rt_sigqueueinfo(pid, task.signal, &mut si).map(drop)
+}
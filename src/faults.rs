@@ -0,0 +1,140 @@
+//! Fault injection for the system calls of the runtime
+//!
+//! With the `fault-injection` feature the runtime asks a `FaultInjector` before each of the
+//! system calls listed in `Syscall`; the injector can make the call fail with any `errno` without
+//! making it. This exercises the error paths that a healthy system never takes, like a full
+//! signal queue (`EAGAIN`), an interrupted wait (`EINTR`) or running out of memory (`ENOMEM`).
+//!
+//! Without the feature the hook is an empty function and the system calls are made directly.
+
+/// The system calls that can be made to fail
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Syscall {
+    /// `rt_sigqueueinfo` / `rt_tgsigqueueinfo`: `spawn` and the other task messages
+    Sigqueue,
+    /// `rt_sigtimedwait`
+    Sigtimedwait,
+    /// `rt_sigprocmask`: start-up and `lock`
+    Sigprocmask,
+    /// `rt_sigaction`: start-up
+    Sigaction,
+    /// `timer_create`: start-up
+    TimerCreate,
+    /// `timer_settime`: the timer queues and the periodic tasks
+    TimerSettime,
+    /// `mlockall`: start-up
+    Mlockall,
+    /// `sched_setscheduler`: thread priorities
+    SchedSetscheduler,
+    /// `sched_setaffinity`: CPU pinning
+    SchedSetaffinity,
+    /// `futex(FUTEX_WAIT)`: start-up barriers and `spawn_blocking`
+    FutexWait,
+    /// `epoll_wait`: the tasks bound to file descriptors
+    EpollWait,
+}
+
+#[cfg(feature = "fault-injection")]
+mod imp {
+    use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    use super::Syscall;
+
+    static SET: AtomicBool = AtomicBool::new(false);
+    static READY: AtomicBool = AtomicBool::new(false);
+    static mut INJECTOR: Option<&'static dyn FaultInjector> = None;
+
+    /// Decides which system calls fail
+    ///
+    /// NOTE `inject` runs in signal handlers and on all the threads of the runtime
+    pub trait FaultInjector: Sync {
+        /// Called before `syscall` is made; returning `Some(errno)` makes it fail with `errno`
+        fn inject(&self, syscall: Syscall) -> Option<i32>;
+    }
+
+    /// Makes the next `times` calls to a system call fail with `errno`
+    pub struct FailNext {
+        syscall: Syscall,
+        errno: i32,
+        remaining: AtomicU32,
+    }
+
+    impl FailNext {
+        pub const fn new(syscall: Syscall, errno: i32, times: u32) -> Self {
+            FailNext {
+                syscall,
+                errno,
+                remaining: AtomicU32::new(times),
+            }
+        }
+
+        /// How many more calls will fail
+        pub fn remaining(&self) -> u32 {
+            self.remaining.load(Ordering::Relaxed)
+        }
+    }
+
+    impl FaultInjector for FailNext {
+        fn inject(&self, syscall: Syscall) -> Option<i32> {
+            if syscall != self.syscall {
+                return None;
+            }
+
+            let mut remaining = self.remaining.load(Ordering::Relaxed);
+            while remaining != 0 {
+                match self.remaining.compare_exchange_weak(
+                    remaining,
+                    remaining - 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return Some(self.errno),
+                    Err(now) => remaining = now,
+                }
+            }
+
+            None
+        }
+    }
+
+    /// Installs `injector`; only the first call has an effect
+    ///
+    /// To fail the start-up system calls install the injector before the application starts,
+    /// e.g. before `rtfm::simulate`
+    pub fn set_fault_injector(injector: &'static dyn FaultInjector) {
+        if SET.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        // NOTE(unsafe) written once, before `READY` publishes it
+        unsafe { INJECTOR = Some(injector) }
+        READY.store(true, Ordering::Release);
+    }
+
+    pub fn inject(syscall: Syscall) -> Result<(), i32> {
+        if !READY.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        // NOTE(unsafe) no longer written; see `set_fault_injector`
+        match unsafe { INJECTOR }.and_then(|injector| injector.inject(syscall)) {
+            Some(errno) => Err(errno),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(not(feature = "fault-injection"))]
+mod imp {
+    use super::Syscall;
+
+    #[inline(always)]
+    pub fn inject(_: Syscall) -> Result<(), i32> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+pub use imp::{set_fault_injector, FailNext, FaultInjector};
+
+pub(crate) use imp::inject;
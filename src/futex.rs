@@ -0,0 +1,325 @@
+//! Resources protected by priority-inheritance futexes: `#[lock(policy = "pi_futex")]`
+//!
+//! Masking signals only keeps out the tasks of the core that masks them. A `PiFutex` resource can
+//! also be shared with the tasks of other cores: `lock` first masks the tasks of its own core that
+//! share the resource, as usual, and then takes the futex. A task of another core that finds the
+//! futex taken sleeps in `FUTEX_LOCK_PI`, and the kernel runs the owner at the priority of the
+//! waiter until it releases the resource.
+//!
+//! With `#[app(processes = true)]` the cores that share the resource may run in different
+//! processes. Then `lock` also puts the futex on the robust list of the thread, so that if the
+//! process dies while it holds the resource the kernel releases it and flags the futex. The next
+//! `lock` clears the flag and counts the death; see `PiFutexProxy::owner_deaths`.
+
+use core::{
+    cell::{Cell, UnsafeCell},
+    mem::size_of,
+    ops::Range,
+    sync::atomic::{self, AtomicI32, AtomicU32, AtomicUsize, Ordering},
+};
+
+use cty::c_long;
+use nc::timespec_t;
+use rtfm_core::Mutex;
+
+use crate::{
+    export::{self, Priority},
+    process,
+};
+
+/// Set by the kernel in the word of a robust futex whose owner died
+const FUTEX_OWNER_DIED: i32 = 0x4000_0000;
+
+/// A resource that the tasks of several cores can share
+// NOTE `repr(C)`: the robust list of the kernel finds `word` right after `next`
+#[repr(C)]
+pub struct PiFutex<T> {
+    // entry of the robust list of the owner; only used when the cores run in processes
+    next: AtomicUsize,
+    // `0` while the resource is free, the thread ID of the owner otherwise; the kernel also sets
+    // `FUTEX_WAITERS` in it while other threads wait for the resource
+    word: AtomicI32,
+    // number of times that the owner died while holding the resource
+    deaths: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for PiFutex<T> where T: Send {}
+
+impl<T> PiFutex<T> {
+    #[doc(hidden)]
+    pub const fn new(value: T) -> Self {
+        PiFutex {
+            next: AtomicUsize::new(0),
+            word: AtomicI32::new(0),
+            deaths: AtomicU32::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a mutable reference to the resource; `init` gets one because it runs before any task
+    pub fn get_mut(&mut self) -> &mut T {
+        // NOTE(unsafe) `&mut self` rules out any other access
+        unsafe { &mut *self.data.get() }
+    }
+
+    // NOTE the caller must keep out the tasks of its core that share the resource; they would
+    // deadlock on the futex
+    unsafe fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let tid = tid();
+        let robust = robust_list();
+
+        if let Some(head) = robust {
+            head.pending(&self.next);
+        }
+
+        // uncontended: no system call
+        if self
+            .word
+            .compare_exchange(0, tid, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            futex(&self.word, nc::FUTEX_LOCK_PI);
+        }
+
+        if let Some(head) = robust {
+            head.push(&self.next);
+
+            // the previous owner died in its critical section
+            if self.word.load(Ordering::Relaxed) & FUTEX_OWNER_DIED != 0 {
+                self.word.fetch_and(!FUTEX_OWNER_DIED, Ordering::Relaxed);
+                self.deaths.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let r = f(&mut *self.data.get());
+
+        if let Some(head) = robust {
+            head.pending(&self.next);
+            head.pop(&self.next);
+        }
+
+        // `FUTEX_WAITERS` is set; the kernel hands the resource over to the highest priority waiter
+        if self
+            .word
+            .compare_exchange(tid, 0, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            futex(&self.word, nc::FUTEX_UNLOCK_PI);
+        }
+
+        if let Some(head) = robust {
+            head.done();
+        }
+
+        r
+    }
+}
+
+/// Proxy of a `PiFutex` resource; what the tasks get in their `resources`
+pub struct PiFutexProxy<'a, T> {
+    futex: &'a PiFutex<T>,
+    name: &'static str,
+    priority: &'a Priority,
+    ceiling: u8,
+    range: Range<u8>,
+}
+
+impl<'a, T> PiFutexProxy<'a, T> {
+    /// NOTE `ceiling` is the highest priority of the tasks of the core of `priority` that share
+    /// the resource
+    #[doc(hidden)]
+    pub unsafe fn new(
+        futex: &'a PiFutex<T>,
+        name: &'static str,
+        priority: &'a Priority,
+        ceiling: u8,
+        range: Range<u8>,
+    ) -> Self {
+        PiFutexProxy {
+            futex,
+            name,
+            priority,
+            ceiling,
+            range,
+        }
+    }
+
+    /// Number of times that a process died while it held the resource; its last update of the
+    /// resource may be incomplete
+    ///
+    /// Always zero unless the cores run in processes of their own (`processes = true`)
+    pub fn owner_deaths(&self) -> u32 {
+        self.futex.deaths.load(Ordering::Relaxed)
+    }
+}
+
+impl<'a, T> Mutex for PiFutexProxy<'a, T> {
+    type T = T;
+
+    fn lock<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        let futex = self.futex;
+
+        unsafe {
+            export::lock(
+                &mut (),
+                self.name,
+                &[],
+                self.priority,
+                self.ceiling,
+                self.range.clone(),
+                |_| futex.lock(f),
+            )
+        }
+    }
+}
+
+thread_local! {
+    // thread ID of this thread; `0` until the thread locks a resource for the first time
+    static TID: Cell<i32> = Cell::new(0);
+
+    // robust list of this thread; registered when the thread locks a resource for the first time
+    static ROBUST: RobustList = RobustList::new();
+}
+
+fn tid() -> i32 {
+    TID.with(|tid| {
+        if tid.get() == 0 {
+            tid.set(nc::gettid());
+        }
+
+        tid.get()
+    })
+}
+
+// `struct robust_list_head` of the kernel ABI
+//
+// NOTE the resources a thread holds are always released in the reverse order they were locked,
+// also by the tasks that preempt the thread, so the list is a stack
+#[repr(C)]
+struct RobustList {
+    // first entry; the list ends with the address of `next` itself
+    next: AtomicUsize,
+    // offset from an entry to its futex word
+    futex_offset: isize,
+    // entry that is being locked or unlocked
+    pending: AtomicUsize,
+    // not part of the ABI
+    registered: Cell<bool>,
+}
+
+impl RobustList {
+    const fn new() -> Self {
+        RobustList {
+            next: AtomicUsize::new(0),
+            futex_offset: size_of::<usize>() as isize,
+            pending: AtomicUsize::new(0),
+            registered: Cell::new(false),
+        }
+    }
+
+    fn pending(&self, entry: &AtomicUsize) {
+        self.pending
+            .store(entry as *const AtomicUsize as usize, Ordering::Relaxed);
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+
+    fn push(&self, entry: &AtomicUsize) {
+        entry.store(self.next.load(Ordering::Relaxed), Ordering::Relaxed);
+        atomic::compiler_fence(Ordering::SeqCst);
+        self.next
+            .store(entry as *const AtomicUsize as usize, Ordering::Relaxed);
+        atomic::compiler_fence(Ordering::SeqCst);
+        self.done();
+    }
+
+    fn pop(&self, entry: &AtomicUsize) {
+        self.next
+            .store(entry.load(Ordering::Relaxed), Ordering::Relaxed);
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+
+    fn done(&self) {
+        self.pending.store(0, Ordering::Relaxed);
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+// The robust list of this thread, if the cores run in processes of their own
+//
+// NOTE this replaces the list that the C library registered for the thread, so robust `pthread`
+// mutexes are not released when the process of a core dies
+fn robust_list() -> Option<&'static RobustList> {
+    if !process::processes() {
+        return None;
+    }
+
+    // NOTE(unsafe) the list lives as long as the thread, and the kernel only reads it when the
+    // thread exits
+    let head = ROBUST.with(|head| unsafe { &*(head as *const RobustList) });
+    if !head.registered.get() {
+        head.next
+            .store(&head.next as *const AtomicUsize as usize, Ordering::Relaxed);
+
+        if unsafe { set_robust_list(head) } != 0 {
+            panic!("error: couldn't register the robust futex list of a thread");
+        }
+        head.registered.set(true);
+    }
+
+    Some(head)
+}
+
+// NOTE `struct robust_list_head` is the first 3 words of `RobustList`
+unsafe fn set_robust_list(head: &RobustList) -> c_long {
+    syscall(
+        SYS_SET_ROBUST_LIST,
+        head as *const RobustList,
+        3 * size_of::<usize>(),
+    )
+}
+
+#[cfg(target_arch = "x86_64")]
+const SYS_SET_ROBUST_LIST: c_long = 273;
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+const SYS_SET_ROBUST_LIST: c_long = 99;
+#[cfg(target_arch = "arm")]
+const SYS_SET_ROBUST_LIST: c_long = 338;
+#[cfg(target_arch = "x86")]
+const SYS_SET_ROBUST_LIST: c_long = 311;
+
+extern "C" {
+    fn syscall(number: c_long, ...) -> c_long;
+}
+
+fn futex(word: &AtomicI32, op: i32) {
+    // `FUTEX_LOCK_PI` takes an absolute `CLOCK_REALTIME` deadline; this one never expires
+    let mut timeout = timespec_t {
+        tv_sec: isize::max_value() as _,
+        tv_nsec: 0,
+    };
+    let mut unused = 0;
+
+    loop {
+        // NOTE the kernel writes `word`, which is an atomic; `as_ptr` derives the mutable
+        // reference that `nc` takes from its `UnsafeCell`
+        match nc::futex(
+            unsafe { &mut *word.as_ptr() },
+            // NOTE the futex is in a mapping that the processes of the cores share
+            if process::processes() {
+                op
+            } else {
+                op | nc::FUTEX_PRIVATE_FLAG
+            },
+            0,
+            &mut timeout,
+            &mut unused,
+            0,
+        ) {
+            Ok(_) => return,
+            // the owner is exiting, or the deadline passed anyway; try again
+            Err(nc::EAGAIN) | Err(nc::EINTR) | Err(nc::ETIMEDOUT) => {}
+            Err(_) => panic!("error: couldn't lock or unlock a PI futex"),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Per-task heartbeats: `#[task(heartbeat = "50ms")]`
+//!
+//! A task with a heartbeat must call `c.heartbeat()` at least once per interval. A monitor thread,
+//! at a `SCHED_FIFO` priority above every task, compares the time of the last beat of each task
+//! against its interval and reports the tasks that fell silent, which are starved by higher
+//! priority tasks or stuck in a livelock.
+
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use std::{process, thread};
+
+use crate::{shutdown, sim, time};
+
+static mut TABLE: &[Heartbeat] = &[];
+
+// the tasks are not expected to beat before they have been enabled
+static ARMED: AtomicBool = AtomicBool::new(false);
+
+/// The heartbeat of a task
+pub struct Heartbeat {
+    name: &'static str,
+    // in nanoseconds
+    interval: u64,
+    // monotonic time of the last beat, in nanoseconds
+    last: AtomicU64,
+}
+
+impl Heartbeat {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str, interval: u64) -> Self {
+        Heartbeat {
+            name,
+            interval,
+            last: AtomicU64::new(0),
+        }
+    }
+}
+
+pub unsafe fn init_heartbeats(table: &'static [Heartbeat]) {
+    TABLE = table;
+}
+
+/// Records a beat of the task at `index` in the table; a single atomic store
+pub fn heartbeat(index: usize) {
+    // NOTE(unsafe) `TABLE` is only written before any task runs
+    if let Some(heartbeat) = unsafe { TABLE }.get(index) {
+        heartbeat.last.store(now(), Ordering::Relaxed);
+    }
+}
+
+/// Starts checking that the tasks beat
+pub fn arm_heartbeats() {
+    // the monitor runs in real time; a simulation only moves when the test drives it
+    if sim::active() {
+        return;
+    }
+
+    let now = now();
+    // NOTE(unsafe) see `heartbeat`
+    for heartbeat in unsafe { TABLE } {
+        heartbeat.last.store(now, Ordering::Relaxed);
+    }
+
+    ARMED.store(true, Ordering::Release);
+}
+
+/// Body of the heartbeat monitor
+///
+/// It wakes up four times per shortest interval and calls `on_missed` with the time since the
+/// last beat of each task that hasn't beaten within its interval; once per silence
+pub fn monitor_heartbeats(on_missed: impl Fn(&'static str, Duration)) -> ! {
+    // NOTE(unsafe) see `heartbeat`
+    let table = unsafe { TABLE };
+    let tick = table
+        .iter()
+        .map(|heartbeat| heartbeat.interval / 4)
+        .min()
+        .unwrap_or(0)
+        .max(1);
+    let mut silent = vec![false; table.len()];
+
+    loop {
+        thread::sleep(Duration::from_nanos(tick));
+
+        // NOTE the tasks may legitimately stop beating while the application drains
+        if !ARMED.load(Ordering::Acquire) || shutdown::shutting_down() {
+            continue;
+        }
+
+        let now = now();
+        for (heartbeat, silent) in table.iter().zip(&mut silent) {
+            let since = now.saturating_sub(heartbeat.last.load(Ordering::Relaxed));
+            if since <= heartbeat.interval {
+                *silent = false;
+            } else if !*silent {
+                *silent = true;
+                on_missed(heartbeat.name, Duration::from_nanos(since));
+            }
+        }
+    }
+}
+
+/// Default policy for a missed heartbeat
+pub fn heartbeat_abort(task: &'static str, silent: Duration) {
+    eprintln!(
+        "error: task `{}` has not beaten for {:?}; aborting",
+        task, silent
+    );
+
+    process::abort()
+}
+
+pub fn heartbeat_log(task: &'static str, silent: Duration) {
+    eprintln!("warning: task `{}` has not beaten for {:?}", task, silent);
+}
+
+fn now() -> u64 {
+    let ts = time::clock_gettime(nc::CLOCK_MONOTONIC);
+
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
@@ -0,0 +1,86 @@
+//! Hybrid dispatch: several logical priorities share the signal of a priority level
+//!
+//! `#[app(signal_levels = N)]` folds the priorities of the tasks onto at most `N` signals. The
+//! tasks of a level don't preempt each other, like the tasks of a single priority, but the handler
+//! of the level runs the releases that are pending in the order of their logical priorities
+//! instead of the order the kernel queued them in.
+
+use core::{mem::size_of, ptr};
+
+use heapless::{consts, Vec};
+use nc::{siginfo_t, sigset_t, timespec_t};
+
+use crate::{
+    export,
+    faults::{self, Syscall},
+};
+
+/// Releases that the handler of a level holds at a time; the rest stay queued in the kernel
+type Capacity = consts::U32;
+
+/// Runs the release `si`, and then every release of signal `signo` that is pending, highest
+/// `rank` first; releases of the same rank run in the order they were sent
+///
+/// NOTE this must be called from the handler of `signo`, which blocks `signo`
+pub unsafe fn hybrid(
+    signo: u8,
+    si: &mut siginfo_t,
+    rank: fn(&siginfo_t) -> u8,
+    dispatch: unsafe fn(&mut siginfo_t),
+) {
+    // (rank, sequence number, release)
+    let mut ready = Vec::<(u8, u32, siginfo_t), Capacity>::new();
+    let mut seq = 0;
+
+    let first = ptr::read(si);
+    ready.push((rank(&first), seq, first)).ok();
+
+    loop {
+        // take the releases that arrived in the meantime off the kernel queue
+        while ready.len() < ready.capacity() {
+            match poll(signo) {
+                Some(si) => {
+                    seq += 1;
+                    ready.push((rank(&si), seq, si)).ok();
+                }
+                None => break,
+            }
+        }
+
+        let next = ready
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))
+            .map(|(i, _)| i);
+
+        match next {
+            Some(i) => {
+                let (_, _, mut si) = ready.swap_remove(i);
+                dispatch(&mut si);
+            }
+            None => break,
+        }
+    }
+}
+
+// Dequeues a pending `signo`, if there's one
+unsafe fn poll(signo: u8) -> Option<siginfo_t> {
+    let set = export::rt_sigset(signo, 1);
+    let timeout = timespec_t {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    loop {
+        let mut si = siginfo_t::default();
+        match faults::inject(Syscall::Sigtimedwait)
+            .and_then(|()| nc::rt_sigtimedwait(&set, &mut si, &timeout, size_of::<sigset_t>()))
+        {
+            Ok(_) => return Some(si),
+            // nothing is pending
+            Err(nc::EAGAIN) => return None,
+            Err(nc::EINTR) => {}
+            Err(_) => panic!("error: couldn't poll the signal of a priority level"),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+use nc::timespec_t;
+
+use crate::time::Instant;
+
+static mut TASKS: &[TaskInfo] = &[];
+static mut TIMER_QUEUES: &[&TimerQueueInfo] = &[];
+
+/// Returns the message queues of the software tasks and the timer queues of the application
+///
+/// Both lists are empty unless the application enables them with
+/// `#[rtfm::app(introspect = true)]`
+pub fn introspect() -> Introspection {
+    // NOTE(unsafe) the tables are only written before any task runs
+    unsafe {
+        Introspection {
+            tasks: TASKS,
+            timer_queues: TIMER_QUEUES,
+        }
+    }
+}
+
+/// Snapshot of the tables returned by `introspect`; the values they hold change over time
+#[derive(Clone, Copy)]
+pub struct Introspection {
+    tasks: &'static [TaskInfo],
+    timer_queues: &'static [&'static TimerQueueInfo],
+}
+
+impl Introspection {
+    /// The software tasks, in declaration order
+    pub fn tasks(&self) -> &'static [TaskInfo] {
+        self.tasks
+    }
+
+    /// The timer queues, one per core that uses `schedule` plus one per core that uses
+    /// `schedule_wall`
+    pub fn timer_queues(&self) -> &'static [&'static TimerQueueInfo] {
+        self.timer_queues
+    }
+}
+
+/// The message slots of a single task
+///
+/// Slots are taken by `spawn` and `schedule` and freed when the task is dispatched or the schedule
+/// cancelled. Messages sent through a `Spawner` use a queue of their own and are not counted.
+pub struct TaskInfo {
+    name: &'static str,
+    priority: u8,
+    core: u8,
+    capacity: u16,
+    depth: AtomicU16,
+    high_water: AtomicU16,
+    deadline_misses: AtomicU64,
+}
+
+impl TaskInfo {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str, priority: u8, core: u8, capacity: u16) -> Self {
+        TaskInfo {
+            name,
+            priority,
+            core,
+            capacity,
+            depth: AtomicU16::new(0),
+            high_water: AtomicU16::new(0),
+            deadline_misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Name of the task
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Priority of the task
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// Core that runs the task
+    pub fn core(&self) -> u8 {
+        self.core
+    }
+
+    /// Number of message slots: `capacity` times the number of cores that send the task messages;
+    /// zero if the task is never spawned or scheduled
+    pub fn capacity(&self) -> u16 {
+        self.capacity
+    }
+
+    /// Number of messages that are pending (spawned or scheduled but not yet dispatched)
+    pub fn depth(&self) -> u16 {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Largest `depth` observed since start-up
+    pub fn high_water_mark(&self) -> u16 {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    /// Number of runs that completed after the `deadline` of the task
+    pub fn deadline_misses(&self) -> u64 {
+        self.deadline_misses.load(Ordering::Relaxed)
+    }
+
+    #[doc(hidden)]
+    pub fn claim(&self) {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.high_water.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    #[doc(hidden)]
+    pub fn release(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    #[doc(hidden)]
+    pub fn missed(&self) {
+        self.deadline_misses.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The timer queue of a core
+pub struct TimerQueueInfo {
+    core: u8,
+    wall: bool,
+    len: AtomicU16,
+    // `u64::MAX` if the queue is empty
+    next: AtomicU64,
+}
+
+impl TimerQueueInfo {
+    #[doc(hidden)]
+    pub const fn new(core: u8, wall: bool) -> Self {
+        TimerQueueInfo {
+            core,
+            wall,
+            len: AtomicU16::new(0),
+            next: AtomicU64::new(u64::max_value()),
+        }
+    }
+
+    /// Core that schedules the entries of this queue
+    pub fn core(&self) -> u8 {
+        self.core
+    }
+
+    /// Whether this queue holds the `schedule_wall` entries
+    pub fn is_wall(&self) -> bool {
+        self.wall
+    }
+
+    /// Number of scheduled entries
+    pub fn len(&self) -> u16 {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Whether the queue has no scheduled entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The instant of the earliest entry; `None` if the queue is empty
+    ///
+    /// The instant is on the `monotonic` clock of the application or, if `is_wall`, on
+    /// `CLOCK_REALTIME`
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match self.next.load(Ordering::Relaxed) {
+            nanos if nanos == u64::max_value() => None,
+            nanos => Some(Instant::from(timespec_t {
+                tv_sec: (nanos / 1_000_000_000) as _,
+                tv_nsec: (nanos % 1_000_000_000) as _,
+            })),
+        }
+    }
+
+    // NOTE called with the timer queue locked so there's a single writer
+    pub(crate) fn publish(&self, len: usize, next: Option<Instant>) {
+        let next = next
+            .map(|instant| {
+                let ts = timespec_t::from(instant);
+                ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+            })
+            .unwrap_or(u64::max_value());
+
+        self.len.store(len as u16, Ordering::Relaxed);
+        self.next.store(next, Ordering::Relaxed);
+    }
+}
+
+pub unsafe fn init_introspection(
+    tasks: &'static [TaskInfo],
+    timer_queues: &'static [&'static TimerQueueInfo],
+) {
+    TASKS = tasks;
+    TIMER_QUEUES = timer_queues;
+}
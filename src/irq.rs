@@ -0,0 +1,77 @@
+//! Interrupt affinity: where the kernel handles the interrupts of a device
+//!
+//! The latency of a task bound to a file descriptor starts with the interrupt of the device. On
+//! the CPU of the core that runs the task the interrupt thread preempts nothing of the rest of
+//! the system, but it does delay the tasks of that core; on another CPU it leaves the core alone
+//! but adds a wake-up across CPUs. Either is a choice to make per device, usually in `init`.
+
+use std::{fs, io};
+
+use crate::shield;
+
+/// The CPUs an interrupt may be handled on
+#[derive(Clone, Copy, Debug)]
+pub enum IrqCpus<'a> {
+    /// Only these CPUs, e.g. the CPU of the core whose task reads the device
+    Only(&'a [u8]),
+    /// Every online CPU but these, e.g. the CPUs of the cores
+    Except(&'a [u8]),
+}
+
+/// Returns the interrupts of `device`, as named in `/proc/interrupts`, e.g. `eth0`
+///
+/// The queues of a device, e.g. `eth0-rx-0` and `eth0-tx-0`, count as the device
+pub fn irqs(device: &str) -> Vec<u32> {
+    let interrupts = fs::read_to_string("/proc/interrupts").unwrap_or_default();
+    let queue = format!("{}-", device);
+
+    interrupts
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.trim_start().splitn(2, ':');
+            // NOTE the per-CPU interrupts, like `NMI` or `LOC`, have no number
+            let irq = parts.next()?.parse::<u32>().ok()?;
+
+            // the counts of each CPU, the chip, the hardware IRQ and the actions, `, `-separated
+            let owned = parts
+                .next()?
+                .split_whitespace()
+                .map(|action| action.trim_end_matches(','))
+                .any(|action| action == device || action.starts_with(&queue));
+
+            if owned {
+                Some(irq)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Moves interrupt `irq` to `cpus`; returns the `errno` on failure
+///
+/// This writes `/proc/irq/$irq/smp_affinity`, which needs root. `EINVAL` means that no online
+/// CPU is left, and `EIO` that the kernel manages the affinity of the interrupt itself
+pub fn set_irq_affinity(irq: u32, cpus: IrqCpus<'_>) -> Result<(), i32> {
+    let online = shield::online().ok_or(nc::EIO)?;
+    let cpus = online
+        .into_iter()
+        .filter(|cpu| match cpus {
+            IrqCpus::Only(cpus) => cpus.contains(cpu),
+            IrqCpus::Except(cpus) => !cpus.contains(cpu),
+        })
+        .collect::<Vec<_>>();
+    if cpus.is_empty() {
+        return Err(nc::EINVAL);
+    }
+
+    fs::write(
+        format!("/proc/irq/{}/smp_affinity", irq),
+        shield::mask(&cpus),
+    )
+    .map_err(errno)
+}
+
+fn errno(e: io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(nc::EIO)
+}
@@ -0,0 +1,113 @@
+use std::fs;
+
+use crate::shield;
+
+/// Exists, and holds `1`, on `PREEMPT_RT` kernels
+const REALTIME: &str = "/sys/kernel/realtime";
+
+/// The preemption model of a `PREEMPT_DYNAMIC` kernel, e.g. `none voluntary (full)`; needs
+/// debugfs, which only root can read
+const PREEMPT: &str = "/sys/kernel/debug/sched/preempt";
+
+/// The build string of the kernel, e.g. `#1 SMP PREEMPT_DYNAMIC ..`
+const VERSION: &str = "/proc/sys/kernel/version";
+
+/// CPUs given to `nohz_full=` on the kernel command line
+const NOHZ_FULL: &str = "/sys/devices/system/cpu/nohz_full";
+
+/// CPUs given to `isolcpus=` on the kernel command line
+const ISOLATED: &str = "/sys/devices/system/cpu/isolated";
+
+/// How the kernel preempts its own code
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Preemption {
+    /// `PREEMPT_NONE` or `PREEMPT_VOLUNTARY`: kernel code runs until it blocks or yields, which
+    /// can delay a task by milliseconds
+    Cooperative,
+    /// `PREEMPT_LAZY`: kernel code is preempted at the latest on the next tick
+    Lazy,
+    /// `PREEMPT`: kernel code is preempted outside of spinlocks and interrupt handlers
+    Full,
+    /// `PREEMPT_RT`: spinlocks sleep and interrupt handlers run in threads, so nearly all kernel
+    /// code can be preempted
+    Rt,
+}
+
+/// The kernel configuration that matters for latency
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KernelConfig {
+    /// The preemption model; `None` if it couldn't be told
+    pub preemption: Option<Preemption>,
+    /// Whether the preemption model can be changed at run time (`PREEMPT_DYNAMIC`)
+    pub dynamic: bool,
+    /// CPUs that stop the scheduler tick while they run a single thread (`nohz_full=`)
+    pub nohz_full: Vec<u8>,
+    /// CPUs that the scheduler doesn't balance load onto (`isolcpus=`)
+    pub isolated: Vec<u8>,
+}
+
+impl KernelConfig {
+    /// Whether the kernel is `PREEMPT_RT`
+    pub fn is_preempt_rt(&self) -> bool {
+        self.preemption == Some(Preemption::Rt)
+    }
+
+    /// Whether `cpu` runs without the scheduler tick (`CONFIG_NO_HZ_FULL` is in effect on it)
+    pub fn is_tickless(&self, cpu: u8) -> bool {
+        self.nohz_full.contains(&cpu)
+    }
+}
+
+/// Detects the kernel configuration from sysfs and procfs
+pub fn kernel_config() -> KernelConfig {
+    let version = fs::read_to_string(VERSION).unwrap_or_default();
+    let dynamic = version.contains("PREEMPT_DYNAMIC");
+
+    KernelConfig {
+        preemption: preemption(&version, dynamic),
+        dynamic,
+        nohz_full: cpus(NOHZ_FULL),
+        isolated: cpus(ISOLATED),
+    }
+}
+
+fn preemption(version: &str, dynamic: bool) -> Option<Preemption> {
+    let realtime = fs::read_to_string(REALTIME)
+        .map(|realtime| realtime.trim() == "1")
+        .unwrap_or(false);
+    if realtime || version.contains("PREEMPT_RT") {
+        return Some(Preemption::Rt);
+    }
+
+    if dynamic {
+        // the current model is the one in parentheses
+        let models = fs::read_to_string(PREEMPT).ok()?;
+        let start = models.find('(')? + 1;
+        let end = start + models[start..].find(')')?;
+
+        return match &models[start..end] {
+            "none" | "voluntary" => Some(Preemption::Cooperative),
+            "lazy" => Some(Preemption::Lazy),
+            "full" => Some(Preemption::Full),
+            _ => None,
+        };
+    }
+
+    if version.contains("PREEMPT_LAZY") {
+        Some(Preemption::Lazy)
+    } else if version.split_whitespace().any(|word| word == "PREEMPT") {
+        Some(Preemption::Full)
+    } else if version.is_empty() {
+        None
+    } else {
+        Some(Preemption::Cooperative)
+    }
+}
+
+// NOTE the lists are empty, or `(null)` on older kernels, when the option is not set
+fn cpus(path: &str) -> Vec<u8> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|list| shield::parse_list(list.trim()))
+        .unwrap_or_default()
+}
@@ -0,0 +1,221 @@
+use core::{
+    fmt::{self, Write as _},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use heapless::{consts, String};
+use nc::timespec_t;
+
+use crate::time::Instant;
+
+/// Sub-buckets per power of two; with 8 the width of a bucket is at most 12.5% of its values
+const SUB_BITS: u32 = 3;
+const SUB: usize = 1 << SUB_BITS;
+
+/// Latencies below this many nanoseconds get a bucket each
+const LINEAR: usize = 2 * SUB;
+
+/// Number of buckets; enough for any `u64` number of nanoseconds
+const BUCKETS: usize = LINEAR + (64 - SUB_BITS as usize - 1) * SUB;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+static mut PROBES: &[LatencyHistogram] = &[];
+
+/// Returns the wakeup-latency histograms of the latency probe, one per core
+///
+/// The slice is empty unless the application enables the probe with
+/// `#[rtfm::app(latency_probe = "1ms")]`
+pub fn latency_probe() -> &'static [LatencyHistogram] {
+    // NOTE(unsafe) `PROBES` is only written before any task runs
+    unsafe { PROBES }
+}
+
+/// Wakeup latencies measured by the probe thread of a core
+///
+/// The buckets are HDR-style: one per nanosecond below 16 ns, then 8 per power of two. Each field
+/// is read independently so a reader may see a sample in `count` but not yet in its bucket.
+pub struct LatencyHistogram {
+    core: u8,
+    count: AtomicU64,
+    total: AtomicU64,
+    max: AtomicU64,
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl LatencyHistogram {
+    #[doc(hidden)]
+    pub const fn new(core: u8) -> Self {
+        LatencyHistogram {
+            core,
+            count: ZERO,
+            total: ZERO,
+            max: ZERO,
+            buckets: [ZERO; BUCKETS],
+        }
+    }
+
+    /// Core whose CPU the probe runs on
+    pub fn core(&self) -> u8 {
+        self.core
+    }
+
+    /// Number of wakeups measured
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Mean latency; `None` if there are no samples yet
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                self.total.load(Ordering::Relaxed) / count,
+            )),
+        }
+    }
+
+    /// Largest latency
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max.load(Ordering::Relaxed))
+    }
+
+    /// Latency that `percent` percent of the samples don't exceed, rounded up to the end of its
+    /// bucket; `None` if there are no samples yet
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((percent / 100. * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+
+            if seen >= rank {
+                return Some(self.max().min(Duration::from_nanos(upper(i))));
+            }
+        }
+
+        Some(self.max())
+    }
+
+    /// The buckets that hold samples, as `(upper bound, samples)` pairs in increasing order
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(i, bucket)| {
+                (
+                    Duration::from_nanos(upper(i)),
+                    bucket.load(Ordering::Relaxed),
+                )
+            })
+            .filter(|&(_, count)| count != 0)
+    }
+
+    // NOTE only the probe thread of the core writes
+    fn record(&self, nanos: u64) {
+        if nanos > self.max.load(Ordering::Relaxed) {
+            self.max.store(nanos, Ordering::Relaxed);
+        }
+
+        self.buckets[index(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(nanos, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub unsafe fn init_latency_probe(probes: &'static [LatencyHistogram]) {
+    PROBES = probes;
+}
+
+/// Body of the probe thread of a core
+///
+/// Like `cyclictest` it sleeps until the next multiple of `period` with `clock_nanosleep` and
+/// records how late it woke up. Periods that pass while the thread is late are skipped.
+pub fn run_latency_probe(histogram: &LatencyHistogram, period: Duration) -> ! {
+    let mut next = Instant::now() + period;
+
+    loop {
+        let target = timespec_t::from(next);
+        if nc::clock_nanosleep(
+            nc::CLOCK_MONOTONIC,
+            nc::TIMER_ABSTIME,
+            &target,
+            &mut timespec_t::default(),
+        )
+        .is_ok()
+        {
+            let now = Instant::now();
+            let late = now.saturating_duration_since(next);
+            histogram.record(late.as_nanos() as u64);
+
+            while next <= now {
+                next += period;
+            }
+        }
+    }
+}
+
+/// Writes the histograms to stderr
+///
+/// NOTE async-signal-safe; it's called from the shutdown handler of core #0
+pub fn dump_latency_probe() {
+    for histogram in latency_probe() {
+        line(format_args!(
+            "latency probe, core #{}: {} samples, mean {:?}, p99 {:?}, p99.99 {:?}, max {:?}\n",
+            histogram.core(),
+            histogram.count(),
+            histogram.mean().unwrap_or_default(),
+            histogram.percentile(99.).unwrap_or_default(),
+            histogram.percentile(99.99).unwrap_or_default(),
+            histogram.max(),
+        ));
+
+        for (upper, count) in histogram.buckets() {
+            line(format_args!("  <= {:?}: {}\n", upper, count));
+        }
+    }
+}
+
+fn line(args: fmt::Arguments<'_>) {
+    let mut line = String::<consts::U256>::new();
+    if line.write_fmt(args).is_ok() {
+        nc::write(2, line.as_ptr() as usize, line.len()).ok();
+    }
+}
+
+fn index(nanos: u64) -> usize {
+    if nanos < LINEAR as u64 {
+        nanos as usize
+    } else {
+        let exponent = 63 - nanos.leading_zeros();
+        let sub = (nanos >> (exponent - SUB_BITS)) as usize & (SUB - 1);
+
+        LINEAR + (exponent - SUB_BITS - 1) as usize * SUB + sub
+    }
+}
+
+fn lower(index: usize) -> u64 {
+    if index < LINEAR {
+        index as u64
+    } else {
+        let exponent = ((index - LINEAR) / SUB) as u32 + SUB_BITS + 1;
+        let sub = ((index - LINEAR) % SUB) as u64;
+
+        (SUB as u64 + sub) << (exponent - SUB_BITS)
+    }
+}
+
+// Largest latency, in nanoseconds, that falls in the bucket `index`
+fn upper(index: usize) -> u64 {
+    if index + 1 == BUCKETS {
+        u64::max_value()
+    } else {
+        lower(index + 1) - 1
+    }
+}
@@ -1,9 +1,77 @@
 #![deny(warnings)]
 
+#[cfg(target_os = "android")]
+mod android;
+mod args;
+mod background;
+mod budget;
+pub mod compat;
+mod crash;
+mod defer;
+mod error;
 pub mod export;
+pub mod external;
+mod faults;
+mod futex;
+mod heartbeat;
+mod hybrid;
+mod introspect;
+mod irq;
+mod kernel;
+mod latency;
+mod log;
+mod metrics;
+mod mode;
+mod per_core;
+mod perf;
+pub mod pool;
+mod preflight;
+mod privileges;
+mod process;
+mod record;
+mod restorer;
+mod rseq;
+mod seccomp;
+mod shield;
+pub mod shm;
+mod shutdown;
+mod sim;
+mod spawner;
+mod stack;
+mod stats;
+mod sync;
+mod thermal;
+mod threads;
+mod throttling;
 pub mod time;
 mod tq;
+mod trace;
+mod watchdog;
 
+pub use args::{Args, Env};
+pub use defer::{defer, DEFER_SLOT_SIZE};
+pub use error::{Error, SpawnError};
+pub use export::RuntimeConfig;
+#[cfg(feature = "fault-injection")]
+pub use faults::{set_fault_injector, FailNext, FaultInjector, Syscall};
+pub use introspect::{introspect, Introspection, TaskInfo, TimerQueueInfo};
+pub use irq::{irqs, set_irq_affinity, IrqCpus};
+pub use kernel::{kernel_config, KernelConfig, Preemption};
+pub use latency::{latency_probe, LatencyHistogram};
 pub use linux_rtfm_macros::app;
-pub use rtfm_core::Mutex;
-pub use time::Instant;
+pub use preflight::{preflight, Problem};
+pub use record::save_recording;
+pub use rseq::PerCpuCounter;
+pub use rtfm_core::{Exclusive, Mutex};
+pub use shutdown::shutdown;
+pub use sim::{simulate, Simulation};
+pub use spawner::Spawner;
+pub use stack::{stack_usage, StackUsage};
+pub use stats::{lock_stats, stats, LockStats, TaskStats};
+pub use thermal::CpuThrottling;
+pub use threads::{
+    set_core_cpu, set_core_priority, set_task_cpu, set_task_priority, threads, Task, Thread,
+};
+pub use throttling::{rt_throttling, RtThrottling};
+pub use time::{Instant, Monotonic};
+pub use watchdog::feed_watchdog;
@@ -0,0 +1,101 @@
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::{fs::OpenOptions, os::unix::io::IntoRawFd};
+
+use heapless::{
+    consts,
+    spsc::{Consumer, Producer, Queue},
+    String,
+};
+use nc::timespec_t;
+
+use crate::Error;
+
+/// A single log record; longer records are rejected
+pub type LogRecord = String<consts::U128>;
+
+/// Log records of a priority level
+///
+/// The producer is the priority level, which never preempts itself, and the consumer is the writer
+/// thread so no syscalls or locks are needed
+pub type LogQueue = Queue<LogRecord, consts::U16>;
+
+/// The end of a `LogQueue` that its priority level writes to
+///
+/// NOTE each queue is split once, before any task runs, so that the priority level and the writer
+/// thread never hold a reference to the same queue
+pub type LogProducer = Producer<'static, LogRecord, consts::U16>;
+
+/// The end of a `LogQueue` that the writer thread reads from
+pub type LogConsumer = Consumer<'static, LogRecord, consts::U16>;
+
+/// Records queued, across all the queues, but not written out yet
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// How long the writer thread sleeps when there's nothing to write
+const LOG_PERIOD: timespec_t = timespec_t {
+    tv_sec: 0,
+    tv_nsec: 10_000_000, // 10 ms
+};
+
+/// How many `LOG_PERIOD`s `log_flush` waits for the writer thread at most: 1 s
+const FLUSH_PERIODS: u32 = 100;
+
+pub fn log(producer: &mut LogProducer, args: fmt::Arguments<'_>) -> Result<(), ()> {
+    let mut record = LogRecord::new();
+    record.write_fmt(args).map_err(|_| ())?;
+
+    // NOTE counted before it's queued so that the writer never takes the count below zero
+    PENDING.fetch_add(1, Ordering::Relaxed);
+    producer.enqueue(record).map_err(|_| {
+        PENDING.fetch_sub(1, Ordering::Relaxed);
+    })
+}
+
+/// Opens the log output; `None` means stderr
+pub fn log_open(path: Option<&str>) -> Result<i32, Error> {
+    if let Some(path) = path {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(IntoRawFd::into_raw_fd)
+            .map_err(|e| Error::LogFile {
+                errno: e.raw_os_error().unwrap_or(0),
+            })
+    } else {
+        Ok(2)
+    }
+}
+
+/// Writes out all the records in the queue of `consumer` and returns how many there were
+pub unsafe fn log_drain(fd: i32, consumer: &mut LogConsumer) -> usize {
+    let mut written = 0;
+    while let Some(record) = consumer.dequeue() {
+        // NOTE logging is best effort; write errors are ignored
+        nc::write(fd, record.as_ptr() as usize, record.len()).ok();
+        written += 1;
+    }
+    PENDING.fetch_sub(written, Ordering::Release);
+
+    written
+}
+
+pub fn log_sleep() {
+    nc::nanosleep(&LOG_PERIOD, None).ok();
+}
+
+/// Waits until the writer thread has written out all the queued records, for 1 s at most
+///
+/// NOTE this must run after the producers have stopped
+pub fn log_flush() {
+    for _ in 0..FLUSH_PERIODS {
+        if PENDING.load(Ordering::Acquire) == 0 {
+            return;
+        }
+
+        log_sleep();
+    }
+}
@@ -0,0 +1,239 @@
+use core::fmt::Write as _;
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    os::unix::io::{FromRawFd, IntoRawFd},
+    time::Duration,
+};
+
+use crate::{introspect, stats, Error};
+
+/// Largest request the endpoint reads; the rest is ignored
+const MAX_REQUEST: usize = 4 * 1024;
+
+/// Listens on `addr` and returns the socket
+pub fn metrics_bind(addr: &str) -> Result<i32, Error> {
+    TcpListener::bind(addr)
+        .map(IntoRawFd::into_raw_fd)
+        .map_err(|e| Error::Metrics {
+            errno: e.raw_os_error().unwrap_or(0),
+        })
+}
+
+/// Body of the metrics thread; answers every `GET /metrics` on the socket `fd` with the metrics of
+/// the application in the Prometheus text format
+///
+/// Requests are served one at a time; the endpoint is meant to be scraped, not browsed
+pub fn serve_metrics(fd: i32) -> ! {
+    // NOTE(unsafe) `fd` was returned by `metrics_bind` and is owned by this thread
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+    loop {
+        // NOTE a failed connection only affects that client
+        if let Ok((stream, _)) = listener.accept() {
+            respond(stream).ok();
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    // a client that stops sending can't stall the endpoint
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+    let mut request = [0; MAX_REQUEST];
+    let mut len = 0;
+    while len < request.len() && !request[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut request[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+
+    let request = &request[..len];
+    let response = if request.starts_with(b"GET /metrics ") || request.starts_with(b"GET / ") {
+        let body = render();
+
+        format!(
+            "HTTP/1.0 200 OK\r\n\
+             Content-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\n\
+             \r\n\
+             {}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned()
+    };
+
+    stream.write_all(response.as_bytes())
+}
+
+// The metrics of the application, in the Prometheus text exposition format
+fn render() -> String {
+    let mut out = String::new();
+
+    let stats = stats::stats();
+    if !stats.is_empty() {
+        header(
+            &mut out,
+            "rtfm_task_runs_total",
+            "counter",
+            "Runs of the task",
+        );
+        for task in stats {
+            let _ = writeln!(
+                out,
+                "rtfm_task_runs_total{{task=\"{}\"}} {}",
+                task.name(),
+                task.count()
+            );
+        }
+
+        header(
+            &mut out,
+            "rtfm_task_execution_seconds",
+            "summary",
+            "CPU time spent by the task, excluding preemption",
+        );
+        for task in stats {
+            let _ = writeln!(
+                out,
+                "rtfm_task_execution_seconds_sum{{task=\"{}\"}} {}\n\
+                 rtfm_task_execution_seconds_count{{task=\"{0}\"}} {}",
+                task.name(),
+                task.total().as_secs_f64(),
+                task.count(),
+            );
+        }
+
+        header(
+            &mut out,
+            "rtfm_task_execution_seconds_max",
+            "gauge",
+            "Longest run of the task",
+        );
+        for task in stats {
+            let _ = writeln!(
+                out,
+                "rtfm_task_execution_seconds_max{{task=\"{}\"}} {}",
+                task.name(),
+                task.max().as_secs_f64(),
+            );
+        }
+
+        let counters: [(&str, &str, fn(&stats::TaskStats) -> Option<u64>); 3] = [
+            (
+                "rtfm_task_cycles_total",
+                "CPU cycles spent by the task",
+                |t| t.cycles(),
+            ),
+            (
+                "rtfm_task_cache_misses_total",
+                "Cache misses caused by the task",
+                |t| t.cache_misses(),
+            ),
+            (
+                "rtfm_task_context_switches_total",
+                "Context switches during the runs of the task",
+                |t| t.context_switches(),
+            ),
+        ];
+
+        for (name, help, value) in counters.iter() {
+            // NOTE counters that weren't opened are left out
+            if stats.iter().all(|task| value(task).is_none()) {
+                continue;
+            }
+
+            header(&mut out, name, "counter", help);
+            for task in stats {
+                if let Some(count) = value(task) {
+                    let _ = writeln!(out, "{}{{task=\"{}\"}} {}", name, task.name(), count);
+                }
+            }
+        }
+    }
+
+    let introspection = introspect::introspect();
+    let tasks = introspection.tasks();
+    if !tasks.is_empty() {
+        let gauges: [(&str, &str, fn(&introspect::TaskInfo) -> u64); 3] = [
+            (
+                "rtfm_task_queue_depth",
+                "Pending messages of the task",
+                |t| u64::from(t.depth()),
+            ),
+            (
+                "rtfm_task_queue_capacity",
+                "Message slots of the task",
+                |t| u64::from(t.capacity()),
+            ),
+            (
+                "rtfm_task_queue_high_water",
+                "Most messages of the task pending at once",
+                |t| u64::from(t.high_water_mark()),
+            ),
+        ];
+
+        for (name, help, value) in gauges.iter() {
+            header(&mut out, name, "gauge", help);
+            for task in tasks {
+                let _ = writeln!(
+                    out,
+                    "{}{{task=\"{}\",core=\"{}\",priority=\"{}\"}} {}",
+                    name,
+                    task.name(),
+                    task.core(),
+                    task.priority(),
+                    value(task),
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "rtfm_task_deadline_misses_total",
+            "counter",
+            "Runs of the task that completed after its deadline",
+        );
+        for task in tasks {
+            let _ = writeln!(
+                out,
+                "rtfm_task_deadline_misses_total{{task=\"{}\"}} {}",
+                task.name(),
+                task.deadline_misses(),
+            );
+        }
+    }
+
+    let timer_queues = introspection.timer_queues();
+    if !timer_queues.is_empty() {
+        header(
+            &mut out,
+            "rtfm_timer_queue_length",
+            "gauge",
+            "Scheduled entries in the timer queue",
+        );
+        for tq in timer_queues {
+            let clock = if tq.is_wall() {
+                "realtime"
+            } else {
+                "monotonic"
+            };
+            let _ = writeln!(
+                out,
+                "rtfm_timer_queue_length{{core=\"{}\",clock=\"{}\"}} {}",
+                tq.core(),
+                clock,
+                tq.len(),
+            );
+        }
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, ty: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, ty);
+}
@@ -0,0 +1,35 @@
+//! Mode changes: `#[app(modes = [..])]`
+//!
+//! The application is always in one of the modes it declares, the first one at start-up. Each
+//! task runs in the modes listed in its `#[task(modes = [..])]`, all of them by default, and a
+//! periodic task can have a different period in each. A task with `#[task(mode_switch = true)]`
+//! moves the application to another mode; in a critical section that masks every task it
+//!
+//! - re-arms the timers of the periodic tasks whose period changes, releasing them right away, and
+//!   disarms the ones of the tasks that the new mode disables
+//! - discards the entries of the timer queue of the disabled tasks, dropping their payloads
+//!
+//! From then on `spawn` and `schedule` fail with `SpawnError::Disabled` for the disabled tasks.
+//! Instances that had already been released before the switch still run.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Index of the current mode
+static MODE: AtomicU8 = AtomicU8::new(0);
+
+/// The index of the current mode
+pub fn current() -> u8 {
+    MODE.load(Ordering::Acquire)
+}
+
+/// Whether the current mode is in `modes`, a bit mask of mode indices
+pub fn enabled(modes: u32) -> bool {
+    modes & (1 << current()) != 0
+}
+
+/// Switches to the mode `mode` and returns the index of the previous one
+///
+/// NOTE must be called with all the task signals masked
+pub unsafe fn set(mode: u8) -> u8 {
+    MODE.swap(mode, Ordering::AcqRel)
+}
@@ -0,0 +1,191 @@
+//! Per-core resources: `#[per_core]`
+//!
+//! Each core gets its own instance of the resource. The tasks of a core only `lock` out the other
+//! tasks of the same core that share the resource, and at the ceiling (e.g. when all of them run
+//! at the same priority) the `lock` costs nothing. The instances of the other cores can be read,
+//! e.g. by a monitoring task that adds them up, without stopping their writers: each instance is
+//! guarded by a sequence lock that the reader retries until it gets a consistent copy.
+
+use core::{
+    cell::UnsafeCell,
+    ops::Range,
+    ptr,
+    sync::atomic::{self, AtomicUsize, Ordering},
+};
+
+use rtfm_core::Mutex;
+
+use crate::export::{self, Priority};
+
+/// One instance of `T` per core
+pub struct PerCore<T: 'static> {
+    slots: &'static [PerCoreSlot<T>],
+}
+
+unsafe impl<T> Sync for PerCore<T> where T: Send + 'static {}
+
+impl<T: 'static> PerCore<T> {
+    #[doc(hidden)]
+    pub const fn new(slots: &'static [PerCoreSlot<T>]) -> Self {
+        PerCore { slots }
+    }
+
+    /// Number of instances, one per core
+    pub fn cores(&self) -> u8 {
+        self.slots.len() as u8
+    }
+
+    /// Returns a mutable reference to the instance of `core`; `init` gets one because it runs
+    /// before any task
+    pub fn get_mut(&mut self, core: u8) -> Option<&mut T> {
+        // NOTE(unsafe) `&mut self` rules out any other access
+        self.slots
+            .get(usize::from(core))
+            .map(|slot| unsafe { &mut *slot.data.get() })
+    }
+}
+
+/// The instance of a single core, on a cache line of its own
+#[doc(hidden)]
+#[repr(align(64))]
+pub struct PerCoreSlot<T> {
+    // odd while the owner core writes the instance
+    seq: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for PerCoreSlot<T> where T: Send {}
+
+impl<T> PerCoreSlot<T> {
+    pub const fn new(value: T) -> Self {
+        PerCoreSlot {
+            seq: AtomicUsize::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    // NOTE only the owner core writes, with the other tasks of the core locked out
+    unsafe fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+
+        let r = f(&mut *self.data.get());
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+
+        r
+    }
+
+    // NOTE this spins while the owner core writes so it must not be called from that core
+    fn read(&self) -> T
+    where
+        T: Copy,
+    {
+        loop {
+            let before = self.seq.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                atomic::spin_loop_hint();
+                continue;
+            }
+
+            // NOTE(unsafe) a torn copy is thrown away below
+            let value = unsafe { ptr::read_volatile(self.data.get()) };
+            atomic::fence(Ordering::Acquire);
+
+            if self.seq.load(Ordering::Relaxed) == before {
+                return value;
+            }
+        }
+    }
+}
+
+/// Proxy of a `PerCore` resource; what the tasks get in their `resources`
+///
+/// `lock` gives access to the instance of the core of the task
+pub struct PerCoreProxy<'a, T: 'static> {
+    per_core: &'a PerCore<T>,
+    core: u8,
+    name: &'static str,
+    priority: &'a Priority,
+    ceiling: u8,
+    range: Range<u8>,
+}
+
+impl<'a, T: 'static> PerCoreProxy<'a, T> {
+    /// NOTE `ceiling` is the highest priority of the tasks of `core` that share the resource
+    #[doc(hidden)]
+    pub unsafe fn new(
+        per_core: &'a PerCore<T>,
+        core: u8,
+        name: &'static str,
+        priority: &'a Priority,
+        ceiling: u8,
+        range: Range<u8>,
+    ) -> Self {
+        PerCoreProxy {
+            per_core,
+            core,
+            name,
+            priority,
+            ceiling,
+            range,
+        }
+    }
+
+    /// Number of instances, one per core
+    pub fn cores(&self) -> u8 {
+        self.per_core.cores()
+    }
+
+    /// Returns a copy of the instance of `core`; `None` if there's no such core
+    ///
+    /// The instance of the core of the task is read under `lock`
+    pub fn read(&mut self, core: u8) -> Option<T>
+    where
+        T: Copy,
+    {
+        if core == self.core {
+            Some(self.lock(|value| *value))
+        } else {
+            self.per_core
+                .slots
+                .get(usize::from(core))
+                .map(|slot| slot.read())
+        }
+    }
+
+    /// Folds a copy of the instance of every core, in core order, into an accumulator
+    pub fn fold<B>(&mut self, init: B, mut f: impl FnMut(B, T) -> B) -> B
+    where
+        T: Copy,
+    {
+        let mut acc = init;
+        for core in 0..self.cores() {
+            if let Some(value) = self.read(core) {
+                acc = f(acc, value);
+            }
+        }
+        acc
+    }
+}
+
+impl<'a, T: 'static> Mutex for PerCoreProxy<'a, T> {
+    type T = T;
+
+    fn lock<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        let slot = &self.per_core.slots[usize::from(self.core)];
+
+        unsafe {
+            export::lock(
+                &mut (),
+                self.name,
+                &[],
+                self.priority,
+                self.ceiling,
+                self.range.clone(),
+                |_| slot.write(f),
+            )
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Per-thread `perf_event_open` counters
+
+use core::{
+    cell::Cell,
+    mem,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Number of counted events
+pub const EVENTS: usize = 3;
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+const PERF_COUNT_SW_CONTEXT_SWITCHES: u64 = 3;
+const PERF_FORMAT_GROUP: u64 = 1 << 3;
+const PERF_FLAG_FD_CLOEXEC: usize = 1 << 3;
+
+// bits of `Attr.flags`
+const EXCLUDE_KERNEL: u64 = 1 << 5;
+const EXCLUDE_HV: u64 = 1 << 6;
+
+/// The counted events, in the order they are added to the group: cycles, cache misses and context
+/// switches
+const CONFIGS: [(u32, u64); EVENTS] = [
+    (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES),
+    (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CACHE_MISSES),
+    (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CONTEXT_SWITCHES),
+];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // `None` until the thread reads the counters for the first time
+    static GROUP: Cell<Option<Group>> = Cell::new(None);
+}
+
+/// `struct perf_event_attr`, first published version (`PERF_ATTR_SIZE_VER0`)
+#[derive(Default)]
+#[repr(C)]
+struct Attr {
+    ty: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+/// The counters of a thread
+#[derive(Clone, Copy)]
+struct Group {
+    // leader of the group; `-1` if no counter could be opened
+    fd: i32,
+    opened: [bool; EVENTS],
+}
+
+/// Turns the counters on and opens those of the calling thread
+///
+/// The counters that can't be opened are reported on stderr; they read as `None` from then on
+pub fn init_perf() {
+    ENABLED.store(true, Ordering::Relaxed);
+
+    let group = open();
+    GROUP.with(|g| g.set(Some(group)));
+
+    let names = ["cycles", "cache-misses", "context-switches"];
+    for (name, opened) in names.iter().zip(group.opened.iter()) {
+        if !opened {
+            eprintln!(
+                "warning: couldn't open the `{}` perf counter; check \
+                 `/proc/sys/kernel/perf_event_paranoid`",
+                name
+            );
+        }
+    }
+}
+
+/// Reads the counters of the calling thread; all `None` unless `init_perf` has been called
+///
+/// NOTE async-signal-safe; the first call on a thread opens its counters, which takes a few system
+/// calls
+pub fn read() -> [Option<u64>; EVENTS] {
+    let mut counts = [None; EVENTS];
+
+    if !ENABLED.load(Ordering::Relaxed) {
+        return counts;
+    }
+
+    let group = GROUP.with(|g| {
+        g.get().unwrap_or_else(|| {
+            let group = open();
+            g.set(Some(group));
+            group
+        })
+    });
+
+    if group.fd < 0 {
+        return counts;
+    }
+
+    // `PERF_FORMAT_GROUP` layout: the number of counters followed by their values
+    let mut buf = [0u64; 1 + EVENTS];
+    if nc::read(group.fd, buf.as_mut_ptr() as usize, mem::size_of_val(&buf)).is_err() {
+        return counts;
+    }
+
+    let mut values = buf[1..].iter();
+    for (count, opened) in counts.iter_mut().zip(group.opened.iter()) {
+        if *opened {
+            *count = values.next().cloned();
+        }
+    }
+
+    counts
+}
+
+fn open() -> Group {
+    let mut group = Group {
+        fd: -1,
+        opened: [false; EVENTS],
+    };
+
+    for (opened, &(ty, config)) in group.opened.iter_mut().zip(CONFIGS.iter()) {
+        // count the kernel too, so the system calls of the tasks are included, if allowed.
+        // Context switches only happen in the kernel so there's no point in counting them without
+        let fd = match event_open(ty, config, false, group.fd) {
+            Err(nc::EACCES) if ty == PERF_TYPE_HARDWARE => event_open(ty, config, true, group.fd),
+            fd => fd,
+        };
+
+        if let Ok(fd) = fd {
+            if group.fd < 0 {
+                group.fd = fd;
+            }
+
+            *opened = true;
+        }
+    }
+
+    group
+}
+
+fn event_open(ty: u32, config: u64, exclude_kernel: bool, group_fd: i32) -> Result<i32, i32> {
+    let attr = Attr {
+        ty,
+        size: mem::size_of::<Attr>() as u32,
+        config,
+        read_format: PERF_FORMAT_GROUP,
+        flags: if exclude_kernel {
+            EXCLUDE_KERNEL | EXCLUDE_HV
+        } else {
+            EXCLUDE_HV
+        },
+        ..Attr::default()
+    };
+
+    // NOTE(unsafe) `attr` outlives the call; `pid = 0, cpu = -1` counts the calling thread on any
+    // CPU
+    unsafe {
+        nc::syscalls::syscall5(
+            nc::SYS_PERF_EVENT_OPEN,
+            &attr as *const Attr as usize,
+            0,
+            -1_isize as usize,
+            group_fd as isize as usize,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+        .map(|fd| fd as i32)
+    }
+}
@@ -0,0 +1,289 @@
+//! Fixed-size block pools for zero-copy message passing
+//!
+//! A message is copied into the message slot of its task by `spawn` and out of it by the
+//! dispatcher, which is a lot of copying for a video frame or an audio buffer. A `Box` from a pool
+//! is a single pointer, so a task that takes a `Box` as its input only gets the pointer copied;
+//! the buffer stays in the block it was written to. Dropping the `Box` returns the block to its
+//! pool.
+//!
+//! Declare a pool with `rtfm::pool!(NAME: Type)` and give it memory, once, from `init` with
+//! `NAME::grow`. `Box::new` and `drop` are lock-free and make no system calls, so any task, at
+//! any priority and on any core, can allocate and free blocks.
+//!
+//! A pool declared with `rtfm::pool!(NAME: Type, cache = N)` also keeps up to `N` free blocks per
+//! CPU. On x86_64 these per-CPU stacks are updated with restartable sequences (see `rseq`), so a
+//! task that frees and then allocates blocks on the same CPU doesn't touch the shared free list
+//! at all. The blocks cached by one CPU can't be allocated on another one, so grow such a pool by
+//! `N` extra blocks per CPU that frees blocks.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::rseq::PerCpuStacks;
+
+// NOTE user-space addresses fit in 48 bits on the 64-bit targets; the bits above the address hold
+// a tag that every `push` increments so that a `pop` that got preempted notices that the free list
+// changed even if the same block is at its head again (the ABA problem)
+#[cfg(target_pointer_width = "64")]
+const ADDRESS_BITS: u32 = 48;
+#[cfg(target_pointer_width = "32")]
+const ADDRESS_BITS: u32 = 32;
+
+const ADDRESS_MASK: u64 = (1 << ADDRESS_BITS) - 1;
+
+// NOTE `repr(C)` puts `next` in the first word of the block, where the per-CPU stacks expect it
+#[repr(C)]
+struct Node<T> {
+    // address of the next free block; `0` if this is the last one
+    next: AtomicUsize,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A lock-free pool of blocks that can hold a `T` each
+pub struct Pool<T> {
+    // tagged address of the first free block
+    head: AtomicU64,
+    cache: PerCpuStacks,
+    _data: PhantomData<T>,
+}
+
+unsafe impl<T> Sync for Pool<T> where T: Send {}
+
+impl<T> Pool<T> {
+    /// An empty pool
+    pub const fn new() -> Self {
+        Self::with_cache(0)
+    }
+
+    /// An empty pool that keeps up to `blocks` free blocks per CPU
+    pub const fn with_cache(blocks: u16) -> Self {
+        Pool {
+            head: AtomicU64::new(0),
+            cache: PerCpuStacks::new(blocks),
+            _data: PhantomData,
+        }
+    }
+
+    /// Splits `memory` into blocks and adds them to the pool; returns the number of blocks
+    ///
+    /// Each block takes the size of `T` plus a pointer, rounded up to the alignment of `T`
+    pub fn grow(&self, memory: &'static mut [u8]) -> usize {
+        let size = mem::size_of::<Node<T>>();
+        let align = mem::align_of::<Node<T>>();
+
+        let end = memory.as_mut_ptr() as usize + memory.len();
+        let mut block = (memory.as_mut_ptr() as usize + align - 1) & !(align - 1);
+        let mut blocks = 0;
+        while block + size <= end {
+            let node = block as *mut Node<T>;
+
+            unsafe {
+                ptr::write(
+                    node,
+                    Node {
+                        next: AtomicUsize::new(0),
+                        data: UnsafeCell::new(MaybeUninit::uninit()),
+                    },
+                );
+
+                self.push(NonNull::new_unchecked(node));
+            }
+
+            blocks += 1;
+            block += size;
+        }
+
+        blocks
+    }
+
+    fn alloc(&self) -> Option<NonNull<Node<T>>> {
+        match self.cache.pop() {
+            Some(block) => NonNull::new(block as *mut Node<T>),
+            None => self.pop(),
+        }
+    }
+
+    fn free(&self, node: NonNull<Node<T>>) {
+        // NOTE(unsafe) the block is free and `next` is its first word
+        if !unsafe { self.cache.push(node.as_ptr() as usize) } {
+            self.push(node)
+        }
+    }
+
+    fn pop(&self) -> Option<NonNull<Node<T>>> {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            let node = NonNull::new((head & ADDRESS_MASK) as usize as *mut Node<T>)?;
+
+            // NOTE the block may have been taken, and be in use, by now; then its `next` is stale
+            // but the tag of `head` has changed too so the exchange fails
+            let next = unsafe { node.as_ref() }.next.load(Ordering::Relaxed) as u64;
+            match self.head.compare_exchange_weak(
+                head,
+                next | (head & !ADDRESS_MASK),
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(node),
+                Err(now) => head = now,
+            }
+        }
+    }
+
+    fn push(&self, node: NonNull<Node<T>>) {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { node.as_ref() }
+                .next
+                .store((head & ADDRESS_MASK) as usize, Ordering::Relaxed);
+
+            let tag = (head >> ADDRESS_BITS).wrapping_add(1) << ADDRESS_BITS;
+            match self.head.compare_exchange_weak(
+                head,
+                node.as_ptr() as usize as u64 | tag,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(now) => head = now,
+            }
+        }
+    }
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A pool that lives in a `static`; declare one with `rtfm::pool!`
+pub trait Singleton: Sized + 'static {
+    /// What a block of the pool holds
+    type Data: 'static;
+
+    /// The pool
+    fn pool() -> &'static Pool<Self::Data>;
+
+    /// Splits `memory` into blocks and adds them to the pool; returns the number of blocks
+    fn grow(memory: &'static mut [u8]) -> usize {
+        Self::pool().grow(memory)
+    }
+}
+
+/// An owning pointer to a block of the pool `P`; its `Drop` returns the block to the pool
+pub struct Box<P>
+where
+    P: Singleton,
+{
+    node: NonNull<Node<P::Data>>,
+    _pool: PhantomData<P>,
+}
+
+unsafe impl<P> Send for Box<P>
+where
+    P: Singleton,
+    P::Data: Send,
+{
+}
+
+unsafe impl<P> Sync for Box<P>
+where
+    P: Singleton,
+    P::Data: Sync,
+{
+}
+
+impl<P> Box<P>
+where
+    P: Singleton,
+{
+    /// Moves `value` into a free block of the pool; hands `value` back if there's none
+    pub fn new(value: P::Data) -> Result<Self, P::Data> {
+        if let Some(node) = P::pool().alloc() {
+            unsafe { (*node.as_ref().data.get()).as_mut_ptr().write(value) }
+
+            Ok(Box {
+                node,
+                _pool: PhantomData,
+            })
+        } else {
+            Err(value)
+        }
+    }
+}
+
+impl<P> Deref for Box<P>
+where
+    P: Singleton,
+{
+    type Target = P::Data;
+
+    fn deref(&self) -> &P::Data {
+        unsafe { &*(*self.node.as_ref().data.get()).as_ptr() }
+    }
+}
+
+impl<P> DerefMut for Box<P>
+where
+    P: Singleton,
+{
+    fn deref_mut(&mut self) -> &mut P::Data {
+        unsafe { &mut *(*self.node.as_ref().data.get()).as_mut_ptr() }
+    }
+}
+
+impl<P> Drop for Box<P>
+where
+    P: Singleton,
+{
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place((*self.node.as_ref().data.get()).as_mut_ptr()) }
+
+        P::pool().free(self.node)
+    }
+}
+
+impl<P> fmt::Debug for Box<P>
+where
+    P: Singleton,
+    P::Data: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        <P::Data as fmt::Debug>::fmt(&**self, f)
+    }
+}
+
+/// Declares a pool of `$ty` blocks named `$name`; see `rtfm::pool`
+///
+/// `$name` is a zero-sized type that implements `rtfm::pool::Singleton`. With `cache = N` the pool
+/// keeps up to `N` free blocks per CPU
+#[macro_export]
+macro_rules! pool {
+    ($(#[$attr:meta])* $vis:vis $name:ident: $ty:ty) => {
+        $crate::pool!($(#[$attr])* $vis $name: $ty, cache = 0);
+    };
+
+    ($(#[$attr:meta])* $vis:vis $name:ident: $ty:ty, cache = $blocks:expr) => {
+        $(#[$attr])*
+        $vis struct $name;
+
+        impl $crate::pool::Singleton for $name {
+            type Data = $ty;
+
+            fn pool() -> &'static $crate::pool::Pool<$ty> {
+                static POOL: $crate::pool::Pool<$ty> =
+                    $crate::pool::Pool::with_cache($blocks);
+
+                &POOL
+            }
+        }
+    };
+}
@@ -0,0 +1,218 @@
+use core::{cmp, fmt};
+use std::fs;
+
+use nc::{rlimit_t, RLIMIT_MEMLOCK, RLIMIT_RTPRIO, RLIMIT_SIGPENDING};
+
+#[cfg(target_os = "android")]
+use crate::android;
+use crate::{
+    export,
+    kernel::{self, Preemption},
+    throttling,
+};
+
+/// `CAP_IPC_LOCK`; see `man 7 capabilities`
+const CAP_IPC_LOCK: u32 = 14;
+
+/// `CAP_SYS_NICE`
+const CAP_SYS_NICE: u32 = 23;
+
+/// `RLIM_INFINITY`
+const RLIM_INFINITY: u64 = !0;
+
+/// Gives the process the capabilities it needs; a service gets them from its `.rc` file instead
+#[cfg(not(target_os = "android"))]
+const FIX_CAPABILITIES: &str = "sudo setcap cap_sys_nice,cap_ipc_lock+ep $binary";
+#[cfg(target_os = "android")]
+const FIX_CAPABILITIES: &str = android::FIX_CAPABILITIES;
+
+/// Lifts `RLIMIT_MEMLOCK`
+#[cfg(not(target_os = "android"))]
+const FIX_MEMLOCK: &str = "ulimit -l unlimited";
+#[cfg(target_os = "android")]
+const FIX_MEMLOCK: &str = android::FIX_MEMLOCK;
+
+/// A problem with the environment that will keep the runtime from starting or from meeting its
+/// timing requirements
+#[derive(Clone, Debug)]
+pub struct Problem {
+    what: String,
+    fix: &'static str,
+}
+
+impl Problem {
+    /// What's wrong
+    pub fn what(&self) -> &str {
+        &self.what
+    }
+
+    /// The command, or the kernel, that fixes it
+    pub fn fix(&self) -> &'static str {
+        self.fix
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}; fix it with `{}`", self.what, self.fix)
+    }
+}
+
+/// Checks that this process can run a real-time application
+///
+/// This looks at the capabilities of the process, its `RLIMIT_RTPRIO` and `RLIMIT_MEMLOCK` limits,
+/// the real-time throttling settings and the preemption model of the kernel, and returns all the
+/// problems found. The generated `main` runs the same checks, and prints the problems, before it
+/// starts the runtime; it also checks the `nohz_full` CPUs against the CPUs of the cores
+pub fn preflight() -> Vec<Problem> {
+    check(1, 1)
+}
+
+/// Like `preflight` but checks that `SCHED_FIFO` priorities up to `rtprio` can be used and, if
+/// any CPU is tickless, that the CPUs of all the `cores` but #0 are too
+pub(crate) fn check(rtprio: u8, cores: u8) -> Vec<Problem> {
+    let mut problems = vec![];
+    let caps = effective_caps();
+
+    if caps & (1 << CAP_SYS_NICE) == 0 {
+        let limit = rlimit(RLIMIT_RTPRIO);
+        if limit.map(|limit| limit < u64::from(rtprio)).unwrap_or(true) {
+            let limit = limit.map(|limit| limit.to_string());
+
+            problems.push(Problem {
+                what: format!(
+                    "the process lacks `CAP_SYS_NICE` and its `RLIMIT_RTPRIO` ({}) is lower \
+                     than the `SCHED_FIFO` priority it needs ({})",
+                    limit.as_ref().map(|s| &s[..]).unwrap_or("unknown"),
+                    rtprio
+                ),
+                fix: FIX_CAPABILITIES,
+            });
+        }
+    }
+
+    if caps & (1 << CAP_IPC_LOCK) == 0 && rlimit(RLIMIT_MEMLOCK) != Some(RLIM_INFINITY) {
+        problems.push(Problem {
+            what: "the process lacks `CAP_IPC_LOCK` and its `RLIMIT_MEMLOCK` is limited so it \
+                   can't lock all its memory"
+                .into(),
+            fix: FIX_MEMLOCK,
+        });
+    }
+
+    match throttling::read(throttling::RUNTIME) {
+        Some(-1) | None => {}
+        Some(runtime) => {
+            let period = throttling::read(throttling::PERIOD).unwrap_or(1_000_000);
+
+            problems.push(Problem {
+                what: format!(
+                    "real-time throttling is enabled: real-time threads are stopped for {} us \
+                     every {} us",
+                    period - runtime,
+                    period
+                ),
+                fix: "sudo sysctl -w kernel.sched_rt_runtime_us=-1",
+            });
+        }
+    }
+
+    let config = kernel::kernel_config();
+    match config.preemption {
+        Some(Preemption::Cooperative) => problems.push(Problem {
+            what: "the kernel is not preemptible (`PREEMPT_NONE` or `PREEMPT_VOLUNTARY`) so a \
+                   system call of another process can delay the tasks by milliseconds"
+                .into(),
+            fix: if config.dynamic {
+                "echo full | sudo tee /sys/kernel/debug/sched/preempt"
+            } else {
+                "a kernel built with CONFIG_PREEMPT_RT=y"
+            },
+        }),
+        Some(Preemption::Lazy) | Some(Preemption::Full) => problems.push(Problem {
+            what: "the kernel is not `PREEMPT_RT` so interrupt handlers and spinlocks can delay \
+                   the tasks by hundreds of microseconds"
+                .into(),
+            fix: "a kernel built with CONFIG_PREEMPT_RT=y",
+        }),
+        Some(Preemption::Rt) | None => {}
+    }
+
+    // NOTE core #0 also runs the housekeeping threads of the application so it doesn't need to be
+    // tickless
+    if !config.nohz_full.is_empty() {
+        for (core, cpu) in (1..cores).filter_map(|core| Some((core, export::core_cpu(core)?))) {
+            if !config.is_tickless(cpu) {
+                problems.push(Problem {
+                    what: format!(
+                        "core #{} runs on CPU {}, which is not in `nohz_full` so the scheduler \
+                         tick keeps interrupting its tasks",
+                        core, cpu
+                    ),
+                    fix: "add the CPUs of the cores to nohz_full= on the kernel command line",
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+/// Raises the soft `RLIMIT_RTPRIO` and `RLIMIT_MEMLOCK` limits to what the application needs,
+/// `rtprio` and unlimited respectively, and `RLIMIT_SIGPENDING` as far as possible
+///
+/// `RLIMIT_SIGPENDING` counts the pending signals of all the processes of the user, and every
+/// message in flight is a pending signal; once it's reached `spawn` returns
+/// `SpawnError::SignalQueueFull`
+///
+/// Unprivileged processes can raise their soft limits up to their hard limits; a process with
+/// `CAP_SYS_RESOURCE` can raise the hard limits too. Whatever can't be raised is reported by
+/// `check`
+pub(crate) fn raise_limits(rtprio: u8) {
+    raise(RLIMIT_RTPRIO, u64::from(rtprio));
+    raise(RLIMIT_MEMLOCK, RLIM_INFINITY);
+    raise(RLIMIT_SIGPENDING, RLIM_INFINITY);
+}
+
+// Raises the soft limit of `resource` to `wanted`, or as close to it as possible
+fn raise(resource: u32, wanted: u64) {
+    let mut limit = rlimit_t::default();
+    if nc::getrlimit(resource, &mut limit).is_err() || limit.rlim_cur as u64 >= wanted {
+        return;
+    }
+
+    let hard = limit.rlim_max as u64;
+
+    // try to raise the hard limit as well first; that only works with `CAP_SYS_RESOURCE`
+    for &hard in &[cmp::max(hard, wanted), hard] {
+        let limit = rlimit_t {
+            rlim_cur: cmp::min(wanted, hard) as _,
+            rlim_max: hard as _,
+        };
+
+        if nc::setrlimit(resource, &limit).is_ok() {
+            return;
+        }
+    }
+}
+
+// Effective capability set of this process; `/proc/self/status` has it as `CapEff: <hex>`
+fn effective_caps() -> u64 {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find(|line| line.starts_with("CapEff:"))
+                .and_then(|line| u64::from_str_radix(line["CapEff:".len()..].trim(), 16).ok())
+        })
+        .unwrap_or(0)
+}
+
+// Soft limit of `resource`
+fn rlimit(resource: u32) -> Option<u64> {
+    let mut limit = rlimit_t::default();
+    nc::getrlimit(resource, &mut limit).ok()?;
+
+    Some(limit.rlim_cur as u64)
+}
@@ -0,0 +1,53 @@
+//! Dropping root once the runtime has started: `RuntimeConfig::drop_privileges`
+//!
+//! The runtime needs its privileges (`CAP_SYS_NICE`, `CAP_IPC_LOCK`, root for the sysfs and procfs
+//! settings) to start: to lock the memory, create the timers and stacks and move the threads to
+//! `SCHED_FIFO`. Right before the tasks are enabled each process of the application switches to
+//! the given user and group, which leaves it without capabilities.
+//!
+//! What was set up keeps working: the threads keep their policies and priorities and the memory
+//! stays locked. `RLIMIT_RTPRIO` and `RLIMIT_MEMLOCK`, which `raise_limits` raised, still let the
+//! process change priorities up to the highest one of the application and lock new memory. What
+//! needs the privileges again fails with `EPERM`, e.g. `set_core_cpu` onto a shielded CPU.
+
+use std::io;
+
+use cty::c_int;
+
+use crate::{sim, Error};
+
+// the user and group to switch to; only written at start-up, before the processes of the cores
+// are forked, so they inherit it
+static mut RUN_AS: Option<(u32, u32)> = None;
+
+/// Makes `drop` switch to `run_as`
+pub(crate) unsafe fn set(run_as: Option<(u32, u32)>) {
+    RUN_AS = run_as;
+}
+
+/// Switches every thread of this process to the user and group of
+/// `RuntimeConfig::drop_privileges`, if any
+///
+/// NOTE the system calls only change the credentials of the calling thread; the wrappers of the C
+/// library change those of every thread of the process
+pub unsafe fn drop() -> Result<(), Error> {
+    let (uid, gid) = match RUN_AS {
+        Some(ids) if !sim::active() => ids,
+        _ => return Ok(()),
+    };
+
+    // the supplementary groups and the group first: only root can change them
+    if setgroups(0, &gid) != 0 || setresgid(gid, gid, gid) != 0 || setresuid(uid, uid, uid) != 0 {
+        return Err(Error::Privileges {
+            errno: io::Error::last_os_error().raw_os_error().unwrap_or(0),
+        });
+    }
+
+    Ok(())
+}
+
+extern "C" {
+    fn setgroups(size: usize, list: *const u32) -> c_int;
+    fn setresgid(rgid: u32, egid: u32, sgid: u32) -> c_int;
+    fn setresuid(ruid: u32, euid: u32, suid: u32) -> c_int;
+}
@@ -0,0 +1,137 @@
+//! Multi-process applications: `#[app(processes = true)]`
+//!
+//! Every core but #0 runs in a process of its own, forked from the main process before `init`,
+//! instead of in a thread. A core that crashes only takes its own process down: the other cores
+//! keep running and their `spawn`s to the dead core fail. The processes of the cores are killed
+//! when the main process dies.
+//!
+//! What the cores share lives in `Shared` statics. Each of them fills whole pages, which `share`
+//! moves to a `memfd` mapping before the first `fork`, so all the processes keep seeing the same
+//! memory at the same address: the message slots of the tasks that other cores spawn, the thread
+//! IDs and barriers of the cores and the `pi_futex` resources. The locks of those resources are
+//! robust; see `PiFutex`.
+
+use core::{
+    mem::size_of,
+    ops::{Deref, DerefMut},
+    slice,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::{fs::File, io::Write, os::unix::io::FromRawFd};
+
+use cty::{c_char, c_int, c_uint, c_ulong};
+use nc::pid_t;
+
+use crate::Error;
+
+/// Whether the cores run in processes of their own
+static PROCESSES: AtomicBool = AtomicBool::new(false);
+
+/// Makes the futexes of the runtime work across processes
+pub unsafe fn use_processes() {
+    PROCESSES.store(true, Ordering::Relaxed)
+}
+
+pub(crate) fn processes() -> bool {
+    PROCESSES.load(Ordering::Relaxed)
+}
+
+/// A static that the processes of the cores share
+///
+/// NOTE the alignment rounds the size up to whole pages, so no other static shares them
+#[repr(C, align(4096))]
+pub struct Shared<T> {
+    value: T,
+}
+
+impl<T> Shared<T> {
+    pub const fn new(value: T) -> Self {
+        Shared { value }
+    }
+
+    /// Moves the pages of the static, with their current contents, to a shared mapping
+    ///
+    /// NOTE this must be called before the first `fork` and before any other thread uses the
+    /// static
+    pub unsafe fn share(&self) -> Result<(), Error> {
+        let addr = self as *const Self as usize;
+        let len = size_of::<Self>();
+
+        let fd = memfd_create(b"rtfm-shared\0".as_ptr() as *const c_char, MFD_CLOEXEC);
+        if fd < 0 {
+            return Err(Error::Process { errno: errno() });
+        }
+
+        // NOTE the mapping outlives the file descriptor
+        let mut file = File::from_raw_fd(fd);
+        file.write_all(slice::from_raw_parts(addr as *const u8, len))
+            .map_err(|e| Error::Process {
+                errno: e.raw_os_error().unwrap_or(0),
+            })?;
+
+        nc::mmap(
+            addr,
+            len,
+            nc::PROT_READ | nc::PROT_WRITE,
+            nc::MAP_SHARED | nc::MAP_FIXED,
+            fd,
+            0,
+        )
+        .map(drop)
+        .map_err(|errno| Error::Process { errno })
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Shared<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+/// Forks the process of a core, which runs `child`, and returns its process ID
+///
+/// NOTE the child inherits the thread-local state of the calling thread, so this must be called
+/// before the calling thread runs any task
+pub unsafe fn fork(child: extern "C" fn() -> !) -> Result<pid_t, Error> {
+    let parent = nc::getpid();
+
+    match fork_() {
+        -1 => Err(Error::Process { errno: errno() }),
+        0 => {
+            // die with the main process, even if it died before this
+            prctl(PR_SET_PDEATHSIG, nc::SIGKILL as c_ulong, 0, 0, 0);
+            if getppid() != parent {
+                nc::exit_group(1);
+            }
+
+            child()
+        }
+        pid => Ok(pid),
+    }
+}
+
+fn errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+/// `MFD_CLOEXEC`
+const MFD_CLOEXEC: c_uint = 1;
+
+/// `PR_SET_PDEATHSIG`
+const PR_SET_PDEATHSIG: c_int = 1;
+
+extern "C" {
+    fn memfd_create(name: *const c_char, flags: c_uint) -> c_int;
+    #[link_name = "fork"]
+    fn fork_() -> pid_t;
+    fn getppid() -> pid_t;
+    fn prctl(option: c_int, arg2: c_ulong, arg3: c_ulong, arg4: c_ulong, arg5: c_ulong) -> c_int;
+}
@@ -0,0 +1,451 @@
+//! Record and replay of the dispatch order
+//!
+//! With the `record` feature the runtime logs, into a ring buffer, every signal it enqueues, every
+//! signal handler it enters and leaves, and every `lock` that lowers the priority again. The
+//! recording is written to a file on shutdown, when a task panics and on `save_recording`.
+//!
+//! `#[app(replay = "rtfm.rec")]` re-executes a single-core application in the recorded order. The
+//! task signals stay blocked for the whole run and a driver on the main thread picks each pending
+//! signal with `sigtimedwait` and calls its handler itself. A dispatch that preempted a task is
+//! replayed at the same point of the preempted task: right after the enqueue, dispatch or unlock
+//! that preceded it in the recording. Tasks only share data inside `lock`s, so this reproduces
+//! every interleaving the tasks can observe.
+//!
+//! Without the feature every hook in this module is empty.
+
+/// Where the recording is written on shutdown and when a task panics
+pub const RECORDING: &str = "rtfm.rec";
+
+/// Number of events the ring buffer holds; older events are overwritten
+pub const CAPACITY: usize = 1 << 16;
+
+#[cfg(feature = "record")]
+mod imp {
+    use core::{
+        cell::Cell,
+        fmt,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+    use std::{fs, process};
+
+    use heapless::{consts, i, Vec as HVec};
+    use nc::siginfo_t;
+
+    use super::CAPACITY;
+    use crate::{
+        export::{self, SIGRTMIN},
+        time, Error,
+    };
+
+    const MAGIC: &[u8; 8] = b"RTFMREC1";
+    const HEADER: usize = 16;
+    const EVENT: usize = 20;
+
+    /// `value` of the signals that were not sent with `sigqueue`, e.g. by a POSIX timer
+    const NO_VALUE: u32 = u32::max_value();
+
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    #[repr(u8)]
+    enum Kind {
+        Enqueue,
+        Dispatch,
+        Complete,
+        Unlock,
+        // `init` has returned
+        Start,
+    }
+
+    impl Kind {
+        fn from(byte: u8) -> Option<Self> {
+            Some(match byte {
+                0 => Kind::Enqueue,
+                1 => Kind::Dispatch,
+                2 => Kind::Complete,
+                3 => Kind::Unlock,
+                4 => Kind::Start,
+                _ => return None,
+            })
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    struct Event {
+        // `CLOCK_MONOTONIC`
+        nanos: u64,
+        tid: i32,
+        // `sival` of `Enqueue` and `Dispatch`; ceiling of `Unlock`
+        value: u32,
+        kind: Kind,
+        // offset from `SIGRTMIN`
+        signo: u8,
+        // signal handlers the thread was nested in when the event happened
+        depth: u8,
+    }
+
+    impl fmt::Debug for Event {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.kind {
+                Kind::Enqueue | Kind::Dispatch if self.value != NO_VALUE => write!(
+                    f,
+                    "{:?} of signal {} (task {}, index {})",
+                    self.kind,
+                    self.signo,
+                    self.value >> 24,
+                    self.value as u16
+                ),
+                Kind::Enqueue | Kind::Dispatch | Kind::Complete => {
+                    write!(f, "{:?} of signal {}", self.kind, self.signo)
+                }
+                Kind::Unlock => write!(f, "Unlock from ceiling {}", self.value),
+                Kind::Start => f.write_str("Start"),
+            }
+        }
+    }
+
+    static HEAD: AtomicUsize = AtomicUsize::new(0);
+    static mut EVENTS: [Event; CAPACITY] = [Event {
+        nanos: 0,
+        tid: 0,
+        value: 0,
+        kind: Kind::Start,
+        signo: 0,
+        depth: 0,
+    }; CAPACITY];
+
+    thread_local! {
+        static DEPTH: Cell<u8> = Cell::new(0);
+    }
+
+    static REPLAYING: AtomicBool = AtomicBool::new(false);
+    // set once `init` has returned; from then on the replay runs the recorded dispatches
+    static DRIVING: AtomicBool = AtomicBool::new(false);
+    static CURSOR: AtomicUsize = AtomicUsize::new(0);
+    static mut REPLAY: &[Event] = &[];
+    // signals that `sigtimedwait` returned ahead of their turn
+    static mut STASH: HVec<siginfo_t, consts::U64> = HVec(i::Vec::new());
+
+    /// Logs the handler of the signal `signo` from its entry until it's dropped
+    pub struct Dispatched {
+        signo: u8,
+    }
+
+    impl Dispatched {
+        pub fn new(signo: u8, si: &siginfo_t) -> Self {
+            DEPTH.with(|depth| depth.set(depth.get() + 1));
+            hook(Kind::Dispatch, signo, value(si));
+
+            Dispatched { signo }
+        }
+    }
+
+    impl Drop for Dispatched {
+        fn drop(&mut self) {
+            hook(Kind::Complete, self.signo, NO_VALUE);
+            DEPTH.with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+
+    /// Sends, with `send`, the signal `signo` carrying `value`
+    pub fn record_enqueue<R>(signo: u8, value: usize, send: impl FnOnce() -> R) -> R {
+        if replaying() {
+            // the dispatches that follow in the recording may need this signal
+            let r = send();
+            hook(Kind::Enqueue, signo, value as u32);
+            r
+        } else {
+            // NOTE the signal may preempt us as soon as it's sent
+            hook(Kind::Enqueue, signo, value as u32);
+            send()
+        }
+    }
+
+    /// NOTE must be called *before* the signals are unblocked; see `record_enqueue`
+    pub fn record_unlock(ceiling: u8) {
+        hook(Kind::Unlock, 0, u32::from(ceiling))
+    }
+
+    /// NOTE must be called *before* the signals are unblocked; see `record_enqueue`
+    pub fn record_start() {
+        hook(Kind::Start, 0, 0)
+    }
+
+    /// Writes the recording to the file at `path`; returns the `errno` on failure
+    ///
+    /// If more than `CAPACITY` events were recorded only the newest ones are written
+    pub fn save_recording(path: &str) -> Result<(), i32> {
+        let head = HEAD.load(Ordering::Acquire);
+        let start = head.saturating_sub(CAPACITY);
+
+        let mut bytes = Vec::with_capacity(HEADER + (head - start) * EVENT);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&nc::getpid().to_ne_bytes());
+        bytes.extend_from_slice(&(start as u32).to_ne_bytes());
+
+        for i in start..head {
+            // NOTE(unsafe) best effort; an event that is being written may be torn
+            let event = unsafe { EVENTS[i % CAPACITY] };
+
+            bytes.extend_from_slice(&event.nanos.to_ne_bytes());
+            bytes.extend_from_slice(&event.tid.to_ne_bytes());
+            bytes.extend_from_slice(&event.value.to_ne_bytes());
+            bytes.extend_from_slice(&[event.kind as u8, event.signo, event.depth, 0]);
+        }
+
+        fs::write(path, bytes).map_err(|e| e.raw_os_error().unwrap_or(0))
+    }
+
+    /// Loads the recording at `path` and turns the replay on
+    pub unsafe fn replay_open(path: &str) -> Result<(), Error> {
+        let bytes = fs::read(path).map_err(|e| Error::Recording {
+            errno: e.raw_os_error().unwrap_or(0),
+        })?;
+        let invalid = Error::Recording { errno: nc::EINVAL };
+
+        // NOTE a recording whose oldest events were overwritten can't be replayed from the start
+        if bytes.len() < HEADER
+            || &bytes[..8] != MAGIC
+            || bytes[12..16] != [0; 4]
+            || (bytes.len() - HEADER) % EVENT != 0
+        {
+            return Err(invalid);
+        }
+
+        let pid = i32::from_ne_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+
+        let mut events = vec![];
+        let mut started = false;
+        for chunk in bytes[HEADER..].chunks(EVENT) {
+            let u32_at =
+                |i: usize| u32::from_ne_bytes([chunk[i], chunk[i + 1], chunk[i + 2], chunk[i + 3]]);
+
+            let mut nanos = [0; 8];
+            nanos.copy_from_slice(&chunk[..8]);
+            let event = Event {
+                nanos: u64::from_ne_bytes(nanos),
+                tid: u32_at(8) as i32,
+                value: u32_at(12),
+                kind: Kind::from(chunk[16]).ok_or(invalid)?,
+                signo: chunk[17],
+                depth: chunk[18],
+            };
+
+            // only the main thread is replayed, and `idle` doesn't run
+            if event.tid != pid || (started && event.depth == 0) {
+                continue;
+            }
+
+            started |= event.kind == Kind::Start;
+            events.push(event);
+        }
+
+        REPLAY = Box::leak(events.into_boxed_slice());
+        REPLAYING.store(true, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    pub fn replaying() -> bool {
+        REPLAYING.load(Ordering::Relaxed)
+    }
+
+    /// Runs the recorded dispatches, in order, in place of `idle`
+    pub unsafe fn replay() -> ! {
+        // NOTE `record_start` has consumed the events of `init` by now
+        DRIVING.store(true, Ordering::Relaxed);
+
+        while let Some(event) = peek() {
+            if event.kind != Kind::Dispatch {
+                diverged(Some(event), format_args!("the dispatch of a task"));
+            }
+
+            dispatch(event);
+        }
+
+        eprintln!("replay: all {} events replayed", REPLAY.len());
+        export::exit_group(0)
+    }
+
+    fn hook(kind: Kind, signo: u8, value: u32) {
+        if replaying() {
+            if nc::gettid() == nc::getpid() {
+                // NOTE(unsafe) only the main thread replays
+                unsafe { replay_step(kind, signo, value) }
+            }
+
+            return;
+        }
+
+        let ts = time::clock_gettime(nc::CLOCK_MONOTONIC);
+        let event = Event {
+            nanos: ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64,
+            tid: nc::gettid(),
+            value,
+            kind,
+            signo,
+            depth: DEPTH.with(|depth| depth.get()),
+        };
+
+        let i = HEAD.fetch_add(1, Ordering::AcqRel);
+        // NOTE(unsafe) each slot has a single writer until the buffer wraps around
+        unsafe { EVENTS[i % CAPACITY] = event }
+    }
+
+    unsafe fn replay_step(kind: Kind, signo: u8, value: u32) {
+        match peek() {
+            Some(event) if event.kind == kind && event.signo == signo && event.value == value => {
+                CURSOR.fetch_add(1, Ordering::Relaxed);
+            }
+            event => {
+                let did = Event {
+                    nanos: 0,
+                    tid: 0,
+                    value,
+                    kind,
+                    signo,
+                    depth: 0,
+                };
+
+                diverged(event, format_args!("{:?}", did))
+            }
+        }
+
+        // the dispatches recorded right after this event preempted the task that caused it
+        if kind != Kind::Complete && DRIVING.load(Ordering::Relaxed) {
+            while let Some(event) = peek() {
+                if event.kind != Kind::Dispatch {
+                    break;
+                }
+
+                dispatch(event);
+            }
+        }
+    }
+
+    unsafe fn peek() -> Option<Event> {
+        REPLAY.get(CURSOR.load(Ordering::Relaxed)).cloned()
+    }
+
+    // Waits for the signal of `event` and calls its handler
+    unsafe fn dispatch(event: Event) {
+        let handler = export::handler(event.signo)
+            .unwrap_or_else(|| diverged(Some(event), format_args!("no such signal handler")));
+
+        let mut si = if let Some(i) = STASH
+            .iter()
+            .position(|si| matches(si, event.signo, event.value))
+        {
+            STASH.swap_remove(i)
+        } else {
+            loop {
+                let si = export::sigwait(event.signo);
+                if matches(&si, event.signo, event.value) {
+                    break si;
+                }
+
+                // e.g. a timer that expired before a message that was sent earlier in the recording
+                if STASH.push(si).is_err() {
+                    diverged(Some(event), format_args!("too many signals out of order"));
+                }
+            }
+        };
+
+        handler(SIGRTMIN + i32::from(event.signo), &mut si, 0);
+    }
+
+    fn matches(si: &siginfo_t, signo: u8, expected: u32) -> bool {
+        si.siginfo.si_signo == SIGRTMIN + i32::from(signo) && value(si) == expected
+    }
+
+    fn value(si: &siginfo_t) -> u32 {
+        // NOTE(unsafe) the union is read according to `si_code`
+        unsafe {
+            if si.siginfo.si_code == nc::SI_QUEUE {
+                si.siginfo.sifields.rt.sigval.sival_ptr as u32
+            } else {
+                NO_VALUE
+            }
+        }
+    }
+
+    fn diverged(event: Option<Event>, did: fmt::Arguments<'_>) -> ! {
+        match event {
+            Some(event) => eprintln!(
+                "error: the replay diverged from the recording at event {}: the recording has \
+                 {:?}, the application did {}",
+                CURSOR.load(Ordering::Relaxed),
+                event,
+                did
+            ),
+            None => eprintln!(
+                "error: the replay ran past the end of the recording: the application did {}",
+                did
+            ),
+        }
+
+        process::abort()
+    }
+}
+
+#[cfg(not(feature = "record"))]
+mod imp {
+    use nc::siginfo_t;
+
+    use crate::Error;
+
+    pub struct Dispatched;
+
+    impl Dispatched {
+        #[inline(always)]
+        pub fn new(_: u8, _: &siginfo_t) -> Self {
+            Dispatched
+        }
+    }
+
+    #[inline(always)]
+    pub fn record_enqueue<R>(_: u8, _: usize, send: impl FnOnce() -> R) -> R {
+        send()
+    }
+
+    #[inline(always)]
+    pub fn record_unlock(_: u8) {}
+
+    #[inline(always)]
+    pub fn record_start() {}
+
+    /// Writes the recording to the file at `path`; returns the `errno` on failure
+    ///
+    /// Without the `record` feature there's no recording and this always fails with `ENOSYS`
+    pub fn save_recording(_: &str) -> Result<(), i32> {
+        Err(nc::ENOSYS)
+    }
+
+    pub unsafe fn replay_open(_: &str) -> Result<(), Error> {
+        Err(Error::Recording { errno: nc::ENOSYS })
+    }
+
+    #[inline(always)]
+    pub fn replaying() -> bool {
+        false
+    }
+
+    pub unsafe fn replay() -> ! {
+        unreachable!()
+    }
+}
+
+pub use imp::{
+    record_enqueue, record_start, record_unlock, replay, replay_open, replaying, save_recording,
+    Dispatched,
+};
+
+/// Saves the recording to `RECORDING`, if there's one; used on shutdown and when a task panics
+pub fn save_on_exit() {
+    if cfg!(feature = "record") && !replaying() {
+        if let Err(errno) = save_recording(RECORDING) {
+            eprintln!(
+                "warning: couldn't write the recording to `{}` (errno = {})",
+                RECORDING, errno
+            );
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Signal handler registration and the `rt_sigreturn` trampolines
+//!
+//! A signal handler returns to the address in the `sa_restorer` field of its `struct sigaction`,
+//! which has to make the `rt_sigreturn` system call to resume the interrupted context. The C
+//! library fills that field in with a trampoline of its own; the runtime registers its handlers
+//! with the raw system call so it ships one per architecture, `__restorer`:
+//!
+//! - x86_64 has no fallback: without `SA_RESTORER` the kernel fails to deliver the signal
+//! - aarch64 and armv7 would fall back to a trampoline in the vDSO or the signal page; the
+//!   runtime uses its own so that the handlers return the same way on every architecture
+//! - riscv64 has no `sa_restorer` field; the kernel always returns through its vDSO
+//! - 32-bit x86 falls back to the trampoline in the vDSO
+
+use core::{mem::size_of, ptr};
+
+use cty::{c_long, c_ulong};
+use nc::{sighandler_t, sigset_t};
+
+/// `SA_SIGINFO`
+const SA_SIGINFO: c_ulong = 0x0000_0004;
+
+/// `SA_ONSTACK`
+const SA_ONSTACK: c_ulong = 0x0800_0000;
+
+/// `SA_RESETHAND`
+const SA_RESETHAND: c_ulong = 0x8000_0000;
+
+/// `SA_RESTORER`
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm"))]
+const SA_RESTORER: c_ulong = 0x0400_0000;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+const SA_RESTORER: c_ulong = 0;
+
+#[cfg(target_arch = "x86_64")]
+const SYS_RT_SIGACTION: c_long = 13;
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+const SYS_RT_SIGACTION: c_long = 134;
+#[cfg(any(target_arch = "arm", target_arch = "x86"))]
+const SYS_RT_SIGACTION: c_long = 174;
+
+// `__NR_rt_sigreturn` is 15
+#[cfg(target_arch = "x86_64")]
+core::arch::global_asm!(
+    ".pushsection .text.__restorer, \"ax\"",
+    ".globl __restorer",
+    ".hidden __restorer",
+    ".type __restorer, %function",
+    "__restorer:",
+    "mov rax, 15",
+    "syscall",
+    ".size __restorer, . - __restorer",
+    ".popsection",
+);
+
+// `__NR_rt_sigreturn` is 139
+#[cfg(target_arch = "aarch64")]
+core::arch::global_asm!(
+    ".pushsection .text.__restorer, \"ax\"",
+    ".globl __restorer",
+    ".hidden __restorer",
+    ".type __restorer, %function",
+    "__restorer:",
+    "mov x8, #139",
+    "svc #0",
+    ".size __restorer, . - __restorer",
+    ".popsection",
+);
+
+// `__NR_rt_sigreturn` is 173; the handler returns with `bx lr` so an ARM (not Thumb) trampoline
+// works with either kind of handler
+#[cfg(target_arch = "arm")]
+core::arch::global_asm!(
+    ".pushsection .text.__restorer, \"ax\"",
+    ".arm",
+    ".globl __restorer",
+    ".hidden __restorer",
+    ".type __restorer, %function",
+    "__restorer:",
+    "mov r7, #173",
+    "svc #0",
+    ".size __restorer, . - __restorer",
+    ".popsection",
+);
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm"))]
+extern "C" {
+    fn __restorer() -> !;
+}
+
+/// `struct sigaction` of the kernel ABI, which is not the one of the C library
+#[repr(C)]
+struct Sigaction {
+    handler: sighandler_t,
+    flags: c_ulong,
+    #[cfg(not(target_arch = "riscv64"))]
+    restorer: Option<unsafe extern "C" fn() -> !>,
+    mask: sigset_t,
+}
+
+/// Makes `handler` the `SA_SIGINFO` handler of `signo`; it runs on the alternate signal stack of
+/// the thread, if it has one (see `sigaltstack`), with the signals in `mask` blocked
+pub(crate) unsafe fn sigaction(
+    signo: i32,
+    handler: sighandler_t,
+    mask: sigset_t,
+) -> Result<(), i32> {
+    install(signo, handler, mask, 0)
+}
+
+/// `sigaction` for a handler that runs at most once: the kernel restores the default action of
+/// `signo` as it enters `handler` (`SA_RESETHAND`)
+pub(crate) unsafe fn sigaction_once(
+    signo: i32,
+    handler: sighandler_t,
+    mask: sigset_t,
+) -> Result<(), i32> {
+    install(signo, handler, mask, SA_RESETHAND)
+}
+
+unsafe fn install(
+    signo: i32,
+    handler: sighandler_t,
+    mask: sigset_t,
+    flags: c_ulong,
+) -> Result<(), i32> {
+    let action = Sigaction {
+        handler,
+        flags: SA_SIGINFO | SA_ONSTACK | SA_RESTORER | flags,
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm"))]
+        restorer: Some(__restorer),
+        #[cfg(target_arch = "x86")]
+        restorer: None,
+        mask,
+    };
+
+    if syscall(
+        SYS_RT_SIGACTION,
+        c_long::from(signo),
+        &action as *const Sigaction,
+        ptr::null_mut::<Sigaction>(),
+        size_of::<sigset_t>(),
+    ) < 0
+    {
+        Err(std::io::Error::last_os_error().raw_os_error().unwrap_or(0))
+    } else {
+        Ok(())
+    }
+}
+
+extern "C" {
+    fn syscall(number: c_long, ...) -> c_long;
+}
@@ -0,0 +1,362 @@
+//! Per-CPU data on restartable sequences (`rseq`)
+//!
+//! Each thread registers an `rseq` area with the kernel, which keeps the number of the CPU that
+//! runs the thread in it. An update of per-CPU data reads that number, picks the slot of the CPU
+//! and writes the slot with a plain store, without the `lock` prefix of an atomic read-modify-write
+//! instruction. If the thread is preempted, migrated or interrupted by a signal before the store,
+//! the kernel moves it back to the start of the sequence, which then runs again from scratch.
+//!
+//! Only the x86_64 sequences are implemented. Elsewhere, on CPUs numbered `CPUS` or higher and in
+//! threads that couldn't register an area, the counters fall back to an atomic counter and the
+//! pool caches are skipped. glibc 2.35 and newer register an area for every thread themselves;
+//! run the application with `GLIBC_TUNABLES=glibc.pthread.rseq=0` to let the runtime use its own.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(target_arch = "x86_64")]
+use core::{arch::asm, cell::Cell, cell::UnsafeCell};
+
+/// Number of CPUs that get a slot of their own
+pub(crate) const CPUS: usize = 64;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+/// A counter that the tasks of any core can add to without atomic instructions
+///
+/// Each CPU adds to a slot of its own; `get` adds up the slots. A reader may see an `add` on one
+/// CPU before an earlier `add` on another CPU.
+pub struct PerCpuCounter {
+    cpus: [AtomicU64; CPUS],
+    // threads without `rseq` and CPUs without a slot add here
+    shared: AtomicU64,
+}
+
+impl PerCpuCounter {
+    /// A counter at zero
+    pub const fn new() -> Self {
+        PerCpuCounter {
+            cpus: [ZERO; CPUS],
+            shared: ZERO,
+        }
+    }
+
+    /// Adds `n` to the counter
+    pub fn add(&self, n: u64) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if let Some(rseq) = area() {
+                // NOTE(unsafe) `rseq` is the area of this thread
+                if unsafe { add(rseq, &self.cpus, n) } {
+                    return;
+                }
+            }
+        }
+
+        self.shared.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// The sum of all the `add`s
+    pub fn get(&self) -> u64 {
+        self.cpus
+            .iter()
+            .fold(self.shared.load(Ordering::Relaxed), |sum, cpu| {
+                sum.wrapping_add(cpu.load(Ordering::Relaxed))
+            })
+    }
+}
+
+impl Default for PerCpuCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-CPU stacks of free pool blocks
+///
+/// The slot of a CPU holds the address of the block at the top of its stack in the low 48 bits
+/// and the depth of the stack in the high 16 bits. The first word of a block holds the address of
+/// the next block.
+pub(crate) struct PerCpuStacks {
+    cpus: [AtomicU64; CPUS],
+    // largest depth of a stack; `0` disables the stacks
+    limit: u16,
+}
+
+impl PerCpuStacks {
+    pub(crate) const fn new(limit: u16) -> Self {
+        PerCpuStacks {
+            cpus: [ZERO; CPUS],
+            limit,
+        }
+    }
+
+    /// Pops a block off the stack of the current CPU; `None` if it's empty
+    pub(crate) fn pop(&self) -> Option<usize> {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if self.limit != 0 {
+                if let Some(rseq) = area() {
+                    // NOTE(unsafe) `rseq` is the area of this thread
+                    let block = unsafe { pop(rseq, &self.cpus) };
+                    if block != 0 {
+                        return Some(block);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Pushes `block` onto the stack of the current CPU; `false` if that stack is full
+    ///
+    /// NOTE `block` must be free and its first word must be writable as a `usize`
+    pub(crate) unsafe fn push(&self, block: usize) -> bool {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if self.limit != 0 {
+                if let Some(rseq) = area() {
+                    return push(rseq, &self.cpus, self.limit, block);
+                }
+            }
+        }
+
+        let _ = block;
+        false
+    }
+}
+
+// `struct rseq` of the kernel ABI
+#[cfg(target_arch = "x86_64")]
+#[repr(C, align(32))]
+struct Rseq {
+    cpu_id_start: u32,
+    cpu_id: u32,
+    // address of the `struct rseq_cs` of the running sequence
+    rseq_cs: u64,
+    flags: u32,
+}
+
+// the abort handlers are preceded by `ud1 RSEQ_SIG(%rip), %edi`, which ends in this signature
+#[cfg(target_arch = "x86_64")]
+const RSEQ_SIG: u32 = 0x5305_3053;
+
+#[cfg(target_arch = "x86_64")]
+const NR_RSEQ: usize = 334;
+
+#[cfg(target_arch = "x86_64")]
+const UNKNOWN: u8 = 0;
+#[cfg(target_arch = "x86_64")]
+const REGISTERED: u8 = 1;
+#[cfg(target_arch = "x86_64")]
+const UNAVAILABLE: u8 = 2;
+
+#[cfg(target_arch = "x86_64")]
+thread_local! {
+    static AREA: UnsafeCell<Rseq> = UnsafeCell::new(Rseq {
+        cpu_id_start: 0,
+        // `RSEQ_CPU_ID_UNINITIALIZED`
+        cpu_id: u32::max_value(),
+        rseq_cs: 0,
+        flags: 0,
+    });
+
+    static STATE: Cell<u8> = Cell::new(UNKNOWN);
+}
+
+// The `rseq` area of this thread; registers it on first use
+#[cfg(target_arch = "x86_64")]
+fn area() -> Option<*mut Rseq> {
+    let state = STATE.try_with(|state| state.get()).ok()?;
+    let area = AREA.try_with(|area| area.get()).ok()?;
+
+    match state {
+        REGISTERED => Some(area),
+        UNAVAILABLE => None,
+        _ => {
+            // NOTE(unsafe) the area lives as long as the thread; the kernel stops using it when
+            // the thread exits
+            let registered = unsafe { register(area) };
+            STATE
+                .try_with(|state| state.set(if registered { REGISTERED } else { UNAVAILABLE }))
+                .ok()?;
+
+            if registered {
+                Some(area)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// NOTE fails with `EBUSY` if glibc has registered an area for this thread
+#[cfg(target_arch = "x86_64")]
+unsafe fn register(area: *mut Rseq) -> bool {
+    let ret: isize;
+    asm!(
+        "syscall",
+        inlateout("rax") NR_RSEQ as isize => ret,
+        in("rdi") area,
+        in("rsi") core::mem::size_of::<Rseq>(),
+        in("rdx") 0,
+        in("r10") RSEQ_SIG,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack),
+    );
+
+    ret == 0
+}
+
+// Adds `n` to the slot of the current CPU; `false` if the CPU has no slot
+#[cfg(target_arch = "x86_64")]
+unsafe fn add(rseq: *mut Rseq, cpus: &[AtomicU64; CPUS], n: u64) -> bool {
+    let cpu: usize;
+    asm!(
+        // `struct rseq_cs`: version, flags, start, length of the sequence and abort handler
+        ".pushsection __rseq_cs, \"aw\"",
+        ".balign 32",
+        "3:",
+        ".long 0, 0",
+        ".quad 4f, (5f - 4f), 6f",
+        ".popsection",
+        ".pushsection __rseq_failure, \"ax\"",
+        ".byte 0x0f, 0xb9, 0x3d",
+        ".long {sig}",
+        "6:",
+        "jmp 2f",
+        ".popsection",
+        "2:",
+        "lea {tmp}, [rip + 3b]",
+        "mov qword ptr [{rseq} + 8], {tmp}",
+        "4:",
+        "mov {cpu:e}, dword ptr [{rseq} + 4]",
+        "cmp {cpu:e}, {cpus}",
+        "jae 5f",
+        // the commit
+        "add qword ptr [{slots} + {cpu} * 8], {n}",
+        "5:",
+        rseq = in(reg) rseq,
+        slots = in(reg) cpus.as_ptr(),
+        n = in(reg) n,
+        cpu = out(reg) cpu,
+        tmp = out(reg) _,
+        sig = const RSEQ_SIG,
+        cpus = const CPUS,
+        options(nostack),
+    );
+
+    cpu < CPUS
+}
+
+// Pops the top block off the stack of the current CPU; `0` if it's empty or the CPU has no stack
+#[cfg(target_arch = "x86_64")]
+unsafe fn pop(rseq: *mut Rseq, cpus: &[AtomicU64; CPUS]) -> usize {
+    let block: usize;
+    asm!(
+        ".pushsection __rseq_cs, \"aw\"",
+        ".balign 32",
+        "3:",
+        ".long 0, 0",
+        ".quad 4f, (5f - 4f), 6f",
+        ".popsection",
+        ".pushsection __rseq_failure, \"ax\"",
+        ".byte 0x0f, 0xb9, 0x3d",
+        ".long {sig}",
+        "6:",
+        "jmp 2f",
+        ".popsection",
+        "2:",
+        "lea {tmp}, [rip + 3b]",
+        "mov qword ptr [{rseq} + 8], {tmp}",
+        "4:",
+        "xor {block:e}, {block:e}",
+        "mov {cpu:e}, dword ptr [{rseq} + 4]",
+        "cmp {cpu:e}, {cpus}",
+        "jae 5f",
+        "mov {slot}, qword ptr [{slots} + {cpu} * 8]",
+        "mov {block}, {slot}",
+        "shl {block}, 16",
+        "shr {block}, 16",
+        "test {block}, {block}",
+        "jz 5f",
+        // next block, one less deep
+        "mov {tmp}, qword ptr [{block}]",
+        "shr {slot}, 48",
+        "sub {slot}, 1",
+        "shl {slot}, 48",
+        "or {slot}, {tmp}",
+        // the commit
+        "mov qword ptr [{slots} + {cpu} * 8], {slot}",
+        "5:",
+        rseq = in(reg) rseq,
+        slots = in(reg) cpus.as_ptr(),
+        block = out(reg) block,
+        cpu = out(reg) _,
+        slot = out(reg) _,
+        tmp = out(reg) _,
+        sig = const RSEQ_SIG,
+        cpus = const CPUS,
+        options(nostack),
+    );
+
+    block
+}
+
+// Pushes `block` onto the stack of the current CPU; `false` if it's full or the CPU has no stack
+#[cfg(target_arch = "x86_64")]
+unsafe fn push(rseq: *mut Rseq, cpus: &[AtomicU64; CPUS], limit: u16, block: usize) -> bool {
+    let pushed: usize;
+    asm!(
+        ".pushsection __rseq_cs, \"aw\"",
+        ".balign 32",
+        "3:",
+        ".long 0, 0",
+        ".quad 4f, (5f - 4f), 6f",
+        ".popsection",
+        ".pushsection __rseq_failure, \"ax\"",
+        ".byte 0x0f, 0xb9, 0x3d",
+        ".long {sig}",
+        "6:",
+        "jmp 2f",
+        ".popsection",
+        "2:",
+        "lea {tmp}, [rip + 3b]",
+        "mov qword ptr [{rseq} + 8], {tmp}",
+        "4:",
+        "xor {pushed:e}, {pushed:e}",
+        "mov {cpu:e}, dword ptr [{rseq} + 4]",
+        "cmp {cpu:e}, {cpus}",
+        "jae 5f",
+        "mov {slot}, qword ptr [{slots} + {cpu} * 8]",
+        "mov {tmp}, {slot}",
+        "shr {tmp}, 48",
+        "cmp {tmp}, {limit}",
+        "jae 5f",
+        // link the block to the current top; the block is ours so this can be redone
+        "shl {slot}, 16",
+        "shr {slot}, 16",
+        "mov qword ptr [{block}], {slot}",
+        "add {tmp}, 1",
+        "shl {tmp}, 48",
+        "or {tmp}, {block}",
+        "mov {pushed:e}, 1",
+        // the commit
+        "mov qword ptr [{slots} + {cpu} * 8], {tmp}",
+        "5:",
+        rseq = in(reg) rseq,
+        slots = in(reg) cpus.as_ptr(),
+        limit = in(reg) usize::from(limit),
+        block = in(reg) block,
+        pushed = out(reg) pushed,
+        cpu = out(reg) _,
+        slot = out(reg) _,
+        tmp = out(reg) _,
+        sig = const RSEQ_SIG,
+        cpus = const CPUS,
+        options(nostack),
+    );
+
+    pushed != 0
+}
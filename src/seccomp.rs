@@ -0,0 +1,269 @@
+//! A seccomp-bpf filter for the steady state: `#[app(seccomp = true)]`
+//!
+//! Right before the tasks are enabled, after `drop_privileges`, each process of the application
+//! installs a filter on all its threads (`SECCOMP_FILTER_FLAG_TSYNC`) that only allows the system
+//! calls the runtime makes once it's running:
+//!
+//! - sending, waiting for and blocking the signals of the tasks, returning from their handlers
+//! - arming the timer queues, creating and arming the budget timers, counting the overruns of
+//!   the periodic tasks, reading the clocks
+//! - waiting on and waking up futexes, sleeping, yielding
+//! - reading and writing already open file descriptors (`epoll`, the log, stdout, stderr)
+//! - `mmap`, `munmap`, `brk` and friends, which the allocator needs
+//! - reading and changing the priority and affinity of the threads, and exiting
+//!
+//! Any other system call kills the whole process with `SIGSYS` (`SECCOMP_RET_KILL_PROCESS`); the
+//! kernel logs which one it was to the audit log. Opening files, sockets and processes is not
+//! allowed, so everything the application needs has to be opened in `init`. This rules out
+//! `perf_counters`, whose counters are opened lazily, and the recording of the `record` feature,
+//! which is written to a new file on shutdown. Only x86_64 and aarch64 are supported.
+
+// the filter is only built on the supported architectures
+#![cfg_attr(
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    allow(dead_code, unused_imports)
+)]
+
+use std::io;
+
+use cty::{c_long, c_ulong};
+
+use crate::{sim, Error};
+
+/// `PR_SET_NO_NEW_PRIVS`; lets unprivileged processes install filters
+const PR_SET_NO_NEW_PRIVS: c_long = 38;
+
+/// `SECCOMP_SET_MODE_FILTER`
+const SECCOMP_SET_MODE_FILTER: c_ulong = 1;
+
+/// `SECCOMP_FILTER_FLAG_TSYNC`; installs the filter on every thread of the process
+const SECCOMP_FILTER_FLAG_TSYNC: c_ulong = 1;
+
+/// `SECCOMP_RET_KILL_PROCESS`
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+
+/// `SECCOMP_RET_ALLOW`
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+/// `BPF_LD | BPF_W | BPF_ABS`
+const BPF_LD_W_ABS: u16 = 0x20;
+
+/// `BPF_JMP | BPF_JEQ | BPF_K`
+const BPF_JMP_JEQ_K: u16 = 0x15;
+
+/// `BPF_RET | BPF_K`
+const BPF_RET_K: u16 = 0x06;
+
+// offsets into `struct seccomp_data`
+const NR: u32 = 0;
+const ARCH: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const SYS_PRCTL: c_long = 157;
+#[cfg(target_arch = "x86_64")]
+const SYS_SECCOMP: c_long = 317;
+
+/// `AUDIT_ARCH_X86_64`
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+
+#[cfg(target_arch = "x86_64")]
+#[rustfmt::skip]
+const ALLOWED: &[u32] = &[
+    0,   // read
+    1,   // write
+    3,   // close
+    7,   // poll
+    8,   // lseek
+    9,   // mmap
+    10,  // mprotect
+    11,  // munmap
+    12,  // brk
+    14,  // rt_sigprocmask
+    15,  // rt_sigreturn
+    20,  // writev
+    24,  // sched_yield
+    25,  // mremap
+    28,  // madvise
+    34,  // pause
+    35,  // nanosleep
+    39,  // getpid
+    60,  // exit
+    62,  // kill
+    128, // rt_sigtimedwait
+    129, // rt_sigqueueinfo
+    130, // rt_sigsuspend
+    131, // sigaltstack
+    141, // setpriority
+    143, // sched_getparam
+    144, // sched_setscheduler
+    186, // gettid
+    202, // futex
+    203, // sched_setaffinity
+    219, // restart_syscall
+    222, // timer_create
+    223, // timer_settime
+    224, // timer_gettime
+    225, // timer_getoverrun
+    226, // timer_delete
+    228, // clock_gettime
+    229, // clock_getres
+    230, // clock_nanosleep
+    231, // exit_group
+    232, // epoll_wait
+    233, // epoll_ctl
+    234, // tgkill
+    271, // ppoll
+    281, // epoll_pwait
+    297, // rt_tgsigqueueinfo
+    314, // sched_setattr
+    334, // rseq
+];
+
+#[cfg(target_arch = "aarch64")]
+const SYS_PRCTL: c_long = 167;
+#[cfg(target_arch = "aarch64")]
+const SYS_SECCOMP: c_long = 277;
+
+/// `AUDIT_ARCH_AARCH64`
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+// NOTE aarch64 has no `poll`, `pause` or `epoll_wait`; the C library uses `ppoll` and
+// `epoll_pwait`
+#[cfg(target_arch = "aarch64")]
+#[rustfmt::skip]
+const ALLOWED: &[u32] = &[
+    21,  // epoll_ctl
+    22,  // epoll_pwait
+    57,  // close
+    62,  // lseek
+    63,  // read
+    64,  // write
+    66,  // writev
+    73,  // ppoll
+    93,  // exit
+    94,  // exit_group
+    98,  // futex
+    101, // nanosleep
+    107, // timer_create
+    108, // timer_gettime
+    109, // timer_getoverrun
+    110, // timer_settime
+    111, // timer_delete
+    113, // clock_gettime
+    114, // clock_getres
+    115, // clock_nanosleep
+    119, // sched_setscheduler
+    121, // sched_getparam
+    122, // sched_setaffinity
+    124, // sched_yield
+    128, // restart_syscall
+    129, // kill
+    131, // tgkill
+    132, // sigaltstack
+    133, // rt_sigsuspend
+    135, // rt_sigprocmask
+    137, // rt_sigtimedwait
+    138, // rt_sigqueueinfo
+    139, // rt_sigreturn
+    140, // setpriority
+    172, // getpid
+    178, // gettid
+    214, // brk
+    215, // munmap
+    216, // mremap
+    222, // mmap
+    226, // mprotect
+    233, // madvise
+    240, // rt_tgsigqueueinfo
+    274, // sched_setattr
+    293, // rseq
+];
+
+/// `struct sock_filter`
+#[repr(C)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+/// `struct sock_fprog`
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+/// Restricts every thread of this process to the system calls of the steady state
+pub unsafe fn install() -> Result<(), Error> {
+    // the test harness of a simulation needs more than the runtime
+    if sim::active() {
+        return Ok(());
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    {
+        let filter = filter();
+        let prog = SockFprog {
+            len: filter.len() as u16,
+            filter: filter.as_ptr(),
+        };
+
+        if syscall(
+            SYS_PRCTL,
+            PR_SET_NO_NEW_PRIVS,
+            1 as c_ulong,
+            0 as c_ulong,
+            0 as c_ulong,
+        ) < 0
+            || syscall(
+                SYS_SECCOMP,
+                SECCOMP_SET_MODE_FILTER,
+                SECCOMP_FILTER_FLAG_TSYNC,
+                &prog as *const SockFprog,
+            ) != 0
+        {
+            return Err(Error::Seccomp {
+                errno: io::Error::last_os_error().raw_os_error().unwrap_or(0),
+            });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        Err(Error::Seccomp { errno: nc::ENOSYS })
+    }
+}
+
+// checks the architecture, then compares the system call number against each allowed one
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn filter() -> Vec<SockFilter> {
+    let insn = |code, jt, jf, k| SockFilter { code, jt, jf, k };
+
+    let mut filter = vec![
+        insn(BPF_LD_W_ABS, 0, 0, ARCH),
+        insn(BPF_JMP_JEQ_K, 1, 0, AUDIT_ARCH),
+        insn(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS),
+        insn(BPF_LD_W_ABS, 0, 0, NR),
+    ];
+
+    // NOTE a match jumps over the remaining comparisons and the `KILL_PROCESS` to the `ALLOW`
+    let n = ALLOWED.len();
+    for (i, &nr) in ALLOWED.iter().enumerate() {
+        filter.push(insn(BPF_JMP_JEQ_K, (n - i) as u8, 0, nr));
+    }
+
+    filter.push(insn(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS));
+    filter.push(insn(BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW));
+
+    filter
+}
+
+extern "C" {
+    fn syscall(number: c_long, ...) -> c_long;
+}
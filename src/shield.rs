@@ -0,0 +1,172 @@
+//! CPU shielding: `#[app(shield_cores = [1, 2])]`
+//!
+//! At start-up the runtime keeps the rest of the system off the shielded CPUs:
+//!
+//! - the process moves to a cgroup of its own, `/sys/fs/cgroup/rtfm`, which keeps every CPU
+//! - every other top-level cgroup (`init.scope`, `system.slice`, `user.slice`, ..) is restricted
+//!   to the other CPUs through its `cpuset.cpus`, which moves the processes in it off the
+//!   shielded CPUs
+//! - the interrupts (`/proc/irq/*/smp_affinity`), the default affinity of the interrupts that
+//!   show up later and the unbound kernel workqueues go to the other CPUs
+//!
+//! Only the unified (v2) cgroup hierarchy is supported and all of it needs root; what fails is
+//! reported as a warning. Per-CPU kernel threads and the interrupts that can't move, like the
+//! local timer, stay on the shielded CPUs. The runtime doesn't undo any of this when the
+//! application exits: writing an empty line to `cpuset.cpus` gives a cgroup the CPUs of its
+//! parent back.
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    process,
+};
+
+/// Root of the unified cgroup hierarchy
+const CGROUP: &str = "/sys/fs/cgroup";
+
+/// The cgroup of the application, under `CGROUP`
+const OWN: &str = "rtfm";
+
+/// The CPUs that the kernel brought online, e.g. `0-7`
+const ONLINE: &str = "/sys/devices/system/cpu/online";
+
+/// Affinity of the interrupts that are registered later
+const IRQ_DEFAULT: &str = "/proc/irq/default_smp_affinity";
+
+/// CPUs of the unbound kernel workqueues
+const WORKQUEUES: &str = "/sys/devices/virtual/workqueue/cpumask";
+
+/// Moves the other processes, the interrupts and the kernel workqueues off the `shielded` CPUs
+pub(crate) fn shield(shielded: &[u8]) {
+    let online = match online() {
+        Some(online) => online,
+        None => {
+            eprintln!(
+                "warning: couldn't read the online CPUs from `{}`; not shielding",
+                ONLINE
+            );
+            return;
+        }
+    };
+
+    let housekeeping = online
+        .into_iter()
+        .filter(|cpu| !shielded.contains(cpu))
+        .collect::<Vec<_>>();
+    if housekeeping.is_empty() {
+        eprintln!(
+            "warning: shielding every online CPU would leave none to the system; not shielding"
+        );
+        return;
+    }
+
+    if let Err(e) = cgroups(&housekeeping) {
+        eprintln!(
+            "warning: couldn't move the other processes off the shielded CPUs ({}); the process \
+             needs root and the cgroup v2 hierarchy at `{}`",
+            e, CGROUP
+        );
+    }
+
+    if let Err(e) = irqs(&housekeeping) {
+        eprintln!(
+            "warning: couldn't move the interrupts off the shielded CPUs ({}); the process needs \
+             write access to `/proc/irq`",
+            e
+        );
+    }
+
+    // NOTE older kernels don't have this file
+    let _ = fs::write(WORKQUEUES, mask(&housekeeping));
+}
+
+/// The CPUs that the kernel brought online
+pub(crate) fn online() -> Option<Vec<u8>> {
+    parse_list(fs::read_to_string(ONLINE).ok()?.trim())
+}
+
+fn cgroups(housekeeping: &[u8]) -> io::Result<()> {
+    let root = Path::new(CGROUP);
+    fs::write(root.join("cgroup.subtree_control"), "+cpuset")?;
+
+    let own = root.join(OWN);
+    if let Err(e) = fs::create_dir(&own) {
+        if e.kind() != ErrorKind::AlreadyExists {
+            return Err(e);
+        }
+    }
+    // NOTE this moves every thread of the process
+    fs::write(own.join("cgroup.procs"), process::id().to_string())?;
+
+    let cpus = housekeeping
+        .iter()
+        .map(|cpu| cpu.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if path == own || !path.join("cpuset.cpus").exists() {
+            continue;
+        }
+
+        fs::write(path.join("cpuset.cpus"), &cpus)?;
+    }
+
+    Ok(())
+}
+
+fn irqs(housekeeping: &[u8]) -> io::Result<()> {
+    let mask = mask(housekeeping);
+    fs::write(IRQ_DEFAULT, &mask)?;
+
+    for entry in fs::read_dir("/proc/irq")? {
+        let path = entry?.path().join("smp_affinity");
+
+        // NOTE per-CPU and managed interrupts refuse to move (`EIO`); they are left alone
+        if path.exists() {
+            let _ = fs::write(path, &mask);
+        }
+    }
+
+    Ok(())
+}
+
+// e.g. `[0, 1, 35]` -> `8,00000003`; the format of the kernel's CPU masks, in 32-bit groups
+pub(crate) fn mask(cpus: &[u8]) -> String {
+    let mut words = vec![0u32; usize::from(cpus.iter().cloned().max().unwrap_or(0) / 32) + 1];
+    for &cpu in cpus {
+        words[usize::from(cpu / 32)] |= 1 << (cpu % 32);
+    }
+
+    words
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, word)| {
+            if i == 0 {
+                format!("{:x}", word)
+            } else {
+                format!("{:08x}", word)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// e.g. `0-3,6` -> `[0, 1, 2, 3, 6]`
+pub(crate) fn parse_list(list: &str) -> Option<Vec<u8>> {
+    let mut cpus = vec![];
+    for range in list.split(',') {
+        let mut bounds = range.splitn(2, '-');
+        let start = bounds.next()?.parse::<u8>().ok()?;
+        let end = match bounds.next() {
+            Some(end) => end.parse::<u8>().ok()?,
+            None => start,
+        };
+
+        cpus.extend(start..=end);
+    }
+
+    Some(cpus)
+}
@@ -0,0 +1,317 @@
+//! Shared-memory channels into tasks
+//!
+//! A task declared with `#[task(shm = "/name")]` gets its input from a single-producer
+//! single-consumer ring buffer that lives in the POSIX shared memory object `/dev/shm/name`. The
+//! application creates the object at start-up, with room for `capacity` messages, and removes it
+//! at shutdown. Another process, e.g. a GUI that must not run at a real-time priority, opens the
+//! channel with `Producer::open` and sends messages with `Producer::push`. Each push signals the
+//! dispatcher of the task, which runs the task once per message.
+//!
+//! Messages are copied byte for byte between the processes so `T` must be plain data: a `Copy`
+//! type without pointers or references, for which every bit pattern is valid. Make it
+//! `#[repr(C)]` so that both programs agree on its layout, and build both for the same target.
+
+use core::{
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    ptr,
+    sync::atomic::{AtomicI32, AtomicU32, AtomicUsize, Ordering},
+};
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+};
+
+use nc::{pid_t, siginfo_t};
+
+use crate::{export::SIGRTMIN, Error};
+
+/// Identifies a channel created by this version of the runtime
+const MAGIC: u32 = 0x7274_666d; // "rtfm"
+
+#[repr(C)]
+struct Header {
+    // `MAGIC` once the rest of the header has been written
+    magic: AtomicU32,
+    // size of a message, in bytes
+    size: u32,
+    capacity: u32,
+    // signal that runs the dispatcher of the task and the `sigval` that selects the task
+    signal: i32,
+    sigval: usize,
+    pid: pid_t,
+    // thread the signal must be sent to; `0` if any thread of `pid` can take it
+    tid: pid_t,
+    // process that owns the producer end; `0` if none does
+    producer: AtomicI32,
+    // number of messages read; only the application writes it
+    head: AtomicUsize,
+    // number of messages written; only the producer writes it
+    tail: AtomicUsize,
+}
+
+/// Consumer end of a channel; the application owns it
+pub struct Channel<T> {
+    header: AtomicUsize,
+    // NOTE kept out of the shared memory, where the producer could change it
+    capacity: AtomicUsize,
+    _message: PhantomData<T>,
+}
+
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+impl<T> Channel<T> {
+    pub const fn uninit() -> Self {
+        Self {
+            header: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(0),
+            _message: PhantomData,
+        }
+    }
+}
+
+impl<T> Channel<T>
+where
+    T: Copy,
+{
+    /// Creates the shared memory object `name`; a message will then run the task with
+    /// `sigval` on the real-time signal `signo` of thread `tid`, or of any thread if `None`
+    ///
+    /// An object left behind by a previous run of the application is replaced
+    pub unsafe fn init(
+        &self,
+        name: &str,
+        capacity: u8,
+        pid: pid_t,
+        tid: Option<pid_t>,
+        signo: u8,
+        sigval: usize,
+    ) -> Result<(), Error> {
+        let capacity = usize::from(capacity);
+        let len = offset::<T>() + capacity * size_of::<T>();
+
+        let path = path(name);
+        let _ = fs::remove_file(&path);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|file| file.set_len(len as u64).map(|_| file))
+            .map_err(|e| Error::Shm { errno: errno(e) })?;
+
+        // NOTE the mapping outlives the file descriptor
+        let addr = nc::mmap(
+            0,
+            len,
+            nc::PROT_READ | nc::PROT_WRITE,
+            nc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+        .map_err(|errno| Error::Shm { errno })?;
+
+        let header = addr as *mut Header;
+        ptr::write(
+            header,
+            Header {
+                magic: AtomicU32::new(0),
+                size: size_of::<T>() as u32,
+                capacity: capacity as u32,
+                signal: SIGRTMIN + i32::from(signo),
+                sigval,
+                pid,
+                tid: tid.unwrap_or(0),
+                producer: AtomicI32::new(0),
+                head: AtomicUsize::new(0),
+                tail: AtomicUsize::new(0),
+            },
+        );
+        (*header).magic.store(MAGIC, Ordering::Release);
+
+        self.capacity.store(capacity, Ordering::Relaxed);
+        self.header.store(addr, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Takes the oldest message out of the channel
+    ///
+    /// NOTE only the thread that runs the task may call this
+    pub unsafe fn pop(&self) -> Option<T> {
+        let header = &*(self.header.load(Ordering::Acquire) as *const Header);
+        let capacity = self.capacity.load(Ordering::Relaxed);
+
+        let head = header.head.load(Ordering::Relaxed);
+        let tail = header.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        // NOTE the producer is not trusted; a bogus `tail` makes us read garbage messages but
+        // never outside the buffer
+        let message = messages::<T>(header).add(head % capacity);
+        let value = ptr::read_volatile(message);
+        header.head.store(head.wrapping_add(1), Ordering::Release);
+
+        Some(value)
+    }
+}
+
+/// Removes the shared memory object `name`; the producer keeps its mapping until it drops it
+pub fn unlink(name: &str) {
+    let _ = fs::remove_file(path(name));
+}
+
+/// Producer end of a channel; another process owns it
+pub struct Producer<T> {
+    header: *mut Header,
+    len: usize,
+    _message: PhantomData<T>,
+}
+
+unsafe impl<T> Send for Producer<T> where T: Send {}
+
+impl<T> Producer<T>
+where
+    T: Copy,
+{
+    /// Opens the channel `name` of a running application; returns the `errno` on failure
+    ///
+    /// `ENOENT` means that the application is not running, `EINVAL` that `T` is not the input
+    /// type of the task and `EBUSY` that another process has the channel open
+    pub fn open(name: &str) -> Result<Self, i32> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path(name))
+            .map_err(errno)?;
+        let len = file.metadata().map_err(errno)?.len() as usize;
+
+        if len < offset::<T>() {
+            return Err(nc::EINVAL);
+        }
+
+        let addr = unsafe {
+            nc::mmap(
+                0,
+                len,
+                nc::PROT_READ | nc::PROT_WRITE,
+                nc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )?
+        };
+
+        // NOTE `drop` releases the mapping if anything below fails
+        let producer = Producer {
+            header: addr as *mut Header,
+            len,
+            _message: PhantomData,
+        };
+        let header = producer.header();
+
+        if header.magic.load(Ordering::Acquire) != MAGIC
+            || header.size as usize != size_of::<T>()
+            || offset::<T>() + header.capacity as usize * size_of::<T>() > len
+        {
+            return Err(nc::EINVAL);
+        }
+
+        let me = nc::getpid();
+        loop {
+            match header
+                .producer
+                .compare_exchange(0, me, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(owner) => {
+                    // take over the channel from a producer that died without closing it
+                    if owner == me || nc::kill(owner, 0) != Err(nc::ESRCH) {
+                        return Err(nc::EBUSY);
+                    }
+
+                    if header
+                        .producer
+                        .compare_exchange(owner, me, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok()
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(producer)
+    }
+
+    /// Sends `value` to the task; returns it back if the channel is full
+    ///
+    /// NOTE if the application has too many pending signals the message stays in the channel
+    /// until the next push
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let header = self.header();
+        let capacity = header.capacity as usize;
+
+        let tail = header.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(header.head.load(Ordering::Acquire)) >= capacity {
+            return Err(value);
+        }
+
+        unsafe {
+            ptr::write_volatile(messages::<T>(header).add(tail % capacity), value);
+        }
+        header.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        let mut si = siginfo_t::default();
+        si.siginfo.si_code = nc::SI_QUEUE;
+        si.siginfo.sifields.rt.sigval.sival_ptr = header.sigval;
+        let _ = if header.tid == 0 {
+            nc::rt_sigqueueinfo(header.pid, header.signal, &mut si)
+        } else {
+            nc::rt_tgsigqueueinfo(header.pid, header.tid, header.signal, &mut si)
+        };
+
+        Ok(())
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let me = nc::getpid();
+            let _ = (*self.header).producer.compare_exchange(
+                me,
+                0,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            );
+
+            let _ = nc::munmap(self.header as usize, self.len);
+        }
+    }
+}
+
+fn path(name: &str) -> String {
+    format!("/dev/shm{}", name)
+}
+
+fn errno(e: io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(nc::EIO)
+}
+
+// Offset of the first message from the start of the shared memory object
+fn offset<T>() -> usize {
+    let align = align_of::<T>();
+    (size_of::<Header>() + align - 1) / align * align
+}
+
+unsafe fn messages<T>(header: &Header) -> *mut T {
+    (header as *const Header as *mut u8).add(offset::<T>()) as *mut T
+}
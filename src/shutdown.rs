@@ -0,0 +1,48 @@
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU8, Ordering};
+
+use nc::pid_t;
+
+use crate::export::SIGRTMIN;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static CODE: AtomicI32 = AtomicI32::new(0);
+// signal of the shutdown handler of core #0
+static SIGNO: AtomicU8 = AtomicU8::new(0);
+
+/// Shuts the application down and exits the process with the given `code`
+///
+/// From this point on `spawn` and `schedule` fail and return their payload. The tasks that are
+/// already pending still run; once each core has no pending tasks its timer is deleted, its
+/// resources are dropped and its thread exits. Then the `#[deinit]` function, if any, runs on core
+/// #0 and the process exits with `code`.
+///
+/// `schedule`-d tasks that are not due yet are discarded and their payloads are dropped. `idle` is
+/// preempted and never resumed. Only the first call has an effect.
+pub fn shutdown(code: i32) {
+    if REQUESTED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    CODE.store(code, Ordering::Relaxed);
+
+    // core #0 runs on the main thread
+    let tgid = nc::getpid();
+    unsafe { request_shutdown(tgid, tgid, SIGNO.load(Ordering::Relaxed)) }
+}
+
+pub fn shutting_down() -> bool {
+    REQUESTED.load(Ordering::Acquire)
+}
+
+pub fn exit_code() -> i32 {
+    CODE.load(Ordering::Relaxed)
+}
+
+pub unsafe fn init_shutdown(signo: u8) {
+    SIGNO.store(signo, Ordering::Relaxed)
+}
+
+pub unsafe fn request_shutdown(tgid: pid_t, tid: pid_t, signo: u8) {
+    nc::tgkill(tgid, tid, SIGRTMIN + i32::from(signo))
+        .expect("error: couldn't send the shutdown signal");
+}
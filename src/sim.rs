@@ -0,0 +1,385 @@
+//! Deterministic simulation of an application, for host tests
+//!
+//! `#[app(simulate = true)]` keeps `main` out of `cfg(test)` builds; the tests start the
+//! application with `rtfm::simulate` instead. The application runs in a child process so each
+//! simulation starts from scratch and a task that panics or aborts only takes its test down.
+//!
+//! In a simulation nothing needs privileges: no `SCHED_FIFO`, no CPU affinity, no `mlockall`. The
+//! task signals stay blocked and only the thread that runs the test dispatches them, with
+//! `sigtimedwait`, so tasks run at well-defined points: `lock`s and `spawn`s let higher priority
+//! tasks preempt right away, like they would, and the rest run when the test calls
+//! `run_pending` or `advance`. The clocks don't move on their own either: `Instant::now`, the
+//! `Monotonic` clocks and the POSIX timers of the timer queues and periodic tasks all follow a
+//! virtual clock that only `advance` moves.
+
+use core::{
+    marker::PhantomData,
+    mem::size_of,
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+use std::panic::{self, AssertUnwindSafe};
+
+use heapless::{consts, i, Vec as HVec};
+use nc::{clockid_t, pid_t, siginfo_t, sigset_t, timer_t, timespec_t};
+
+use crate::{
+    export::{self, SIGRTMIN},
+    shutdown,
+    time::Instant,
+};
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// nanoseconds the virtual clock has advanced since the simulation started
+static ELAPSED: AtomicU64 = AtomicU64::new(0);
+// priority of the running context; the maximum until `init` has returned
+static LEVEL: AtomicU8 = AtomicU8::new(u8::max_value());
+// the task signals, `START..END`; the shutdown signal, `END`, is never dispatched
+static START: AtomicU8 = AtomicU8::new(0);
+static END: AtomicU8 = AtomicU8::new(0);
+
+// what `CLOCK_MONOTONIC` and `CLOCK_REALTIME` read when the simulation started
+static mut MONOTONIC: timespec_t = timespec_t {
+    tv_sec: 0,
+    tv_nsec: 0,
+};
+static mut REALTIME: timespec_t = timespec_t {
+    tv_sec: 0,
+    tv_nsec: 0,
+};
+
+static mut TIMERS: HVec<Timer, consts::U32> = HVec(i::Vec::new());
+
+/// A POSIX timer of the application
+struct Timer {
+    id: timer_t,
+    signo: u8,
+    value: usize,
+    clock: clockid_t,
+    // next expiration, on `clock`; `None` if disarmed
+    next: Option<Instant>,
+    // nanoseconds; `0` if the timer doesn't repeat
+    period: u64,
+}
+
+impl Timer {
+    // how far into the simulation the timer expires next
+    fn due(&self) -> Option<Duration> {
+        self.next
+            .map(|next| next.saturating_duration_since(base(self.clock).into()))
+    }
+}
+
+/// Handle to the simulated application; see `rtfm::simulate`
+pub struct Simulation {
+    // the tasks can only be dispatched from the thread that started the simulation
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Simulation {
+    /// The current instant of the virtual clock
+    pub fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Runs the tasks that are pending at the current instant, highest priority first
+    ///
+    /// This picks up the tasks spawned from other threads, e.g. with a `Spawner`, and the tasks
+    /// bound to file descriptors
+    pub fn run_pending(&self) {
+        unsafe { run_above(0) }
+    }
+
+    /// Advances the virtual clock by `by`, expiring the timers and running the tasks on the way
+    pub fn advance(&self, by: Duration) {
+        self.advance_to(self.now() + by)
+    }
+
+    /// Advances the virtual clock to `instant`; see `advance`
+    ///
+    /// The timers expire in order and the clock stops at each expiration until the tasks it
+    /// released have run. An `instant` that has already passed only runs the pending tasks
+    pub fn advance_to(&self, instant: Instant) {
+        let target = instant.saturating_duration_since(base(nc::CLOCK_MONOTONIC).into());
+
+        unsafe {
+            run_above(0);
+
+            loop {
+                let next = TIMERS
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, timer)| timer.due().map(|due| (due, i)))
+                    .filter(|&(due, _)| due <= target)
+                    .min();
+
+                if let Some((due, i)) = next {
+                    if due > elapsed() {
+                        ELAPSED.store(due.as_nanos() as u64, Ordering::Relaxed);
+                    }
+
+                    expire(i);
+                    run_above(0);
+                } else {
+                    break;
+                }
+            }
+
+            if target > elapsed() {
+                ELAPSED.store(target.as_nanos() as u64, Ordering::Relaxed);
+            }
+
+            run_above(0);
+        }
+    }
+
+    /// When the next armed timer expires, on the virtual `CLOCK_MONOTONIC`; `None` if no timer is
+    /// armed
+    pub fn next_expiration(&self) -> Option<Instant> {
+        unsafe { TIMERS.iter().filter_map(Timer::due).min() }
+            .map(|due| Instant::from(base(nc::CLOCK_MONOTONIC)) + due)
+    }
+
+    /// The exit code passed to `rtfm::shutdown`, if it has been called
+    ///
+    /// The simulation doesn't shut the application down; the test decides what to do next
+    pub fn shutdown_code(&self) -> Option<i32> {
+        if shutdown::shutting_down() {
+            Some(shutdown::exit_code())
+        } else {
+            None
+        }
+    }
+}
+
+/// Runs the application of this crate, which must use `#[app(simulate = true)]`, in a simulation
+/// and calls `test` once `init` has returned and the tasks it spawned have run
+///
+/// The simulation runs in a child process; this panics if `test` panics, a task panics or the
+/// process exits before `test` returns.
+///
+/// NOTE the output capturing of the test harness doesn't reach the child process: run the tests
+/// with `--nocapture` to see what the application prints. Panic messages are always shown
+pub fn simulate<F>(test: F)
+where
+    F: FnOnce(&Simulation),
+{
+    extern "C" {
+        // generated by `#[app(simulate = true)]`
+        fn __rtfm_simulate();
+    }
+
+    match unsafe { fork() } {
+        -1 => panic!("error: couldn't fork the simulation"),
+        0 => unsafe {
+            // `kill(0, ..)` signals the process group; keep the test harness out of it
+            nc::setpgid(0, 0).ok();
+
+            panic::set_hook(Box::new(|info| {
+                let message = format!("{}\n", info);
+                nc::write(2, message.as_ptr() as usize, message.len()).ok();
+            }));
+
+            start();
+            __rtfm_simulate();
+
+            let sim = Simulation {
+                _not_send: PhantomData,
+            };
+            let code = match panic::catch_unwind(AssertUnwindSafe(|| test(&sim))) {
+                Ok(()) => 0,
+                Err(_) => 101,
+            };
+
+            export::exit_group(code)
+        },
+        child => {
+            let mut status = 0;
+            if unsafe { waitpid(child, &mut status, 0) } != child {
+                panic!("error: couldn't wait for the simulation");
+            }
+
+            match (status & 0x7f, (status >> 8) & 0xff) {
+                (0, 0) => {}
+                (0, code) => panic!("the simulation exited with code {}", code),
+                (signal, _) => panic!("the simulation was killed by signal {}", signal),
+            }
+        }
+    }
+}
+
+extern "C" {
+    fn fork() -> pid_t;
+    fn waitpid(pid: pid_t, status: *mut i32, options: i32) -> pid_t;
+}
+
+unsafe fn start() {
+    MONOTONIC = read(nc::CLOCK_MONOTONIC);
+    REALTIME = read(nc::CLOCK_REALTIME);
+
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+fn read(clock: clockid_t) -> timespec_t {
+    let mut ts = timespec_t::default();
+    nc::clock_gettime(clock, &mut ts).expect("Failed to get time");
+    ts
+}
+
+pub(crate) fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+fn elapsed() -> Duration {
+    Duration::from_nanos(ELAPSED.load(Ordering::Relaxed))
+}
+
+fn base(clock: clockid_t) -> timespec_t {
+    // NOTE(unsafe) only written before the application starts
+    unsafe {
+        if clock == nc::CLOCK_REALTIME {
+            REALTIME
+        } else {
+            MONOTONIC
+        }
+    }
+}
+
+/// The virtual time of `clock`; `None` outside a simulation and for the CPU-time clocks
+pub(crate) fn clock_gettime(clock: clockid_t) -> Option<timespec_t> {
+    if !active() {
+        return None;
+    }
+
+    match clock {
+        nc::CLOCK_MONOTONIC | nc::CLOCK_MONOTONIC_RAW | nc::CLOCK_BOOTTIME | nc::CLOCK_REALTIME => {
+            Some((Instant::from(base(clock)) + elapsed()).into())
+        }
+        _ => None,
+    }
+}
+
+/// Takes note of the task signals; called by `init_runtime`
+pub(crate) fn init_signals(signals: Option<Range<u8>>) {
+    if let Some(Range { start, end }) = signals {
+        START.store(start, Ordering::Relaxed);
+        // the last signal is the shutdown signal
+        END.store(end - 1, Ordering::Relaxed);
+    }
+}
+
+/// Takes note of a new timer; it sends `signo` with `value` when it expires
+pub(crate) unsafe fn timer_created(id: timer_t, signo: u8, clock: clockid_t, value: usize) {
+    if !active() {
+        return;
+    }
+
+    let timer = Timer {
+        id,
+        signo,
+        value,
+        clock,
+        next: None,
+        period: 0,
+    };
+
+    if TIMERS.push(timer).is_err() {
+        panic!("error: the simulation supports at most 32 timers");
+    }
+}
+
+/// Arms the timer `id` to expire at `instant` and then every `period` nanoseconds, if not `0`
+pub(crate) unsafe fn arm(id: timer_t, instant: Instant, period: u64) {
+    if let Some(timer) = TIMERS.iter_mut().find(|timer| timer.id == id) {
+        timer.next = Some(instant);
+        timer.period = period;
+    }
+}
+
+/// Disarms the timer `id`
+pub(crate) unsafe fn disarm(id: timer_t) {
+    if let Some(timer) = TIMERS.iter_mut().find(|timer| timer.id == id) {
+        timer.next = None;
+    }
+}
+
+// Sends the signal of the `i`-th timer, as the kernel would, and re-arms it
+unsafe fn expire(i: usize) {
+    let timer = &mut TIMERS[i];
+
+    let mut si = siginfo_t::default();
+    si.siginfo.si_code = nc::SI_TIMER;
+    si.siginfo.sifields.rt.sigval.sival_ptr = timer.value;
+    nc::rt_sigqueueinfo(nc::getpid(), SIGRTMIN + i32::from(timer.signo), &mut si)
+        .expect("error: couldn't send the timer signal");
+
+    timer.next = match (timer.next, timer.period) {
+        (Some(next), period) if period != 0 => Some(next + Duration::from_nanos(period)),
+        _ => None,
+    };
+}
+
+/// `mask` in a simulation: the signals stay blocked and only the simulated priority changes
+pub(crate) fn mask(current: u8, ceiling: u8, block: bool) {
+    if block {
+        LEVEL.store(ceiling, Ordering::Relaxed);
+    } else {
+        LEVEL.store(current, Ordering::Relaxed);
+        preempt();
+    }
+}
+
+/// `free` in a simulation
+pub(crate) fn free<R>(f: impl FnOnce() -> R) -> R {
+    let level = LEVEL.swap(u8::max_value(), Ordering::Relaxed);
+    let r = f();
+    LEVEL.store(level, Ordering::Relaxed);
+    preempt();
+
+    r
+}
+
+/// Runs the pending tasks that preempt the running context; the other threads never run tasks
+pub(crate) fn preempt() {
+    if active() && nc::gettid() == nc::getpid() {
+        unsafe { run_above(LEVEL.load(Ordering::Relaxed)) }
+    }
+}
+
+// Dispatches, highest priority first, the pending tasks whose priority is above `level`
+unsafe fn run_above(level: u8) {
+    let start = START.load(Ordering::Relaxed);
+    let end = END.load(Ordering::Relaxed);
+
+    // priority `p` is signal `end - p`
+    let stop = end.saturating_sub(level);
+    if stop <= start {
+        return;
+    }
+
+    let set = export::rt_sigset(start, stop - start);
+    let poll = timespec_t {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    loop {
+        let mut si = siginfo_t::default();
+        match nc::rt_sigtimedwait(&set, &mut si, &poll, size_of::<sigset_t>()) {
+            Ok(_) => {}
+            // nothing is pending
+            Err(nc::EAGAIN) => break,
+            Err(nc::EINTR) => continue,
+            Err(_) => panic!("error: couldn't poll the task signals"),
+        }
+
+        // NOTE the lowest pending signal, i.e. the highest priority, comes first
+        let signo = (si.siginfo.si_signo - SIGRTMIN) as u8;
+        let handler = export::handler(signo).expect("error: a task signal has no handler");
+
+        let preempted = LEVEL.swap(end - signo, Ordering::Relaxed);
+        handler(si.siginfo.si_signo, &mut si, 0);
+        LEVEL.store(preempted, Ordering::Relaxed);
+    }
+}
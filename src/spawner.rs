@@ -0,0 +1,207 @@
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    mem::{size_of, MaybeUninit},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    export::{self, Pid},
+    SpawnError,
+};
+
+/// A message slot of a `SpawnQueue`
+pub struct SpawnSlot<T> {
+    // position of the message this slot holds, or will hold next
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// Messages sent through the `Spawner`s of a task; `A` is `[SpawnSlot<T>; N]`
+///
+/// This is a bounded multi-producer queue (Vyukov's): the producers are threads the runtime
+/// doesn't manage, which may block, so neither side ever takes a lock
+pub struct SpawnQueue<T, A> {
+    slots: UnsafeCell<MaybeUninit<A>>,
+    capacity: AtomicUsize,
+    enqueue: AtomicUsize,
+    dequeue: AtomicUsize,
+    _message: PhantomData<T>,
+}
+
+unsafe impl<T, A> Sync for SpawnQueue<T, A> where T: Send {}
+
+impl<T, A> SpawnQueue<T, A> {
+    pub const fn uninit() -> Self {
+        Self {
+            slots: UnsafeCell::new(MaybeUninit::uninit()),
+            capacity: AtomicUsize::new(0),
+            enqueue: AtomicUsize::new(0),
+            dequeue: AtomicUsize::new(0),
+            _message: PhantomData,
+        }
+    }
+
+    /// NOTE this must run before the first `Spawner` of the task is created
+    pub unsafe fn init(&self) {
+        let capacity = size_of::<A>() / size_of::<SpawnSlot<T>>();
+
+        for i in 0..capacity {
+            ptr::write(
+                self.slot(i) as *const SpawnSlot<T> as *mut SpawnSlot<T>,
+                SpawnSlot {
+                    sequence: AtomicUsize::new(i),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                },
+            );
+        }
+
+        self.capacity.store(capacity, Ordering::Release);
+    }
+
+    /// Takes the oldest message out of the queue
+    ///
+    /// NOTE only the thread that runs the task may call this
+    pub unsafe fn dequeue(&self) -> Option<T> {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        let pos = self.dequeue.load(Ordering::Relaxed);
+        let slot = self.slot(pos % capacity);
+
+        // the producer that claimed this position has not finished writing it
+        if slot.sequence.load(Ordering::Acquire) != pos.wrapping_add(1) {
+            return None;
+        }
+
+        self.dequeue.store(pos.wrapping_add(1), Ordering::Relaxed);
+        let value = ptr::read((*slot.value.get()).as_ptr());
+        slot.sequence
+            .store(pos.wrapping_add(capacity), Ordering::Release);
+
+        Some(value)
+    }
+
+    fn enqueue(&self, value: T) -> Result<(), T> {
+        let capacity = self.capacity.load(Ordering::Acquire);
+        let mut pos = self.enqueue.load(Ordering::Relaxed);
+
+        loop {
+            let slot = unsafe { self.slot(pos % capacity) };
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            match sequence.wrapping_sub(pos) as isize {
+                // free; try to claim it
+                0 => match self.enqueue.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).as_mut_ptr().write(value) }
+                        slot.sequence.store(pos.wrapping_add(1), Ordering::Release);
+
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                },
+                // the task has not taken the message of the previous lap yet
+                diff if diff < 0 => return Err(value),
+                // another producer claimed this position first
+                _ => pos = self.enqueue.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    unsafe fn slot(&self, i: usize) -> &SpawnSlot<T> {
+        &*((*self.slots.get()).as_ptr() as *const SpawnSlot<T>).add(i)
+    }
+}
+
+// Erases the capacity of a `SpawnQueue`
+trait Enqueue<T>: Sync {
+    fn enqueue(&self, value: T) -> Result<(), T>;
+}
+
+impl<T, A> Enqueue<T> for SpawnQueue<T, A>
+where
+    T: Send,
+{
+    fn enqueue(&self, value: T) -> Result<(), T> {
+        SpawnQueue::enqueue(self, value)
+    }
+}
+
+/// Spawns a task from a thread that the runtime doesn't manage, e.g. a `std::thread` or a tokio
+/// worker
+///
+/// Get one from `foo::spawner()` in any context of the application; the task must be declared
+/// with `#[task(spawner = true)]`. A task with several inputs takes them as a tuple; one without
+/// inputs takes `()`.
+pub struct Spawner<T> {
+    queue: &'static dyn Enqueue<T>,
+    tgid: &'static Pid,
+    tid: Option<&'static Pid>,
+    signo: u8,
+    sigval: usize,
+}
+
+impl<T> Spawner<T>
+where
+    T: Send,
+{
+    #[doc(hidden)]
+    pub unsafe fn new<A>(
+        queue: &'static SpawnQueue<T, A>,
+        tgid: &'static Pid,
+        tid: Option<&'static Pid>,
+        signo: u8,
+        sigval: usize,
+    ) -> Self {
+        Spawner {
+            queue,
+            tgid,
+            tid,
+            signo,
+            sigval,
+        }
+    }
+
+    /// Spawns the task with `payload` as its input; returns the payload back if the task has
+    /// `capacity` messages pending or the application is shutting down
+    ///
+    /// NOTE this only blocks while the core of the task is starting up so it can be called from
+    /// async code
+    pub fn spawn(&self, payload: T) -> Result<(), SpawnError<T>> {
+        if export::shutting_down() {
+            return Err(SpawnError::ShuttingDown(payload));
+        }
+
+        self.queue.enqueue(payload).map_err(SpawnError::Full)?;
+
+        // NOTE if the application has too many pending signals the message is delivered along
+        // with the next one
+        unsafe {
+            let _ = export::try_sigqueue(
+                self.tgid.wait(),
+                self.tid.map(Pid::wait),
+                self.signo,
+                self.sigval,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> Clone for Spawner<T> {
+    fn clone(&self) -> Self {
+        Spawner {
+            queue: self.queue,
+            tgid: self.tgid,
+            tid: self.tid,
+            signo: self.signo,
+            sigval: self.sigval,
+        }
+    }
+}
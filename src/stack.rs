@@ -0,0 +1,150 @@
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::Error;
+
+pub(crate) const PAGE_SIZE: usize = 4 * 1024; // 4 KiB (output of `getconf PAGESIZE`)
+
+/// How many stacks can be told apart when one overflows
+const MAX_STACKS: usize = 64;
+
+/// Byte the stacks are filled with before use; see `stack_usage`
+const PATTERN: u8 = 0xa5;
+
+#[derive(Clone, Copy)]
+struct Stack {
+    // lowest address; the guard page is right below it
+    low: usize,
+    size: usize,
+    name: &'static str,
+}
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+static mut STACKS: [Option<Stack>; MAX_STACKS] = [None; MAX_STACKS];
+
+/// Maps and pre-faults a stack of (at least) `size` bytes with an inaccessible guard page below
+/// it; returns the lowest address of the stack and its actual size
+///
+/// An overflow of a stack that has a `name` is reported as such by the `SIGSEGV` handler, and its
+/// usage is reported by `stack_usage`
+pub(crate) unsafe fn map(size: usize, name: Option<&'static str>) -> Result<(usize, usize), Error> {
+    // round up to a whole number of pages
+    let size = (size + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+
+    let guard = nc::mmap(
+        0,                // address; 0 means any page-aligned address
+        PAGE_SIZE + size, // length of mapping
+        nc::PROT_READ | // read access
+        nc::PROT_WRITE, // write access
+        nc::MAP_ANONYMOUS | // mapping is not backed by any file
+        nc::MAP_PRIVATE, // mapping is private to other threads / processes
+        -1,               // file descriptor; needs to be `-1` because of MAP_ANONYMOUS
+        0,                // offset; ignored because of MAP_ANONYMOUS
+    )
+    .map_err(|errno| Error::Stack { size, errno })?;
+
+    nc::mprotect(guard, PAGE_SIZE, nc::PROT_NONE).map_err(|errno| Error::Stack { size, errno })?;
+
+    let stack_low = guard + PAGE_SIZE;
+
+    // fault in the whole stack now rather than when a deeply nested task first touches it; the
+    // pattern also marks the bytes that have never been used
+    ptr::write_bytes(stack_low as *mut u8, PATTERN, size);
+
+    if let Some(name) = name {
+        let i = COUNT.fetch_add(1, Ordering::Relaxed);
+
+        // NOTE stacks past the first `MAX_STACKS` still have a guard page; their overflows are
+        // reported as plain segmentation faults
+        if i < MAX_STACKS {
+            STACKS[i] = Some(Stack {
+                low: stack_low,
+                size,
+                name,
+            });
+        }
+    }
+
+    Ok((stack_low, size))
+}
+
+/// Returns the peak usage of the stacks of all the threads, and of the alternate signal stacks
+/// the tasks run on
+pub fn stack_usage() -> Vec<StackUsage> {
+    let count = COUNT.load(Ordering::Relaxed);
+
+    // NOTE(unsafe) a slot is written once, before the thread that uses the stack starts
+    unsafe {
+        STACKS
+            .iter()
+            .take(count)
+            .filter_map(|stack| *stack)
+            .map(|stack| StackUsage {
+                name: stack.name,
+                size: stack.size,
+                peak: stack.size - untouched(stack.low, stack.size),
+            })
+            .collect()
+    }
+}
+
+/// Peak usage of a single stack
+///
+/// The usage is measured by looking for the lowest byte that no longer holds the pattern the stack
+/// was filled with so it may be underestimated by a few bytes
+#[derive(Clone, Copy, Debug)]
+pub struct StackUsage {
+    name: &'static str,
+    size: usize,
+    peak: usize,
+}
+
+impl StackUsage {
+    /// Which thread or tasks use the stack, e.g. "the tasks of core #0"
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Size of the stack, in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Highest number of bytes that have been in use at the same time
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+}
+
+// Number of bytes at the bottom of the stack that have never been written
+unsafe fn untouched(low: usize, size: usize) -> usize {
+    // NOTE the stack is in use by another thread so it's read with volatile loads
+    (low..low + size)
+        .take_while(|&addr| ptr::read_volatile(addr as *const u8) == PATTERN)
+        .count()
+}
+
+/// The name of the stack whose guard page holds `addr`, if any; a fault at `addr` is an overflow
+/// of that stack
+pub(crate) fn overflowed(addr: usize) -> Option<&'static str> {
+    let count = COUNT.load(Ordering::Relaxed);
+
+    // NOTE(unsafe) a slot is written once, before the thread that uses the stack starts
+    unsafe {
+        STACKS
+            .iter()
+            .take(count)
+            .filter_map(|stack| *stack)
+            .find(|stack| (stack.low - PAGE_SIZE..stack.low).contains(&addr))
+            .map(|stack| stack.name)
+    }
+}
+
+// Writes `parts` to stderr without allocating or locking
+pub(crate) unsafe fn report(parts: &[&str]) {
+    for part in parts {
+        let _ = nc::write(2, part.as_ptr() as usize, part.len());
+    }
+}
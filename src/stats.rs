@@ -0,0 +1,271 @@
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    export,
+    perf::{self, EVENTS},
+    rseq::PerCpuCounter,
+    time,
+};
+
+static mut TABLE: &[TaskStats] = &[];
+static mut LOCK_TABLE: &[LockStats] = &[];
+
+/// Returns the execution-time statistics of all the software tasks
+///
+/// The slice is empty unless the application enables them with `#[rtfm::app(stats = true)]`
+pub fn stats() -> &'static [TaskStats] {
+    // NOTE(unsafe) `TABLE` is only written before any task runs
+    unsafe { TABLE }
+}
+
+/// Returns the lock contention counters of all the resources that need a lock
+///
+/// The slice is empty unless the application enables them with `#[rtfm::app(stats = true)]`
+pub fn lock_stats() -> &'static [LockStats] {
+    // NOTE(unsafe) `LOCK_TABLE` is only written before any task runs
+    unsafe { LOCK_TABLE }
+}
+
+/// Execution-time statistics of a single task
+///
+/// Times are measured with `CLOCK_THREAD_CPUTIME_ID` and exclude the time spent in higher priority
+/// tasks that preempted the task. Each field is read independently so the values may come from
+/// different runs of the task.
+///
+/// With `#[rtfm::app(perf_counters = true)]` the runs are also measured with `perf_event_open`
+/// counters; like the times these exclude the preempting tasks.
+pub struct TaskStats {
+    name: &'static str,
+    count: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+    total: AtomicU64,
+    // `u64::MAX` if the counter isn't available
+    perf: [AtomicU64; EVENTS],
+}
+
+impl TaskStats {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str) -> Self {
+        TaskStats {
+            name,
+            count: AtomicU64::new(0),
+            min: AtomicU64::new(u64::max_value()),
+            max: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            perf: [
+                AtomicU64::new(u64::max_value()),
+                AtomicU64::new(u64::max_value()),
+                AtomicU64::new(u64::max_value()),
+            ],
+        }
+    }
+
+    /// Name of the task
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Number of times the task has run to completion
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Shortest observed execution time; `None` if the task hasn't run yet
+    pub fn min(&self) -> Option<Duration> {
+        match self.min.load(Ordering::Relaxed) {
+            nanos if nanos == u64::max_value() => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// Longest observed execution time
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max.load(Ordering::Relaxed))
+    }
+
+    /// Mean execution time; `None` if the task hasn't run yet
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                self.total.load(Ordering::Relaxed) / count,
+            )),
+        }
+    }
+
+    /// Total execution time of all the runs
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.total.load(Ordering::Relaxed))
+    }
+
+    /// CPU cycles spent by all the runs; `None` without `perf_counters = true` or if the counter
+    /// couldn't be opened
+    pub fn cycles(&self) -> Option<u64> {
+        self.perf(0)
+    }
+
+    /// Cache misses caused by all the runs; `None` without `perf_counters = true` or if the
+    /// counter couldn't be opened
+    pub fn cache_misses(&self) -> Option<u64> {
+        self.perf(1)
+    }
+
+    /// Context switches that happened during all the runs, for example while a task blocked in a
+    /// system call; `None` without `perf_counters = true` or if the counter couldn't be opened
+    pub fn context_switches(&self) -> Option<u64> {
+        self.perf(2)
+    }
+
+    fn perf(&self, event: usize) -> Option<u64> {
+        match self.perf[event].load(Ordering::Relaxed) {
+            count if count == u64::max_value() => None,
+            count => Some(count),
+        }
+    }
+
+    fn record(&self, nanos: u64) {
+        // NOTE a task never preempts itself so there's a single writer
+        if nanos < self.min.load(Ordering::Relaxed) {
+            self.min.store(nanos, Ordering::Relaxed);
+        }
+
+        if nanos > self.max.load(Ordering::Relaxed) {
+            self.max.store(nanos, Ordering::Relaxed);
+        }
+
+        self.total.fetch_add(nanos, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_perf(&self, event: usize, count: u64) {
+        // NOTE single writer; see `record`
+        let total = &self.perf[event];
+        match total.load(Ordering::Relaxed) {
+            unavailable if unavailable == u64::max_value() => total.store(count, Ordering::Relaxed),
+            _ => {
+                total.fetch_add(count, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Lock contention counter of a single resource
+///
+/// Counts the `lock`s that had to raise the priority to the ceiling of the resource, that is the
+/// ones that block signals with `rt_sigprocmask` (or change the thread priority with `threads =
+/// true`). Locks taken by a context that already runs at the ceiling, e.g. inside another lock,
+/// cost nothing and aren't counted. A resource with a high count is a candidate for a lock-free
+/// design.
+pub struct LockStats {
+    name: &'static str,
+    count: PerCpuCounter,
+}
+
+impl LockStats {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str) -> Self {
+        LockStats {
+            name,
+            count: PerCpuCounter::new(),
+        }
+    }
+
+    /// Name of the resource
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Number of times a `lock` raised the priority to the ceiling of the resource
+    pub fn count(&self) -> u64 {
+        self.count.get()
+    }
+
+    pub(crate) fn record(&self) {
+        // NOTE several cores may lock a resource at the same time
+        self.count.add(1);
+    }
+}
+
+/// CPU time and counted events consumed by the tasks of a core that have run to completion
+pub struct Busy {
+    nanos: AtomicU64,
+    perf: [AtomicU64; EVENTS],
+}
+
+impl Busy {
+    pub const fn new() -> Self {
+        Busy {
+            nanos: AtomicU64::new(0),
+            perf: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
+        }
+    }
+}
+
+pub unsafe fn init_stats(
+    table: &'static [TaskStats],
+    locks: &'static [LockStats],
+    perf_counters: bool,
+) {
+    TABLE = table;
+    LOCK_TABLE = locks;
+
+    if perf_counters {
+        perf::init_perf();
+    }
+}
+
+/// Runs `f`, a task of the core that owns `busy`, and records its execution time in `stats`
+pub fn measure(stats: &TaskStats, busy: &Busy, f: impl FnOnce()) {
+    let start = thread_cputime();
+    let busy_start = busy.nanos.load(Ordering::Relaxed);
+    let perf_start = perf::read();
+    let busy_perf_start = load(&busy.perf);
+
+    f();
+
+    let perf_end = perf::read();
+    let elapsed = thread_cputime() - start;
+    let nanos = if export::threads() {
+        // higher priority tasks run on other threads so they don't add to the CPU time of this one
+        elapsed
+    } else {
+        // time spent in tasks that preempted this one
+        let preempted = busy.nanos.load(Ordering::Relaxed) - busy_start;
+        elapsed.saturating_sub(preempted)
+    };
+
+    busy.nanos.fetch_add(nanos, Ordering::Relaxed);
+    stats.record(nanos);
+
+    for event in 0..EVENTS {
+        if let (Some(start), Some(end)) = (perf_start[event], perf_end[event]) {
+            let count = if export::threads() {
+                end - start
+            } else {
+                let preempted = busy.perf[event].load(Ordering::Relaxed) - busy_perf_start[event];
+                (end - start).saturating_sub(preempted)
+            };
+
+            busy.perf[event].fetch_add(count, Ordering::Relaxed);
+            stats.record_perf(event, count);
+        }
+    }
+}
+
+fn load(counts: &[AtomicU64; EVENTS]) -> [u64; EVENTS] {
+    let mut values = [0; EVENTS];
+    for (value, count) in values.iter_mut().zip(counts.iter()) {
+        *value = count.load(Ordering::Relaxed);
+    }
+    values
+}
+
+pub(crate) fn thread_cputime() -> u64 {
+    let ts = time::clock_gettime(nc::CLOCK_THREAD_CPUTIME_ID);
+
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
@@ -0,0 +1,264 @@
+//! The synchronization primitives that the threads of the runtime share
+//!
+//! With the `loom` feature the atomics and the futex come from `loom` so that `tests/loom.rs` can
+//! model-check these primitives and the `lock` sequence. The feature is only meant for those
+//! tests: `loom` atomics can't be created in a `static` so applications don't build with it.
+
+#[cfg(not(feature = "loom"))]
+use core::sync::atomic::{AtomicI32, Ordering};
+#[cfg(feature = "loom")]
+use loom::sync::atomic::{AtomicI32, Ordering};
+
+use self::imp::{futex_wait, futex_wake};
+
+// A `const` constructor, except with `loom` whose atomics are not `const`-constructible
+macro_rules! constructor {
+    (pub fn $name:ident() -> Self $body:block) => {
+        #[cfg(not(feature = "loom"))]
+        pub const fn $name() -> Self $body
+
+        #[cfg(feature = "loom")]
+        pub fn $name() -> Self $body
+    };
+}
+
+pub struct Barrier {
+    inner: AtomicI32,
+}
+
+impl Barrier {
+    constructor! {
+        pub fn new() -> Self {
+            Self {
+                inner: AtomicI32::new(0),
+            }
+        }
+    }
+
+    pub fn release(&self) {
+        self.inner.store(1, Ordering::Release);
+        futex_wake(&self.inner);
+    }
+
+    pub fn wait(&self) {
+        while self.inner.load(Ordering::Acquire) == 0 {
+            futex_wait(&self.inner, 0);
+        }
+    }
+}
+
+pub struct Pid {
+    inner: AtomicI32,
+}
+
+impl Pid {
+    constructor! {
+        pub fn uninit() -> Self {
+            Self {
+                inner: AtomicI32::new(0),
+            }
+        }
+    }
+
+    pub fn get(&self) -> pid_t {
+        self.inner.load(Ordering::Relaxed)
+    }
+
+    pub fn init(&self, pid: pid_t) {
+        self.inner.store(pid, Ordering::Release);
+        futex_wake(&self.inner);
+    }
+
+    pub fn wait(&self) -> pid_t {
+        loop {
+            let pid = self.inner.load(Ordering::Acquire);
+
+            if pid == 0 {
+                futex_wait(&self.inner, 0);
+            } else {
+                break pid;
+            }
+        }
+    }
+}
+
+/// Wakes up the contexts that wait in `spawn_blocking` for a free message slot of a task
+pub struct FreeSlots {
+    // bumped every time a message slot is freed
+    seq: AtomicI32,
+    // number of contexts that are (about to be) sleeping on `seq`
+    waiters: AtomicI32,
+}
+
+impl FreeSlots {
+    constructor! {
+        pub fn new() -> Self {
+            Self {
+                seq: AtomicI32::new(0),
+                waiters: AtomicI32::new(0),
+            }
+        }
+    }
+
+    /// Must be called *before* trying to claim a message slot; pass the result to `wait`
+    pub fn token(&self) -> i32 {
+        self.seq.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps until a message slot is freed after `token` was taken
+    ///
+    /// NOTE this may return early (e.g. a signal handler ran or the futex timed out); callers must
+    /// try to claim a slot again
+    pub fn wait(&self, token: i32) {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        futex_wait(&self.seq, token);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Called by the dispatcher after it returns a message slot to a free queue
+    pub fn notify(&self) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+
+        // NOTE a waiter that registers after this load will see the new `seq` and not sleep
+        if self.waiters.load(Ordering::SeqCst) != 0 {
+            futex_wake(&self.seq);
+        }
+    }
+}
+
+/// Wakes up the background workers; rung by every `spawn` of a background task
+pub struct Doorbell {
+    // bumped on every ring
+    seq: AtomicI32,
+    // number of workers that are (about to be) sleeping on `seq`
+    waiters: AtomicI32,
+}
+
+impl Doorbell {
+    constructor! {
+        pub fn new() -> Self {
+            Self {
+                seq: AtomicI32::new(0),
+                waiters: AtomicI32::new(0),
+            }
+        }
+    }
+
+    /// Must be called *before* looking for messages; pass the result to `wait`
+    pub fn token(&self) -> i32 {
+        self.seq.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps until the doorbell rings after `token` was taken
+    ///
+    /// NOTE this may return early; callers must look for messages again
+    pub fn wait(&self, token: i32) {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        futex_wait(&self.seq, token);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Called after a message has been queued
+    ///
+    /// NOTE this doesn't block: it's two atomic operations, plus a `FUTEX_WAKE` if a worker sleeps
+    pub fn ring(&self) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+
+        if self.waiters.load(Ordering::SeqCst) != 0 {
+            futex_wake(&self.seq);
+        }
+    }
+}
+
+#[cfg(not(feature = "loom"))]
+mod imp {
+    use core::sync::atomic::AtomicI32;
+
+    use nc::timespec_t;
+
+    use crate::{
+        faults::{self, Syscall},
+        process,
+    };
+
+    // NOTE the private futex operations suffice unless the cores run in processes of their own
+    fn private() -> i32 {
+        if process::processes() {
+            0
+        } else {
+            nc::FUTEX_PRIVATE_FLAG
+        }
+    }
+
+    // Sleeps until `word` is woken up, unless it no longer holds `expected`
+    pub fn futex_wait(word: &AtomicI32, expected: i32) {
+        // the timeout only bounds each sleep; callers re-check `word` and wait again
+        let mut timeout = timespec_t {
+            tv_sec: 1,
+            tv_nsec: 0,
+        };
+        let mut unused = 0;
+
+        // NOTE the kernel only reads `word`; `nc` takes a mutable reference all the same, which
+        // `as_ptr` derives from the `UnsafeCell` of the atomic
+        match faults::inject(Syscall::FutexWait).and_then(|()| {
+            nc::futex(
+                unsafe { &mut *word.as_ptr() },
+                nc::FUTEX_WAIT | private(),
+                expected as u32,
+                &mut timeout,
+                &mut unused,
+                0,
+            )
+        }) {
+            // woken up, `word` changed or timed out
+            Ok(_) | Err(nc::EAGAIN) | Err(nc::EINTR) | Err(nc::ETIMEDOUT) => {}
+            Err(_) => panic!("error: couldn't wait on a futex"),
+        }
+    }
+
+    // Wakes up all the threads waiting on `word`
+    pub fn futex_wake(word: &AtomicI32) {
+        let mut unused_ts = timespec_t::default();
+        let mut unused = 0;
+
+        nc::futex(
+            unsafe { &mut *word.as_ptr() },
+            nc::FUTEX_WAKE | private(),
+            i32::max_value() as u32,
+            &mut unused_ts,
+            &mut unused,
+            0,
+        )
+        .expect("error: couldn't wake up the futex waiters");
+    }
+}
+
+// A futex made of a mutex and a condition variable that `loom` can explore
+//
+// NOTE like the kernel, `futex_wait` checks `word` and goes to sleep while holding the lock that
+// `futex_wake` takes so a wake up can't be lost; waking up all the waiters of all the words is
+// allowed because callers re-check their word
+#[cfg(feature = "loom")]
+mod imp {
+    use loom::sync::{
+        atomic::{AtomicI32, Ordering},
+        Condvar, Mutex,
+    };
+
+    loom::lazy_static! {
+        static ref FUTEX: (Mutex<()>, Condvar) = (Mutex::new(()), Condvar::new());
+    }
+
+    pub fn futex_wait(word: &AtomicI32, expected: i32) {
+        let guard = FUTEX.0.lock().unwrap();
+        if word.load(Ordering::SeqCst) == expected {
+            drop(FUTEX.1.wait(guard).unwrap());
+        }
+    }
+
+    pub fn futex_wake(_: &AtomicI32) {
+        let _guard = FUTEX.0.lock().unwrap();
+        FUTEX.1.notify_all();
+    }
+}
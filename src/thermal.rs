@@ -0,0 +1,226 @@
+//! CPU throttling notifications: `#[task(throttling = true)]`
+//!
+//! A thread of the runtime watches the thermal zones (`/sys/class/thermal/thermal_zone*`) and the
+//! cpufreq policies (`/sys/devices/system/cpu/cpufreq/policy*`) and spawns the task, through its
+//! `Spawner`, whenever the CPUs start or stop being throttled:
+//!
+//! - a thermal zone is too hot once its `temp` reaches the lowest of its `passive` trip points,
+//!   where the kernel starts cooling it down by lowering the CPU frequency
+//! - a cpufreq policy is capped while its `scaling_max_freq` is below the value it had at
+//!   start-up; the thermal cooling devices and the firmware limits lower it
+//!
+//! The attributes are `poll`-ed for `POLLPRI`, which wakes the thread up right away on the
+//! drivers that `sysfs_notify` them, and read again every `#[app(throttling_poll = ..)]` (1 s by
+//! default) otherwise. A state that the task couldn't be spawned with, because it had too many
+//! messages pending, is sent again after the next read.
+
+use core::time::Duration;
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use cty::{c_int, c_short, c_ulong};
+
+use crate::{sim, spawner::Spawner};
+
+/// The thermal zones
+const THERMAL: &str = "/sys/class/thermal";
+
+/// The cpufreq policies
+const CPUFREQ: &str = "/sys/devices/system/cpu/cpufreq";
+
+/// Throttling state of the CPUs, as the throttling task receives it
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CpuThrottling {
+    /// Whether a thermal zone is too hot or a cpufreq policy is capped
+    pub throttled: bool,
+    /// Temperature of the hottest thermal zone, in millidegrees Celsius
+    pub temperature: Option<i32>,
+    /// Frequency cap of the most capped cpufreq policy, in kHz
+    pub max_freq: Option<u32>,
+    /// The cap that same policy had at start-up, in kHz
+    pub start_freq: Option<u32>,
+}
+
+struct Zone {
+    temp: File,
+    // lowest `passive` trip point, in millidegrees Celsius
+    trip: Option<i64>,
+}
+
+struct Policy {
+    max_freq: File,
+    start_freq: i64,
+}
+
+/// Body of the throttling monitor
+///
+/// It spawns the task with the current state every time `throttled` changes, starting from
+/// `false`
+pub fn monitor(spawner: Spawner<CpuThrottling>, period: Duration) -> ! {
+    // the simulation decides when, and if, the CPUs are throttled
+    if sim::active() {
+        loop {
+            thread::sleep(period);
+        }
+    }
+
+    let mut zones = zones();
+    let mut policies = policies();
+    if zones.is_empty() && policies.is_empty() {
+        eprintln!(
+            "warning: found no thermal zones or cpufreq policies; the throttling task won't run"
+        );
+    }
+
+    let mut fds = zones
+        .iter()
+        .map(|zone| &zone.temp)
+        .chain(policies.iter().map(|policy| &policy.max_freq))
+        .map(|file| PollFd {
+            fd: file.as_raw_fd(),
+            events: POLLPRI | POLLERR,
+            revents: 0,
+        })
+        .collect::<Vec<_>>();
+    let timeout = period.as_millis().min(c_int::max_value() as u128) as c_int;
+
+    let mut throttled = false;
+    loop {
+        // NOTE reading the attributes also re-arms their notifications
+        let state = sample(&mut zones, &mut policies);
+        if state.throttled != throttled && spawner.spawn(state).is_ok() {
+            throttled = state.throttled;
+        }
+
+        if fds.is_empty() {
+            thread::sleep(period);
+        } else {
+            // NOTE(unsafe) `fds` outlives the call
+            unsafe {
+                poll(fds.as_mut_ptr(), fds.len() as c_ulong, timeout);
+            }
+        }
+    }
+}
+
+fn sample(zones: &mut [Zone], policies: &mut [Policy]) -> CpuThrottling {
+    let mut state = CpuThrottling {
+        throttled: false,
+        temperature: None,
+        max_freq: None,
+        start_freq: None,
+    };
+
+    for zone in zones {
+        if let Some(temp) = read(&mut zone.temp) {
+            state.throttled |= zone.trip.map(|trip| temp >= trip).unwrap_or(false);
+            state.temperature = state.temperature.max(Some(temp as i32));
+        }
+    }
+
+    // (cap, cap at start-up) of the policy with the lowest ratio between the two
+    let mut capped: Option<(i64, i64)> = None;
+    for policy in policies {
+        if let Some(max_freq) = read(&mut policy.max_freq) {
+            state.throttled |= max_freq < policy.start_freq;
+
+            let lower = capped
+                .map(|(cap, start)| max_freq * start < cap * policy.start_freq)
+                .unwrap_or(true);
+            if lower {
+                capped = Some((max_freq, policy.start_freq));
+            }
+        }
+    }
+
+    if let Some((cap, start)) = capped {
+        state.max_freq = Some(cap as u32);
+        state.start_freq = Some(start as u32);
+    }
+
+    state
+}
+
+fn zones() -> Vec<Zone> {
+    entries(THERMAL, "thermal_zone")
+        .filter_map(|zone| {
+            Some(Zone {
+                temp: File::open(zone.join("temp")).ok()?,
+                trip: passive_trip(&zone),
+            })
+        })
+        .collect()
+}
+
+fn policies() -> Vec<Policy> {
+    entries(CPUFREQ, "policy")
+        .filter_map(|policy| {
+            let mut max_freq = File::open(policy.join("scaling_max_freq")).ok()?;
+            let start_freq = read(&mut max_freq)?;
+
+            Some(Policy {
+                max_freq,
+                start_freq,
+            })
+        })
+        .collect()
+}
+
+// the entries of `dir` whose names start with `prefix`
+fn entries(dir: &str, prefix: &'static str) -> impl Iterator<Item = PathBuf> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(move |entry| entry.file_name().to_string_lossy().starts_with(prefix))
+        .map(|entry| entry.path())
+}
+
+fn passive_trip(zone: &Path) -> Option<i64> {
+    (0..)
+        .map(|i| {
+            (
+                zone.join(format!("trip_point_{}_type", i)),
+                zone.join(format!("trip_point_{}_temp", i)),
+            )
+        })
+        .take_while(|(ty, _)| ty.exists())
+        .filter(|(ty, _)| {
+            fs::read_to_string(ty)
+                .map(|ty| ty.trim() == "passive")
+                .unwrap_or(false)
+        })
+        .filter_map(|(_, temp)| read(&mut File::open(temp).ok()?))
+        .min()
+}
+
+// reads an integer attribute from the start
+fn read(file: &mut File) -> Option<i64> {
+    let mut value = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut value).ok()?;
+    value.trim().parse().ok()
+}
+
+/// `struct pollfd`
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+/// `POLLPRI`
+const POLLPRI: c_short = 0x002;
+
+/// `POLLERR`
+const POLLERR: c_short = 0x008;
+
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
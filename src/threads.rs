@@ -0,0 +1,275 @@
+use core::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::fs;
+
+use nc::pid_t;
+
+use crate::export;
+
+/// How many threads `threads` can list
+const MAX_THREADS: usize = 64;
+
+/// Placement of a thread that is not pinned to a CPU
+const NO_CPU: u8 = u8::max_value();
+
+#[derive(Clone, Copy)]
+struct Entry {
+    tid: pid_t,
+    // logical core whose tasks, or `idle`, the thread runs; `None` for the helper threads
+    core: Option<u8>,
+}
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+static mut THREADS: [Option<Entry>; MAX_THREADS] = [None; MAX_THREADS];
+
+// CPU and `SCHED_FIFO` priority of each thread, packed as `cpu << 8 | priority`; unlike the
+// entries these change at runtime
+// NOTE only accessed through `placement`
+static mut PLACEMENTS: [u16; MAX_THREADS] = [0; MAX_THREADS];
+
+/// Names the calling thread, if `name` is given, and adds it to the list returned by `threads`
+///
+/// `core` is the logical core whose tasks the thread runs and `cpu` the CPU it's pinned to
+///
+/// NOTE the kernel truncates names to 15 bytes
+pub(crate) fn register(name: Option<&str>, core: Option<u8>, cpu: Option<u8>, priority: u8) {
+    if let Some(name) = name {
+        let mut buffer = [0u8; 16];
+        let len = name.len().min(buffer.len() - 1);
+        buffer[..len].copy_from_slice(&name.as_bytes()[..len]);
+
+        let _ = nc::prctl(nc::PR_SET_NAME, buffer.as_ptr() as usize, 0, 0, 0);
+    }
+
+    let i = COUNT.fetch_add(1, Ordering::Relaxed);
+    if i < MAX_THREADS {
+        place(i, cpu, priority);
+
+        // NOTE(unsafe) each slot has a single writer
+        unsafe {
+            THREADS[i] = Some(Entry {
+                tid: nc::gettid(),
+                core,
+            });
+        }
+    }
+}
+
+/// Pins all the threads that run the tasks of `core`, and its `idle`, to the CPU `cpu`; returns
+/// the `errno` on failure
+///
+/// The logical cores of `#[app(cores = ..)]` start on the CPUs with the same number. Only the
+/// threads move: the tasks, their dispatchers and the ceilings of their resources stay the same.
+/// `EINVAL` means that `core` doesn't exist or runs under `SCHED_DEADLINE`, whose threads can't be
+/// pinned
+pub fn set_core_cpu(core: u8, cpu: u8) -> Result<(), i32> {
+    if cpu >= 64 {
+        return Err(nc::EINVAL);
+    }
+
+    let group = group(core);
+    if group.is_empty()
+        || group
+            .iter()
+            .any(|&(i, _)| placement(i).load(Ordering::Relaxed) >> 8 == u16::from(NO_CPU))
+    {
+        return Err(nc::EINVAL);
+    }
+
+    for (i, entry) in group {
+        nc::sched_setaffinity(entry.tid, 1, &[1 << cpu])?;
+
+        let priority = placement(i).load(Ordering::Relaxed) as u8;
+        place(i, Some(cpu), priority);
+    }
+
+    Ok(())
+}
+
+/// Changes the `SCHED_FIFO` priority of the thread that runs the tasks of `core`; returns the
+/// `errno` on failure
+///
+/// This moves the whole core relative to the other threads of the system, e.g. to demote the
+/// non-critical part of the application in a degraded mode. The tasks of the core keep their
+/// priorities relative to each other so the ceilings of the resources stay valid. `EINVAL` means
+/// that `core` doesn't exist, that it runs under `SCHED_DEADLINE`, or that each priority level
+/// has a thread of its own (`dispatch = "threads"`), whose priorities implement `lock`
+pub fn set_core_priority(core: u8, priority: u8) -> Result<(), i32> {
+    if !(1..=99).contains(&priority) || export::threads() {
+        return Err(nc::EINVAL);
+    }
+
+    let group = group(core);
+    let (i, entry) = match group[..] {
+        [(i, entry)] if placement(i).load(Ordering::Relaxed) as u8 != 0 => (i, entry),
+        _ => return Err(nc::EINVAL),
+    };
+
+    // NOTE(unsafe) only the scheduling policy of one of our threads changes
+    unsafe { export::set_priority(entry.tid, priority).map_err(|e| e.errno())? }
+
+    let cpu = (placement(i).load(Ordering::Relaxed) >> 8) as u8;
+    place(i, if cpu == NO_CPU { None } else { Some(cpu) }, priority);
+
+    Ok(())
+}
+
+/// Changes the `SCHED_FIFO` priority of the dispatcher of `task`; returns the `errno` on failure
+///
+/// The dispatcher of a task is the thread of its core, which runs the other tasks of the core as
+/// well. They move together: changing the priority of one task relative to the tasks it shares
+/// resources with would invalidate the ceilings, which are computed at compile time. `EINVAL`
+/// means that `task` is a background task, or see `set_core_priority`
+pub fn set_task_priority(task: Task, priority: u8) -> Result<(), i32> {
+    set_core_priority(task.core.ok_or(nc::EINVAL)?, priority)
+}
+
+/// Pins the dispatcher of `task` to the CPU `cpu`; returns the `errno` on failure
+///
+/// With `dispatch = "threads"` the dispatcher of a task is the thread of its priority level, but
+/// the threads of the other levels of its core move along: `lock` relies on all of them sharing a
+/// CPU. `EINVAL` means that `task` is a background task, or see `set_core_cpu`
+pub fn set_task_cpu(task: Task, cpu: u8) -> Result<(), i32> {
+    set_core_cpu(task.core.ok_or(nc::EINVAL)?, cpu)
+}
+
+/// A task of the application, as passed to `set_task_priority` and `set_task_cpu`
+///
+/// The module of each task has one, e.g. `foo::TASK`
+#[derive(Clone, Copy, Debug)]
+pub struct Task {
+    name: &'static str,
+    // `None` for the background tasks, which run outside of the cores
+    core: Option<u8>,
+}
+
+impl Task {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str, core: Option<u8>) -> Self {
+        Task { name, core }
+    }
+
+    /// Name of the task
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Logical core whose dispatcher runs the task; `None` for the background tasks
+    pub fn core(&self) -> Option<u8> {
+        self.core
+    }
+}
+
+// The registered threads that run the tasks of `core`
+fn group(core: u8) -> Vec<(usize, Entry)> {
+    let count = COUNT.load(Ordering::Relaxed).min(MAX_THREADS);
+
+    // NOTE(unsafe) a slot is written once, before the thread it describes runs any task
+    unsafe {
+        THREADS
+            .iter()
+            .take(count)
+            .enumerate()
+            .filter_map(|(i, entry)| entry.map(|entry| (i, entry)))
+            .filter(|(_, entry)| entry.core == Some(core))
+            .collect()
+    }
+}
+
+/// Updates the `SCHED_FIFO` priority that `threads` reports for the calling thread
+pub(crate) fn reprioritize(priority: u8) {
+    let tid = nc::gettid();
+    let count = COUNT.load(Ordering::Relaxed).min(MAX_THREADS);
+
+    // NOTE(unsafe) a slot is written once, before the thread it describes runs any task
+    let i = unsafe {
+        THREADS
+            .iter()
+            .take(count)
+            .position(|entry| entry.map(|entry| entry.tid) == Some(tid))
+    };
+
+    if let Some(i) = i {
+        let cpu = (placement(i).load(Ordering::Relaxed) >> 8) as u8;
+        place(i, if cpu == NO_CPU { None } else { Some(cpu) }, priority);
+    }
+}
+
+fn placement(i: usize) -> &'static AtomicU16 {
+    // NOTE(unsafe) `AtomicU16` has the same in-memory representation as `u16`
+    unsafe { &*(&PLACEMENTS[i] as *const u16 as *const AtomicU16) }
+}
+
+fn place(i: usize, cpu: Option<u8>, priority: u8) {
+    let cpu = cpu.unwrap_or(NO_CPU);
+
+    placement(i).store(u16::from(cpu) << 8 | u16::from(priority), Ordering::Relaxed);
+}
+
+/// Returns the threads of the runtime: the thread of each core and the threads that run priority
+/// levels, watch file descriptors or write out the log
+pub fn threads() -> Vec<Thread> {
+    let count = COUNT.load(Ordering::Relaxed);
+
+    // NOTE(unsafe) a slot is written once, before the thread it describes runs any task
+    unsafe {
+        THREADS
+            .iter()
+            .take(count)
+            .enumerate()
+            .filter_map(|(i, entry)| entry.map(|entry| (i, entry)))
+            .map(|(i, entry)| {
+                let placement = placement(i).load(Ordering::Relaxed);
+                let cpu = (placement >> 8) as u8;
+
+                Thread {
+                    name: fs::read_to_string(format!("/proc/self/task/{}/comm", entry.tid))
+                        .map(|comm| comm.trim_end().to_owned())
+                        .unwrap_or_default(),
+                    tid: entry.tid,
+                    core: entry.core,
+                    cpu: if cpu == NO_CPU { None } else { Some(cpu) },
+                    priority: placement as u8,
+                }
+            })
+            .collect()
+    }
+}
+
+/// A thread of the runtime
+#[derive(Clone, Debug)]
+pub struct Thread {
+    name: String,
+    tid: pid_t,
+    core: Option<u8>,
+    cpu: Option<u8>,
+    priority: u8,
+}
+
+impl Thread {
+    /// Name of the thread, as shown by `ps -L` or `htop`, e.g. `rtfm-core1`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Thread ID, as used by `perf --tid` or `chrt --pid`
+    pub fn tid(&self) -> pid_t {
+        self.tid
+    }
+
+    /// Logical core whose tasks, or `idle`, the thread runs; `None` for the helper threads, e.g.
+    /// `rtfm-epoll`
+    pub fn core(&self) -> Option<u8> {
+        self.core
+    }
+
+    /// CPU the thread is pinned to; `None` for `SCHED_DEADLINE` threads, which can't be pinned
+    pub fn cpu(&self) -> Option<u8> {
+        self.cpu
+    }
+
+    /// `SCHED_FIFO` priority the thread runs at outside of `lock`; `0` if it doesn't use
+    /// `SCHED_FIFO`
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+}
@@ -0,0 +1,67 @@
+use core::{
+    sync::atomic::{AtomicI64, Ordering},
+    time::Duration,
+};
+use std::fs;
+
+pub(crate) const RUNTIME: &str = "/proc/sys/kernel/sched_rt_runtime_us";
+pub(crate) const PERIOD: &str = "/proc/sys/kernel/sched_rt_period_us";
+
+/// Value of `sched_rt_runtime_us` when throttling is disabled
+const DISABLED: i64 = -1;
+
+// detected at start-up, in microseconds
+static RUNTIME_US: AtomicI64 = AtomicI64::new(DISABLED);
+static PERIOD_US: AtomicI64 = AtomicI64::new(0);
+
+/// Real-time throttling settings of the kernel
+///
+/// All the real-time threads of the system may only run for `runtime` out of every `period`; for
+/// the rest of the period they are stopped, even if they are busy
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RtThrottling {
+    /// CPU time real-time threads get in each period
+    pub runtime: Duration,
+    /// Length of the throttling period
+    pub period: Duration,
+}
+
+/// Returns the real-time throttling settings detected at start-up; `None` means that throttling
+/// is disabled (or that it was disabled by the application, see `disable_rt_throttling`)
+pub fn rt_throttling() -> Option<RtThrottling> {
+    match RUNTIME_US.load(Ordering::Relaxed) {
+        runtime if runtime < 0 => None,
+        runtime => Some(RtThrottling {
+            runtime: Duration::from_micros(runtime as u64),
+            period: Duration::from_micros(PERIOD_US.load(Ordering::Relaxed) as u64),
+        }),
+    }
+}
+
+/// Detects the real-time throttling settings; if `disable` is set throttling is turned off, which
+/// requires write access to `/proc/sys/kernel` (i.e. root)
+pub fn init_throttling(disable: bool) {
+    let runtime = match read(RUNTIME) {
+        Some(runtime) if runtime != DISABLED => runtime,
+        // disabled or unknown
+        _ => return,
+    };
+
+    if disable {
+        match fs::write(RUNTIME, "-1") {
+            Ok(()) => return,
+            Err(e) => eprintln!(
+                "warning: couldn't disable real-time throttling ({}); \
+                 run `sudo sysctl -w kernel.sched_rt_runtime_us=-1` first",
+                e
+            ),
+        }
+    }
+
+    RUNTIME_US.store(runtime, Ordering::Relaxed);
+    PERIOD_US.store(read(PERIOD).unwrap_or(1_000_000), Ordering::Relaxed);
+}
+
+pub(crate) fn read(path: &str) -> Option<i64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
@@ -1,9 +1,93 @@
 //! Temporal quantification
 
 use core::{convert::TryFrom, ops, time::Duration};
-use nc::timespec_t;
+pub use nc::{clockid_t, timespec_t};
 use std::cmp::Ordering;
 
+/// A monotonic clock that drives the `schedule` API
+///
+/// The clock is selected with `#[rtfm::app(monotonic = path::to::Clock)]`; the default is
+/// `ClockMonotonic`
+pub trait Monotonic {
+    /// Clock on which the POSIX timer of the timer queue is armed
+    ///
+    /// `timer_create` supports fewer clocks than `clock_gettime`. When this is not the clock read
+    /// by `now`, `TIMER_ABSOLUTE` must be `false`
+    const TIMER_CLOCK: clockid_t;
+
+    /// Whether the instants of `now` are instants of `TIMER_CLOCK` too, so that timers can be armed
+    /// to expire at an absolute instant
+    ///
+    /// Otherwise the timers are armed to expire after the time left until the instant, as read by
+    /// `now`; the timer queue re-arms its timer if it expires before `now` reaches the instant
+    const TIMER_ABSOLUTE: bool = true;
+
+    /// Returns an instant corresponding to "now"
+    fn now() -> Instant;
+}
+
+/// `CLOCK_MONOTONIC`
+pub struct ClockMonotonic;
+
+impl Monotonic for ClockMonotonic {
+    const TIMER_CLOCK: clockid_t = nc::CLOCK_MONOTONIC;
+
+    fn now() -> Instant {
+        Instant::now()
+    }
+}
+
+/// `CLOCK_MONOTONIC_RAW`; like `CLOCK_MONOTONIC` but not subject to NTP adjustments
+pub struct ClockMonotonicRaw;
+
+impl Monotonic for ClockMonotonicRaw {
+    // `timer_create` doesn't support `CLOCK_MONOTONIC_RAW`
+    const TIMER_CLOCK: clockid_t = nc::CLOCK_MONOTONIC;
+    // NOTE NTP moves `CLOCK_MONOTONIC` away from `CLOCK_MONOTONIC_RAW`: an absolute expiry would
+    // be in the past, and fire again right away, or late by the offset between them
+    const TIMER_ABSOLUTE: bool = false;
+
+    fn now() -> Instant {
+        Instant {
+            ts: clock_gettime(nc::CLOCK_MONOTONIC_RAW),
+        }
+    }
+}
+
+/// `CLOCK_BOOTTIME`; like `CLOCK_MONOTONIC` but it keeps counting while the system is suspended
+///
+/// With this clock an instant scheduled before a suspend stays the same point in real time: a task
+/// scheduled to run 1 hour from now runs 1 hour from now even if the system sleeps for 30 minutes
+/// in between. If the scheduled instant passes while the system sleeps the task runs right after
+/// the resume
+pub struct ClockBoottime;
+
+impl Monotonic for ClockBoottime {
+    const TIMER_CLOCK: clockid_t = nc::CLOCK_BOOTTIME;
+
+    fn now() -> Instant {
+        Instant {
+            ts: clock_gettime(nc::CLOCK_BOOTTIME),
+        }
+    }
+}
+
+/// `CLOCK_BOOTTIME` whose timers wake the system up from suspend (`CLOCK_BOOTTIME_ALARM`)
+///
+/// NOTE the process needs the `CAP_WAKE_ALARM` capability to create these timers
+pub struct ClockBoottimeAlarm;
+
+impl Monotonic for ClockBoottimeAlarm {
+    const TIMER_CLOCK: clockid_t = nc::CLOCK_BOOTTIME_ALARM;
+
+    // `clock_gettime` doesn't support `CLOCK_BOOTTIME_ALARM`; it's the same clock
+    fn now() -> Instant {
+        ClockBoottime::now()
+    }
+}
+
+const NANOS_IN_ONE_SEC: i64 = 1_000_000_000;
+
 /// A measurement of a monotonically nondecreasing clock. Opaque and useful only with `Duration`
 #[derive(Clone, Copy)]
 pub struct Instant {
@@ -42,7 +126,34 @@ impl Ord for Instant {
     }
 }
 
-fn clock_gettime(clk_id: nc::clockid_t) -> timespec_t {
+/// The `timer_settime` flags and expiration that make a timer on `M::TIMER_CLOCK` expire at
+/// `instant` of `M`; `now` is `M::now()`
+pub(crate) fn expiration<M>(instant: Instant, now: Instant) -> (i32, timespec_t)
+where
+    M: Monotonic,
+{
+    if M::TIMER_ABSOLUTE {
+        (nc::TIMER_ABSTIME, instant.into())
+    } else {
+        // NOTE a zero expiration disarms the timer
+        let timeout = (instant - now).max(Duration::from_nanos(1));
+
+        (
+            0,
+            timespec_t {
+                tv_sec: timeout.as_secs() as _,
+                tv_nsec: timeout.subsec_nanos() as _,
+            },
+        )
+    }
+}
+
+pub(crate) fn clock_gettime(clk_id: nc::clockid_t) -> timespec_t {
+    // a simulation runs on a virtual clock
+    if let Some(ts) = crate::sim::clock_gettime(clk_id) {
+        return ts;
+    }
+
     let mut ts = timespec_t::default();
     nc::clock_gettime(clk_id, &mut ts).expect("Failed to get time");
     ts
@@ -59,25 +170,41 @@ impl Instant {
     /// Returns `Some(t)` where t is the time `self + duration` if t can be represented as `Instant`
     /// (which means it's inside the bounds of the underlying data structure), `None` otherwise.
     pub fn checked_add(&self, dur: Duration) -> Option<Instant> {
-        const NANOS_IN_ONE_SEC: isize = 1_000_000_000;
-
         let mut secs = self
-            .ts
-            .tv_sec
-            .checked_add(isize::try_from(dur.as_secs()).ok()?)?;
-        let mut nanos = self.ts.tv_nsec.wrapping_add(dur.subsec_nanos() as isize);
+            .secs()
+            .checked_add(i64::try_from(dur.as_secs()).ok()?)?;
+        let mut nanos = self.nanos() + i64::from(dur.subsec_nanos());
 
-        if nanos > NANOS_IN_ONE_SEC {
+        if nanos >= NANOS_IN_ONE_SEC {
             nanos -= NANOS_IN_ONE_SEC;
             secs = secs.checked_add(1)?;
         }
 
-        Some(Instant {
-            ts: timespec_t {
-                tv_sec: secs,
-                tv_nsec: nanos,
-            },
-        })
+        Instant::from_parts(secs, nanos)
+    }
+
+    /// Returns `Some(t)` where t is the time `self - duration` if t can be represented as `Instant`
+    /// (which means it's inside the bounds of the underlying data structure), `None` otherwise.
+    pub fn checked_sub(&self, dur: Duration) -> Option<Instant> {
+        let mut secs = self
+            .secs()
+            .checked_sub(i64::try_from(dur.as_secs()).ok()?)?;
+        let mut nanos = self.nanos() - i64::from(dur.subsec_nanos());
+
+        if nanos < 0 {
+            nanos += NANOS_IN_ONE_SEC;
+            secs = secs.checked_sub(1)?;
+        }
+
+        Instant::from_parts(secs, nanos)
+    }
+
+    /// Returns the amount of time elapsed since this instant was created.
+    ///
+    /// NOTE "now" is read from `CLOCK_MONOTONIC` (`Instant::now`) so this is only meaningful for
+    /// instants that come from the same clock
+    pub fn elapsed(&self) -> Duration {
+        Instant::now() - *self
     }
 
     /// Returns the amount of time elapsed from another instant to this one, or `None` if that
@@ -86,15 +213,12 @@ impl Instant {
         if self < &earlier {
             None
         } else {
-            let (sec, nsec) = if self.ts.tv_nsec >= earlier.ts.tv_nsec {
-                (
-                    self.ts.tv_sec - earlier.ts.tv_sec,
-                    self.ts.tv_nsec - earlier.ts.tv_nsec,
-                )
+            let (sec, nsec) = if self.nanos() >= earlier.nanos() {
+                (self.secs() - earlier.secs(), self.nanos() - earlier.nanos())
             } else {
                 (
-                    self.ts.tv_sec - 1 - earlier.ts.tv_sec,
-                    self.ts.tv_nsec + 1_000_000_000 - earlier.ts.tv_nsec,
+                    self.secs() - 1 - earlier.secs(),
+                    self.nanos() + NANOS_IN_ONE_SEC - earlier.nanos(),
                 )
             };
 
@@ -110,6 +234,27 @@ impl Instant {
         self.checked_duration_since(earlier)
             .unwrap_or(Duration::new(0, 0))
     }
+
+    // NOTE `tv_sec` and `tv_nsec` are `long`s, which are 32 bits wide on armv7, so the arithmetic
+    // is done on `i64`s
+    fn secs(&self) -> i64 {
+        self.ts.tv_sec as i64
+    }
+
+    fn nanos(&self) -> i64 {
+        self.ts.tv_nsec as i64
+    }
+
+    // `None` if `secs` doesn't fit in a `tv_sec`
+    fn from_parts(secs: i64, nanos: i64) -> Option<Instant> {
+        Some(Instant {
+            ts: timespec_t {
+                tv_sec: TryFrom::try_from(secs).ok()?,
+                // NOTE `nanos` is always less than `NANOS_IN_ONE_SEC`
+                tv_nsec: nanos as _,
+            },
+        })
+    }
 }
 
 impl ops::Add<Duration> for Instant {
@@ -120,8 +265,43 @@ impl ops::Add<Duration> for Instant {
     }
 }
 
+impl ops::AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, dur: Duration) {
+        *self = *self + dur;
+    }
+}
+
+impl ops::Sub<Duration> for Instant {
+    type Output = Self;
+
+    fn sub(self, dur: Duration) -> Self {
+        self.checked_sub(dur).unwrap()
+    }
+}
+
+impl ops::SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, dur: Duration) {
+        *self = *self - dur;
+    }
+}
+
+impl ops::Sub<Instant> for Instant {
+    type Output = Duration;
+
+    /// Panics if `earlier` is later than `self`; see `saturating_duration_since`
+    fn sub(self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap()
+    }
+}
+
 impl From<Instant> for timespec_t {
     fn from(i: Instant) -> timespec_t {
         i.ts
     }
 }
+
+impl From<timespec_t> for Instant {
+    fn from(ts: timespec_t) -> Instant {
+        Instant { ts }
+    }
+}
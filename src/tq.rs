@@ -1,27 +1,58 @@
 use core::cmp::Ordering;
 
-use crate::time::Instant;
-use heapless::{binary_heap::Min, ArrayLength, BinaryHeap};
-use nc::{itimerspec_t, pid_t, timer_t, timespec_t, SIGRTMIN, TIMER_ABSTIME};
+use crate::{
+    export::SIGRTMIN,
+    faults::{self, Syscall},
+    introspect::TimerQueueInfo,
+    sim,
+    time::{self, Instant, Monotonic},
+    trace,
+};
+use heapless::{binary_heap::Min, ArrayLength, BinaryHeap, Vec};
+use nc::{itimerspec_t, pid_t, timer_t, timespec_t};
 
-pub struct TimerQueue<T, N>(pub BinaryHeap<NotReady<T>, N, Min>)
+pub struct TimerQueue<T, N>
 where
-    T: Copy,
-    N: ArrayLength<NotReady<T>>;
+    N: ArrayLength<NotReady<T>>,
+{
+    pub heap: BinaryHeap<NotReady<T>, N, Min>,
+    /// Marker that will be given to the next enqueued entry
+    pub marker: u32,
+    /// Where to publish the length and the head of the queue; see `rtfm::introspect`
+    pub info: Option<&'static TimerQueueInfo>,
+}
 
 impl<T, N> TimerQueue<T, N>
 where
-    T: Copy,
     N: ArrayLength<NotReady<T>>,
 {
-    pub unsafe fn enqueue_unchecked(
+    /// Like `enqueue_unchecked` but hands `nr` back if the queue is full
+    pub unsafe fn enqueue(
         &mut self,
         nr: NotReady<T>,
         tgid_tid: Option<(pid_t, pid_t)>,
         signo: u8,
-    ) {
+    ) -> Result<u32, NotReady<T>> {
+        if self.heap.len() == self.heap.capacity() {
+            Err(nr)
+        } else {
+            Ok(self.enqueue_unchecked(nr, tgid_tid, signo))
+        }
+    }
+
+    /// Enqueues `nr` and returns the marker that identifies it
+    pub unsafe fn enqueue_unchecked(
+        &mut self,
+        mut nr: NotReady<T>,
+        tgid_tid: Option<(pid_t, pid_t)>,
+        signo: u8,
+    ) -> u32 {
+        let marker = self.marker;
+        self.marker = marker.wrapping_add(1);
+        nr.marker = marker;
+
         if self
-            .0
+            .heap
             .peek()
             .map(|head| nr.instant < head.instant)
             .unwrap_or(true)
@@ -36,75 +67,154 @@ where
             }
         }
 
-        self.0.push_unchecked(nr);
+        self.heap.push_unchecked(nr);
+        self.publish();
+
+        marker
     }
 
-    pub fn dequeue(&mut self, timer_id: timer_t) -> Option<(T, u8)> {
-        if let Some(instant) = self.0.peek().map(|p| p.instant) {
-            let now = Instant::now();
+    /// Removes the entry identified by `marker`, if it's still in the queue
+    pub fn cancel(&mut self, marker: u32) -> Option<NotReady<T>> {
+        // the entries that come before the cancelled one
+        let mut earlier = Vec::<_, N>::new();
+
+        let mut cancelled = None;
+        while let Some(nr) = self.heap.pop() {
+            if nr.marker == marker {
+                cancelled = Some(nr);
+                break;
+            }
+
+            earlier.push(nr).ok();
+        }
+
+        // NOTE if the cancelled entry was the head the timer will fire early and get re-armed
+        while let Some(nr) = earlier.pop() {
+            unsafe { self.heap.push_unchecked(nr) }
+        }
+        self.publish();
+
+        cancelled
+    }
+
+    /// Removes the entries of the tasks for which `keep` returns `false` and hands them to
+    /// `discard`
+    ///
+    /// A mode change uses this to drop the entries of the tasks that the new mode disables
+    pub fn retain(
+        &mut self,
+        mut keep: impl FnMut(&T) -> bool,
+        mut discard: impl FnMut(NotReady<T>),
+    ) {
+        let mut kept = Vec::<_, N>::new();
+        while let Some(nr) = self.heap.pop() {
+            if keep(&nr.task) {
+                kept.push(nr).ok();
+            } else {
+                discard(nr);
+            }
+        }
+
+        // NOTE if the head was discarded the timer will fire early and get re-armed
+        while let Some(nr) = kept.pop() {
+            unsafe { self.heap.push_unchecked(nr) }
+        }
+        self.publish();
+    }
+
+    /// Removes the entry that's due first, without re-arming the timer
+    ///
+    /// The shutdown handler uses this to drop the payloads of the entries that will never run
+    pub fn pop(&mut self) -> Option<NotReady<T>> {
+        let nr = self.heap.pop();
+        self.publish();
+
+        nr
+    }
+
+    /// Moves all the entries whose instant has passed into `ready` and re-arms the timer for the
+    /// next entry, if any
+    ///
+    /// `now` is read only once so entries that expire while this runs are left for the next
+    /// expiration of the timer
+    pub fn dequeue<M>(&mut self, timer_id: timer_t, ready: &mut Vec<(T, u16), N>)
+    where
+        M: Monotonic,
+        N: ArrayLength<(T, u16)>,
+    {
+        let now = M::now();
+
+        while let Some(instant) = self.heap.peek().map(|p| p.instant) {
             if now >= instant {
                 // task became ready
-                let nr = unsafe { self.0.pop_unchecked() };
+                let nr = unsafe { self.heap.pop_unchecked() };
 
-                Some((nr.task, nr.index))
+                // NOTE `ready` has the capacity of the whole queue
+                ready.push((nr.task, nr.index)).ok();
             } else {
+                trace::trace_timer((instant - now).as_nanos() as u64);
+
+                if sim::active() {
+                    unsafe { sim::arm(timer_id, instant, 0) }
+                    break;
+                }
+
                 // set a new timeout
-                nc::timer_settime(
-                    timer_id,
-                    TIMER_ABSTIME,
-                    &itimerspec_t {
-                        it_interval: timespec_t {
-                            tv_sec: 0,
-                            tv_nsec: 0,
-                        },
-                        it_value: instant.into(),
-                    },
-                    None,
-                )
-                .expect("Failed to set timer");
-
-                None
+                let (flags, it_value) = time::expiration::<M>(instant, now);
+                faults::inject(Syscall::TimerSettime)
+                    .and_then(|()| {
+                        nc::timer_settime(
+                            timer_id,
+                            flags,
+                            &itimerspec_t {
+                                it_interval: timespec_t {
+                                    tv_sec: 0,
+                                    tv_nsec: 0,
+                                },
+                                it_value,
+                            },
+                            None,
+                        )
+                    })
+                    .expect("Failed to set timer");
+
+                break;
             }
-        } else {
-            // the queue is empty
-            None
+        }
+
+        self.publish();
+    }
+
+    fn publish(&self) {
+        if let Some(info) = self.info {
+            info.publish(self.heap.len(), self.heap.peek().map(|nr| nr.instant));
         }
     }
 }
 
-pub struct NotReady<T>
-where
-    T: Copy,
-{
-    pub index: u8,
+pub struct NotReady<T> {
+    pub index: u16,
     pub instant: Instant,
     pub task: T,
+    /// Assigned by `TimerQueue::enqueue_unchecked`
+    pub marker: u32,
 }
 
-impl<T> Eq for NotReady<T> where T: Copy {}
+impl<T> Eq for NotReady<T> {}
 
-impl<T> Ord for NotReady<T>
-where
-    T: Copy,
-{
+impl<T> Ord for NotReady<T> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.instant.cmp(&other.instant)
     }
 }
 
-impl<T> PartialEq for NotReady<T>
-where
-    T: Copy,
-{
+impl<T> PartialEq for NotReady<T> {
     fn eq(&self, other: &Self) -> bool {
         self.instant == other.instant
     }
 }
 
-impl<T> PartialOrd for NotReady<T>
-where
-    T: Copy,
-{
+impl<T> PartialOrd for NotReady<T> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(&other))
     }
@@ -0,0 +1,105 @@
+//! `trace_marker` instrumentation
+//!
+//! With the `trace` feature the runtime writes an event to the ftrace `trace_marker` file when a
+//! task starts and ends, when a lock raises the priority and when a timer queue arms its timer.
+//! The events use the atrace format (`B|pid|name`, `E|pid`, `C|pid|name|value`) so kernelshark,
+//! `trace-cmd` and Perfetto show the tasks as slices next to the kernel scheduling events.
+//!
+//! Without the feature every function in this module is empty.
+
+#[cfg(feature = "trace")]
+mod imp {
+    use core::{
+        fmt::{self, Write},
+        sync::atomic::{AtomicI32, Ordering},
+    };
+    use std::{fs::OpenOptions, os::unix::io::IntoRawFd};
+
+    use heapless::{consts, String};
+
+    /// Where the `trace_marker` file may be, depending on how `tracefs` is mounted
+    const PATHS: [&str; 2] = [
+        "/sys/kernel/tracing/trace_marker",
+        "/sys/kernel/debug/tracing/trace_marker",
+    ];
+
+    static FD: AtomicI32 = AtomicI32::new(-1);
+    static TGID: AtomicI32 = AtomicI32::new(0);
+
+    /// Opens `trace_marker`; called once during start-up
+    pub fn trace_open() {
+        for path in PATHS.iter() {
+            if let Ok(file) = OpenOptions::new().write(true).open(path) {
+                TGID.store(nc::getpid(), Ordering::Relaxed);
+                FD.store(file.into_raw_fd(), Ordering::Relaxed);
+
+                return;
+            }
+        }
+
+        eprintln!("warning: couldn't open `trace_marker`; tracing is disabled");
+    }
+
+    /// A task starts
+    pub fn trace_begin(name: &str) {
+        emit(format_args!("B|{}|{}", TGID.load(Ordering::Relaxed), name))
+    }
+
+    /// The innermost task or lock that started on this thread ends
+    pub fn trace_end() {
+        emit(format_args!("E|{}", TGID.load(Ordering::Relaxed)))
+    }
+
+    /// A lock raises the priority to `ceiling`; ended by `trace_end`
+    pub fn trace_lock(name: &str, ceiling: u8) {
+        emit(format_args!(
+            "B|{}|lock {} (ceiling {})",
+            TGID.load(Ordering::Relaxed),
+            name,
+            ceiling
+        ))
+    }
+
+    /// A timer queue arms its timer to fire in `nanos` nanoseconds
+    pub fn trace_timer(nanos: u64) {
+        emit(format_args!(
+            "C|{}|rtfm timer|{}",
+            TGID.load(Ordering::Relaxed),
+            nanos
+        ))
+    }
+
+    // NOTE async-signal-safe: the event is formatted on the stack and written with one syscall
+    fn emit(args: fmt::Arguments<'_>) {
+        let fd = FD.load(Ordering::Relaxed);
+        if fd < 0 {
+            return;
+        }
+
+        let mut event = String::<consts::U128>::new();
+        if event.write_fmt(args).is_ok() {
+            // NOTE tracing is best effort; write errors (e.g. tracing is off) are ignored
+            nc::write(fd, event.as_ptr() as usize, event.len()).ok();
+        }
+    }
+}
+
+#[cfg(not(feature = "trace"))]
+mod imp {
+    #[inline(always)]
+    pub fn trace_open() {}
+
+    #[inline(always)]
+    pub fn trace_begin(_: &str) {}
+
+    #[inline(always)]
+    pub fn trace_end() {}
+
+    #[inline(always)]
+    pub fn trace_lock(_: &str, _: u8) {}
+
+    #[inline(always)]
+    pub fn trace_timer(_: u64) {}
+}
+
+pub use imp::{trace_begin, trace_end, trace_lock, trace_open, trace_timer};
@@ -0,0 +1,117 @@
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use std::{env, ffi::OsString, os::unix::net::UnixDatagram, process, thread, time::Instant};
+
+use crate::{shutdown, sim};
+
+// set by the tasks; cleared by the watchdog thread once per window
+static FED: AtomicBool = AtomicBool::new(false);
+
+// the watchdog thread doesn't expect to be fed before the tasks have been started
+static ARMED: AtomicBool = AtomicBool::new(false);
+
+/// Tells the watchdog that the application is making progress
+///
+/// With `#[app(watchdog = "100ms")]` some task must call this at least once every 100 ms or the
+/// watchdog applies its starvation policy. This is a single atomic store so it can be called from
+/// any context. It does nothing if the application has no watchdog.
+pub fn feed_watchdog() {
+    FED.store(true, Ordering::Relaxed);
+}
+
+/// Starts checking that the tasks feed the watchdog
+pub fn arm_watchdog() {
+    // the watchdog runs in real time; a simulation only moves when the test drives it
+    if sim::active() {
+        return;
+    }
+
+    FED.store(true, Ordering::Relaxed);
+    ARMED.store(true, Ordering::Release);
+}
+
+/// Body of the watchdog thread
+///
+/// Once per `window` it checks that the tasks fed the watchdog. If they did it pets the systemd
+/// watchdog (`WATCHDOG=1`); otherwise it calls `on_starved` with the time since the last feed.
+pub fn watchdog(window: Duration, on_starved: impl Fn(Duration)) -> ! {
+    let notifier = Notifier::new();
+    let mut last_fed = Instant::now();
+    let mut stopping = false;
+
+    loop {
+        thread::sleep(window);
+
+        if stopping {
+            continue;
+        }
+
+        // NOTE the tasks may legitimately stop feeding the watchdog while the application drains
+        if shutdown::shutting_down() {
+            notify(&notifier, "STOPPING=1");
+            stopping = true;
+            continue;
+        }
+
+        if !ARMED.load(Ordering::Acquire) {
+            last_fed = Instant::now();
+            continue;
+        }
+
+        if FED.swap(false, Ordering::Relaxed) {
+            last_fed = Instant::now();
+            notify(&notifier, "WATCHDOG=1");
+        } else {
+            on_starved(last_fed.elapsed());
+        }
+    }
+}
+
+/// Default starvation policy
+pub fn watchdog_abort(starved: Duration) {
+    eprintln!(
+        "error: the watchdog has not been fed for {:?}; aborting",
+        starved
+    );
+
+    process::abort()
+}
+
+pub fn watchdog_log(starved: Duration) {
+    eprintln!("warning: the watchdog has not been fed for {:?}", starved);
+}
+
+/// Sends `state` (e.g. `READY=1`) to systemd; does nothing if the process is not a systemd service
+/// of `Type=notify` or with `WatchdogSec=`
+pub fn sd_notify(state: &str) {
+    notify(&Notifier::new(), state)
+}
+
+// The `$NOTIFY_SOCKET` of the service manager
+//
+// NOTE abstract socket names (`@...`) are not supported
+struct Notifier {
+    socket: UnixDatagram,
+    path: OsString,
+}
+
+impl Notifier {
+    fn new() -> Option<Self> {
+        let path = env::var_os("NOTIFY_SOCKET")?;
+        let socket = UnixDatagram::unbound().ok()?;
+
+        Some(Notifier { socket, path })
+    }
+}
+
+fn notify(notifier: &Option<Notifier>, state: &str) {
+    if let Some(notifier) = notifier {
+        // NOTE best effort; systemd will notice a process that stopped notifying it
+        notifier
+            .socket
+            .send_to(state.as_bytes(), &notifier.path)
+            .ok();
+    }
+}
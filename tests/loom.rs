@@ -0,0 +1,179 @@
+//! Model checks of the synchronization primitives of the runtime and of the `lock` sequence
+//!
+//! Run with `cargo test --release --features loom --test loom`
+
+#![cfg(feature = "loom")]
+
+use core::{cell::Cell, ops::Range};
+
+use loom::{
+    cell::UnsafeCell,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+};
+use rtfm::export::{self, Barrier, FreeSlots, Pid, Priority, SignalMask};
+
+// Data that one thread initializes and another one reads once the barrier is released
+struct Init {
+    barrier: Barrier,
+    data: UnsafeCell<u32>,
+}
+
+unsafe impl Sync for Init {}
+
+#[test]
+fn barrier_publishes_initialization() {
+    loom::model(|| {
+        let init = Arc::new(Init {
+            barrier: Barrier::new(),
+            data: UnsafeCell::new(0),
+        });
+
+        let core1 = {
+            let init = init.clone();
+            thread::spawn(move || {
+                init.barrier.wait();
+                assert_eq!(init.data.with(|data| unsafe { *data }), 42);
+            })
+        };
+
+        init.data.with_mut(|data| unsafe { *data = 42 });
+        init.barrier.release();
+
+        core1.join().unwrap();
+    });
+}
+
+#[test]
+fn pid_wait_returns_the_pid() {
+    loom::model(|| {
+        let pid = Arc::new(Pid::uninit());
+
+        let child = {
+            let pid = pid.clone();
+            thread::spawn(move || pid.init(1234))
+        };
+
+        assert_eq!(pid.wait(), 1234);
+        child.join().unwrap();
+    });
+}
+
+// `spawn_blocking` against the dispatcher that frees the only message slot: the wake up can't get
+// lost, or the model would deadlock
+#[test]
+fn free_slots_wake_up_the_waiter() {
+    loom::model(|| {
+        let state = Arc::new((FreeSlots::new(), AtomicBool::new(true)));
+
+        let dispatcher = {
+            let state = state.clone();
+            thread::spawn(move || {
+                state.1.store(false, Ordering::Release);
+                state.0.notify();
+            })
+        };
+
+        loop {
+            let token = state.0.token();
+            if state
+                .1
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+
+            state.0.wait(token);
+        }
+
+        dispatcher.join().unwrap();
+    });
+}
+
+/// The signals `0..2` of a core: priority 2 is signal 0 and priority 1 is signal 1
+const SIGNALS: Range<u8> = 0..2;
+
+/// A core with a task of priority 1 and a task of priority 2 that share a resource
+///
+/// Like the kernel, the model delivers a pending signal that is not blocked as soon as the mask
+/// changes; `preemption_point` delivers them anywhere else
+struct Core {
+    // NOTE only the thread of the core touches these
+    blocked: Cell<u32>,
+    counter: Cell<u32>,
+    // set by other threads
+    pending: Arc<AtomicU32>,
+}
+
+impl Core {
+    fn preemption_point(&self) {
+        let ready = self.pending.load(Ordering::Acquire) & !self.blocked.get();
+
+        // the priority 2 task
+        if ready & 1 != 0 {
+            self.pending.fetch_and(!1, Ordering::AcqRel);
+            self.counter.set(self.counter.get() + 1);
+        }
+    }
+}
+
+impl SignalMask for Core {
+    unsafe fn mask(&self, range: Range<u8>, current: u8, ceiling: u8, block: bool) {
+        let signals = export::lock_signals(range, current, ceiling);
+        let bits = signals
+            .map(|signo| 1 << signo)
+            .fold(0, |bits, bit| bits | bit);
+
+        if block {
+            self.preemption_point();
+            self.blocked.set(self.blocked.get() | bits);
+        } else {
+            self.blocked.set(self.blocked.get() & !bits);
+            self.preemption_point();
+        }
+    }
+}
+
+// the priority 2 task, spawned from another core, never runs inside the critical section of the
+// priority 1 task
+#[test]
+fn lock_masks_the_ceiling() {
+    loom::model(|| {
+        let pending = Arc::new(AtomicU32::new(0));
+        let core = Core {
+            blocked: Cell::new(0),
+            counter: Cell::new(0),
+            pending: pending.clone(),
+        };
+
+        let spawner = thread::spawn(move || {
+            pending.fetch_or(1, Ordering::AcqRel);
+        });
+
+        unsafe {
+            let priority = Priority::new(1);
+            export::lock_with(
+                &core,
+                core.counter.as_ptr(),
+                "counter",
+                &priority,
+                2,
+                SIGNALS,
+                |counter| {
+                    let read = *counter;
+                    core.preemption_point();
+                    *counter = read + 1;
+                },
+            );
+        }
+
+        spawner.join().unwrap();
+        core.preemption_point();
+
+        assert_eq!(core.counter.get(), 2);
+    });
+}
@@ -0,0 +1,27 @@
+//! The signal path: a handler that the runtime registers runs and returns to the code it
+//! interrupted, through the `rt_sigreturn` trampoline of the target architecture
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use rtfm::export::{self, siginfo_t};
+
+static RAN: AtomicU32 = AtomicU32::new(0);
+
+extern "C" fn handler(_: i32, _: &mut siginfo_t, _: usize) {
+    RAN.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test]
+fn handlers_return_to_the_interrupted_code() {
+    unsafe {
+        // the handler of the only priority level of the signals `0..1`
+        export::register(0..1, 1, handler).unwrap();
+
+        // NOTE a signal sent to the calling thread is delivered before the system call returns
+        for expected in 1..=3 {
+            export::enqueue_fd_event(nc::getpid(), Some(nc::gettid()), 0, 0);
+
+            assert_eq!(RAN.load(Ordering::Relaxed), expected);
+        }
+    }
+}
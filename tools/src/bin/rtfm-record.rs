@@ -0,0 +1,68 @@
+//! Prints a recording made with the `record` feature of `linux-rtfm`
+
+use std::{env, error::Error, fs, process};
+
+const HEADER: usize = 16;
+const EVENT: usize = 20;
+const NO_VALUE: u32 = u32::max_value();
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = if let Some(path) = env::args().nth(1) {
+        path
+    } else {
+        eprintln!("usage: rtfm-record <recording>");
+        process::exit(1)
+    };
+
+    let bytes = fs::read(&path)?;
+    if bytes.len() < HEADER || &bytes[..8] != b"RTFMREC1" || (bytes.len() - HEADER) % EVENT != 0 {
+        return Err(format!("`{}` is not a recording", path).into());
+    }
+
+    let pid = i32::from_ne_bytes(word(&bytes, 8));
+    let lost = u32::from_ne_bytes(word(&bytes, 12));
+    println!("process {}; {} older events were overwritten", pid, lost);
+
+    let mut first = None;
+    for (i, event) in bytes[HEADER..].chunks(EVENT).enumerate() {
+        let mut nanos = [0; 8];
+        nanos.copy_from_slice(&event[..8]);
+        let nanos = u64::from_ne_bytes(nanos);
+        let tid = i32::from_ne_bytes(word(event, 8));
+        let value = u32::from_ne_bytes(word(event, 12));
+        let (kind, signo, depth) = (event[16], event[17], event[18]);
+
+        let elapsed = nanos - *first.get_or_insert(nanos);
+        let what = match kind {
+            0 | 1 if value != NO_VALUE => format!(
+                "{} signal {} (task {}, index {})",
+                if kind == 0 { "enqueue" } else { "dispatch" },
+                signo,
+                value >> 24,
+                value as u16
+            ),
+            0 => format!("enqueue signal {}", signo),
+            1 => format!("dispatch signal {}", signo),
+            2 => format!("complete signal {}", signo),
+            3 => format!("unlock from ceiling {}", value),
+            4 => "start".to_owned(),
+            _ => format!("unknown event {}", kind),
+        };
+
+        println!(
+            "{:>6} +{}.{:09}s tid {:<6} {}{}",
+            u64::from(lost) + i as u64,
+            elapsed / 1_000_000_000,
+            elapsed % 1_000_000_000,
+            tid,
+            "  ".repeat(usize::from(depth)),
+            what
+        );
+    }
+
+    Ok(())
+}
+
+fn word(bytes: &[u8], i: usize) -> [u8; 4] {
+    [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]
+}
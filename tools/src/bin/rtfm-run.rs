@@ -7,7 +7,7 @@ use std::{
 fn main() -> Result<(), Box<Error>> {
     if let Some(path) = env::args().nth(1) {
         let mut c = Command::new("sudo");
-        c.args(&["setcap", "cap_sys_nice+ep", &path]);
+        c.args(&["setcap", "cap_sys_nice,cap_ipc_lock+ep", &path]);
         eprintln!("$ {:?}", c);
         let status = c.status()?;
 